@@ -105,3 +105,13 @@ fn bench_mock_hyper(b: &mut test::Bencher) {
     });
 }
 
+
+#[bench]
+fn bench_request_head(b: &mut test::Bencher) {
+    let url = hyper::Url::parse("http://127.0.0.1:1337/some/path?with=query").unwrap();
+    b.iter(|| {
+        let mut req = hyper::client::Request::detached(hyper::Get, url.clone()).unwrap();
+        req.headers_mut().set(Foo);
+        req.write_to(io::sink()).unwrap().end().unwrap()
+    });
+}
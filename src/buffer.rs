@@ -1,4 +1,5 @@
 use std::cmp;
+use std::fmt;
 use std::iter;
 use std::io::{self, Read, BufRead};
 
@@ -46,6 +47,15 @@ impl<R: Read> BufReader<R> {
         }
     }
 
+    #[inline]
+    pub fn get_mut_buf(&mut self) -> &mut [u8] {
+        if self.pos < self.cap {
+            &mut self.buf[self.pos..self.cap]
+        } else {
+            &mut []
+        }
+    }
+
     #[inline]
     pub fn into_inner(self) -> R { self.inner }
 
@@ -76,6 +86,12 @@ impl<R: Read> BufReader<R> {
     }
 }
 
+impl<R: fmt::Debug> fmt::Debug for BufReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BufReader {{ inner: {:?}, pos: {}, cap: {} }}", self.inner, self.pos, self.cap)
+    }
+}
+
 impl<R: Read> Read for BufReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if self.cap == self.pos && buf.len() >= self.buf.len() {
@@ -0,0 +1,227 @@
+//! Sending many requests at once.
+//!
+//! `send_all` sends a batch of requests from a fixed number of worker
+//! threads, so that no more than that many are in flight at a time. It is
+//! meant for crawlers, and for bulk calls to an API.
+//!
+//! A `ResponseSet` waits on requests that have already been started, each
+//! on its own connection, and hands back their responses in the order they
+//! arrive.
+//!
+//! ```no_run
+//! use hyper::Client;
+//! use hyper::client::RequestParts;
+//! use hyper::client::batch::send_all;
+//! use hyper::method::Method::Get;
+//! use hyper::Url;
+//!
+//! let requests = (1..100).map(|id| {
+//!     let url = Url::parse(&format!("http://example.domain/users/{}", id)).unwrap();
+//!     RequestParts::new(Get, url)
+//! }).collect();
+//!
+//! for res in send_all(Client::new, requests, 8) {
+//!     match res {
+//!         Ok(res) => println!("{}", res.status),
+//!         Err(e) => println!("error: {}", e),
+//!     }
+//! }
+//! ```
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::thread;
+
+use net::Streaming;
+use super::{Client, Request, RequestParts, Response};
+
+/// Send every request, with at most `max_in_flight` in flight at a time.
+///
+/// Each worker thread sends its requests through its own `Client`, made by
+/// calling `new_client`. The results are returned in the same order as the
+/// requests, with an error for each request that failed, so one failure
+/// doesn't stop the rest of the batch.
+///
+/// The response bodies are not read, so each successful response keeps its
+/// connection until it is read or dropped.
+///
+/// # Panics
+///
+/// Panics if `max_in_flight` is 0, or if a worker thread panics.
+pub fn send_all<F>(new_client: F, requests: Vec<RequestParts>, max_in_flight: usize)
+    -> Vec<::Result<Response>>
+where F: Fn() -> Client + Send + Sync + 'static {
+    assert!(max_in_flight > 0, "max_in_flight must be greater than 0");
+    let len = requests.len();
+    let queue = Arc::new(Mutex::new(requests.into_iter().enumerate()));
+    let new_client = Arc::new(new_client);
+    let (tx, rx) = mpsc::channel();
+
+    let workers = (0..::std::cmp::min(max_in_flight, len)).map(|_| {
+        let queue = queue.clone();
+        let new_client = new_client.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let mut client = new_client();
+            loop {
+                // the lock is released before the request is sent
+                let next = queue.lock().unwrap().next();
+                let (i, parts) = match next {
+                    Some(next) => next,
+                    None => return
+                };
+                trace!("send_all sending request {}", i);
+                if tx.send((i, client.execute(&parts))).is_err() {
+                    return;
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+    drop(tx);
+
+    let mut results = (0..len).map(|_| None).collect::<Vec<_>>();
+    for (i, res) in rx.iter() {
+        results[i] = Some(res);
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            panic!("send_all worker thread panicked");
+        }
+    }
+    results.into_iter().map(|res| res.unwrap()).collect()
+}
+
+/// A set of in-flight requests, whose responses are returned as soon as
+/// their heads arrive.
+///
+/// ```no_run
+/// # use hyper::client::Request;
+/// # use hyper::client::batch::ResponseSet;
+/// # use hyper::method::Method::Get;
+/// # use hyper::Url;
+/// let mut set = ResponseSet::new();
+/// for host in &["http://a.domain", "http://b.domain"] {
+///     let req = Request::new(Get, Url::parse(host).unwrap()).unwrap();
+///     set.push(req.start().unwrap());
+/// }
+/// while let Some((id, res)) = set.next() {
+///     println!("request {} answered: {:?}", id, res.map(|res| res.status));
+/// }
+/// ```
+pub struct ResponseSet {
+    tx: mpsc::Sender<(usize, ::Result<Response>)>,
+    rx: mpsc::Receiver<(usize, ::Result<Response>)>,
+    pushed: usize,
+    pending: usize,
+}
+
+impl ResponseSet {
+    /// Create an empty `ResponseSet`.
+    pub fn new() -> ResponseSet {
+        let (tx, rx) = mpsc::channel();
+        ResponseSet {
+            tx: tx,
+            rx: rx,
+            pushed: 0,
+            pending: 0,
+        }
+    }
+
+    /// Finish sending a started request, and wait for its response in the
+    /// background.
+    ///
+    /// Returns the id its response will be returned with, which counts up
+    /// from 0 in the order requests were pushed.
+    pub fn push(&mut self, req: Request<Streaming>) -> usize {
+        let id = self.pushed;
+        self.pushed += 1;
+        self.pending += 1;
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            let _ = tx.send((id, req.send()));
+        });
+        id
+    }
+
+    /// The number of requests still waiting for a response.
+    pub fn len(&self) -> usize {
+        self.pending
+    }
+
+    /// Block until the next response head arrives, returning it with the id
+    /// of its request, or `None` once every response has been returned.
+    pub fn next(&mut self) -> Option<(usize, ::Result<Response>)> {
+        if self.pending == 0 {
+            return None;
+        }
+        // the set keeps a Sender of its own, so this can't fail
+        let next = self.rx.recv().unwrap();
+        self.pending -= 1;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use client::{Client, RequestParts};
+    use client::stub::{StubConnector, StubResponse};
+    use method::Method::Get;
+    use status::StatusCode;
+    use url::Url;
+    use super::send_all;
+
+    fn stub_client() -> Client {
+        let stub = StubConnector::new();
+        stub.route(None, "http://127.0.0.1/missing", StubResponse::new(StatusCode::NotFound));
+        stub.route(None, "http://127.0.0.1/*", StubResponse::new(StatusCode::Ok));
+        Client::with_connector(stub)
+    }
+
+    #[test]
+    fn test_send_all_in_order() {
+        let requests = vec!["/a", "/missing", "/b", "/c", "/missing"].into_iter().map(|path| {
+            RequestParts::new(Get, Url::parse(&format!("http://127.0.0.1{}", path)).unwrap())
+        }).collect();
+        let statuses = send_all(stub_client, requests, 2).into_iter()
+            .map(|res| res.unwrap().status)
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, vec![StatusCode::Ok, StatusCode::NotFound, StatusCode::Ok,
+                                  StatusCode::Ok, StatusCode::NotFound]);
+    }
+
+    #[test]
+    fn test_response_set_completion_order() {
+        use client::Request;
+        use server::testing::{TestServer, Expected, Reply};
+        use super::ResponseSet;
+
+        let slow = TestServer::new();
+        slow.expect(Expected::new(Get, "/"), Reply::new(StatusCode::Ok).delay_ms(100));
+        let fast = TestServer::new();
+        fast.expect(Expected::new(Get, "/"), Reply::new(StatusCode::Accepted));
+
+        let mut set = ResponseSet::new();
+        for server in &[&slow, &fast] {
+            let req = Request::new(Get, Url::parse(&server.url("/")).unwrap()).unwrap();
+            set.push(req.start().unwrap());
+        }
+        assert_eq!(set.len(), 2);
+
+        let (id, res) = set.next().unwrap();
+        assert_eq!((id, res.unwrap().status), (1, StatusCode::Accepted));
+        let (id, res) = set.next().unwrap();
+        assert_eq!((id, res.unwrap().status), (0, StatusCode::Ok));
+        assert!(set.next().is_none());
+    }
+
+    #[test]
+    fn test_send_all_errors() {
+        let requests = vec![
+            RequestParts::new(Get, Url::parse("http://127.0.0.1/a").unwrap()),
+            RequestParts::new(Get, Url::parse("http://127.0.0.2/a").unwrap()),
+        ];
+        let results = send_all(stub_client, requests, 4);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+}
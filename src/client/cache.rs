@@ -0,0 +1,609 @@
+//! A private HTTP cache for the `Client`, as defined in
+//! [RFC7234](https://tools.ietf.org/html/rfc7234).
+//!
+//! A `Client` given a `Cache` with `set_cache` keeps the responses to its
+//! `GET` requests that the server allows it to. While a stored response is
+//! fresh, by its `Cache-Control: max-age` or `Expires`, it is served without
+//! contacting the server. Once stale, it is revalidated with `If-None-Match`
+//! or `If-Modified-Since`, and served again if the server answers
+//! `304 Not Modified`.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use hyper::client::cache::{Cache, DirStore};
+//!
+//! let mut client = Client::new();
+//! client.set_cache(Some(Cache::with_store(DirStore::open("http-cache").unwrap())));
+//! ```
+//!
+//! Responses are kept in a `CacheStore`. The default `MemoryStore` forgets
+//! them when it is dropped, while a `DirStore` keeps each in a file of its
+//! own, so they outlast the process.
+//!
+//! Only responses with a `Content-Length` are stored, and none with a
+//! `Vary` header, as requests are only told apart by their URL.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use time::Timespec;
+
+use buffer::BufReader;
+use clock::{Clock, SystemClock};
+use header::{Headers, CacheControl, CacheDirective, ContentLength, Date, Expires, HttpDate,
+             Pragma, TransferEncoding};
+use header::parsing::from_digits;
+use http::{self, RawStatus, LINE_ENDING};
+use method::Method;
+use net::NetworkStream;
+use status::StatusCode;
+use version::HttpVersion;
+use Url;
+
+use super::Response;
+
+/// A response kept by a `Cache`.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The status code and reason the server sent.
+    pub status: RawStatus,
+    /// The HTTP version of the response.
+    pub version: HttpVersion,
+    /// The headers of the response, with its `Content-Length`.
+    pub headers: Headers,
+    /// The whole body of the response.
+    pub body: Vec<u8>,
+    /// When the response was received, or last revalidated.
+    pub stored: Timespec,
+}
+
+impl Entry {
+    // The response as it would arrive on the wire.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{} {} {}{}{}{}", self.version, self.status.0, self.status.1,
+                                LINE_ENDING, self.headers, LINE_ENDING).into_bytes();
+        bytes.extend(self.body.iter().cloned());
+        bytes
+    }
+
+    fn from_bytes(stored: Timespec, bytes: &[u8]) -> Option<Entry> {
+        let head_end = match http::find_head_end(bytes) {
+            Some(end) => end,
+            None => return None
+        };
+        let head = match http::parse_response(&mut BufReader::new(&bytes[..head_end])) {
+            Ok(head) => head,
+            Err(_) => return None
+        };
+        Some(Entry {
+            status: head.subject,
+            version: head.version,
+            headers: head.headers,
+            body: bytes[head_end..].to_vec(),
+            stored: stored,
+        })
+    }
+
+    fn response(&self, age: i64) -> ::Result<Response> {
+        let mut entry = self.clone();
+        entry.headers.set_raw("Age", vec![age.to_string().into_bytes()]);
+        let stream = CachedStream(Cursor::new(entry.to_bytes()));
+        Response::with_method(Box::new(stream), &Method::Get)
+    }
+
+    // How long the response is fresh for after it was stored, in seconds.
+    fn lifetime(&self) -> i64 {
+        if let Some(&CacheControl(ref directives)) = self.headers.get() {
+            for directive in directives {
+                if let CacheDirective::MaxAge(secs) = *directive {
+                    return secs as i64;
+                }
+            }
+        }
+        match self.headers.get::<Expires>() {
+            Some(&Expires(HttpDate(ref expires))) => {
+                let date = match self.headers.get::<Date>() {
+                    Some(&Date(HttpDate(ref date))) => date.to_timespec().sec,
+                    None => self.stored.sec
+                };
+                expires.to_timespec().sec - date
+            },
+            None => 0
+        }
+    }
+
+    // How old the response is now, counting the age it already had when it
+    // was received.
+    fn age(&self, now: Timespec) -> i64 {
+        let initial = self.headers.get_raw("Age")
+            .and_then(|raw| raw.first())
+            .and_then(|raw| from_digits(raw))
+            .unwrap_or(0);
+        (now.sec - self.stored.sec) + initial as i64
+    }
+
+    fn has_validators(&self) -> bool {
+        self.headers.get_raw("ETag").is_some() || self.headers.get_raw("Last-Modified").is_some()
+    }
+}
+
+/// Where a `Cache` keeps its responses, by URL.
+pub trait CacheStore: Send + Sync {
+    /// The response stored for `key`, fresh or not.
+    fn get(&self, key: &str) -> Option<Entry>;
+    /// Store the response for `key`, replacing any it had.
+    fn put(&self, key: &str, entry: Entry);
+    /// Forget the response for `key`.
+    fn remove(&self, key: &str);
+}
+
+/// A `CacheStore` that keeps its responses in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore(Mutex<HashMap<String, Entry>>);
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore(Mutex::new(HashMap::new()))
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<Entry> {
+        self.0.lock().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: &str, entry: Entry) {
+        self.0.lock().unwrap().insert(key.to_string(), entry);
+    }
+
+    fn remove(&self, key: &str) {
+        self.0.lock().unwrap().remove(key);
+    }
+}
+
+/// A `CacheStore` that keeps each response in a file of its own, in a
+/// directory.
+///
+/// A file holds the URL it is for, the second the response was stored, and
+/// the response itself as it came over the wire.
+#[derive(Debug)]
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+impl DirStore {
+    /// Open the store in the directory `dir`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<DirStore> {
+        let dir = dir.as_ref().to_path_buf();
+        try!(fs::create_dir_all(&dir));
+        Ok(DirStore { dir: dir })
+    }
+
+    // URLs can be long, and hold characters a file name can't, so files are
+    // named by a hash of the URL, which they also record to rule out
+    // collisions.
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for b in key.bytes() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        self.dir.join(format!("{:016x}", hash))
+    }
+
+    fn read(&self, key: &str) -> io::Result<Option<Entry>> {
+        let mut bytes = Vec::new();
+        try!(try!(File::open(self.path(key))).read_to_end(&mut bytes));
+        let mut lines = bytes.splitn(3, |&b| b == b'\n');
+        let (url, stored, response) = match (lines.next(), lines.next(), lines.next()) {
+            (Some(url), Some(stored), Some(response)) => (url, stored, response),
+            _ => return Ok(None)
+        };
+        if url != key.as_bytes() {
+            return Ok(None);
+        }
+        let stored = match from_digits(stored) {
+            Some(stored) => Timespec::new(stored as i64, 0),
+            None => return Ok(None)
+        };
+        Ok(Entry::from_bytes(stored, response))
+    }
+}
+
+impl CacheStore for DirStore {
+    fn get(&self, key: &str) -> Option<Entry> {
+        match self.read(key) {
+            Ok(entry) => entry,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                debug!("error reading cached response for {}: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    fn put(&self, key: &str, entry: Entry) {
+        let res = File::create(self.path(key)).and_then(|mut file| {
+            try!(write!(file, "{}\n{}\n", key, entry.stored.sec));
+            file.write_all(&entry.to_bytes())
+        });
+        if let Err(e) = res {
+            debug!("error caching response for {}: {:?}", key, e);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        match fs::remove_file(self.path(key)) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => debug!("error removing cached response for {}: {:?}", key, e)
+        }
+    }
+}
+
+/// The responses a `Client` keeps, to answer later requests without the
+/// server, or with only a short answer from it.
+pub struct Cache {
+    store: Box<CacheStore>,
+    clock: Arc<Clock>,
+    max_entry_size: u64,
+}
+
+impl Cache {
+    /// Create a `Cache` that keeps its responses in memory.
+    pub fn new() -> Cache {
+        Cache::with_store(MemoryStore::new())
+    }
+
+    /// Create a `Cache` that keeps its responses in `store`.
+    pub fn with_store<S: CacheStore + 'static>(store: S) -> Cache {
+        Cache {
+            store: Box::new(store),
+            clock: Arc::new(SystemClock),
+            max_entry_size: 1024 * 1024,
+        }
+    }
+
+    /// Set the `Clock` used to decide when responses are stale.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// The largest body to store, in bytes. The default is 1MB.
+    pub fn set_max_entry_size(&mut self, max: u64) {
+        self.max_entry_size = max;
+    }
+
+    /// The stored response for a `GET` of `url`, if it is fresh enough to
+    /// be served without asking the server.
+    ///
+    /// `headers` are those of the request, which can ask for the server to
+    /// be asked anyway, with `Cache-Control: no-cache`.
+    pub fn get(&self, url: &Url, headers: &Headers) -> ::Result<Option<Response>> {
+        if bypass(headers) || no_cache(headers) {
+            return Ok(None);
+        }
+        let key = url.serialize();
+        let entry = match self.store.get(&key) {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+        let age = entry.age(self.clock.now());
+        if age >= entry.lifetime() || has_directive(&entry.headers, CacheDirective::NoCache) {
+            if !entry.has_validators() {
+                self.store.remove(&key);
+            }
+            return Ok(None);
+        }
+        debug!("serving {} from cache, {}s old", key, age);
+        entry.response(age).map(Some)
+    }
+
+    /// Add `If-None-Match` and `If-Modified-Since` to the headers of a
+    /// `GET` of `url`, if a stale response is stored that the server can
+    /// confirm is still current.
+    pub fn validate(&self, url: &Url, headers: &mut Headers) {
+        if bypass(headers) {
+            return;
+        }
+        if let Some(entry) = self.store.get(&url.serialize()) {
+            if let Some(etag) = entry.headers.get_raw("ETag") {
+                headers.set_raw("If-None-Match", etag.to_vec());
+            }
+            if let Some(modified) = entry.headers.get_raw("Last-Modified") {
+                headers.set_raw("If-Modified-Since", modified.to_vec());
+            }
+        }
+    }
+
+    /// Learn from the response `res` to a request for `url`.
+    ///
+    /// A `GET` response the server allows to be stored is read whole and
+    /// stored, and a `304 Not Modified` to a request made conditional by
+    /// `validate` is answered with the stored response. Any other request
+    /// that succeeds, such as a `PUT`, makes the stored response stale, and
+    /// it is forgotten.
+    ///
+    /// `headers` are those the request was made with, before `validate`.
+    pub fn observe(&self, url: &Url, method: &Method, headers: &Headers, mut res: Response)
+        -> ::Result<Response> {
+        let key = url.serialize();
+        if *method != Method::Get {
+            if !method.is_safe() && !res.status.is_client_error() && !res.status.is_server_error() {
+                self.store.remove(&key);
+            }
+            return Ok(res);
+        }
+        if bypass(headers) {
+            return Ok(res);
+        }
+        let now = self.clock.now();
+
+        if res.status == StatusCode::NotModified {
+            if let Some(mut entry) = self.store.get(&key) {
+                debug!("cached response for {} is still current", key);
+                entry.headers.extend(res.headers.iter().filter(|header| {
+                    !header.is::<ContentLength>() && !header.is::<TransferEncoding>()
+                }));
+                entry.stored = now;
+                self.store.put(&key, entry.clone());
+                return entry.response(0);
+            }
+            return Ok(res);
+        }
+
+        if !self.storable(headers, &res) {
+            self.store.remove(&key);
+            return Ok(res);
+        }
+        let mut body = Vec::new();
+        try!(res.read_to_end(&mut body));
+        let entry = Entry {
+            status: res.status_raw().clone(),
+            version: res.version,
+            headers: res.headers.clone(),
+            body: body,
+            stored: now,
+        };
+        debug!("caching response for {}", key);
+        self.store.put(&key, entry.clone());
+        entry.response(0)
+    }
+
+    fn storable(&self, req: &Headers, res: &Response) -> bool {
+        match res.status {
+            StatusCode::Ok | StatusCode::NonAuthoritativeInformation |
+            StatusCode::NotFound | StatusCode::Gone => (),
+            _ => return false
+        }
+        let len = match res.headers.get::<ContentLength>() {
+            Some(&ContentLength(len)) => len,
+            None => return false
+        };
+        let has_validators = res.headers.get_raw("ETag").is_some() ||
+            res.headers.get_raw("Last-Modified").is_some();
+        let has_lifetime = res.headers.get_raw("Expires").is_some() ||
+            res.headers.get::<CacheControl>().map_or(false, |cc| cc.iter().any(|directive| {
+                match *directive {
+                    CacheDirective::MaxAge(_) => true,
+                    _ => false
+                }
+            }));
+        len <= self.max_entry_size &&
+            (has_validators || has_lifetime) &&
+            !has_directive(&res.headers, CacheDirective::NoStore) &&
+            res.headers.get_raw("Vary").is_none() &&
+            // a response to a request with credentials is only for others
+            // to see if the server says so
+            (req.get_raw("Authorization").is_none() ||
+             has_directive(&res.headers, CacheDirective::Public))
+    }
+}
+
+fn has_directive(headers: &Headers, directive: CacheDirective) -> bool {
+    headers.get::<CacheControl>().map_or(false, |cc| cc.contains(&directive))
+}
+
+// Requests the cache stays out of entirely: those the caller made
+// conditional on their own, and those that ask for nothing to be stored.
+fn bypass(headers: &Headers) -> bool {
+    headers.get_raw("If-None-Match").is_some() ||
+        headers.get_raw("If-Modified-Since").is_some() ||
+        has_directive(headers, CacheDirective::NoStore)
+}
+
+fn no_cache(headers: &Headers) -> bool {
+    has_directive(headers, CacheDirective::NoCache) ||
+        has_directive(headers, CacheDirective::MaxAge(0)) ||
+        headers.get::<Pragma>() == Some(&Pragma::NoCache)
+}
+
+// Serves a stored response to `Response` as if it came from a server.
+struct CachedStream(Cursor<Vec<u8>>);
+
+impl Read for CachedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for CachedStream {
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        Ok(msg.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for CachedStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok("127.0.0.1:0".parse().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use time::{Duration, Timespec};
+
+    use client::Client;
+    use clock::TestClock;
+    use header::{CacheControl, CacheDirective, Headers};
+    use http::RawStatus;
+    use net::{NetworkConnector, NetworkStream, ContextVerifier};
+    use status::StatusCode;
+    use version::HttpVersion;
+    use url::Url;
+    use super::{Cache, CacheStore, DirStore, Entry};
+
+    // Answers every request with a cacheable response, or a 304 if the
+    // request was conditional, and counts the requests it saw.
+    struct Origin(Arc<Mutex<Vec<String>>>);
+
+    struct OriginStream {
+        requests: Arc<Mutex<Vec<String>>>,
+        written: Vec<u8>,
+        response: Option<Cursor<Vec<u8>>>,
+    }
+
+    impl NetworkConnector for Origin {
+        type Stream = OriginStream;
+
+        fn connect(&self, _: &str, _: u16, _: &str) -> ::Result<OriginStream> {
+            Ok(OriginStream {
+                requests: self.0.clone(),
+                written: vec![],
+                response: None,
+            })
+        }
+
+        fn set_ssl_verifier(&mut self, _: ContextVerifier) {}
+    }
+
+    impl Read for OriginStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.response.is_none() {
+                let req = String::from_utf8_lossy(&self.written).into_owned();
+                let res = if req.contains("If-None-Match: \"v1\"\r\n") {
+                    "HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=60\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nETag: \"v1\"\r\n\
+                     Content-Length: 5\r\n\r\nhello"
+                };
+                self.requests.lock().unwrap().push(req);
+                self.response = Some(Cursor::new(res.as_bytes().to_vec()));
+            }
+            self.response.as_mut().unwrap().read(buf)
+        }
+    }
+
+    impl Write for OriginStream {
+        fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+            self.written.write(msg)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl NetworkStream for OriginStream {
+        fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+            Ok("127.0.0.1:0".parse().unwrap())
+        }
+    }
+
+    fn body(mut res: ::client::Response) -> String {
+        let mut s = String::new();
+        res.read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_fresh_and_revalidated() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let clock = Arc::new(TestClock::new(Timespec::new(1000, 0)));
+        let mut cache = Cache::new();
+        cache.set_clock(clock.clone());
+        let mut client = Client::with_connector(Origin(requests.clone()));
+        client.set_cache(Some(cache));
+
+        assert_eq!(body(client.get("http://example.domain/a").send().unwrap()), "hello");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // fresh, so the origin isn't asked
+        clock.advance(Duration::seconds(30));
+        let res = client.get("http://example.domain/a").send().unwrap();
+        assert_eq!(res.headers.get_raw("Age"), Some(&[b"30".to_vec()][..]));
+        assert_eq!(body(res), "hello");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // stale, so it is revalidated, and the stored body served again
+        clock.advance(Duration::seconds(31));
+        let res = client.get("http://example.domain/a").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(body(res), "hello");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(requests.lock().unwrap()[1].contains("If-None-Match: \"v1\"\r\n"));
+
+        // and fresh again
+        assert_eq!(body(client.get("http://example.domain/a").send().unwrap()), "hello");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        // unless the request asks for the origin
+        let mut headers = Headers::new();
+        headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        let res = client.get("http://example.domain/a").headers(headers).send().unwrap();
+        assert_eq!(body(res), "hello");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // a successful PUT makes the stored response stale
+        client.put("http://example.domain/a").body("new").send().unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 4);
+        client.get("http://example.domain/a").send().unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 5);
+        assert!(!requests.lock().unwrap()[4].contains("If-None-Match"));
+    }
+
+    #[test]
+    fn test_dir_store() {
+        let dir = env::temp_dir().join("hyper-test-cache");
+        let _ = fs::remove_dir_all(&dir);
+        let store = DirStore::open(&dir).unwrap();
+        let url = Url::parse("http://example.domain/a?b=c").unwrap().serialize();
+        assert!(store.get(&url).is_none());
+
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Length", vec![b"5".to_vec()]);
+        headers.set_raw("ETag", vec![b"\"v1\"".to_vec()]);
+        store.put(&url, Entry {
+            status: RawStatus(200, "OK".into()),
+            version: HttpVersion::Http11,
+            headers: headers,
+            body: b"hello".to_vec(),
+            stored: Timespec::new(1000, 0),
+        });
+
+        let entry = DirStore::open(&dir).unwrap().get(&url).unwrap();
+        assert_eq!(entry.status, RawStatus(200, "OK".into()));
+        assert_eq!(entry.headers.get_raw("ETag"), Some(&[b"\"v1\"".to_vec()][..]));
+        assert_eq!(entry.body, b"hello");
+        assert_eq!(entry.stored, Timespec::new(1000, 0));
+        assert!(store.get("http://example.domain/other").is_none());
+
+        store.remove(&url);
+        assert!(store.get(&url).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+//! Cancelling requests from another thread.
+//!
+//! A `CancelHandle` is made before a request is sent, and given to the
+//! `RequestBuilder`. Calling `cancel` on it from any thread shuts down the
+//! connection of the request, so a blocked write or read returns at once,
+//! and `send` fails with `Error::Cancelled`.
+//!
+//! ```no_run
+//! # use std::thread;
+//! # use hyper::Client;
+//! use hyper::client::cancel::CancelHandle;
+//!
+//! let handle = CancelHandle::new();
+//! let canceller = handle.clone();
+//! thread::spawn(move || {
+//!     thread::sleep_ms(1000);
+//!     canceller.cancel();
+//! });
+//!
+//! let mut client = Client::new();
+//! let res = client.get("http://example.domain/slow").cancel_handle(&handle).send();
+//! ```
+//!
+//! Connecting can't be interrupted, but a request cancelled while it is
+//! connecting fails as soon as the connection is made.
+//!
+//! The handle lets go of the connection once the request and its response
+//! are dropped, so cancelling later doesn't touch a connection that has
+//! gone back to the pool. A connection that was cancelled is never reused.
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use header::KeepAlive;
+use net::{NetworkConnector, NetworkStream, ContextVerifier};
+use Error;
+
+/// A handle to cancel a request with, from any thread.
+#[derive(Clone)]
+pub struct CancelHandle(Arc<Inner>);
+
+struct Inner {
+    cancelled: AtomicBool,
+    // The socket being watched, with the id it was watched under.
+    socket: Mutex<Option<(usize, TcpStream)>>,
+    next_id: AtomicUsize,
+}
+
+impl CancelHandle {
+    /// Create a handle that hasn't been cancelled.
+    pub fn new() -> CancelHandle {
+        CancelHandle(Arc::new(Inner {
+            cancelled: AtomicBool::new(false),
+            socket: Mutex::new(None),
+            next_id: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Cancel the request, shutting down its connection if it has one.
+    ///
+    /// A cancelled handle stays cancelled, and any request sent with it
+    /// fails.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        if let Some((_, socket)) = self.0.socket.lock().unwrap().take() {
+            debug!("cancelling request");
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Whether `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    // The socket is only kept if the handle isn't cancelled yet, with both
+    // checked under the lock, so a cancel can't slip in between. The id it
+    // returns is given to `unwatch`.
+    fn watch(&self, socket: Option<TcpStream>) -> ::Result<usize> {
+        let mut current = self.0.socket.lock().unwrap();
+        if self.is_cancelled() {
+            if let Some(socket) = socket {
+                let _ = socket.shutdown(Shutdown::Both);
+            }
+            return Err(Error::Cancelled);
+        }
+        let id = self.0.next_id.fetch_add(1, Ordering::SeqCst);
+        *current = socket.map(|socket| (id, socket));
+        Ok(id)
+    }
+
+    // Stops watching the socket watched under `id`, unless the handle has
+    // moved on to another request's socket since.
+    fn unwatch(&self, id: usize) {
+        let mut current = self.0.socket.lock().unwrap();
+        if current.as_ref().map_or(false, |&(watched, _)| watched == id) {
+            *current = None;
+        }
+    }
+}
+
+/// A `NetworkConnector` that lets a `CancelHandle` shut down the
+/// connections it makes.
+///
+/// The `RequestBuilder` uses one when it is given a `CancelHandle`. It is
+/// public so a `Request` made directly can be cancelled too. Only
+/// connections whose stream has a `shutdown_handle` can be interrupted.
+pub struct CancelableConnector<'a, C: 'a> {
+    connector: &'a C,
+    handle: &'a CancelHandle,
+}
+
+impl<'a, C: NetworkConnector> CancelableConnector<'a, C> {
+    /// Wrap `connector`, to make connections that `handle` can cancel.
+    pub fn new(connector: &'a C, handle: &'a CancelHandle) -> CancelableConnector<'a, C> {
+        CancelableConnector {
+            connector: connector,
+            handle: handle,
+        }
+    }
+}
+
+impl<'a, C: NetworkConnector> NetworkConnector for CancelableConnector<'a, C> {
+    type Stream = Box<NetworkStream + Send>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str)
+        -> ::Result<Box<NetworkStream + Send>> {
+        if self.handle.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let stream: Box<NetworkStream + Send> =
+            try!(self.connector.connect(host, port, scheme)).into();
+        let id = try!(self.handle.watch(stream.shutdown_handle()));
+        Ok(Box::new(CancelableStream {
+            inner: stream,
+            handle: self.handle.clone(),
+            id: id,
+            live: Arc::new(AtomicUsize::new(1)),
+        }))
+    }
+
+    /// Does nothing, as the wrapped connector is only borrowed. Set the
+    /// verifier on it instead.
+    fn set_ssl_verifier(&mut self, _verifier: ContextVerifier) {}
+}
+
+// A connection made by a CancelableConnector. Once it and all its clones
+// are dropped, the handle stops watching its socket. If it was cancelled,
+// it is closed first, so a pool doesn't take the shut down connection back.
+struct CancelableStream {
+    inner: Box<NetworkStream + Send>,
+    handle: CancelHandle,
+    id: usize,
+    live: Arc<AtomicUsize>,
+}
+
+impl Read for CancelableStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for CancelableStream {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        self.inner.write(msg)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl NetworkStream for CancelableStream {
+    #[inline]
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    #[inline]
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.inner.close(how)
+    }
+
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.inner.peer_certificate()
+    }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.inner.alpn_protocol()
+    }
+
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        self.inner.shutdown_handle()
+    }
+
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        let inner = try!(self.inner.try_clone());
+        self.live.fetch_add(1, Ordering::SeqCst);
+        Ok(Box::new(CancelableStream {
+            inner: inner,
+            handle: self.handle.clone(),
+            id: self.id,
+            live: self.live.clone(),
+        }))
+    }
+
+    #[inline]
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.inner.set_keep_alive(keep_alive)
+    }
+}
+
+impl Drop for CancelableStream {
+    fn drop(&mut self) {
+        if self.handle.is_cancelled() {
+            let _ = self.inner.close(Shutdown::Both);
+        }
+        if self.live.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.handle.unwatch(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use client::Client;
+    use method::Method::Get;
+    use server::testing::{TestServer, Expected, Reply};
+    use status::StatusCode;
+    use Error;
+    use super::CancelHandle;
+
+    #[test]
+    fn test_cancel_blocked_read() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/slow"),
+                      Reply::new(StatusCode::Ok).delay_ms(500));
+
+        let handle = CancelHandle::new();
+        let canceller = handle.clone();
+        thread::spawn(move || {
+            thread::sleep_ms(50);
+            canceller.cancel();
+        });
+
+        let mut client = Client::new();
+        match client.get(&server.url("/slow")).cancel_handle(&handle).send() {
+            Err(Error::Cancelled) => (),
+            other => panic!("expected Cancelled, got {:?}", other)
+        }
+        assert!(handle.is_cancelled());
+    }
+
+    #[test]
+    fn test_lets_go_of_finished_request() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/"), Reply::new(StatusCode::Ok));
+
+        let handle = CancelHandle::new();
+        let mut client = Client::new();
+        let res = client.get(&server.url("/")).cancel_handle(&handle).send().unwrap();
+        assert!(handle.0.socket.lock().unwrap().is_some());
+        drop(res);
+        assert!(handle.0.socket.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_closes_cancelled_stream() {
+        use std::io::{self, Read, Write};
+        use std::net::{Shutdown, SocketAddr};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use net::{NetworkConnector, NetworkStream};
+        use super::CancelableConnector;
+
+        struct Closes(Arc<AtomicBool>);
+        impl Read for Closes {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { Ok(0) }
+        }
+        impl Write for Closes {
+            fn write(&mut self, msg: &[u8]) -> io::Result<usize> { Ok(msg.len()) }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        impl NetworkStream for Closes {
+            fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+                Ok("127.0.0.1:1337".parse().unwrap())
+            }
+            fn close(&mut self, _: Shutdown) -> io::Result<()> {
+                self.0.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        struct Connector(Arc<AtomicBool>);
+        impl NetworkConnector for Connector {
+            type Stream = Closes;
+            fn connect(&self, _: &str, _: u16, _: &str) -> ::Result<Closes> {
+                Ok(Closes(self.0.clone()))
+            }
+            fn set_ssl_verifier(&mut self, _: ::net::ContextVerifier) {}
+        }
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let connector = Connector(closed.clone());
+        let handle = CancelHandle::new();
+        let cancelable = CancelableConnector::new(&connector, &handle);
+
+        drop(cancelable.connect("127.0.0.1", 80, "http").unwrap());
+        assert!(!closed.load(Ordering::SeqCst));
+
+        let stream = cancelable.connect("127.0.0.1", 80, "http").unwrap();
+        handle.cancel();
+        drop(stream);
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_cancel_before_send() {
+        let handle = CancelHandle::new();
+        handle.cancel();
+        let mut client = Client::new();
+        match client.get("http://127.0.0.1:1/").cancel_handle(&handle).send() {
+            Err(Error::Cancelled) => (),
+            other => panic!("expected Cancelled, got {:?}", other)
+        }
+    }
+}
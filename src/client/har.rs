@@ -0,0 +1,525 @@
+//! Recording client traffic as an HTTP Archive.
+//!
+//! A `HarRecorder` collects every exchange made through a `HarConnector`,
+//! with its headers, timings and the start of each body, and writes them
+//! out in the [HAR 1.2](http://www.softwareishard.com/blog/har-12-spec/)
+//! format. The file can be opened in the network panel of a browser's
+//! developer tools.
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use hyper::Client;
+//! use hyper::client::har::HarRecorder;
+//!
+//! let recorder = HarRecorder::new(64 * 1024);
+//! let mut client = Client::new();
+//! client.set_har_recorder(Some(recorder.clone()));
+//! client.get("http://example.domain").send().unwrap();
+//!
+//! recorder.write_to(&mut File::create("traffic.har").unwrap()).unwrap();
+//! ```
+//!
+//! Exchanges are read back from the bytes that crossed the connection, so
+//! one whose request or response head never arrived is left out. The values
+//! of the headers a recorder's `Redaction` names are written as
+//! `[redacted]`.
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use serialize::base64::{ToBase64, STANDARD};
+use serialize::json::{self, Json};
+use time::{self, precise_time_ns, Tm};
+
+use buffer::BufReader;
+use header::{ContentType, Headers, KeepAlive, Redaction, TransferEncoding, REDACTED};
+use header::Encoding::Chunked;
+use http::{self, HttpReader};
+use net::{NetworkConnector, NetworkStream, ContextVerifier};
+use uri::RequestUri;
+use Url;
+
+/// A log of the exchanges made through its `HarConnector`s.
+///
+/// Clones share the same log, so one can be given to a `Client` while
+/// another is kept to write the log out.
+#[derive(Clone)]
+pub struct HarRecorder {
+    entries: Arc<Mutex<Vec<Entry>>>,
+    max_body: usize,
+    redaction: Arc<Redaction>,
+}
+
+struct Entry {
+    started: Tm,
+    scheme: String,
+    host: String,
+    port: u16,
+    connect_ns: u64,
+    capture: Arc<Mutex<Capture>>,
+}
+
+// What crossed a connection, with the head and the first `max_body` bytes
+// of the body kept in each direction.
+#[derive(Default)]
+struct Capture {
+    written: Vec<u8>,
+    written_head: Option<usize>,
+    read: Vec<u8>,
+    read_head: Option<usize>,
+    first_write: u64,
+    last_write: u64,
+    first_read: u64,
+    last_read: u64,
+}
+
+impl HarRecorder {
+    /// Create an empty recorder, keeping up to `max_body` bytes of each
+    /// body, as they were sent, so counting any chunked framing.
+    ///
+    /// The headers the default `Redaction` names are redacted.
+    pub fn new(max_body: usize) -> HarRecorder {
+        HarRecorder::with_redaction(max_body, Redaction::new())
+    }
+
+    /// Create an empty recorder, as `new` does, that redacts the headers
+    /// `redaction` names instead.
+    pub fn with_redaction(max_body: usize, redaction: Redaction) -> HarRecorder {
+        HarRecorder {
+            entries: Arc::new(Mutex::new(Vec::new())),
+            max_body: max_body,
+            redaction: Arc::new(redaction),
+        }
+    }
+
+    /// The number of connections recorded so far.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Forget everything recorded so far.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Write everything recorded so far to `sink`, as a HAR document.
+    pub fn write_to<W: Write>(&self, sink: &mut W) -> io::Result<()> {
+        let entries = self.entries.lock().unwrap().iter()
+            .filter_map(|entry| entry.to_json(&self.redaction))
+            .collect();
+        let mut creator = BTreeMap::new();
+        creator.insert("name".to_string(), Json::String("hyper".to_string()));
+        creator.insert("version".to_string(),
+                       Json::String(env!("CARGO_PKG_VERSION").to_string()));
+        let mut log = BTreeMap::new();
+        log.insert("version".to_string(), Json::String("1.2".to_string()));
+        log.insert("creator".to_string(), Json::Object(creator));
+        log.insert("entries".to_string(), Json::Array(entries));
+        let mut har = BTreeMap::new();
+        har.insert("log".to_string(), Json::Object(log));
+        write!(sink, "{}", json::as_pretty_json(&Json::Object(har)))
+    }
+
+    fn add(&self, entry: Entry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+}
+
+/// A `NetworkConnector` that records each connection it makes in a
+/// `HarRecorder`.
+///
+/// The `Client` uses one when it has a recorder. As with the
+/// `CancelableConnector`, it is public so a `Request` made directly can be
+/// recorded too. Each connection is one entry, so it should carry a single
+/// exchange, as a connection checked out of a `Pool` for a request does.
+pub struct HarConnector<'a, C: 'a> {
+    connector: &'a C,
+    recorder: &'a HarRecorder,
+}
+
+impl<'a, C: NetworkConnector> HarConnector<'a, C> {
+    /// Wrap `connector`, recording its connections in `recorder`.
+    pub fn new(connector: &'a C, recorder: &'a HarRecorder) -> HarConnector<'a, C> {
+        HarConnector {
+            connector: connector,
+            recorder: recorder,
+        }
+    }
+}
+
+impl<'a, C: NetworkConnector> NetworkConnector for HarConnector<'a, C>
+where C::Stream: Into<Box<NetworkStream + Send>> {
+    type Stream = Box<NetworkStream + Send>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str)
+        -> ::Result<Box<NetworkStream + Send>> {
+        let started = time::now_utc();
+        let start = precise_time_ns();
+        let stream = try!(self.connector.connect(host, port, scheme)).into();
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        self.recorder.add(Entry {
+            started: started,
+            scheme: scheme.to_string(),
+            host: host.to_string(),
+            port: port,
+            connect_ns: precise_time_ns() - start,
+            capture: capture.clone(),
+        });
+        Ok(Box::new(HarStream {
+            inner: stream,
+            capture: capture,
+            max_body: self.recorder.max_body,
+        }))
+    }
+
+    /// Does nothing, as the wrapped connector is only borrowed. Set the
+    /// verifier on it instead.
+    fn set_ssl_verifier(&mut self, _verifier: ContextVerifier) {}
+}
+
+struct HarStream {
+    inner: Box<NetworkStream + Send>,
+    capture: Arc<Mutex<Capture>>,
+    max_body: usize,
+}
+
+// Appends to a capture until the head has ended and `max_body` bytes of
+// body follow it.
+fn keep(buf: &mut Vec<u8>, head: &mut Option<usize>, bytes: &[u8], max_body: usize) {
+    if head.is_none() {
+        buf.extend(bytes.iter().cloned());
+        *head = http::find_head_end(buf);
+        if let Some(len) = *head {
+            buf.truncate(len + max_body);
+        }
+    } else {
+        let room = (head.unwrap() + max_body).saturating_sub(buf.len());
+        let n = ::std::cmp::min(room, bytes.len());
+        buf.extend(bytes[..n].iter().cloned());
+    }
+}
+
+impl Read for HarStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        let now = precise_time_ns();
+        let mut capture = self.capture.lock().unwrap();
+        if capture.first_read == 0 {
+            capture.first_read = now;
+        }
+        capture.last_read = now;
+        let Capture { ref mut read, ref mut read_head, .. } = *capture;
+        keep(read, read_head, &buf[..n], self.max_body);
+        Ok(n)
+    }
+}
+
+impl Write for HarStream {
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        let now = precise_time_ns();
+        let n = try!(self.inner.write(msg));
+        let mut capture = self.capture.lock().unwrap();
+        if capture.first_write == 0 {
+            capture.first_write = now;
+        }
+        capture.last_write = precise_time_ns();
+        let Capture { ref mut written, ref mut written_head, .. } = *capture;
+        keep(written, written_head, &msg[..n], self.max_body);
+        Ok(n)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl NetworkStream for HarStream {
+    #[inline]
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    #[inline]
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.inner.close(how)
+    }
+
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.inner.peer_certificate()
+    }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.inner.alpn_protocol()
+    }
+
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        self.inner.shutdown_handle()
+    }
+
+    /// The clone records into the same entry, such as the response read
+    /// by a `PendingResponse`.
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        Ok(Box::new(HarStream {
+            inner: try!(self.inner.try_clone()),
+            capture: self.capture.clone(),
+            max_body: self.max_body,
+        }))
+    }
+
+    #[inline]
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.inner.set_keep_alive(keep_alive)
+    }
+}
+
+fn millis(from: u64, to: u64) -> Json {
+    Json::F64(to.saturating_sub(from) as f64 / 1_000_000.0)
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn string<S: Into<String>>(s: S) -> Json {
+    Json::String(s.into())
+}
+
+fn headers_json(headers: &Headers, redaction: &Redaction) -> Json {
+    Json::Array(headers.iter().map(|header| {
+        let value = if redaction.is_redacted(header.name()) {
+            REDACTED.to_string()
+        } else {
+            header.value_string()
+        };
+        object(vec![("name", string(header.name())), ("value", string(value))])
+    }).collect())
+}
+
+// Decodes what was kept of a body, stopping where the capture was cut off.
+fn decode_body(mut rest: BufReader<&[u8]>, headers: &Headers) -> Vec<u8> {
+    let chunked = match headers.get::<TransferEncoding>() {
+        Some(&TransferEncoding(ref codings)) => codings.last() == Some(&Chunked),
+        None => false
+    };
+    let mut body = Vec::new();
+    if chunked {
+        let mut reader = HttpReader::ChunkedReader(rest, None);
+        let mut buf = [0; 4096];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            body.extend(buf[..n].iter().cloned());
+        }
+    } else {
+        let _ = rest.read_to_end(&mut body);
+    }
+    body
+}
+
+fn content_json(body: &[u8], headers: &Headers) -> Json {
+    let mime = headers.get::<ContentType>().map(|ct| ct.to_string()).unwrap_or(String::new());
+    let mut fields = vec![("size", Json::U64(body.len() as u64)), ("mimeType", string(mime))];
+    match ::std::str::from_utf8(body) {
+        Ok(text) => fields.push(("text", string(text))),
+        Err(_) => {
+            fields.push(("text", string(body.to_base64(STANDARD))));
+            fields.push(("encoding", string("base64")));
+        }
+    }
+    object(fields)
+}
+
+impl Entry {
+    fn to_json(&self, redaction: &Redaction) -> Option<Json> {
+        let capture = self.capture.lock().unwrap();
+        if capture.written_head.is_none() || capture.read_head.is_none() {
+            return None;
+        }
+
+        let mut written = BufReader::new(&capture.written[..]);
+        let req = match http::parse_request(&mut written) {
+            Ok(req) => req,
+            Err(_) => return None
+        };
+        let mut read = BufReader::new(&capture.read[..]);
+        let res = match http::parse_response(&mut read) {
+            Ok(res) => res,
+            Err(_) => return None
+        };
+        let req_body = decode_body(written, &req.headers);
+        let res_body = decode_body(read, &res.headers);
+
+        let (method, uri) = req.subject;
+        let url = match uri {
+            RequestUri::AbsolutePath(path) => {
+                let default = match &self.scheme[..] {
+                    "http" => 80,
+                    "https" => 443,
+                    _ => 0
+                };
+                if self.port == default {
+                    format!("{}://{}{}", self.scheme, self.host, path)
+                } else {
+                    format!("{}://{}:{}{}", self.scheme, self.host, self.port, path)
+                }
+            },
+            RequestUri::AbsoluteUri(url) => url.serialize(),
+            RequestUri::Authority(authority) => authority,
+            RequestUri::Star => "*".to_string(),
+        };
+        let query = match Url::parse(&url) {
+            Ok(url) => url.query_pairs().unwrap_or(vec![]),
+            Err(_) => vec![]
+        };
+
+        let mut request = vec![
+            ("method", string(method.to_string())),
+            ("url", string(url)),
+            ("httpVersion", string(req.version.to_string())),
+            ("cookies", Json::Array(vec![])),
+            ("headers", headers_json(&req.headers, redaction)),
+            ("queryString", Json::Array(query.into_iter().map(|(name, value)| {
+                object(vec![("name", string(name)), ("value", string(value))])
+            }).collect())),
+            ("headersSize", Json::I64(-1)),
+            ("bodySize", Json::U64(req_body.len() as u64)),
+        ];
+        if !req_body.is_empty() {
+            request.push(("postData", content_json(&req_body, &req.headers)));
+        }
+        let response = vec![
+            ("status", Json::U64(res.subject.0 as u64)),
+            ("statusText", string(res.subject.1.into_owned())),
+            ("httpVersion", string(res.version.to_string())),
+            ("cookies", Json::Array(vec![])),
+            ("headers", headers_json(&res.headers, redaction)),
+            ("content", content_json(&res_body, &res.headers)),
+            ("redirectURL", string(res.headers.get_raw("Location")
+                .and_then(|values| values.get(0))
+                .map(|value| String::from_utf8_lossy(value).into_owned())
+                .unwrap_or(String::new()))),
+            ("headersSize", Json::I64(-1)),
+            ("bodySize", Json::U64(res_body.len() as u64)),
+        ];
+
+        let timings = object(vec![
+            ("blocked", Json::I64(-1)),
+            ("dns", Json::I64(-1)),
+            ("connect", millis(0, self.connect_ns)),
+            ("send", millis(capture.first_write, capture.last_write)),
+            ("wait", millis(capture.last_write, capture.first_read)),
+            ("receive", millis(capture.first_read, capture.last_read)),
+        ]);
+        let total = self.connect_ns + capture.last_read.saturating_sub(capture.first_write);
+        Some(object(vec![
+            ("startedDateTime", string(self.started.rfc3339().to_string())),
+            ("time", millis(0, total)),
+            ("request", object(request)),
+            ("response", object(response)),
+            ("cache", object(vec![])),
+            ("timings", timings),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use serialize::json::Json;
+
+    use client::Request;
+    use header::Redaction;
+    use method::Method::{Get, Post};
+    use url::Url;
+    use super::{HarConnector, HarRecorder};
+
+    mock_connector!(MockChunked {
+        "http://127.0.0.1" =>       "HTTP/1.1 200 OK\r\n\
+                                     Transfer-Encoding: chunked\r\n\
+                                     \r\n\
+                                     5\r\n\
+                                     hello\r\n\
+                                     6\r\n\
+                                     \x20world\r\n\
+                                     0\r\n\
+                                     \r\n"
+    });
+
+    #[test]
+    fn test_har_entry() {
+        let recorder = HarRecorder::new(1024);
+        {
+            let connector = HarConnector::new(&MockChunked, &recorder);
+            let url = Url::parse("http://127.0.0.1:3000/echo?q=a%20b&page=2").unwrap();
+            let mut req = Request::with_connector(Post, url, &connector).unwrap().start().unwrap();
+            req.write_all(b"ping").unwrap();
+            let mut res = req.send().unwrap();
+            let mut body = String::new();
+            res.read_to_string(&mut body).unwrap();
+            assert_eq!(body, "hello world");
+        }
+        assert_eq!(recorder.len(), 1);
+
+        let mut har = Vec::new();
+        recorder.write_to(&mut har).unwrap();
+        let har = Json::from_str(::std::str::from_utf8(&har).unwrap()).unwrap();
+        let entry = &har.find_path(&["log", "entries"]).unwrap().as_array().unwrap()[0];
+        let find = |path: &[&str]| entry.find_path(path).unwrap().clone();
+        assert_eq!(find(&["request", "method"]), Json::String("POST".to_string()));
+        assert_eq!(find(&["request", "url"]),
+                   Json::String("http://127.0.0.1:3000/echo?q=a%20b&page=2".to_string()));
+        let pair = |name: &str, value: &str| {
+            Json::from_str(&format!("{{\"name\":\"{}\",\"value\":\"{}\"}}", name, value))
+                .unwrap()
+        };
+        assert_eq!(find(&["request", "queryString"]),
+                   Json::Array(vec![pair("q", "a b"), pair("page", "2")]));
+        assert_eq!(find(&["request", "postData", "text"]), Json::String("ping".to_string()));
+        assert_eq!(find(&["response", "status"]), Json::U64(200));
+        assert_eq!(find(&["response", "content", "text"]),
+                   Json::String("hello world".to_string()));
+    }
+
+    #[test]
+    fn test_har_redaction() {
+        fn headers(recorder: HarRecorder) -> Json {
+            {
+                let connector = HarConnector::new(&MockChunked, &recorder);
+                let url = Url::parse("http://127.0.0.1:3000/").unwrap();
+                let mut req = Request::with_connector(Get, url, &connector).unwrap();
+                req.headers_mut().set_raw("Cookie", vec![b"session=secret".to_vec()]);
+                req.headers_mut().set_raw("X-Token", vec![b"t0ken".to_vec()]);
+                req.start().unwrap().send().unwrap().read_to_end(&mut vec![]).unwrap();
+            }
+            let mut har = Vec::new();
+            recorder.write_to(&mut har).unwrap();
+            let har = Json::from_str(::std::str::from_utf8(&har).unwrap()).unwrap();
+            let entry = &har.find_path(&["log", "entries"]).unwrap().as_array().unwrap()[0];
+            entry.find_path(&["request", "headers"]).unwrap().clone()
+        }
+        let value = |headers: &Json, name: &str| {
+            headers.as_array().unwrap().iter()
+                .find(|header| header.find("name").unwrap().as_string() == Some(name))
+                .map(|header| header.find("value").unwrap().as_string().unwrap().to_string())
+                .unwrap()
+        };
+
+        let defaults = headers(HarRecorder::new(1024));
+        assert_eq!(value(&defaults, "Cookie"), "[redacted]");
+        assert_eq!(value(&defaults, "X-Token"), "t0ken");
+
+        let mut redaction = Redaction::new();
+        redaction.redact("X-Token");
+        redaction.unredact("Cookie");
+        let custom = headers(HarRecorder::with_redaction(1024, redaction));
+        assert_eq!(value(&custom, "Cookie"), "session=secret");
+        assert_eq!(value(&custom, "X-Token"), "[redacted]");
+    }
+}
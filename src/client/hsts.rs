@@ -0,0 +1,367 @@
+//! HTTP Strict Transport Security, as defined in
+//! [RFC6797](https://tools.ietf.org/html/rfc6797).
+//!
+//! A host that answers over HTTPS with a `Strict-Transport-Security` header
+//! asks to only ever be reached over HTTPS. A `Client` given an `Hsts` with
+//! `set_hsts` remembers those hosts, and sends later `http://` requests to
+//! them, and to their subdomains if asked to, over `https://` instead.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use hyper::client::hsts::{Hsts, FileStore};
+//!
+//! let mut client = Client::new();
+//! client.set_hsts(Some(Hsts::with_store(FileStore::open("hsts.txt").unwrap())));
+//! ```
+//!
+//! Policies are kept in an `HstsStore`. The default `MemoryStore` forgets
+//! them when it is dropped, while a `FileStore` keeps them in a file, so
+//! they outlast the process.
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use time::{Duration, Timespec};
+
+use clock::{Clock, SystemClock};
+use header::Headers;
+use Url;
+
+/// What a host asked for in its `Strict-Transport-Security` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HstsPolicy {
+    /// When the policy runs out.
+    pub expires: Timespec,
+    /// Whether the policy covers the subdomains of the host too.
+    pub include_subdomains: bool,
+}
+
+/// Where an `Hsts` keeps its policies, by host.
+pub trait HstsStore: Send + Sync {
+    /// The policy stored for `host`, expired or not.
+    fn get(&self, host: &str) -> Option<HstsPolicy>;
+    /// Store the policy for `host`, replacing any it had.
+    fn put(&self, host: &str, policy: HstsPolicy);
+    /// Forget the policy for `host`.
+    fn remove(&self, host: &str);
+}
+
+/// An `HstsStore` that keeps its policies in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore(Mutex<HashMap<String, HstsPolicy>>);
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore(Mutex::new(HashMap::new()))
+    }
+}
+
+impl HstsStore for MemoryStore {
+    fn get(&self, host: &str) -> Option<HstsPolicy> {
+        self.0.lock().unwrap().get(host).cloned()
+    }
+
+    fn put(&self, host: &str, policy: HstsPolicy) {
+        self.0.lock().unwrap().insert(host.to_string(), policy);
+    }
+
+    fn remove(&self, host: &str) {
+        self.0.lock().unwrap().remove(host);
+    }
+}
+
+/// An `HstsStore` that keeps its policies in a file.
+///
+/// The file is read when the store is opened, and written again whenever a
+/// policy changes. Each line holds a host, the second its policy expires,
+/// and whether it includes subdomains.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    policies: Mutex<HashMap<String, HstsPolicy>>,
+}
+
+impl FileStore {
+    /// Open the store in the file at `path`, which is created when a policy
+    /// is first stored if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileStore> {
+        let path = path.as_ref().to_path_buf();
+        let mut policies = HashMap::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                let mut text = String::new();
+                try!(file.read_to_string(&mut text));
+                for line in text.lines() {
+                    let fields = line.split(' ').collect::<Vec<_>>();
+                    if fields.len() == 3 {
+                        if let Ok(expires) = fields[1].parse() {
+                            policies.insert(fields[0].to_string(), HstsPolicy {
+                                expires: Timespec::new(expires, 0),
+                                include_subdomains: fields[2] == "1",
+                            });
+                            continue;
+                        }
+                    }
+                    debug!("skipping malformed HSTS line {:?}", line);
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e)
+        }
+        Ok(FileStore {
+            path: path,
+            policies: Mutex::new(policies),
+        })
+    }
+
+    fn save(&self, policies: &HashMap<String, HstsPolicy>) {
+        let mut text = String::new();
+        for (host, policy) in policies {
+            text.push_str(&format!("{} {} {}\n", host, policy.expires.sec,
+                                   if policy.include_subdomains { 1 } else { 0 }));
+        }
+        let res = File::create(&self.path).and_then(|mut file| file.write_all(text.as_bytes()));
+        if let Err(e) = res {
+            debug!("error saving HSTS policies to {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+impl HstsStore for FileStore {
+    fn get(&self, host: &str) -> Option<HstsPolicy> {
+        self.policies.lock().unwrap().get(host).cloned()
+    }
+
+    fn put(&self, host: &str, policy: HstsPolicy) {
+        let mut policies = self.policies.lock().unwrap();
+        if policies.insert(host.to_string(), policy) != Some(policy) {
+            self.save(&policies);
+        }
+    }
+
+    fn remove(&self, host: &str) {
+        let mut policies = self.policies.lock().unwrap();
+        if policies.remove(host).is_some() {
+            self.save(&policies);
+        }
+    }
+}
+
+/// The hosts that are only to be reached over HTTPS.
+pub struct Hsts {
+    store: Box<HstsStore>,
+    clock: Arc<Clock>,
+}
+
+impl Hsts {
+    /// Create an `Hsts` that keeps its policies in memory.
+    pub fn new() -> Hsts {
+        Hsts::with_store(MemoryStore::new())
+    }
+
+    /// Create an `Hsts` that keeps its policies in `store`.
+    pub fn with_store<S: HstsStore + 'static>(store: S) -> Hsts {
+        Hsts {
+            store: Box::new(store),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Set the `Clock` used to decide when policies have expired.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Whether `host` is only to be reached over HTTPS, by its own policy
+    /// or one of a parent domain that includes subdomains.
+    pub fn is_secure(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        let now = self.clock.now();
+        let mut domain = &host[..];
+        let mut exact = true;
+        loop {
+            if let Some(policy) = self.store.get(domain) {
+                if policy.expires <= now {
+                    self.store.remove(domain);
+                } else if exact || policy.include_subdomains {
+                    return true;
+                }
+            }
+            match domain.find('.') {
+                Some(dot) => domain = &domain[dot + 1..],
+                None => return false
+            }
+            exact = false;
+        }
+    }
+
+    /// Rewrite `url` to `https` if its host is only to be reached over
+    /// HTTPS, returning whether it was.
+    ///
+    /// An explicit port 80 becomes 443, while any other port is kept.
+    pub fn upgrade(&self, url: &mut Url) -> bool {
+        if url.scheme != "http" {
+            return false;
+        }
+        let secure = match url.domain() {
+            Some(host) => self.is_secure(host),
+            None => false
+        };
+        if !secure {
+            return false;
+        }
+        url.scheme = "https".to_string();
+        if let Some(data) = url.relative_scheme_data_mut() {
+            if data.port == Some(80) {
+                data.port = None;
+            }
+            data.default_port = Some(443);
+        }
+        true
+    }
+
+    /// Remember the `Strict-Transport-Security` header of a response to a
+    /// request for `url`.
+    ///
+    /// The header is ignored unless the response came over HTTPS from a
+    /// host named by a domain, rather than an IP address. A `max-age` of 0
+    /// forgets the host.
+    pub fn observe(&self, url: &Url, headers: &Headers) {
+        if url.scheme != "https" {
+            return;
+        }
+        let host = match url.domain() {
+            Some(host) if host.parse::<Ipv4Addr>().is_err() => host.to_ascii_lowercase(),
+            _ => return
+        };
+        let raw = match headers.get_raw("Strict-Transport-Security") {
+            Some(raw) if !raw.is_empty() => &raw[0],
+            _ => return
+        };
+        let (max_age, include_subdomains) = match ::std::str::from_utf8(raw).ok()
+                .and_then(parse_sts) {
+            Some(sts) => sts,
+            None => {
+                debug!("invalid Strict-Transport-Security header from {}", host);
+                return;
+            }
+        };
+        if max_age == 0 {
+            trace!("forgetting HSTS policy of {}", host);
+            self.store.remove(&host);
+        } else {
+            trace!("remembering HSTS policy of {} for {}s", host, max_age);
+            self.store.put(&host, HstsPolicy {
+                expires: self.clock.now() + Duration::seconds(max_age as i64),
+                include_subdomains: include_subdomains,
+            });
+        }
+    }
+}
+
+// Returns the max-age and includeSubDomains directives, or None without a
+// valid max-age.
+fn parse_sts(value: &str) -> Option<(u64, bool)> {
+    let mut max_age = None;
+    let mut include_subdomains = false;
+    for directive in value.split(';') {
+        let mut parts = directive.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = parts.next().map(|v| v.trim().trim_matches('"'));
+        match (&name[..], value) {
+            ("max-age", Some(secs)) => match secs.parse() {
+                Ok(secs) => max_age = Some(secs),
+                Err(_) => return None
+            },
+            ("includesubdomains", None) => include_subdomains = true,
+            _ => ()
+        }
+    }
+    max_age.map(|secs| (secs, include_subdomains))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use time::{Duration, Timespec};
+
+    use clock::TestClock;
+    use header::Headers;
+    use url::Url;
+    use super::{Hsts, HstsStore, FileStore, parse_sts};
+
+    fn sts(value: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Strict-Transport-Security", vec![value.as_bytes().to_vec()]);
+        headers
+    }
+
+    #[test]
+    fn test_parse_sts() {
+        assert_eq!(parse_sts("max-age=31536000"), Some((31536000, false)));
+        assert_eq!(parse_sts("max-age=\"60\"; includeSubDomains"), Some((60, true)));
+        assert_eq!(parse_sts("includeSubDomains"), None);
+        assert_eq!(parse_sts("max-age=soon"), None);
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let hsts = Hsts::new();
+        hsts.observe(&Url::parse("http://plain.domain/").unwrap(), &sts("max-age=60"));
+        hsts.observe(&Url::parse("https://example.domain/").unwrap(),
+                     &sts("max-age=60; includeSubDomains"));
+        hsts.observe(&Url::parse("https://127.0.0.1/").unwrap(), &sts("max-age=60"));
+
+        let mut url = Url::parse("http://api.example.domain:80/users").unwrap();
+        assert!(hsts.upgrade(&mut url));
+        assert_eq!(url.serialize(), "https://api.example.domain/users");
+        let mut url = Url::parse("http://example.domain:8080/").unwrap();
+        assert!(hsts.upgrade(&mut url));
+        assert_eq!(url.serialize(), "https://example.domain:8080/");
+
+        assert!(!hsts.upgrade(&mut Url::parse("http://plain.domain/").unwrap()));
+        assert!(!hsts.upgrade(&mut Url::parse("http://127.0.0.1/").unwrap()));
+        assert!(!hsts.upgrade(&mut Url::parse("http://notexample.domain/").unwrap()));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let clock = ::std::sync::Arc::new(TestClock::new(Timespec::new(0, 0)));
+        let mut hsts = Hsts::new();
+        hsts.set_clock(clock.clone());
+        let url = Url::parse("https://example.domain/").unwrap();
+
+        hsts.observe(&url, &sts("max-age=60"));
+        assert!(hsts.is_secure("Example.Domain"));
+        assert!(!hsts.is_secure("www.example.domain"));
+        clock.advance(Duration::seconds(60));
+        assert!(!hsts.is_secure("example.domain"));
+
+        hsts.observe(&url, &sts("max-age=60"));
+        hsts.observe(&url, &sts("max-age=0"));
+        assert!(!hsts.is_secure("example.domain"));
+    }
+
+    #[test]
+    fn test_file_store() {
+        let path = env::temp_dir().join("hyper-test-hsts.txt");
+        let _ = fs::remove_file(&path);
+        {
+            let hsts = Hsts::with_store(FileStore::open(&path).unwrap());
+            hsts.observe(&Url::parse("https://example.domain/").unwrap(),
+                         &sts("max-age=60; includeSubDomains"));
+        }
+        let store = FileStore::open(&path).unwrap();
+        let policy = store.get("example.domain").unwrap();
+        assert!(policy.include_subdomains);
+        assert!(Hsts::with_store(store).is_secure("www.example.domain"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -31,28 +31,59 @@
 //!     .unwrap();
 //! assert_eq!(res.status, hyper::Ok);
 //! ```
+use std::ascii::AsciiExt;
 use std::default::Default;
 use std::io::{self, copy, Read};
 use std::iter::Extend;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
 
 use url::UrlParser;
 use url::ParseError as UrlError;
 
 use header::{Headers, Header, HeaderFormat};
-use header::{ContentLength, Location};
+use header::{Authorization, Basic, ContentLength, Encoding, Location, TransferEncoding,
+             UserAgent};
+use http;
 use method::Method;
-use net::{NetworkConnector, NetworkStream, ContextVerifier};
+use net::{Fresh, NetworkConnector, NetworkStream, ContextVerifier};
 use status::StatusClass::Redirection;
+use status::StatusCode;
 use {Url};
 use Error;
 
+use self::cancel::{CancelHandle, CancelableConnector};
+use self::har::{HarConnector, HarRecorder};
+use self::cache::Cache;
+use self::hsts::Hsts;
+use self::netrc::Netrc;
+use self::proxy::{ProxyPool, ProxyRules};
+use self::sign::{Payload, SignRequest, Signer};
+
 pub use self::pool::Pool;
 pub use self::request::Request;
-pub use self::response::Response;
+pub use self::response::{Response, ResponseBody, Chunks, BodyRead, Redirect, SpooledBody};
 
+pub mod batch;
+pub mod cache;
+pub mod cancel;
+pub mod har;
+pub mod hsts;
+pub mod netrc;
 pub mod pool;
+pub mod proxy;
+pub mod range;
+pub mod replay;
 pub mod request;
 pub mod response;
+pub mod sign;
+pub mod sse;
+pub mod stub;
+pub mod template;
+pub mod websocket;
+
+const DEFAULT_USER_AGENT: &'static str = concat!("hyper/", env!("CARGO_PKG_VERSION"));
 
 /// A Client to use additional features with Requests.
 ///
@@ -60,6 +91,18 @@ pub mod response;
 pub struct Client {
     connector: Connector,
     redirect_policy: RedirectPolicy,
+    absolute_form: bool,
+    default_headers: Headers,
+    user_agent: Option<String>,
+    har: Option<HarRecorder>,
+    proxies: Option<Arc<RwLock<ProxyRules>>>,
+    netrc: Option<Netrc>,
+    hsts: Option<Hsts>,
+    cache: Option<Cache>,
+    signer: Option<Box<Signer>>,
+    max_decoded: u64,
+    max_ratio: u64,
+    unfold_headers: bool,
 }
 
 impl Client {
@@ -71,7 +114,14 @@ impl Client {
 
     /// Create a new Client with a configured Pool Config.
     pub fn with_pool_config(config: pool::Config) -> Client {
-        Client::with_connector(Pool::new(config))
+        Client::with_proxy_rules(config, ProxyRules::new())
+    }
+
+    fn with_proxy_rules(config: pool::Config, rules: ProxyRules) -> Client {
+        let rules = Arc::new(RwLock::new(rules));
+        let mut client = Client::with_connector(ProxyPool::new(config, rules.clone()));
+        client.proxies = Some(rules);
+        client
     }
 
     /// Create a new client with a specific connector.
@@ -79,7 +129,42 @@ impl Client {
     where C: NetworkConnector<Stream=S> + Send + 'static, S: NetworkStream + Send {
         Client {
             connector: with_connector(connector),
-            redirect_policy: Default::default()
+            redirect_policy: Default::default(),
+            absolute_form: false,
+            default_headers: Headers::new(),
+            user_agent: Some(DEFAULT_USER_AGENT.to_string()),
+            har: None,
+            proxies: None,
+            netrc: None,
+            hsts: None,
+            cache: None,
+            signer: None,
+            max_decoded: http::DEFAULT_MAX_DECODED,
+            max_ratio: http::DEFAULT_MAX_RATIO,
+            unfold_headers: true,
+        }
+    }
+
+    /// Create a new Client that sends requests through the proxies set in
+    /// the `http_proxy`, `https_proxy` and `no_proxy` environment
+    /// variables, as curl does.
+    ///
+    /// The variables are read once, when the Client is created.
+    pub fn with_system_proxies() -> Client {
+        Client::with_proxy_rules(Default::default(), ProxyRules::from_env())
+    }
+
+    /// Change the proxies requests are sent through. See the `proxy`
+    /// module.
+    ///
+    /// Idle connections are only reused for a host while it is still sent
+    /// through the same proxy, with the same credentials. This only works on a Client created by
+    /// `new`, `with_pool_config` or `with_system_proxies`, as a Client with
+    /// its own connector connects however that connector does.
+    pub fn set_proxy_rules(&mut self, rules: ProxyRules) {
+        match self.proxies {
+            Some(ref proxies) => *proxies.write().unwrap() = rules,
+            None => debug!("set_proxy_rules has no effect with a custom connector")
         }
     }
 
@@ -93,6 +178,97 @@ impl Client {
         self.redirect_policy = policy;
     }
 
+    /// Send every request with its whole URI as the request target.
+    ///
+    /// Use this when the connector sends plain HTTP requests through a
+    /// forward proxy. See `Request::set_absolute_form`.
+    pub fn set_absolute_form(&mut self, absolute: bool) {
+        self.absolute_form = absolute;
+    }
+
+    /// Set headers to send with every request, such as an API key or
+    /// `Accept`.
+    ///
+    /// A header set on an individual request takes precedence over a
+    /// default of the same name.
+    pub fn set_default_headers(&mut self, headers: Headers) {
+        self.default_headers = headers;
+    }
+
+    /// The most bytes a response body sent with a `gzip` or `deflate`
+    /// transfer coding may decode to, before reading it fails.
+    ///
+    /// The default is `http::DEFAULT_MAX_DECODED`.
+    pub fn set_max_decoded_size(&mut self, max: u64) {
+        self.max_decoded = max;
+    }
+
+    /// The most bytes a response body sent with a `gzip` or `deflate`
+    /// transfer coding may decode to for each byte received, once it has
+    /// decoded a megabyte, before reading it fails.
+    ///
+    /// The default is `http::DEFAULT_MAX_RATIO`.
+    pub fn set_max_decoded_ratio(&mut self, ratio: u64) {
+        self.max_ratio = ratio;
+    }
+
+    /// Whether header values a response folds over several lines, as some
+    /// legacy servers do, are unfolded into one, instead of failing the
+    /// request. See `Request::set_unfold_headers`.
+    ///
+    /// The default is to unfold them.
+    pub fn set_unfold_headers(&mut self, unfold: bool) {
+        self.unfold_headers = unfold;
+    }
+
+    /// Set the `User-Agent` sent with requests that don't have one, or
+    /// `None` to leave it out.
+    ///
+    /// The default is `hyper/` followed by the version of hyper.
+    pub fn set_user_agent(&mut self, user_agent: Option<String>) {
+        self.user_agent = user_agent;
+    }
+
+    /// Record every request in `recorder`, or stop recording with `None`.
+    ///
+    /// See the `har` module.
+    pub fn set_har_recorder(&mut self, recorder: Option<HarRecorder>) {
+        self.har = recorder;
+    }
+
+    /// Send the credentials in `netrc` for the host of each request that has
+    /// no `Authorization` header, or stop with `None`.
+    ///
+    /// See the `netrc` module.
+    pub fn set_netrc(&mut self, netrc: Option<Netrc>) {
+        self.netrc = netrc;
+    }
+
+    /// Remember the hosts that ask for HTTPS only with a
+    /// `Strict-Transport-Security` header, and send later `http` requests to
+    /// them over `https`, or stop with `None`.
+    ///
+    /// See the `hsts` module.
+    pub fn set_hsts(&mut self, hsts: Option<Hsts>) {
+        self.hsts = hsts;
+    }
+
+    /// Keep the responses to `GET` requests that servers allow to be
+    /// cached, and answer later requests from them, or stop with `None`.
+    ///
+    /// See the `cache` module.
+    pub fn set_cache(&mut self, cache: Option<Cache>) {
+        self.cache = cache;
+    }
+
+    /// Have `signer` add its headers to each request right before it is
+    /// sent, or stop with `None`.
+    ///
+    /// See the `sign` module.
+    pub fn set_signer(&mut self, signer: Option<Box<Signer>>) {
+        self.signer = signer;
+    }
+
     /// Build a Get request.
     pub fn get<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U> {
         self.request(Method::Get, url)
@@ -119,6 +295,20 @@ impl Client {
     }
 
 
+    /// Send a request built ahead of time, and receive a Response back.
+    ///
+    /// The parts are only borrowed, so the same request can be sent again,
+    /// such as to retry it.
+    pub fn execute(&mut self, parts: &RequestParts) -> ::Result<Response> {
+        let req = self.request(parts.method.clone(), parts.url.clone())
+            .headers(parts.headers.clone());
+        if parts.body.is_empty() {
+            req.send()
+        } else {
+            req.body(&parts.body[..]).send()
+        }
+    }
+
     /// Build a new request using this Client.
     pub fn request<U: IntoUrl>(&mut self, method: Method, url: U) -> RequestBuilder<U> {
         RequestBuilder {
@@ -127,10 +317,25 @@ impl Client {
             url: url,
             body: None,
             headers: None,
+            query: String::new(),
+            cancel: None,
+            error: None,
         }
     }
 }
 
+// Connects a Request, recording the connection if there is a recorder.
+fn open<C>(har: Option<&HarRecorder>, method: Method, url: Url, connector: &C)
+    -> ::Result<Request<Fresh>>
+where C: NetworkConnector<Stream=Box<NetworkStream + Send>> {
+    match har {
+        Some(recorder) => {
+            Request::with_connector(method, url, &HarConnector::new(connector, recorder))
+        },
+        None => Request::with_connector(method, url, connector)
+    }
+}
+
 fn with_connector<C: NetworkConnector<Stream=S> + Send + 'static, S: NetworkStream + Send>(c: C) -> Connector {
     Connector(Box::new(ConnAdapter(c)))
 }
@@ -175,6 +380,10 @@ pub struct RequestBuilder<'a, U: IntoUrl> {
     headers: Option<Headers>,
     method: Method,
     body: Option<Body<'a>>,
+    query: String,
+    cancel: Option<CancelHandle>,
+    // an error building the request, returned from `send`
+    error: Option<Error>,
 }
 
 impl<'a, U: IntoUrl> RequestBuilder<'a, U> {
@@ -207,40 +416,214 @@ impl<'a, U: IntoUrl> RequestBuilder<'a, U> {
         self
     }
 
+    /// Send `value`, serialized as JSON, as the request body.
+    ///
+    /// Sets the `Content-Type` to `application/json`. If `value` can't be
+    /// serialized, `send` fails with `Error::Json`. Only available with
+    /// the `json` feature.
+    #[cfg(feature = "json")]
+    pub fn json<T: ::serde::Serialize>(mut self, value: &T) -> RequestBuilder<'a, U> {
+        use mime::{Mime, TopLevel, SubLevel};
+        use header::ContentType;
+
+        match ::serde_json::to_vec(value) {
+            Ok(json) => {
+                self.header(ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![])))
+                    .body(json)
+            },
+            Err(e) => {
+                self.error = Some(Error::Json(e));
+                self
+            }
+        }
+    }
+
+    /// Append pairs to the query string of the URL, after any it already
+    /// has.
+    ///
+    /// Keys and values are percent-encoded, including spaces, `&`, `=` and
+    /// any non-ASCII characters.
+    pub fn query_pairs<I, K, V>(mut self, pairs: I) -> RequestBuilder<'a, U>
+    where I: IntoIterator<Item=(K, V)>, K: AsRef<str>, V: AsRef<str> {
+        for (key, value) in pairs {
+            if !self.query.is_empty() {
+                self.query.push('&');
+            }
+            encode_query(&mut self.query, key.as_ref());
+            self.query.push('=');
+            encode_query(&mut self.query, value.as_ref());
+        }
+        self
+    }
+
+    /// Let `handle` cancel this request, from any thread.
+    ///
+    /// Once cancelled, `send` fails with `Error::Cancelled`. See the
+    /// `cancel` module.
+    pub fn cancel_handle(mut self, handle: &CancelHandle) -> RequestBuilder<'a, U> {
+        self.cancel = Some(handle.clone());
+        self
+    }
+
     /// Execute this request and receive a Response back.
+    ///
+    /// When following redirects, a `303 See Other` is followed with a `GET`,
+    /// as is a `301` or `302` answering a `POST`. Any other redirect keeps
+    /// the method and sends the body again, unless the body is a reader that
+    /// can't be sent twice, in which case the redirect response is returned.
+    /// The `Authorization`, `Cookie` and `Proxy-Authorization` headers,
+    /// whether given to the request or as default headers of the `Client`,
+    /// are only sent to the original origin.
     pub fn send(self) -> ::Result<Response> {
-        let RequestBuilder { client, method, url, headers, body } = self;
+        let cancel = self.cancel.clone();
+        let res = self.send_following();
+        match cancel {
+            // whatever error a shut down connection caused, report why
+            Some(ref handle) if res.is_err() && handle.is_cancelled() => Err(Error::Cancelled),
+            _ => res
+        }
+    }
+
+    fn send_following(self) -> ::Result<Response> {
+        let RequestBuilder {
+            client, mut method, url, mut headers, body, query, cancel, error
+        } = self;
+        if let Some(error) = error {
+            return Err(error);
+        }
         let mut url = try!(url.into_url());
+        if !query.is_empty() {
+            url.query = Some(match url.query.take() {
+                Some(ref existing) if !existing.is_empty() => format!("{}&{}", existing, query),
+                _ => query
+            });
+        }
         trace!("send {:?} {:?}", method, url);
 
-        let can_have_body = match &method {
-            &Method::Get | &Method::Head => false,
-            _ => true
-        };
-
-        let mut body = if can_have_body {
-            body
-        } else {
-            None
-        };
+        // a body given for a GET or HEAD is sent anyway, as some APIs
+        // expect one
+        let mut body = body;
+        let mut redirects = Vec::new();
+        let mut cross_origin = false;
 
         loop {
-            let mut req = try!(Request::with_connector(method.clone(), url.clone(), &client.connector));
+            let can_have_body = method.allows_body();
+            let sent_body = body.is_some();
+            // an owned body is rewound once it is sent, rather than copied
+            // up front for a redirect that seldom comes
+            let replay = match body {
+                Some(Body::BufBody(buf, len)) => Some(Body::BufBody(buf, len)),
+                _ => None
+            };
+            if let Some(ref hsts) = client.hsts {
+                if hsts.upgrade(&mut url) {
+                    debug!("HSTS upgraded request to {}", url);
+                }
+            }
+            let no_headers = Headers::new();
+            let cache = match client.cache {
+                Some(ref cache) if method == Method::Get && body.is_none() => Some(cache),
+                _ => None
+            };
+            if let Some(cache) = cache {
+                if let Some(res) = try!(cache.get(&url, headers.as_ref().unwrap_or(&no_headers))) {
+                    return Ok(with_history(res, url, redirects));
+                }
+            }
+            let har = client.har.as_ref();
+            let mut req = match cancel {
+                Some(ref handle) => {
+                    let connector = CancelableConnector::new(&client.connector, handle);
+                    try!(open(har, method.clone(), url.clone(), &connector))
+                },
+                None => try!(open(har, method.clone(), url.clone(), &client.connector))
+            };
+            req.set_unfold_headers(client.unfold_headers);
+            if client.absolute_form {
+                req.set_absolute_form(true);
+            }
+            if let Some(ref rules) = client.proxies {
+                try!(proxy::prepare(rules, &mut req));
+            }
+            for header in client.default_headers.iter() {
+                if cross_origin && is_credential(header.name()) {
+                    continue;
+                }
+                if req.headers().get_raw(header.name()).is_none() {
+                    req.headers_mut().extend(Some(header));
+                }
+            }
+            if let Some(ref ua) = client.user_agent {
+                if !req.headers().has::<UserAgent>() {
+                    req.headers_mut().set(UserAgent(ua.clone()));
+                }
+            }
             headers.as_ref().map(|headers| req.headers_mut().extend(headers.iter()));
+            if let Some(ref netrc) = client.netrc {
+                if !req.headers().has::<Authorization<Basic>>() {
+                    let (host, _) = try!(get_host_and_port(&url));
+                    if let Some(basic) = netrc.credentials(&host) {
+                        req.headers_mut().set(Authorization(basic));
+                    }
+                }
+            }
+
+            if let Some(cache) = cache {
+                cache.validate(&url, req.headers_mut());
+            }
 
             match (can_have_body, body.as_ref()) {
-                (true, Some(body)) => match body.size() {
+                (_, Some(body)) => match body.size() {
                     Some(size) => req.headers_mut().set(ContentLength(size)),
+                    // Request only adds chunked on its own for methods with a body
+                    None if !can_have_body => {
+                        req.headers_mut().set(TransferEncoding(vec![Encoding::Chunked]))
+                    },
                     None => (), // chunked, Request will add it automatically
                 },
                 (true, None) => req.headers_mut().set(ContentLength(0)),
                 _ => () // neither
             }
+            if let Some(ref signer) = client.signer {
+                let payload = match body {
+                    None => Payload::Empty,
+                    Some(Body::BufBody(buf, _)) => Payload::Bytes(buf),
+                    Some(Body::OwnedBody(ref buf)) => Payload::Bytes(&buf.get_ref()[..]),
+                    Some(_) => Payload::Streamed,
+                };
+                try!(signer.sign(&mut SignRequest {
+                    method: &method,
+                    url: &url,
+                    headers: req.headers_mut(),
+                    body: payload,
+                }));
+            }
             let mut streaming = try!(req.start());
-            body.take().map(|mut rdr| copy(&mut rdr, &mut streaming));
-            let res = try!(streaming.send());
+            let replay = match body.take().map(|mut rdr| {
+                let _ = copy(&mut rdr, &mut streaming);
+                rdr
+            }) {
+                Some(Body::OwnedBody(mut buf)) => {
+                    buf.set_position(0);
+                    Some(Body::OwnedBody(buf))
+                },
+                _ => replay
+            };
+            let mut res = try!(streaming.send());
+            res.set_max_decoded_size(client.max_decoded);
+            res.set_max_decoded_ratio(client.max_ratio);
+            if let Some(ref hsts) = client.hsts {
+                hsts.observe(&url, &res.headers);
+            }
+            let res = match client.cache {
+                // a GET with a body is left alone, as the body isn't part of the key
+                Some(ref cache) if !(sent_body && method == Method::Get) => {
+                    try!(cache.observe(&url, &method, headers.as_ref().unwrap_or(&no_headers), res))
+                },
+                _ => res
+            };
             if res.status.class() != Redirection {
-                return Ok(res)
+                return Ok(with_history(res, url, redirects))
             }
             debug!("redirect code {:?} for {}", res.status, url);
 
@@ -258,22 +641,133 @@ impl<'a, U: IntoUrl> RequestBuilder<'a, U> {
                 };
                 match loc {
                     Some(r) => r,
-                    None => return Ok(res)
+                    None => return Ok(with_history(res, url, redirects))
                 }
             };
-            url = match loc {
+            let loc = match loc {
                 Ok(u) => u,
                 Err(e) => {
                     debug!("Location header had invalid URI: {:?}", e);
-                    return Ok(res);
+                    return Ok(with_history(res, url, redirects));
                 }
             };
             match client.redirect_policy {
                 // separate branches because they can't be one
                 RedirectPolicy::FollowAll => (), //continue
-                RedirectPolicy::FollowIf(cond) if cond(&url) => (), //continue
-                _ => return Ok(res),
+                RedirectPolicy::FollowIf(cond) if cond(&loc) => (), //continue
+                _ => return Ok(with_history(res, url, redirects)),
             }
+
+            let keep_body = match res.status {
+                StatusCode::SeeOther => {
+                    if method != Method::Head {
+                        method = Method::Get;
+                    }
+                    false
+                },
+                StatusCode::MovedPermanently | StatusCode::Found if method == Method::Post => {
+                    method = Method::Get;
+                    false
+                },
+                _ => true
+            };
+            body = if keep_body {
+                if sent_body && replay.is_none() {
+                    debug!("cannot send a streamed body again for {:?}", res.status);
+                    return Ok(with_history(res, url, redirects));
+                }
+                replay
+            } else {
+                None
+            };
+
+            if !same_origin(&url, &loc) {
+                cross_origin = true;
+                if let Some(ref mut headers) = headers {
+                    for name in CREDENTIALS {
+                        headers.remove_raw(name);
+                    }
+                }
+            }
+            redirects.push(Redirect {
+                url: mem::replace(&mut url, loc),
+                status: res.status,
+            });
+        }
+    }
+}
+
+// Records where a Response came from, for `Response::url` and
+// `Response::redirect_chain`.
+fn with_history(mut res: Response, url: Url, redirects: Vec<Redirect>) -> Response {
+    res.set_history(url, redirects);
+    res
+}
+
+// Everything but the unreserved characters of RFC 3986 is percent-encoded,
+// so a value can never be mistaken for a separator.
+fn encode_query(query: &mut String, s: &str) {
+    for &b in s.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                query.push(b as char)
+            },
+            _ => query.push_str(&format!("%{:02X}", b))
+        }
+    }
+}
+
+// Headers that carry credentials, which aren't sent on once a redirect
+// leaves the origin of the request.
+const CREDENTIALS: &'static [&'static str] = &["Authorization", "Cookie", "Proxy-Authorization"];
+
+fn is_credential(name: &str) -> bool {
+    CREDENTIALS.iter().any(|credential| credential.eq_ignore_ascii_case(name))
+}
+
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme == b.scheme &&
+        a.serialize_host() == b.serialize_host() &&
+        a.port_or_default() == b.port_or_default()
+}
+
+/// A request that isn't tied to a connection.
+///
+/// The parts of a request can be built, inspected, signed or stored, and
+/// sent later with `Client::execute`.
+#[derive(Clone, Debug)]
+pub struct RequestParts {
+    /// The method of the request.
+    pub method: Method,
+    /// The URL to send the request to.
+    pub url: Url,
+    /// The headers to send. `Host` and `Content-Length` are set when the
+    /// request is sent.
+    pub headers: Headers,
+    /// The body to send, if not empty.
+    pub body: Vec<u8>,
+}
+
+impl RequestParts {
+    /// A request without any headers, or a body.
+    pub fn new(method: Method, url: Url) -> RequestParts {
+        RequestParts {
+            method: method,
+            url: url,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+}
+
+impl From<(Method, Url, Headers, Vec<u8>)> for RequestParts {
+    fn from(parts: (Method, Url, Headers, Vec<u8>)) -> RequestParts {
+        let (method, url, headers, body) = parts;
+        RequestParts {
+            method: method,
+            url: url,
+            headers: headers,
+            body: body,
         }
     }
 }
@@ -286,6 +780,9 @@ pub enum Body<'a> {
     SizedBody(&'a mut (Read + 'a), u64),
     /// A String has a size, and uses Content-Length.
     BufBody(&'a [u8] , usize),
+    /// Bytes the body owns, like a serialized value, also using
+    /// Content-Length.
+    OwnedBody(io::Cursor<Vec<u8>>),
 }
 
 impl<'a> Body<'a> {
@@ -293,6 +790,7 @@ impl<'a> Body<'a> {
         match *self {
             Body::SizedBody(_, len) => Some(len),
             Body::BufBody(_, len) => Some(len as u64),
+            Body::OwnedBody(ref buf) => Some(buf.get_ref().len() as u64),
             _ => None
         }
     }
@@ -305,6 +803,7 @@ impl<'a> Read for Body<'a> {
             Body::ChunkedBody(ref mut r) => r.read(buf),
             Body::SizedBody(ref mut r, _) => r.read(buf),
             Body::BufBody(ref mut r, _) => Read::read(r, buf),
+            Body::OwnedBody(ref mut r) => r.read(buf),
         }
     }
 }
@@ -330,6 +829,13 @@ impl<'a> Into<Body<'a>> for &'a String {
     }
 }
 
+impl<'a> From<Vec<u8>> for Body<'a> {
+    #[inline]
+    fn from(buf: Vec<u8>) -> Body<'a> {
+        Body::OwnedBody(io::Cursor::new(buf))
+    }
+}
+
 impl<'a, R: Read> From<&'a mut R> for Body<'a> {
     #[inline]
     fn from(r: &'a mut R) -> Body<'a> {
@@ -361,6 +867,42 @@ impl<'a> IntoUrl for &'a String {
     }
 }
 
+impl IntoUrl for String {
+    fn into_url(self) -> Result<Url, UrlError> {
+        Url::parse(&self)
+    }
+}
+
+/// An `http` URL for the root of the server at this address.
+impl IntoUrl for SocketAddr {
+    fn into_url(self) -> Result<Url, UrlError> {
+        Url::parse(&format!("http://{}/", self))
+    }
+}
+
+/// An `http` URL for the root of the server at this host and port.
+impl<'a> IntoUrl for (&'a str, u16) {
+    fn into_url(self) -> Result<Url, UrlError> {
+        (self.0, self.1, "/").into_url()
+    }
+}
+
+/// An `http` URL for a path, which may include a query, on the server at
+/// this host and port.
+impl<'a, 'b> IntoUrl for (&'a str, u16, &'b str) {
+    fn into_url(self) -> Result<Url, UrlError> {
+        let (host, port, path) = self;
+        // an IPv6 address needs brackets to be told apart from the port
+        let host = if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]", host)
+        } else {
+            host.to_string()
+        };
+        let slash = if path.starts_with('/') { "" } else { "/" };
+        Url::parse(&format!("http://{}:{}{}{}", host, port, slash, path))
+    }
+}
+
 /// Behavior regarding how to handle redirects within a Client.
 #[derive(Copy)]
 pub enum RedirectPolicy {
@@ -402,6 +944,7 @@ fn get_host_and_port(url: &Url) -> ::Result<(String, u16)> {
 #[cfg(test)]
 mod tests {
     use header::Server;
+    use status::StatusCode;
     use super::{Client, RedirectPolicy};
     use url::Url;
     use mock::ChannelMockConnector;
@@ -431,6 +974,12 @@ mod tests {
 
         let res = client.get("http://127.0.0.1").send().unwrap();
         assert_eq!(res.headers.get(), Some(&Server("mock3".to_string())));
+        assert_eq!(res.url(), Some(&Url::parse("https://127.0.0.3").unwrap()));
+        let chain = res.redirect_chain().iter()
+            .map(|hop| (hop.url.serialize(), hop.status))
+            .collect::<Vec<_>>();
+        assert_eq!(chain, vec![("http://127.0.0.1/".to_string(), StatusCode::MovedPermanently),
+                               ("http://127.0.0.2/".to_string(), StatusCode::Found)]);
     }
 
     #[test]
@@ -452,6 +1001,234 @@ mod tests {
         assert_eq!(res.headers.get(), Some(&Server("mock2".to_string())));
     }
 
+    mock_connector!(MockHsts {
+        "https://example.domain" => "HTTP/1.1 200 OK\r\n\
+                                     Strict-Transport-Security: max-age=60\r\n\
+                                     Server: secure\r\n\
+                                     \r\n\
+                                    "
+        "http://example.domain" =>  "HTTP/1.1 200 OK\r\n\
+                                     Server: plain\r\n\
+                                     \r\n\
+                                    "
+    });
+
+    #[test]
+    fn test_hsts_upgrade() {
+        use client::hsts::Hsts;
+        let mut client = Client::with_connector(MockHsts);
+        client.set_hsts(Some(Hsts::new()));
+
+        let res = client.get("http://example.domain/").send().unwrap();
+        assert_eq!(res.headers.get(), Some(&Server("plain".to_string())));
+        client.get("https://example.domain/").send().unwrap();
+        let res = client.get("http://example.domain/").send().unwrap();
+        assert_eq!(res.headers.get(), Some(&Server("secure".to_string())));
+    }
+
+    #[test]
+    fn test_redirect_see_other() {
+        use client::stub::{StubConnector, StubResponse};
+        use header::Location;
+        use method::Method::{Get, Post};
+        use status::StatusCode;
+
+        let stub = StubConnector::new();
+        stub.route(Some(Post), "http://127.0.0.1/form",
+                   StubResponse::new(StatusCode::SeeOther).header(Location("/done".to_string())));
+        stub.route(Some(Get), "http://127.0.0.1/done", StubResponse::new(StatusCode::Ok));
+        stub.route(Some(Post), "http://127.0.0.1/old",
+                   StubResponse::new(StatusCode::TemporaryRedirect).header(Location("new".to_string())));
+        stub.route(Some(Post), "http://127.0.0.1/new", StubResponse::new(StatusCode::Created));
+
+        let mut client = Client::with_connector(stub);
+        let res = client.post("http://127.0.0.1/form").body("a=b").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let res = client.post("http://127.0.0.1/old").body("a=b").send().unwrap();
+        assert_eq!(res.status, StatusCode::Created);
+        let res = client.post("http://127.0.0.1/old").body(b"a=b".to_vec()).send().unwrap();
+        assert_eq!(res.status, StatusCode::Created);
+
+        let mut body: &[u8] = b"a=b";
+        let res = client.post("http://127.0.0.1/old").body(&mut body).send().unwrap();
+        assert_eq!(res.status, StatusCode::TemporaryRedirect);
+    }
+
+    #[test]
+    fn test_into_url_addrs() {
+        use std::net::SocketAddr;
+        use super::IntoUrl;
+
+        let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(addr.into_url().unwrap().serialize(), "http://127.0.0.1:8080/");
+        assert_eq!(("127.0.0.1", 8080).into_url().unwrap().serialize(),
+                   "http://127.0.0.1:8080/");
+        assert_eq!(("localhost", 8080, "/health?full=1").into_url().unwrap().serialize(),
+                   "http://localhost:8080/health?full=1");
+        assert_eq!(("::1", 8080, "health").into_url().unwrap().serialize(),
+                   "http://[::1]:8080/health");
+    }
+
+    #[test]
+    fn test_netrc() {
+        use header::{Authorization, Basic};
+        use method::Method::Get;
+        use server::testing::{TestServer, Expected, Reply};
+        use status::StatusCode;
+        use super::netrc::Netrc;
+
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/").header(Authorization(Basic {
+            username: "alice".to_string(),
+            password: Some("s3cret".to_string()),
+        })), Reply::new(StatusCode::Ok));
+
+        let mut client = Client::new();
+        client.set_netrc(Some(Netrc::parse("machine 127.0.0.1 login alice password s3cret")));
+        let res = client.get(&server.url("/")).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        server.assert_done();
+    }
+
+    #[test]
+    fn test_query_pairs() {
+        use client::stub::{StubConnector, StubResponse};
+        use status::StatusCode;
+
+        let stub = StubConnector::new();
+        stub.route(None, "http://127.0.0.1/search?lang=en&q=a%20b%26c%3Dd&city=Z%C3%BCrich",
+                   StubResponse::new(StatusCode::Ok));
+        stub.route(None, "http://127.0.0.1/search?page=2", StubResponse::new(StatusCode::Ok));
+        let mut client = Client::with_connector(stub);
+
+        let res = client.get("http://127.0.0.1/search?lang=en")
+            .query_pairs(vec![("q", "a b&c=d"), ("city", "Z\u{fc}rich")])
+            .send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let res = client.get("http://127.0.0.1/search")
+            .query_pairs(Some(("page", "2".to_string())))
+            .send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_execute() {
+        use client::stub::{StubConnector, StubResponse};
+        use header::{Headers, UserAgent};
+        use method::Method::Put;
+        use status::StatusCode;
+        use super::RequestParts;
+
+        let stub = StubConnector::new();
+        stub.route(Some(Put), "http://127.0.0.1/item", StubResponse::new(StatusCode::Created));
+        let mut client = Client::with_connector(stub);
+
+        let mut headers = Headers::new();
+        headers.set(UserAgent("test".to_string()));
+        let url = Url::parse("http://127.0.0.1/item").unwrap();
+        let parts: RequestParts = (Put, url, headers, b"item".to_vec()).into();
+        assert_eq!(client.execute(&parts).unwrap().status, StatusCode::Created);
+        assert_eq!(client.execute(&parts).unwrap().status, StatusCode::Created);
+    }
+
+    #[test]
+    fn test_default_headers() {
+        use header::{Accept, Headers, UserAgent, qitem};
+        use mime::{Mime, TopLevel, SubLevel};
+        use server::testing::{TestServer, Expected, Reply};
+        use method::Method::Get;
+        use status::StatusCode;
+
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/")
+                          .header(UserAgent("default".to_string()))
+                          .header(Accept(vec![qitem(Mime(TopLevel::Application, SubLevel::Json, vec![]))])),
+                      Reply::new(StatusCode::Ok));
+        server.expect(Expected::new(Get, "/")
+                          .header(UserAgent("override".to_string()))
+                          .header(Accept(vec![qitem(Mime(TopLevel::Application, SubLevel::Json, vec![]))])),
+                      Reply::new(StatusCode::Ok));
+
+        let mut defaults = Headers::new();
+        defaults.set(UserAgent("default".to_string()));
+        defaults.set(Accept(vec![qitem(Mime(TopLevel::Application, SubLevel::Json, vec![]))]));
+        let mut client = Client::new();
+        client.set_default_headers(defaults);
+
+        client.get(&server.url("/")).send().unwrap();
+        client.get(&server.url("/")).header(UserAgent("override".to_string())).send().unwrap();
+        server.assert_done();
+    }
+
+    #[test]
+    fn test_redirect_drops_default_credentials() {
+        use std::sync::{Arc, Mutex};
+        use header::{Authorization, Cookie, Headers, Location};
+        use server::{Request, Response, Server};
+        use server::testing::{TestServer, Expected, Reply};
+        use method::Method::Get;
+        use status::StatusCode;
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let recorded = seen.clone();
+        let mut other = Server::http(move |req: Request, res: Response| {
+            recorded.lock().unwrap().push((req.headers.has::<Authorization<String>>(),
+                                           req.headers.has::<Cookie>()));
+            res.send(b"").unwrap();
+        }).listen_threads("127.0.0.1:0", 1).unwrap();
+
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/")
+                          .header(Authorization("secret".to_string())),
+                      Reply::new(StatusCode::Found)
+                          .header(Location(format!("http://{}/", other.socket))));
+
+        let mut defaults = Headers::new();
+        defaults.set(Authorization("secret".to_string()));
+        defaults.set_raw("Cookie", vec![b"session=1".to_vec()]);
+        let mut client = Client::new();
+        client.set_default_headers(defaults);
+
+        let res = client.get(&server.url("/")).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        server.assert_done();
+        assert_eq!(*seen.lock().unwrap(), vec![(false, false)]);
+        other.close().unwrap();
+    }
+
+    #[test]
+    fn test_user_agent() {
+        use header::UserAgent;
+        use server::testing::{TestServer, Expected, Reply};
+        use method::Method::Get;
+        use status::StatusCode;
+        use super::DEFAULT_USER_AGENT;
+
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/").header(UserAgent(DEFAULT_USER_AGENT.to_string())),
+                      Reply::new(StatusCode::Ok));
+        server.expect(Expected::new(Get, "/").header(UserAgent("custom/1.0".to_string())),
+                      Reply::new(StatusCode::Ok));
+
+        let mut client = Client::new();
+        client.get(&server.url("/")).send().unwrap();
+        client.set_user_agent(Some("custom/1.0".to_string()));
+        client.get(&server.url("/")).send().unwrap();
+        server.assert_done();
+
+        assert!(DEFAULT_USER_AGENT.starts_with("hyper/0."));
+    }
+
+    #[test]
+    fn test_same_origin() {
+        use super::same_origin;
+        let url = |s| Url::parse(s).unwrap();
+        assert!(same_origin(&url("http://127.0.0.1/a"), &url("http://127.0.0.1:80/b")));
+        assert!(!same_origin(&url("http://127.0.0.1/"), &url("https://127.0.0.1/")));
+        assert!(!same_origin(&url("http://127.0.0.1/"), &url("http://127.0.0.2/")));
+        assert!(!same_origin(&url("http://127.0.0.1/"), &url("http://127.0.0.1:8080/")));
+    }
+
     /// Tests that the `Client::set_ssl_verifier` method does not drop the
     /// old connector, but rather delegates the change to the connector itself.
     #[test]
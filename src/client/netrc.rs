@@ -0,0 +1,179 @@
+//! Credentials from a `.netrc` file.
+//!
+//! Command-line tools like curl and wget look up the user name and
+//! password for a host in `~/.netrc`. A `Client` does the same once it is
+//! given a `Netrc` with `set_netrc`, sending the credentials for the host
+//! of each request that has no `Authorization` header of its own.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use hyper::client::netrc::Netrc;
+//!
+//! let mut client = Client::new();
+//! client.set_netrc(Some(Netrc::from_home().unwrap()));
+//! ```
+//!
+//! The file is a list of `machine` entries, each with a `login` and
+//! `password`, and optionally a `default` entry for every other host.
+//! Lines starting with `#` are comments, and `macdef` macros are skipped.
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use header::Basic;
+
+/// The entries of a `.netrc` file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Netrc {
+    machines: Vec<(String, Machine)>,
+    default: Option<Machine>,
+}
+
+/// The credentials for one host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Machine {
+    /// The user name.
+    pub login: Option<String>,
+    /// The password.
+    pub password: Option<String>,
+    /// An additional account password, which HTTP has no use for.
+    pub account: Option<String>,
+}
+
+impl Netrc {
+    /// Read the `.netrc` file in the home directory of the current user.
+    ///
+    /// On Windows, `_netrc` is read instead if there is no `.netrc`.
+    pub fn from_home() -> io::Result<Netrc> {
+        let home = match env::home_dir() {
+            Some(home) => home,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound,
+                                              "no home directory"))
+        };
+        let path = home.join(".netrc");
+        if cfg!(windows) && fs::metadata(&path).is_err() {
+            return Netrc::from_file(home.join("_netrc"));
+        }
+        Netrc::from_file(path)
+    }
+
+    /// Read a `.netrc` file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Netrc> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        Ok(Netrc::parse(&text))
+    }
+
+    /// Parse the contents of a `.netrc` file.
+    ///
+    /// Anything that isn't understood is skipped, so this can't fail.
+    pub fn parse(text: &str) -> Netrc {
+        let mut netrc = Netrc::default();
+        // the entry being filled in, and its host if it isn't the default
+        let mut current: Option<(Option<String>, Machine)> = None;
+        let mut in_macro = false;
+        for line in text.lines() {
+            if in_macro {
+                // a macro runs until the next empty line
+                in_macro = !line.trim().is_empty();
+                continue;
+            }
+            if line.trim_left().starts_with("#") {
+                continue;
+            }
+            let mut tokens = line.split(|c: char| c.is_whitespace()).filter(|t| !t.is_empty());
+            while let Some(token) = tokens.next() {
+                match token {
+                    "machine" | "default" => {
+                        netrc.finish(current.take());
+                        let host = if token == "machine" {
+                            match tokens.next() {
+                                Some(host) => Some(host.to_lowercase()),
+                                None => break
+                            }
+                        } else {
+                            None
+                        };
+                        current = Some((host, Machine::default()));
+                    },
+                    "login" | "password" | "account" => {
+                        let value = tokens.next().map(|value| value.to_string());
+                        if let Some((_, ref mut machine)) = current {
+                            match token {
+                                "login" => machine.login = value,
+                                "password" => machine.password = value,
+                                _ => machine.account = value,
+                            }
+                        }
+                    },
+                    "macdef" => {
+                        in_macro = true;
+                        break;
+                    },
+                    _ => trace!("ignoring netrc token {:?}", token)
+                }
+            }
+        }
+        netrc.finish(current);
+        netrc
+    }
+
+    fn finish(&mut self, entry: Option<(Option<String>, Machine)>) {
+        match entry {
+            Some((Some(host), machine)) => self.machines.push((host, machine)),
+            // only the first default counts, as with curl
+            Some((None, machine)) => if self.default.is_none() {
+                self.default = Some(machine);
+            },
+            None => ()
+        }
+    }
+
+    /// The entry for `host`, or the default entry if it has none.
+    pub fn machine(&self, host: &str) -> Option<&Machine> {
+        let host = host.to_lowercase();
+        self.machines.iter()
+            .find(|&&(ref name, _)| *name == host)
+            .map(|&(_, ref machine)| machine)
+            .or(self.default.as_ref())
+    }
+
+    /// The Basic credentials to send to `host`, if its entry has a login.
+    pub fn credentials(&self, host: &str) -> Option<Basic> {
+        self.machine(host).and_then(|machine| {
+            machine.login.as_ref().map(|login| Basic {
+                username: login.clone(),
+                password: machine.password.clone(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Netrc;
+
+    #[test]
+    fn test_parse() {
+        let netrc = Netrc::parse("\
+            # work\n\
+            machine api.example.domain login alice password s3cret\n\
+            macdef init\n\
+            machine ignored.domain login mallory\n\
+            \n\
+            machine Other.Domain\n\
+            \tlogin bob\n\
+            \tpassword hunter2 account extra\n\
+            default login anonymous password guest\n");
+
+        let alice = netrc.credentials("api.example.domain").unwrap();
+        assert_eq!(alice.username, "alice");
+        assert_eq!(alice.password, Some("s3cret".to_string()));
+        let bob = netrc.machine("other.domain").unwrap();
+        assert_eq!(bob.login, Some("bob".to_string()));
+        assert_eq!(bob.account, Some("extra".to_string()));
+        assert_eq!(netrc.credentials("ignored.domain").unwrap().username, "anonymous");
+        assert_eq!(netrc.credentials("anywhere.domain").unwrap().username, "anonymous");
+    }
+}
@@ -1,16 +1,22 @@
 //! Client Connection Pooling
 use std::borrow::ToOwned;
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Read, Write};
-use std::net::{SocketAddr, Shutdown};
-use std::sync::{Arc, Mutex};
+use std::net::{SocketAddr, Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, Condvar};
 
+use time::{Duration, Timespec};
+
+use clock::{Clock, SystemClock};
+use header::KeepAlive;
 use net::{NetworkConnector, NetworkStream, HttpConnector, ContextVerifier};
 
 /// The `NetworkConnector` that behaves as a connection pool used by hyper's `Client`.
 pub struct Pool<C: NetworkConnector> {
     connector: C,
-    inner: Arc<Mutex<PoolImpl<<C as NetworkConnector>::Stream>>>
+    inner: Arc<Mutex<PoolImpl<<C as NetworkConnector>::Stream>>>,
+    released: Arc<Condvar>,
 }
 
 /// Config options for the `Pool`.
@@ -18,6 +24,22 @@ pub struct Pool<C: NetworkConnector> {
 pub struct Config {
     /// The maximum idle connections *per host*.
     pub max_idle: usize,
+    /// The maximum connections, idle or in use, across *all* hosts.
+    ///
+    /// `None` means there is no limit.
+    pub max_connections: Option<usize>,
+    /// The maximum connections in use *per host*.
+    ///
+    /// `None` means there is no limit.
+    pub max_connections_per_host: Option<usize>,
+    /// How long a connection may sit idle in the pool before it is closed.
+    ///
+    /// Expired connections are evicted the next time the pool is used.
+    /// `None` means idle connections are kept until the server closes them.
+    pub idle_timeout: Option<Duration>,
+    /// What to do when a new connection is needed, but `max_connections`
+    /// or `max_connections_per_host` has been reached.
+    pub on_limit: LimitPolicy,
 }
 
 impl Default for Config {
@@ -25,20 +47,54 @@ impl Default for Config {
     fn default() -> Config {
         Config {
             max_idle: 5,
+            max_connections: None,
+            max_connections_per_host: None,
+            idle_timeout: None,
+            on_limit: LimitPolicy::OpenAnyway,
         }
     }
 }
 
-#[derive(Debug)]
+/// What the `Pool` does when a connection limit has been reached.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LimitPolicy {
+    /// Open the connection anyway, going over the limit.
+    OpenAnyway,
+    /// Wait for another connection to be released, giving up with
+    /// `Error::Timeout` after the duration, as told by the pool's `Clock`.
+    ///
+    /// Waiting threads are served in the order they started waiting, so
+    /// none of them can be starved by threads arriving later.
+    Wait(Duration),
+}
+
 struct PoolImpl<S> {
-    conns: HashMap<Key, Vec<S>>,
+    conns: HashMap<Key, Vec<Idle<S>>>,
     config: Config,
+    clock: Arc<Clock>,
+    active: usize,
+    active_hosts: HashMap<Key, usize>,
+    waiting: VecDeque<(u64, Key)>,
+    next_ticket: u64,
+}
+
+struct Idle<S> {
+    conn: S,
+    since: Timespec,
+    // When the server said it would close the connection, if it did.
+    expires: Option<Timespec>,
 }
 
-type Key = (String, u16, Scheme);
+// The last part is the affinity token, if the connection was made for one.
+type Key = (String, u16, Scheme, Option<String>);
 
 fn key<T: Into<Scheme>>(host: &str, port: u16, scheme: T) -> Key {
-    (host.to_owned(), port, scheme.into())
+    (host.to_owned(), port, scheme.into(), None)
+}
+
+// Connection limits count every connection to a host, whatever its token.
+fn host_key(key: &Key) -> Key {
+    (key.0.clone(), key.1, key.2.clone(), None)
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
@@ -75,23 +131,212 @@ impl<C: NetworkConnector> Pool<C> {
             inner: Arc::new(Mutex::new(PoolImpl {
                 conns: HashMap::new(),
                 config: config,
-            }))
+                clock: Arc::new(SystemClock),
+                active: 0,
+                active_hosts: HashMap::new(),
+                waiting: VecDeque::new(),
+                next_ticket: 0,
+            })),
+            released: Arc::new(Condvar::new()),
         }
     }
 
+    /// Set the `Clock` used to decide when idle connections have expired,
+    /// and when a wait for a connection has timed out.
+    pub fn set_clock<K: Clock + 'static>(&mut self, clock: K) {
+        self.inner.lock().unwrap().clock = Arc::new(clock);
+    }
+
     /// Clear all idle connections from the Pool, closing them.
     #[inline]
     pub fn clear_idle(&mut self) {
         self.inner.lock().unwrap().conns.clear();
     }
+
+    /// A connector that only shares connections with others using the same
+    /// affinity `token`, such as the identity of a downstream client.
+    ///
+    /// A gateway in front of a backend that keeps state per connection can
+    /// use it to keep one upstream connection for each client session.
+    /// Connections with a token still count towards the limits of their
+    /// host.
+    ///
+    /// ```no_run
+    /// # use hyper::client::{Pool, Request};
+    /// # use hyper::method::Method::Get;
+    /// # use hyper::Url;
+    /// let pool = Pool::new(Default::default());
+    /// let url = Url::parse("http://backend.domain/").unwrap();
+    /// let req = Request::with_connector(Get, url, &pool.with_affinity("session-42"));
+    /// ```
+    pub fn with_affinity<T: Into<String>>(&self, token: T) -> AffinityConnector<C> {
+        AffinityConnector {
+            pool: self,
+            token: token.into(),
+        }
+    }
+}
+
+/// A `NetworkConnector` for connections of a `Pool` with an affinity token.
+///
+/// See `Pool::with_affinity`.
+pub struct AffinityConnector<'a, C: NetworkConnector + 'a> {
+    pool: &'a Pool<C>,
+    token: String,
+}
+
+impl<'a, C: NetworkConnector<Stream=S>, S: NetworkStream + Send> NetworkConnector
+for AffinityConnector<'a, C> {
+    type Stream = PooledStream<S>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<PooledStream<S>> {
+        let mut key = key(host, port, scheme);
+        key.3 = Some(self.token.clone());
+        self.pool.connect_keyed(key, host, port, scheme)
+    }
+
+    /// Does nothing, as the pool is only borrowed. Set the verifier on the
+    /// pool instead.
+    fn set_ssl_verifier(&mut self, _verifier: ContextVerifier) {}
 }
 
 impl<S> PoolImpl<S> {
-    fn reuse(&mut self, key: Key, conn: S) {
-        trace!("reuse {:?}", key);
+    fn reuse(&mut self, key: Key, conn: S, keep_alive: KeepAlive) {
+        trace!("reuse {:?}, {:?}", key, keep_alive);
+        if keep_alive.max == Some(0) {
+            return;
+        }
+        let since = self.clock.now();
+        // The server's timer started when it sent the response, a little
+        // before we got here, so give up on the connection a second early.
+        let expires = keep_alive.timeout.map(|secs| {
+            since + Duration::seconds(secs as i64) - Duration::seconds(1)
+        });
         let conns = self.conns.entry(key).or_insert(vec![]);
         if conns.len() < self.config.max_idle {
-            conns.push(conn);
+            conns.push(Idle { conn: conn, since: since, expires: expires });
+        }
+    }
+
+    fn take(&mut self, key: &Key) -> Option<S> {
+        let mut should_remove = false;
+        let conn = self.conns.get_mut(key).and_then(|vec| {
+            should_remove = vec.len() <= 1;
+            vec.pop()
+        });
+        if should_remove {
+            self.conns.remove(key);
+        }
+        conn.map(|idle| idle.conn)
+    }
+
+    fn evict_expired(&mut self) {
+        let timeout = self.config.idle_timeout;
+        let now = self.clock.now();
+        let mut empty = vec![];
+        for (key, conns) in self.conns.iter_mut() {
+            conns.retain(|idle| {
+                timeout.map_or(true, |timeout| now - idle.since < timeout) &&
+                    idle.expires.map_or(true, |expires| now < expires)
+            });
+            if conns.is_empty() {
+                trace!("evicted idle connections to {:?}", key);
+                empty.push(key.clone());
+            }
+        }
+        for key in empty {
+            self.conns.remove(&key);
+        }
+    }
+
+    // Close the idle connection that has waited the longest, to make room
+    // for a connection to a different host.
+    fn evict_oldest(&mut self) -> bool {
+        let mut oldest: Option<(Timespec, &Key)> = None;
+        for (key, conns) in self.conns.iter() {
+            if let Some(idle) = conns.first() {
+                if oldest.map_or(true, |(since, _)| idle.since < since) {
+                    oldest = Some((idle.since, key));
+                }
+            }
+        }
+        match oldest.map(|(_, key)| key.clone()) {
+            Some(key) => {
+                let should_remove = {
+                    let conns = self.conns.get_mut(&key).unwrap();
+                    conns.remove(0);
+                    conns.is_empty()
+                };
+                if should_remove {
+                    self.conns.remove(&key);
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self.config.max_connections {
+            Some(max) => {
+                let idle = self.conns.values().fold(0, |n, conns| n + conns.len());
+                self.active + idle >= max
+            },
+            None => false
+        }
+    }
+
+    fn is_host_full(&self, key: &Key) -> bool {
+        match self.config.max_connections_per_host {
+            Some(max) => self.active_hosts.get(&host_key(key)).map_or(0, |&n| n) >= max,
+            None => false
+        }
+    }
+
+    // Whether a connection to `key` can be handed out right now, by reusing
+    // an idle one, opening a new one, or closing an idle one to make room.
+    fn has_room(&self, key: &Key) -> bool {
+        !self.is_host_full(key) &&
+            (self.conns.contains_key(key) || !self.is_full() || !self.conns.is_empty())
+    }
+
+    // Waiters are served in the order they arrived. A connect only has to let
+    // the earlier waiters it competes with go first: those for the same host,
+    // and those that could be served right now. A waiter held back by the
+    // limit of its own host doesn't hold up other hosts.
+    fn is_next(&self, ticket: u64, key: &Key) -> bool {
+        for &(t, ref k) in self.waiting.iter() {
+            if t == ticket {
+                return true;
+            }
+            if host_key(k) == host_key(key) || self.has_room(k) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn stop_waiting(&mut self, ticket: u64) {
+        self.waiting.retain(|&(t, _)| t != ticket);
+    }
+
+    fn acquire(&mut self, key: &Key) {
+        self.active += 1;
+        *self.active_hosts.entry(host_key(key)).or_insert(0) += 1;
+    }
+
+    fn release(&mut self, key: &Key) {
+        self.active -= 1;
+        let host = host_key(key);
+        let none_left = match self.active_hosts.get_mut(&host) {
+            Some(n) => {
+                *n -= 1;
+                *n == 0
+            },
+            None => false
+        };
+        if none_left {
+            self.active_hosts.remove(&host);
         }
     }
 }
@@ -99,31 +344,84 @@ impl<S> PoolImpl<S> {
 impl<C: NetworkConnector<Stream=S>, S: NetworkStream + Send> NetworkConnector for Pool<C> {
     type Stream = PooledStream<S>;
     fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<PooledStream<S>> {
-        let key = key(host, port, scheme);
+        self.connect_keyed(key(host, port, scheme), host, port, scheme)
+    }
+    #[inline]
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.connector.set_ssl_verifier(verifier);
+    }
+}
+
+impl<C: NetworkConnector<Stream=S>, S: NetworkStream + Send> Pool<C> {
+    fn connect_keyed(&self, key: Key, host: &str, port: u16, scheme: &str)
+        -> ::Result<PooledStream<S>> {
         let mut locked = self.inner.lock().unwrap();
-        let mut should_remove = false;
-        let conn = match locked.conns.get_mut(&key) {
-            Some(ref mut vec) => {
+        let start = locked.clock.now();
+        let ticket = locked.next_ticket;
+        locked.next_ticket += 1;
+        let mut queued = false;
+        let reused;
+        loop {
+            locked.evict_expired();
+            if locked.is_next(ticket, &key) && locked.has_room(&key) {
+                reused = locked.take(&key);
+                if reused.is_none() && locked.is_full() {
+                    locked.evict_oldest();
+                }
+                break;
+            }
+            let timeout = match locked.config.on_limit {
+                LimitPolicy::OpenAnyway => {
+                    reused = locked.take(&key);
+                    break;
+                },
+                LimitPolicy::Wait(timeout) => timeout
+            };
+            let waited = locked.clock.now() - start;
+            if waited >= timeout {
+                locked.stop_waiting(ticket);
+                self.released.notify_all();
+                return Err(::Error::Timeout);
+            }
+            if !queued {
+                locked.waiting.push_back((ticket, key.clone()));
+                queued = true;
+            }
+            let remaining = (timeout - waited).num_milliseconds() as u32;
+            locked = self.released.wait_timeout_ms(locked, remaining + 1).unwrap().0;
+        }
+        if queued {
+            locked.stop_waiting(ticket);
+            // the waiters behind this one may be next now
+            self.released.notify_all();
+        }
+        locked.acquire(&key);
+        drop(locked);
+
+        let conn = match reused {
+            Some(conn) => {
                 trace!("Pool had connection, using");
-                should_remove = vec.len() == 1;
-                vec.pop().unwrap()
+                conn
+            },
+            None => match self.connector.connect(host, port, scheme) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    self.inner.lock().unwrap().release(&key);
+                    self.released.notify_all();
+                    return Err(e);
+                }
             }
-            _ => try!(self.connector.connect(host, port, scheme))
         };
-        if should_remove {
-            locked.conns.remove(&key);
-        }
         Ok(PooledStream {
             inner: Some((key, conn)),
             is_closed: false,
             is_drained: false,
-            pool: self.inner.clone()
+            is_cloned: Cell::new(false),
+            keep_alive: KeepAlive::default(),
+            pool: self.inner.clone(),
+            released: self.released.clone(),
         })
     }
-    #[inline]
-    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
-        self.connector.set_ssl_verifier(verifier);
-    }
 }
 
 /// A Stream that will try to be returned to the Pool when dropped.
@@ -131,7 +429,10 @@ pub struct PooledStream<S> {
     inner: Option<(Key, S)>,
     is_closed: bool,
     is_drained: bool,
-    pool: Arc<Mutex<PoolImpl<S>>>
+    is_cloned: Cell<bool>,
+    keep_alive: KeepAlive,
+    pool: Arc<Mutex<PoolImpl<S>>>,
+    released: Arc<Condvar>,
 }
 
 impl<S: NetworkStream> Read for PooledStream<S> {
@@ -170,30 +471,68 @@ impl<S: NetworkStream> NetworkStream for PooledStream<S> {
         self.is_closed = true;
         self.inner.as_mut().unwrap().1.close(how)
     }
+
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.inner.as_ref().unwrap().1.peer_certificate()
+    }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.inner.as_ref().unwrap().1.alpn_protocol()
+    }
+
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        self.inner.as_ref().unwrap().1.shutdown_handle()
+    }
+
+    /// The clone is not pooled. Once cloned, the connection is never
+    /// returned to the Pool, as the clone may still be using it.
+    #[inline]
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        let clone = try!(self.inner.as_ref().unwrap().1.try_clone());
+        self.is_cloned.set(true);
+        Ok(clone)
+    }
+
+    /// Remembered for when the connection is returned to the Pool, which
+    /// then keeps it only as long as the server said it would.
+    #[inline]
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.keep_alive = keep_alive;
+    }
 }
 
 impl<S> Drop for PooledStream<S> {
     fn drop(&mut self) {
-        trace!("PooledStream.drop, is_closed={}, is_drained={}", self.is_closed, self.is_drained);
-        if !self.is_closed && self.is_drained {
-            self.inner.take().map(|(key, conn)| {
-                if let Ok(mut pool) = self.pool.lock() {
-                    pool.reuse(key, conn);
-                }
-                // else poisoned, give up
-            });
+        trace!("PooledStream.drop, is_closed={}, is_drained={}, is_cloned={}",
+               self.is_closed, self.is_drained, self.is_cloned.get());
+        if let Ok(mut pool) = self.pool.lock() {
+            if let Some(&(ref key, _)) = self.inner.as_ref() {
+                pool.release(key);
+            }
+            if !self.is_closed && self.is_drained && !self.is_cloned.get() {
+                let keep_alive = self.keep_alive;
+                self.inner.take().map(|(key, conn)| pool.reuse(key, conn, keep_alive));
+            }
         }
+        // else poisoned, give up
+        self.released.notify_all();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::net::Shutdown;
+    use std::sync::{Arc, mpsc};
+    use time::{Duration, Timespec};
+    use clock::TestClock;
+    use header::KeepAlive;
     use mock::{MockConnector, ChannelMockConnector};
     use net::{NetworkConnector, NetworkStream};
-    use std::sync::mpsc;
 
-    use super::{Pool, key};
+    use super::{Pool, Config, LimitPolicy, key};
 
     macro_rules! mocked {
         () => ({
@@ -219,6 +558,223 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cloned_not_reused() {
+        let pool = mocked!();
+        let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        let _clone = stream.try_clone().unwrap();
+        stream.is_drained = true;
+        drop(stream);
+        assert!(pool.inner.lock().unwrap().conns.is_empty());
+    }
+
+    #[test]
+    fn test_affinity() {
+        let pool = Pool::with_connector(Config {
+            max_connections_per_host: Some(2),
+            on_limit: LimitPolicy::Wait(Duration::milliseconds(10)),
+            .. Default::default()
+        }, MockConnector);
+        let mut session = key("127.0.0.1", 3000, "http");
+        session.3 = Some("session".to_string());
+
+        pool.with_affinity("session").connect("127.0.0.1", 3000, "http").unwrap()
+            .is_drained = true;
+        {
+            let _plain = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            let locked = pool.inner.lock().unwrap();
+            assert_eq!(locked.conns.get(&session).unwrap().len(), 1);
+        }
+        let _first = pool.with_affinity("session").connect("127.0.0.1", 3000, "http").unwrap();
+        assert!(pool.inner.lock().unwrap().conns.get(&session).is_none());
+
+        // the host limit covers connections with any token
+        let _second = pool.with_affinity("other").connect("127.0.0.1", 3000, "http").unwrap();
+        match pool.connect("127.0.0.1", 3000, "http") {
+            Err(::Error::Timeout) => (),
+            other => panic!("expected timeout, got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let clock = Arc::new(TestClock::new(Timespec::new(0, 0)));
+        let mut pool = Pool::with_connector(Config {
+            idle_timeout: Some(Duration::seconds(90)),
+            .. Default::default()
+        }, MockConnector);
+        pool.set_clock(clock.clone());
+
+        pool.connect("127.0.0.1", 3000, "http").unwrap().is_drained = true;
+        clock.advance(Duration::seconds(60));
+        pool.connect("127.0.0.1", 3001, "http").unwrap().is_drained = true;
+        clock.advance(Duration::seconds(60));
+
+        // only the connection to 3001 is still young enough
+        pool.connect("127.0.0.1", 3002, "http").unwrap();
+        let locked = pool.inner.lock().unwrap();
+        assert!(locked.conns.get(&key("127.0.0.1", 3000, "http")).is_none());
+        assert_eq!(locked.conns.get(&key("127.0.0.1", 3001, "http")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_keep_alive_header() {
+        let clock = Arc::new(TestClock::new(Timespec::new(0, 0)));
+        let mut pool = mocked!();
+        pool.set_clock(clock.clone());
+
+        let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        stream.set_keep_alive(KeepAlive { timeout: Some(5), max: Some(10) });
+        stream.is_drained = true;
+        drop(stream);
+        let mut stream = pool.connect("127.0.0.1", 3001, "http").unwrap();
+        stream.set_keep_alive(KeepAlive { timeout: Some(30), max: Some(0) });
+        stream.is_drained = true;
+        drop(stream);
+        assert!(pool.inner.lock().unwrap().conns.get(&key("127.0.0.1", 3001, "http")).is_none());
+
+        clock.advance(Duration::seconds(3));
+        pool.connect("127.0.0.1", 3002, "http").unwrap();
+        assert_eq!(pool.inner.lock().unwrap().conns.len(), 1);
+
+        // within the last second of the server's timeout
+        clock.advance(Duration::seconds(1));
+        pool.connect("127.0.0.1", 3002, "http").unwrap();
+        assert_eq!(pool.inner.lock().unwrap().conns.len(), 0);
+    }
+
+    #[test]
+    fn test_max_connections_evicts_idle() {
+        let pool = Pool::with_connector(Config {
+            max_connections: Some(1),
+            on_limit: LimitPolicy::Wait(Duration::zero()),
+            .. Default::default()
+        }, MockConnector);
+        pool.connect("127.0.0.1", 3000, "http").unwrap().is_drained = true;
+        let _stream = pool.connect("127.0.0.1", 3001, "http").unwrap();
+        let locked = pool.inner.lock().unwrap();
+        assert_eq!(locked.conns.len(), 0);
+        assert_eq!(locked.active, 1);
+    }
+
+    #[test]
+    fn test_max_connections_wait() {
+        let pool = Pool::with_connector(Config {
+            max_connections: Some(1),
+            on_limit: LimitPolicy::Wait(Duration::milliseconds(10)),
+            .. Default::default()
+        }, MockConnector);
+        let stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        match pool.connect("127.0.0.1", 3001, "http") {
+            Err(::Error::Timeout) => (),
+            other => panic!("expected timeout, got {:?}", other.map(|_| ()))
+        }
+        drop(stream);
+        assert!(pool.connect("127.0.0.1", 3001, "http").is_ok());
+    }
+
+    #[test]
+    fn test_max_connections_wait_clock() {
+        use std::thread;
+
+        let clock = Arc::new(TestClock::new(Timespec::new(0, 0)));
+        let mut pool = Pool::with_connector(Config {
+            max_connections: Some(1),
+            on_limit: LimitPolicy::Wait(Duration::milliseconds(10)),
+            .. Default::default()
+        }, MockConnector);
+        pool.set_clock(clock.clone());
+        let pool = Arc::new(pool);
+        let stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+
+        // the clock doesn't move, so the wait never times out
+        let waiter = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.connect("127.0.0.1", 3001, "http").map(|_| ()))
+        };
+        thread::sleep_ms(50);
+        drop(stream);
+        assert!(waiter.join().unwrap().is_ok());
+
+        let _stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        let waiter = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.connect("127.0.0.1", 3001, "http").map(|_| ()))
+        };
+        while pool.inner.lock().unwrap().waiting.is_empty() {
+            thread::yield_now();
+        }
+        clock.advance(Duration::milliseconds(10));
+        match waiter.join().unwrap() {
+            Err(::Error::Timeout) => (),
+            other => panic!("expected timeout, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_max_connections_per_host() {
+        let pool = Pool::with_connector(Config {
+            max_connections_per_host: Some(1),
+            on_limit: LimitPolicy::Wait(Duration::milliseconds(10)),
+            .. Default::default()
+        }, MockConnector);
+        let stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        assert!(pool.connect("127.0.0.1", 3001, "http").is_ok());
+        match pool.connect("127.0.0.1", 3000, "http") {
+            Err(::Error::Timeout) => (),
+            other => panic!("expected timeout, got {:?}", other.map(|_| ()))
+        }
+        drop(stream);
+        assert!(pool.connect("127.0.0.1", 3000, "http").is_ok());
+    }
+
+    #[test]
+    fn test_waiters_served_in_order() {
+        use std::thread;
+
+        let pool = Arc::new(Pool::with_connector(Config {
+            max_connections: Some(1),
+            on_limit: LimitPolicy::Wait(Duration::seconds(5)),
+            .. Default::default()
+        }, MockConnector));
+        let stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+
+        // waits until `n` threads are queued
+        let queued = |n| while pool.inner.lock().unwrap().waiting.len() < n {
+            thread::yield_now();
+        };
+        let (tx, rx) = mpsc::channel();
+        let waiters = (0..3).map(|i| {
+            let pool = pool.clone();
+            let tx = tx.clone();
+            let waiter = thread::spawn(move || {
+                let stream = pool.connect("127.0.0.1", 3001 + i, "http").unwrap();
+                tx.send(i).unwrap();
+                drop(stream);
+            });
+            // let each waiter queue up before the next one starts
+            queued(i as usize + 1);
+            waiter
+        }).collect::<Vec<_>>();
+        drop(stream);
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(rx.iter().take(3).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_max_connections_open_anyway() {
+        let pool = Pool::with_connector(Config {
+            max_connections: Some(1),
+            .. Default::default()
+        }, MockConnector);
+        let _first = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        let _second = pool.connect("127.0.0.1", 3001, "http").unwrap();
+        assert_eq!(pool.inner.lock().unwrap().active, 2);
+    }
+
     #[test]
     fn test_closed() {
         let pool = mocked!();
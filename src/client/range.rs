@@ -0,0 +1,240 @@
+//! Random access to a remote file, through `Range` requests.
+//!
+//! A `RangedReader` reads a resource from a server that answers with
+//! `Accept-Ranges: bytes` as if it were a local file. Each read that isn't
+//! already buffered asks the server for just the bytes it needs, and some
+//! more after them, so that a format with an index at the end, such as a
+//! zip archive, can be read without downloading all of it.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use std::io::{Read, Seek, SeekFrom};
+//! use hyper::client::range::RangedReader;
+//!
+//! let mut client = Client::new();
+//! let mut archive = RangedReader::new(&mut client, "http://example.domain/huge.zip").unwrap();
+//! // the end of central directory record is in the last 22 bytes
+//! archive.seek(SeekFrom::End(-22)).unwrap();
+//! let mut record = [0; 22];
+//! archive.read(&mut record).unwrap();
+//! ```
+use std::cmp::min;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use header::{AcceptRanges, ContentLength, Headers, RangeUnit};
+use status::StatusCode;
+use Url;
+
+use super::{Client, IntoUrl};
+
+/// The default number of bytes asked for in each request.
+pub const DEFAULT_READAHEAD: usize = 64 * 1024;
+
+/// A `Read` and `Seek` over a remote resource, fetched in ranges as needed.
+pub struct RangedReader<'a> {
+    client: &'a mut Client,
+    url: Url,
+    len: u64,
+    etag: Option<Vec<Vec<u8>>>,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+    readahead: usize,
+}
+
+impl<'a> RangedReader<'a> {
+    /// Open the resource at `url` with a `HEAD` request.
+    ///
+    /// Fails with an `InvalidInput` error if the server doesn't say it
+    /// accepts byte ranges, or doesn't give the length of the resource.
+    pub fn new<U: IntoUrl>(client: &'a mut Client, url: U) -> ::Result<RangedReader<'a>> {
+        let url = try!(url.into_url());
+        let res = try!(client.head(url.clone()).send());
+        let ranges = match res.headers.get::<AcceptRanges>() {
+            Some(&AcceptRanges(ref units)) => units.contains(&RangeUnit::Bytes),
+            None => false
+        };
+        let len = match res.headers.get::<ContentLength>() {
+            Some(&ContentLength(len)) if ranges => len,
+            _ => return Err(::Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                       "server does not serve byte ranges")))
+        };
+        Ok(RangedReader {
+            client: client,
+            url: url,
+            len: len,
+            etag: res.headers.get_raw("ETag").map(|etag| etag.to_vec()),
+            pos: 0,
+            buf: Vec::new(),
+            buf_start: 0,
+            readahead: DEFAULT_READAHEAD,
+        })
+    }
+
+    /// Set how many bytes to ask for at once, at least. The default is
+    /// `DEFAULT_READAHEAD`.
+    pub fn set_readahead(&mut self, readahead: usize) {
+        self.readahead = readahead;
+    }
+
+    /// The length of the resource, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    // Fetches the range starting at `pos`, of at least `want` bytes if
+    // there are that many left.
+    fn fetch(&mut self, want: usize) -> io::Result<()> {
+        let size = ::std::cmp::max(want, self.readahead) as u64;
+        let last = min(self.pos.saturating_add(size), self.len) - 1;
+        debug!("fetching bytes {}-{} of {}", self.pos, last, self.url);
+
+        let mut headers = Headers::new();
+        headers.set_raw("Range", vec![format!("bytes={}-{}", self.pos, last).into_bytes()]);
+        if let Some(ref etag) = self.etag {
+            // a changed resource is sent whole, instead of a range of it
+            headers.set_raw("If-Range", etag.clone());
+        }
+        let mut res = try!(self.client.get(self.url.clone()).headers(headers).send()
+                               .map_err(into_io));
+
+        let expected = format!("bytes {}-{}/", self.pos, last);
+        let matches = res.headers.get_raw("Content-Range")
+            .map_or(false, |raw| raw.len() == 1 && raw[0].starts_with(expected.as_bytes()));
+        if res.status != StatusCode::PartialContent || !matches {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("expected {}, got {}", expected, res.status)));
+        }
+
+        let mut buf = Vec::with_capacity((last - self.pos + 1) as usize);
+        try!(res.read_to_end(&mut buf));
+        if buf.len() as u64 != last - self.pos + 1 {
+            return Err(io::Error::new(io::ErrorKind::Other, "range ended early"));
+        }
+        self.buf = buf;
+        self.buf_start = self.pos;
+        Ok(())
+    }
+}
+
+fn into_io(e: ::Error) -> io::Error {
+    match e {
+        ::Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+impl<'a> Read for RangedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let buf_end = self.buf_start + self.buf.len() as u64;
+        if self.pos < self.buf_start || self.pos >= buf_end {
+            try!(self.fetch(buf.len()));
+        }
+        let offset = (self.pos - self.buf_start) as usize;
+        let n = try!((&self.buf[offset..]).read(buf));
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for RangedReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(offset) => self.len as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek to before the start of the resource"));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+    use std::str;
+    use std::sync::{Arc, Mutex};
+
+    use client::Client;
+    use method::Method;
+    use server::{Handler, Request, Response, Server};
+    use status::StatusCode;
+    use super::RangedReader;
+
+    const FILE: &'static [u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    // Serves FILE in ranges, and records the ranges asked for.
+    struct Ranges(Arc<Mutex<Vec<String>>>);
+
+    impl Handler for Ranges {
+        fn handle(&self, req: Request, mut res: Response) {
+            res.headers_mut().set_raw("Accept-Ranges", vec![b"bytes".to_vec()]);
+            res.headers_mut().set_raw("ETag", vec![b"\"v1\"".to_vec()]);
+            if req.method == Method::Head {
+                res.headers_mut().set_raw("Content-Length",
+                                          vec![FILE.len().to_string().into_bytes()]);
+                res.start().unwrap().end().unwrap();
+                return;
+            }
+            let range = str::from_utf8(&req.headers.get_raw("Range").unwrap()[0]).unwrap()
+                .to_string();
+            assert_eq!(req.headers.get_raw("If-Range"), Some(&[b"\"v1\"".to_vec()][..]));
+            let (first, last) = {
+                let mut bounds = range["bytes=".len()..].split('-')
+                    .map(|n| n.parse::<usize>().unwrap());
+                (bounds.next().unwrap(), bounds.next().unwrap())
+            };
+            self.0.lock().unwrap().push(range.clone());
+            *res.status_mut() = StatusCode::PartialContent;
+            res.headers_mut().set_raw("Content-Range",
+                vec![format!("bytes {}-{}/{}", first, last, FILE.len()).into_bytes()]);
+            res.send(&FILE[first..last + 1]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ranged_reader() {
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let mut listening = Server::http(Ranges(ranges.clone()))
+            .listen_threads("127.0.0.1:0", 1).unwrap();
+        let url = format!("http://{}/file", listening.socket);
+        let mut client = Client::new();
+        {
+            let mut file = RangedReader::new(&mut client, &*url).unwrap();
+            file.set_readahead(8);
+            assert_eq!(file.len(), 36);
+
+            let mut buf = [0; 4];
+            assert_eq!(file.seek(SeekFrom::End(-6)).unwrap(), 30);
+            assert_eq!(file.read(&mut buf).unwrap(), 4);
+            assert_eq!(&buf, b"uvwx");
+            // the rest of the range was kept
+            assert_eq!(file.read(&mut buf).unwrap(), 2);
+            assert_eq!(&buf[..2], b"yz");
+            assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+            assert_eq!(file.seek(SeekFrom::Start(2)).unwrap(), 2);
+            assert_eq!(file.read(&mut buf).unwrap(), 4);
+            assert_eq!(&buf, b"2345");
+            assert_eq!(file.seek(SeekFrom::Current(-1)).unwrap(), 5);
+            assert_eq!(file.read(&mut buf).unwrap(), 4);
+            assert_eq!(&buf, b"5678");
+            assert!(file.seek(SeekFrom::Current(-10)).is_err());
+
+            let mut big = [0; 12];
+            assert_eq!(file.read(&mut big).unwrap(), 1);
+            assert_eq!(file.read(&mut big).unwrap(), 12);
+            assert_eq!(&big, b"abcdefghijkl");
+        }
+        assert_eq!(*ranges.lock().unwrap(),
+                   vec!["bytes=30-35", "bytes=2-9", "bytes=10-21"]);
+        listening.close().unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! Record and replay connections.
+//!
+//! A `ReplayConnector` wraps another `NetworkConnector`. The first time a
+//! connection is made, every byte written to and read from the stream is
+//! recorded into a "cassette" directory. Once a recording exists, it is
+//! replayed instead, and the wrapped connector is never touched.
+//!
+//! A replayed connection checks that the client writes the same request
+//! that was recorded. If it doesn't, such as when requests are sent in a
+//! different order, writing or reading fails with an `InvalidInput` error
+//! naming the recording, rather than answering with the wrong response.
+//!
+//! This makes tests of code built on the `Client` deterministic, and lets
+//! them run without a network.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! # use hyper::net::HttpConnector;
+//! use hyper::client::replay::ReplayConnector;
+//!
+//! let connector = ReplayConnector::new(HttpConnector(None), "tests/cassettes");
+//! let mut client = Client::with_connector(connector);
+//! let res = client.get("http://example.domain").send().unwrap();
+//! ```
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, Cursor, ErrorKind};
+use std::net::{SocketAddr, Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use header::KeepAlive;
+use net::{NetworkConnector, NetworkStream, ContextVerifier};
+
+/// A `NetworkConnector` that records connections, and replays them later.
+pub struct ReplayConnector<C> {
+    connector: C,
+    dir: PathBuf,
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+impl<C: NetworkConnector> ReplayConnector<C> {
+    /// Creates a `ReplayConnector` that keeps its recordings in `dir`.
+    ///
+    /// The directory is created when the first recording is saved.
+    pub fn new<P: AsRef<Path>>(connector: C, dir: P) -> ReplayConnector<C> {
+        ReplayConnector {
+            connector: connector,
+            dir: dir.as_ref().to_path_buf(),
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The directory where recordings are kept.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Each connection to the same host gets its own numbered recording, so
+    // that a sequence of requests replays in the order it was recorded.
+    fn next_name(&self, host: &str, port: u16, scheme: &str) -> String {
+        let key = format!("{}_{}_{}", scheme, host.replace(':', "_"), port);
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(key.clone()).or_insert(0);
+        let name = format!("{}_{}", key, *count);
+        *count += 1;
+        name
+    }
+}
+
+impl<C: NetworkConnector<Stream=S>, S: NetworkStream + Send> NetworkConnector for ReplayConnector<C> {
+    type Stream = ReplayStream<S>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<ReplayStream<S>> {
+        let name = self.next_name(host, port, scheme);
+        let response = self.dir.join(format!("{}.response", name));
+        if fs::metadata(&response).is_ok() {
+            debug!("replaying {:?}", response);
+            let request = self.dir.join(format!("{}.request", name));
+            let mut recorded = Vec::new();
+            try!(try!(File::open(&request)).read_to_end(&mut recorded));
+            let mut replayed = Vec::new();
+            try!(try!(File::open(&response)).read_to_end(&mut replayed));
+            Ok(ReplayStream::Replay(Playback {
+                name: name,
+                request: recorded,
+                written: 0,
+                response: Cursor::new(replayed),
+            }))
+        } else {
+            debug!("recording {:?}", name);
+            let stream = try!(self.connector.connect(host, port, scheme));
+            Ok(ReplayStream::Record(Recording {
+                stream: stream,
+                dir: self.dir.clone(),
+                name: name,
+                request: Vec::new(),
+                response: Vec::new(),
+            }))
+        }
+    }
+
+    #[inline]
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.connector.set_ssl_verifier(verifier);
+    }
+}
+
+/// A stream that is either being recorded, or replayed from a recording.
+pub enum ReplayStream<S> {
+    /// A live stream, recording everything passing through it.
+    Record(Recording<S>),
+    /// A previously recorded exchange, checking what is written against
+    /// the recorded request.
+    Replay(Playback),
+}
+
+/// A live stream whose exchange is saved when it is dropped.
+pub struct Recording<S> {
+    stream: S,
+    dir: PathBuf,
+    name: String,
+    request: Vec<u8>,
+    response: Vec<u8>,
+}
+
+impl<S> Recording<S> {
+    fn save(&self) -> io::Result<()> {
+        try!(fs::create_dir_all(&self.dir));
+        let request = self.dir.join(format!("{}.request", self.name));
+        try!(try!(File::create(request)).write_all(&self.request));
+        let response = self.dir.join(format!("{}.response", self.name));
+        try!(try!(File::create(response)).write_all(&self.response));
+        Ok(())
+    }
+}
+
+/// A recorded exchange being played back.
+pub struct Playback {
+    name: String,
+    request: Vec<u8>,
+    written: usize,
+    response: Cursor<Vec<u8>>,
+}
+
+impl Playback {
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        let end = self.written + msg.len();
+        if end > self.request.len() || &self.request[self.written..end] != msg {
+            return Err(self.mismatch());
+        }
+        self.written = end;
+        Ok(msg.len())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.written < self.request.len() {
+            return Err(self.mismatch());
+        }
+        self.response.read(buf)
+    }
+
+    fn mismatch(&self) -> io::Error {
+        io::Error::new(ErrorKind::InvalidInput,
+                       format!("request doesn't match recording {:?}", self.name))
+    }
+}
+
+impl<S> Drop for Recording<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            error!("failed to save recording {:?}: {:?}", self.name, e);
+        }
+    }
+}
+
+impl<S: NetworkStream> Read for ReplayStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            ReplayStream::Record(ref mut rec) => {
+                let n = try!(rec.stream.read(buf));
+                rec.response.extend(buf[..n].iter().cloned());
+                Ok(n)
+            },
+            ReplayStream::Replay(ref mut playback) => playback.read(buf),
+        }
+    }
+}
+
+impl<S: NetworkStream> Write for ReplayStream<S> {
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        match *self {
+            ReplayStream::Record(ref mut rec) => {
+                let n = try!(rec.stream.write(msg));
+                rec.request.extend(msg[..n].iter().cloned());
+                Ok(n)
+            },
+            ReplayStream::Replay(ref mut playback) => playback.write(msg),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            ReplayStream::Record(ref mut rec) => rec.stream.flush(),
+            ReplayStream::Replay(..) => Ok(()),
+        }
+    }
+}
+
+impl<S: NetworkStream> NetworkStream for ReplayStream<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        match *self {
+            ReplayStream::Record(ref mut rec) => rec.stream.peer_addr(),
+            ReplayStream::Replay(..) => Ok("127.0.0.1:0".parse().unwrap()),
+        }
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        match *self {
+            ReplayStream::Record(ref mut rec) => rec.stream.close(how),
+            ReplayStream::Replay(..) => Ok(()),
+        }
+    }
+
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        match *self {
+            ReplayStream::Record(ref rec) => rec.stream.peer_certificate(),
+            ReplayStream::Replay(..) => None,
+        }
+    }
+
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match *self {
+            ReplayStream::Record(ref rec) => rec.stream.alpn_protocol(),
+            ReplayStream::Replay(..) => None,
+        }
+    }
+
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        match *self {
+            ReplayStream::Record(ref rec) => rec.stream.shutdown_handle(),
+            ReplayStream::Replay(..) => None,
+        }
+    }
+
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        if let ReplayStream::Record(ref mut rec) = *self {
+            rec.stream.set_keep_alive(keep_alive)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+
+    use client::Client;
+    use header::Server;
+    use mock::MockConnector;
+    use super::ReplayConnector;
+
+    mock_connector!(MockRecorded {
+        "http://127.0.0.1" =>       "HTTP/1.1 200 OK\r\n\
+                                     Server: recorded\r\n\
+                                     Content-Length: 5\r\n\
+                                     \r\n\
+                                     hello"
+    });
+
+    #[test]
+    fn test_record_then_replay() {
+        let dir = env::temp_dir().join("hyper-test-record-then-replay");
+        let _ = fs::remove_dir_all(&dir);
+
+        {
+            let mut client = Client::with_connector(ReplayConnector::new(MockRecorded, &dir));
+            let mut res = client.get("http://127.0.0.1").send().unwrap();
+            let mut body = String::new();
+            res.read_to_string(&mut body).unwrap();
+            assert_eq!(body, "hello");
+        }
+        assert!(fs::metadata(dir.join("http_127.0.0.1_80_0.request")).is_ok());
+        assert!(fs::metadata(dir.join("http_127.0.0.1_80_0.response")).is_ok());
+
+        // MockConnector would answer with an empty stream, so a successful
+        // response proves the recording was used.
+        let mut client = Client::with_connector(ReplayConnector::new(MockConnector, &dir));
+        let mut res = client.get("http://127.0.0.1").send().unwrap();
+        assert_eq!(res.headers.get(), Some(&Server("recorded".to_string())));
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_replay_other_request() {
+        use std::io::ErrorKind;
+        use Error;
+
+        let dir = env::temp_dir().join("hyper-test-replay-other-request");
+        let _ = fs::remove_dir_all(&dir);
+
+        {
+            let mut client = Client::with_connector(ReplayConnector::new(MockRecorded, &dir));
+            client.get("http://127.0.0.1/a").send().unwrap();
+        }
+
+        let mut client = Client::with_connector(ReplayConnector::new(MockConnector, &dir));
+        match client.get("http://127.0.0.1/b").send() {
+            Err(Error::Io(ref e)) if e.kind() == ErrorKind::InvalidInput => (),
+            other => panic!("expected a mismatch, got {:?}", other.map(|res| res.status))
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
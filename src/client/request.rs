@@ -1,30 +1,41 @@
 //! Client Requests
+use std::cell::RefCell;
 use std::marker::PhantomData;
-use std::io::{self, Write, BufWriter};
+use std::io::{self, Read, Write, BufWriter};
+use std::mem;
+use std::net::{SocketAddr, Shutdown, TcpStream};
+use std::sync::Arc;
 
+use time::Duration;
 use url::Url;
 
+use clock::{Clock, SystemClock};
 use method::{self, Method};
 use header::Headers;
 use header::{self, Host};
 use net::{NetworkStream, NetworkConnector, HttpConnector, Fresh, Streaming};
-use http::{HttpWriter, LINE_ENDING};
-use http::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter, EmptyWriter};
+use http::{Chunker, HttpWriter, LINE_ENDING};
+use http::HttpWriter::{ThroughWriter, ChunkedWriter, ConfiguredChunkedWriter, SizedWriter,
+                       EmptyWriter};
 use version;
 use client::{Response, get_host_and_port};
 
 
 /// A client request to a remote server.
 pub struct Request<W> {
-    /// The target URI for this request.
-    pub url: Url,
-
     /// The HTTP version of this request.
     pub version: version::HttpVersion,
 
+    url: Url,
+    // The request target, serialized from `url` and `form` when the head is
+    // written. Changing either clears it.
+    target: Option<String>,
+    form: Form,
+    unfold_headers: bool,
     body: HttpWriter<BufWriter<Box<NetworkStream + Send>>>,
     headers: Headers,
     method: method::Method,
+    chunker: Option<Chunker>,
 
     _marker: PhantomData<W>,
 }
@@ -36,7 +47,16 @@ impl<W> Request<W> {
 
     /// Read the Request method.
     #[inline]
-    pub fn method(&self) -> method::Method { self.method.clone() }
+    pub fn method(&self) -> &method::Method { &self.method }
+
+    /// Read a copy of the Request method, as `method` used to return.
+    #[deprecated(since = "0.5.0", note = "use `method().clone()` instead")]
+    #[inline]
+    pub fn cloned_method(&self) -> method::Method { self.method.clone() }
+
+    /// Read the target URI of the Request.
+    #[inline]
+    pub fn url(&self) -> &Url { &self.url }
 }
 
 impl Request<Fresh> {
@@ -47,114 +67,575 @@ impl Request<Fresh> {
     }
 
     /// Create a new client request with a specific underlying NetworkStream.
+    ///
+    /// The `Host` header is set from the URL, leaving out the port if it is
+    /// the default for the scheme. To send a different `Host`, set it with
+    /// `headers_mut` before starting the Request.
     pub fn with_connector<C, S>(method: method::Method, url: Url, connector: &C)
         -> ::Result<Request<Fresh>> where
         C: NetworkConnector<Stream=S>,
         S: Into<Box<NetworkStream + Send>> {
         let (host, port) = try!(get_host_and_port(&url));
-
         let stream = try!(connector.connect(&*host, port, &*url.scheme)).into();
-        let stream = ThroughWriter(BufWriter::new(stream));
+        Ok(Request::with_stream(method, url, host, port, stream))
+    }
 
+    /// Create a new client request that is not connected to any server.
+    ///
+    /// A detached Request can be serialized with `write_to`, but writing
+    /// to its connection, such as with `start`, will fail.
+    pub fn detached(method: method::Method, url: Url) -> ::Result<Request<Fresh>> {
+        let (host, port) = try!(get_host_and_port(&url));
+        Ok(Request::with_stream(method, url, host, port, Box::new(Detached)))
+    }
+
+    fn with_stream(method: method::Method, url: Url, host: String, port: u16,
+                   stream: Box<NetworkStream + Send>) -> Request<Fresh> {
+        let port = match (&*url.scheme, port) {
+            ("http", 80) | ("https", 443) => None,
+            (_, port) => Some(port)
+        };
         let mut headers = Headers::new();
         headers.set(Host {
             hostname: host,
-            port: Some(port),
+            port: port,
         });
 
-        Ok(Request {
+        Request {
             method: method,
             headers: headers,
+            target: None,
+            form: Form::Origin,
+            unfold_headers: true,
             url: url,
             version: version::HttpVersion::Http11,
-            body: stream,
+            body: ThroughWriter(BufWriter::new(stream)),
+            chunker: None,
             _marker: PhantomData,
-        })
+        }
     }
 
     /// Consume a Fresh Request, writing the headers and method,
     /// returning a Streaming Request.
+    ///
+    /// Nothing is sent until the buffer fills, or the Request is sent. A
+    /// body with a known length of up to 64KB is buffered along with the
+    /// head, so a small request goes out in a single write.
     pub fn start(mut self) -> ::Result<Request<Streaming>> {
-        let mut uri = self.url.serialize_path().unwrap();
-        //TODO: this needs a test
-        if let Some(ref q) = self.url.query {
-            uri.push('?');
-            uri.push_str(&q[..]);
-        }
-
-        debug!("request line: {:?} {:?} {:?}", self.method, uri, self.version);
-        try!(write!(&mut self.body, "{} {} {}{}",
-                    self.method, uri, self.version, LINE_ENDING));
-
-
-        let stream = match self.method {
-            Method::Get | Method::Head => {
-                debug!("headers={:?}", self.headers);
-                try!(write!(&mut self.body, "{}{}", self.headers, LINE_ENDING));
-                EmptyWriter(self.body.into_inner())
-            },
-            _ => {
-                let mut chunked = true;
-                let mut len = 0;
-
-                match self.headers.get::<header::ContentLength>() {
-                    Some(cl) => {
-                        chunked = false;
-                        len = **cl;
-                    },
-                    None => ()
-                };
-
-                // can't do in match above, thanks borrowck
-                if chunked {
-                    let encodings = match self.headers.get_mut::<header::TransferEncoding>() {
-                        Some(&mut header::TransferEncoding(ref mut encodings)) => {
-                            //TODO: check if chunked is already in encodings. use HashSet?
-                            encodings.push(header::Encoding::Chunked);
-                            false
-                        },
-                        None => true
-                    };
-
-                    if encodings {
-                        self.headers.set::<header::TransferEncoding>(
-                            header::TransferEncoding(vec![header::Encoding::Chunked]))
-                    }
-                }
-
-                debug!("headers={:?}", self.headers);
-                try!(write!(&mut self.body, "{}{}", self.headers, LINE_ENDING));
-
-                if chunked {
-                    ChunkedWriter(self.body.into_inner())
-                } else {
-                    SizedWriter(self.body.into_inner(), len)
-                }
+        let mut stream = self.body.into_inner();
+        if let Some(&header::ContentLength(len)) = self.headers.get() {
+            if len <= MAX_COALESCED_BODY {
+                let raw = try!(stream.into_inner().map_err(io::Error::from));
+                stream = BufWriter::with_capacity(HEAD_BUFFER_SIZE + len as usize, raw);
             }
-        };
+        }
+        let target = self.target.take().unwrap_or_else(|| request_target(&self.url, self.form));
+        let body = try!(write_head(&self.method, &target, self.version,
+                                   &mut self.headers, &mut stream));
+        let stream = body.writer(stream, self.chunker.take());
 
         Ok(Request {
             method: self.method,
             headers: self.headers,
             url: self.url,
+            target: Some(target),
+            form: self.form,
+            unfold_headers: self.unfold_headers,
             version: self.version,
             body: stream,
+            chunker: None,
             _marker: PhantomData,
         })
     }
 
+    /// Consume a Fresh Request, writing the headers and method into `w`
+    /// instead of the connection.
+    ///
+    /// The returned writer frames the body exactly as a Streaming Request
+    /// would, so once it is ended, `w` holds the wire bytes of the whole
+    /// request. This is most useful with a `detached` Request.
+    ///
+    /// ```
+    /// # use hyper::client::Request;
+    /// # use hyper::Post;
+    /// # use hyper::header::ContentLength;
+    /// # use hyper::Url;
+    /// use std::io::Write;
+    ///
+    /// let url = Url::parse("http://example.domain/").unwrap();
+    /// let mut req = Request::detached(Post, url).unwrap();
+    /// req.headers_mut().set(ContentLength(3));
+    /// let mut body = req.write_to(Vec::new()).unwrap();
+    /// body.write_all(b"foo").unwrap();
+    /// let bytes = body.end().unwrap();
+    /// assert!(bytes.starts_with(b"POST / HTTP/1.1\r\n"));
+    /// ```
+    pub fn write_to<T: Write>(mut self, mut w: T) -> ::Result<HttpWriter<T>> {
+        let target = self.target.take().unwrap_or_else(|| request_target(&self.url, self.form));
+        let body = try!(write_head(&self.method, &target, self.version,
+                                   &mut self.headers, &mut w));
+        Ok(body.writer(w, self.chunker.take()))
+    }
+
+    /// Send the Request without a body, and wait for the response.
+    ///
+    /// A body that `start` doesn't know the length of is sent chunked, even
+    /// if nothing is ever written to it, which confuses some servers. A
+    /// `DELETE` or an empty `POST` sent this way has `Content-Length: 0`
+    /// instead.
+    pub fn send(mut self) -> ::Result<Response> {
+        self.without_body();
+        try!(self.start()).send()
+    }
+
+    fn without_body(&mut self) {
+        self.headers.remove::<header::TransferEncoding>();
+        if self.method.allows_body() {
+            self.headers.set(header::ContentLength(0));
+        } else {
+            self.headers.remove::<header::ContentLength>();
+        }
+    }
+
     /// Get a mutable reference to the Request headers.
     #[inline]
     pub fn headers_mut(&mut self) -> &mut Headers { &mut self.headers }
+
+    /// Split a chunked body into chunks with `chunker`, instead of sending
+    /// each write as a chunk of its own.
+    pub fn set_chunker(&mut self, chunker: Chunker) {
+        self.chunker = Some(chunker);
+    }
+
+    /// Change the target URI of the Request.
+    ///
+    /// The Request stays on the connection it was created with, and keeps
+    /// its `Host` header, so the new URI should be on the same server.
+    pub fn set_url(&mut self, url: Url) {
+        self.url = url;
+        self.target = None;
+    }
+
+    /// Send the whole URI as the request target, such as
+    /// `GET http://example.domain/path HTTP/1.1`, instead of only the path
+    /// and query.
+    ///
+    /// This absolute form is needed when sending plain HTTP requests
+    /// through a forward proxy.
+    pub fn set_absolute_form(&mut self, absolute: bool) {
+        self.form = if absolute { Form::Absolute } else { Form::Origin };
+        self.target = None;
+    }
+
+    /// Send `*` as the request target, addressing the server as a whole
+    /// rather than one of its resources.
+    ///
+    /// This asterisk form is only allowed for a server-wide `OPTIONS`
+    /// request, such as `OPTIONS * HTTP/1.1`.
+    pub fn set_asterisk_form(&mut self) {
+        self.form = Form::Asterisk;
+        self.target = None;
+    }
+
+    /// Whether header values the response folds over several lines
+    /// (obs-fold), as some legacy servers do, are unfolded into one.
+    /// Otherwise the response fails to parse.
+    ///
+    /// The default is to unfold them, as RFC 7230 asks of user agents.
+    pub fn set_unfold_headers(&mut self, unfold: bool) {
+        self.unfold_headers = unfold;
+    }
 }
 
 impl Request<Streaming> {
     /// Completes writing the request, and returns a response to read from.
     ///
     /// Consumes the Request.
-    pub fn send(self) -> ::Result<Response> {
+    pub fn send(mut self) -> ::Result<Response> {
+        if let Some(flow) = self.body.get_mut().get_mut().downcast_mut::<FlowControl>() {
+            flow.corked = false;
+        }
         let raw = try!(self.body.end()).into_inner().unwrap(); // end() already flushes
-        Response::new(raw)
+        Response::with_unfolding(raw, &self.method, self.unfold_headers)
+    }
+
+    /// Hold back everything written from now on, until `uncork` is called
+    /// or the Request is sent.
+    ///
+    /// While corked, nothing reaches the connection, even when flushed, so
+    /// that many small writes can go out together.
+    pub fn cork(&mut self) {
+        self.flow().corked = true;
+    }
+
+    /// Send everything held back since `cork`, and flush it.
+    pub fn uncork(&mut self) -> io::Result<()> {
+        self.flow().corked = false;
+        self.body.flush()
+    }
+
+    /// Call `f` whenever a write to the connection blocks for at least
+    /// `threshold`, with how long it blocked.
+    ///
+    /// A write only blocks for long once the send buffer of the connection
+    /// is full, meaning the body is being produced faster than the network
+    /// takes it. The callback is the place to slow the producer down.
+    pub fn on_blocked<F>(&mut self, threshold: Duration, f: F)
+    where F: FnMut(Duration) + Send + 'static {
+        self.flow().blocked = Some((threshold, Box::new(f)));
+    }
+
+    /// Set the `Clock` used to measure how long writes block, for
+    /// `on_blocked`.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.flow().clock = Arc::new(clock);
+    }
+
+    // The connection is only wrapped once flow control is first asked for.
+    fn flow(&mut self) -> &mut FlowControl {
+        let stream = self.body.get_mut().get_mut();
+        if !stream.is::<FlowControl>() {
+            let inner = mem::replace(stream, Box::new(Detached));
+            *stream = Box::new(FlowControl {
+                inner: inner,
+                corked: false,
+                held: Vec::new(),
+                blocked: None,
+                clock: Arc::new(SystemClock),
+            });
+        }
+        stream.downcast_mut::<FlowControl>().unwrap()
+    }
+
+    /// Split the Request into a writer for the rest of its body, and a
+    /// handle to read the response with, so that both can proceed at once
+    /// from different threads.
+    ///
+    /// This is for servers that start answering before the request body is
+    /// done, such as with long-lived tunnels. The head is flushed first, so
+    /// the server sees the request right away. The connection needs to
+    /// support `NetworkStream::try_clone`, and is never reused.
+    pub fn split(mut self) -> ::Result<(RequestWriter, PendingResponse)> {
+        try!(self.body.flush());
+        let reader = try!(self.body.get_ref().get_ref().try_clone());
+        Ok((RequestWriter { body: self.body }, PendingResponse {
+            stream: reader,
+            method: self.method,
+            unfold_headers: self.unfold_headers,
+        }))
+    }
+}
+
+/// The body of a Request that has been `split`.
+pub struct RequestWriter {
+    body: HttpWriter<BufWriter<Box<NetworkStream + Send>>>,
+}
+
+impl RequestWriter {
+    /// Finish the body, and flush it.
+    pub fn end(self) -> ::Result<()> {
+        try!(self.body.end());
+        Ok(())
+    }
+}
+
+impl Write for RequestWriter {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        self.body.write(msg)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.body.flush()
+    }
+}
+
+/// The response to a Request that has been `split`.
+pub struct PendingResponse {
+    stream: Box<NetworkStream + Send>,
+    method: Method,
+    unfold_headers: bool,
+}
+
+impl PendingResponse {
+    /// Wait for the head of the response.
+    pub fn response(self) -> ::Result<Response> {
+        Response::with_unfolding(self.stream, &self.method, self.unfold_headers)
+    }
+}
+
+// The largest body that `start` will buffer to send with the head, and the
+// room left for the head itself when it does.
+const MAX_COALESCED_BODY: u64 = 64 * 1024;
+const HEAD_BUFFER_SIZE: usize = 8 * 1024;
+
+// The kind of body a Request will have, decided while writing its head.
+enum Body {
+    Empty,
+    Chunked,
+    Sized(u64),
+}
+
+impl Body {
+    fn writer<W: Write>(self, w: W, chunker: Option<Chunker>) -> HttpWriter<W> {
+        match (self, chunker) {
+            (Body::Empty, _) => EmptyWriter(w),
+            (Body::Chunked, Some(chunker)) => ConfiguredChunkedWriter(w, chunker),
+            (Body::Chunked, None) => ChunkedWriter(w),
+            (Body::Sized(len), _) => SizedWriter(w, len),
+        }
+    }
+}
+
+thread_local!(static HEAD: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(512)));
+
+fn write_head<W: Write>(method: &Method, target: &str, version: version::HttpVersion,
+                        headers: &mut Headers, w: &mut W) -> ::Result<Body> {
+    HEAD.with(|head| {
+        let mut head = head.borrow_mut();
+        head.clear();
+        let body = try!(build_head(method, target, version, headers, &mut head));
+        try!(w.write_all(&head));
+        Ok(body)
+    })
+}
+
+// The forms of request target a Request can be sent with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Form {
+    Origin,
+    Absolute,
+    Asterisk,
+}
+
+// The path and query of a URL, as sent in the request line. It is worked out
+// once, when the Request is created, and kept for every time it is written.
+//
+// Any character that isn't allowed in a request target, such as a space,
+// a control byte or non-ASCII, is percent-encoded on the way. The absolute
+// form starts with the scheme and authority.
+fn request_target(url: &Url, form: Form) -> String {
+    let mut target = String::new();
+    if form == Form::Asterisk {
+        target.push('*');
+        return target;
+    }
+    if form == Form::Absolute {
+        if let Ok((host, port)) = get_host_and_port(url) {
+            target.push_str(&url.scheme);
+            target.push_str("://");
+            target.push_str(&host);
+            match (&*url.scheme, port) {
+                ("http", 80) | ("https", 443) => (),
+                (_, port) => target.push_str(&format!(":{}", port))
+            }
+        }
+    }
+    let start = target.len();
+    match url.path() {
+        Some(path) => for segment in path {
+            target.push('/');
+            push_encoded(&mut target, segment, b"");
+        },
+        None => push_encoded(&mut target, &url.serialize_path().unwrap_or(String::new()), b"/")
+    }
+    if !target[start..].starts_with("/") {
+        target.insert(start, '/');
+    }
+    if let Some(ref q) = url.query {
+        target.push('?');
+        push_encoded(&mut target, q, b"/?");
+    }
+    target
+}
+
+// Pushes `s` onto `target`, percent-encoding any byte that isn't a pchar
+// (RFC 3986), or in `extra`. A `%` is left alone, assuming it already
+// starts an encoded byte.
+fn push_encoded(target: &mut String, s: &str, extra: &[u8]) {
+    for &b in s.as_bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+            b'-' | b'.' | b'_' | b'~' | b'%' |
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' |
+            b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' => target.push(b as char),
+            b if extra.contains(&b) => target.push(b as char),
+            b => target.push_str(&format!("%{:02X}", b))
+        }
+    }
+}
+
+// The head is built into a buffer that is reused between requests, with the
+// request line appended byte by byte, so that writing it allocates nothing.
+fn build_head(method: &Method, target: &str, version: version::HttpVersion,
+              headers: &mut Headers, head: &mut Vec<u8>) -> ::Result<Body> {
+    head.extend(method.as_ref().bytes());
+    head.push(b' ');
+    head.extend(target.bytes());
+    head.push(b' ');
+    head.extend(version.as_ref().bytes());
+    head.extend(LINE_ENDING.bytes());
+    debug!("request line: {:?}", String::from_utf8_lossy(&head));
+
+    // a GET or HEAD only has a body if the caller framed one explicitly
+    let body = match headers.get::<header::ContentLength>() {
+        Some(cl) => Body::Sized(**cl),
+        None if method.allows_body() || headers.has::<header::TransferEncoding>() => {
+            Body::Chunked
+        },
+        None => Body::Empty
+    };
+
+    // can't do in match above, thanks borrowck
+    if let Body::Chunked = body {
+        let encodings = match headers.get_mut::<header::TransferEncoding>() {
+            Some(&mut header::TransferEncoding(ref mut encodings)) => {
+                if !encodings.contains(&header::Encoding::Chunked) {
+                    encodings.push(header::Encoding::Chunked);
+                }
+                false
+            },
+            None => true
+        };
+
+        if encodings {
+            headers.set::<header::TransferEncoding>(
+                header::TransferEncoding(vec![header::Encoding::Chunked]))
+        }
+    }
+
+    debug!("headers={:?}", headers);
+    try!(write!(head, "{}{}", headers, LINE_ENDING));
+    Ok(body)
+}
+
+// The stream of a detached Request, which has no connection.
+struct Detached;
+
+impl Read for Detached {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "Request is detached"))
+    }
+}
+
+impl Write for Detached {
+    fn write(&mut self, _msg: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "Request is detached"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for Detached {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "Request is detached"))
+    }
+}
+
+// Wraps the connection of a Request that uses `cork` or `on_blocked`.
+struct FlowControl {
+    inner: Box<NetworkStream + Send>,
+    corked: bool,
+    held: Vec<u8>,
+    blocked: Option<(Duration, Box<FnMut(Duration) + Send>)>,
+    clock: Arc<Clock>,
+}
+
+impl FlowControl {
+    fn write_timed(&mut self, msg: &[u8]) -> io::Result<usize> {
+        let start = self.clock.now();
+        let n = try!(self.inner.write(msg));
+        if let Some((threshold, ref mut f)) = self.blocked {
+            let blocked = self.clock.now() - start;
+            if blocked >= threshold {
+                f(blocked);
+            }
+        }
+        Ok(n)
+    }
+
+    fn release_held(&mut self) -> io::Result<()> {
+        let held = mem::replace(&mut self.held, Vec::new());
+        let mut pos = 0;
+        while pos < held.len() {
+            match self.write_timed(&held[pos..]) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                                   "failed to write held body")),
+                Ok(n) => pos += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.held = held[pos..].to_vec();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for FlowControl {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for FlowControl {
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        if self.corked {
+            self.held.extend(msg.iter().cloned());
+            return Ok(msg.len());
+        }
+        try!(self.release_held());
+        self.write_timed(msg)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.corked {
+            return Ok(());
+        }
+        try!(self.release_held());
+        self.inner.flush()
+    }
+}
+
+impl NetworkStream for FlowControl {
+    #[inline]
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    #[inline]
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.inner.close(how)
+    }
+
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.inner.peer_certificate()
+    }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.inner.alpn_protocol()
+    }
+
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        self.inner.shutdown_handle()
+    }
+
+    #[inline]
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        self.inner.try_clone()
+    }
+
+    #[inline]
+    fn set_keep_alive(&mut self, keep_alive: header::KeepAlive) {
+        self.inner.set_keep_alive(keep_alive)
     }
 }
 
@@ -174,7 +655,8 @@ impl Write for Request<Streaming> {
 mod tests {
     use std::str::from_utf8;
     use url::Url;
-    use method::Method::{Get, Head};
+    use header::ContentLength;
+    use method::Method::{Get, Head, Post};
     use mock::{MockStream, MockConnector};
     use super::Request;
 
@@ -205,4 +687,315 @@ mod tests {
         assert!(!s.contains("Content-Length:"));
         assert!(!s.contains("Transfer-Encoding:"));
     }
+
+    #[test]
+    fn test_delete_without_body() {
+        use method::Method::Delete;
+        let mut req = Request::with_connector(
+            Delete, Url::parse("http://example.dom").unwrap(), &mut MockConnector
+        ).unwrap();
+        req.without_body();
+        let req = req.start().unwrap();
+        let stream = *req.body.end().unwrap()
+            .into_inner().unwrap().downcast::<MockStream>().ok().unwrap();
+        let s = from_utf8(&stream.write[..]).unwrap();
+        assert!(s.contains("Content-Length: 0\r\n"));
+        assert!(!s.contains("Transfer-Encoding:"));
+    }
+
+    #[test]
+    fn test_get_explicit_body() {
+        use std::io::Write;
+        use header::{TransferEncoding, Encoding};
+
+        let mut req = Request::with_connector(
+            Get, Url::parse("http://example.dom").unwrap(), &mut MockConnector
+        ).unwrap();
+        req.headers_mut().set(ContentLength(2));
+        let mut req = req.start().unwrap();
+        req.write_all(b"{}").unwrap();
+        let stream = *req.body.end().unwrap()
+            .into_inner().unwrap().downcast::<MockStream>().ok().unwrap();
+        let s = from_utf8(&stream.write[..]).unwrap();
+        assert!(s.starts_with("GET / HTTP/1.1\r\n"));
+        assert!(s.ends_with("Content-Length: 2\r\n\r\n{}"));
+
+        let mut req = Request::with_connector(
+            Get, Url::parse("http://example.dom").unwrap(), &mut MockConnector
+        ).unwrap();
+        req.headers_mut().set(TransferEncoding(vec![Encoding::Chunked]));
+        let mut req = req.start().unwrap();
+        req.write_all(b"{}").unwrap();
+        let stream = *req.body.end().unwrap()
+            .into_inner().unwrap().downcast::<MockStream>().ok().unwrap();
+        let s = from_utf8(&stream.write[..]).unwrap();
+        assert!(s.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(s.ends_with("\r\n\r\n2\r\n{}\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_split() {
+        use std::io::{Read, Write};
+        use header::{TransferEncoding, Encoding};
+
+        let stream = MockStream::with_input(b"HTTP/1.1 200 OK\r\n\r\nfrom server");
+        let url = Url::parse("http://example.dom").unwrap();
+        let mut req = Request::with_stream(Post, url, "example.dom".to_string(), 80,
+                                           Box::new(stream));
+        req.headers_mut().set(TransferEncoding(vec![Encoding::Chunked]));
+        let (mut writer, pending) = req.start().unwrap().split().unwrap();
+
+        // the response can be read before the request body is done
+        let mut res = pending.response().unwrap();
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "from server");
+
+        writer.write_all(b"from client").unwrap();
+        writer.end().unwrap();
+    }
+
+    #[test]
+    fn test_cork() {
+        use std::io::Write;
+        use super::FlowControl;
+
+        fn written(req: &mut Request<::net::Streaming>) -> Vec<u8> {
+            let flow = req.body.get_mut().get_mut().downcast_mut::<FlowControl>().unwrap();
+            flow.inner.downcast_mut::<MockStream>().unwrap().write.clone()
+        }
+
+        let url = Url::parse("http://example.dom").unwrap();
+        let mut req = Request::with_stream(Post, url, "example.dom".to_string(), 80,
+                                           Box::new(MockStream::new()));
+        req.headers_mut().set(ContentLength(5));
+        let mut req = req.start().unwrap();
+        req.cork();
+        req.write_all(b"hello").unwrap();
+        req.flush().unwrap();
+        assert!(written(&mut req).is_empty());
+
+        req.uncork().unwrap();
+        let bytes = written(&mut req);
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.starts_with("POST / HTTP/1.1\r\n"));
+        assert!(s.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_on_blocked() {
+        use std::io::Write;
+        use std::sync::{Arc, Mutex};
+        use time::{Duration, Timespec};
+        use clock::Clock;
+
+        // every write seems to block for a second
+        struct Ticking(Mutex<Timespec>);
+
+        impl Clock for Ticking {
+            fn now(&self) -> Timespec {
+                let mut now = self.0.lock().unwrap();
+                *now = *now + Duration::seconds(1);
+                *now
+            }
+        }
+
+        for &(threshold, called) in &[(Duration::seconds(1), true), (Duration::seconds(2), false)] {
+            let url = Url::parse("http://example.dom").unwrap();
+            let mut req = Request::with_stream(Post, url, "example.dom".to_string(), 80,
+                                               Box::new(MockStream::new()));
+            req.headers_mut().set(ContentLength(5));
+            let mut req = req.start().unwrap();
+            let calls = Arc::new(Mutex::new(vec![]));
+            let blocked = calls.clone();
+            req.set_clock(Ticking(Mutex::new(Timespec::new(0, 0))));
+            req.on_blocked(threshold, move |dur| blocked.lock().unwrap().push(dur));
+            req.write_all(b"hello").unwrap();
+            req.flush().unwrap();
+            let calls = calls.lock().unwrap();
+            assert_eq!(!calls.is_empty(), called);
+            assert!(calls.iter().all(|&dur| dur == Duration::seconds(1)));
+        }
+    }
+
+    #[test]
+    fn test_small_body_single_write() {
+        use std::io::{self, Read, Write};
+        use std::net::SocketAddr;
+        use std::sync::{Arc, Mutex};
+        use net::NetworkStream;
+
+        struct CountingStream(Arc<Mutex<Vec<usize>>>);
+
+        impl Read for CountingStream {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Ok(0)
+            }
+        }
+
+        impl Write for CountingStream {
+            fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().push(msg.len());
+                Ok(msg.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl NetworkStream for CountingStream {
+            fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+                Ok("127.0.0.1:1337".parse().unwrap())
+            }
+        }
+
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let url = Url::parse("http://example.dom").unwrap();
+        let stream = Box::new(CountingStream(writes.clone()));
+        let mut req = Request::with_stream(Post, url, "example.dom".to_string(), 80, stream);
+        let body = vec![b'a'; 20 * 1024];
+        req.headers_mut().set(ContentLength(body.len() as u64));
+        let mut req = req.start().unwrap();
+        req.write_all(&body).unwrap();
+        req.body.end().unwrap();
+
+        let writes = writes.lock().unwrap();
+        assert_eq!(writes.len(), 1);
+        assert!(writes[0] > body.len());
+    }
+
+    #[test]
+    fn test_write_to_sized() {
+        use std::io::Write;
+        let mut req = Request::detached(
+            Post, Url::parse("http://example.dom/path?q=1").unwrap()
+        ).unwrap();
+        req.headers_mut().set(ContentLength(3));
+        let mut body = req.write_to(Vec::new()).unwrap();
+        body.write_all(b"foo").unwrap();
+        let bytes = body.end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.starts_with("POST /path?q=1 HTTP/1.1\r\n"));
+        assert!(s.contains("Content-Length: 3\r\n"));
+        assert!(s.ends_with("\r\n\r\nfoo"));
+    }
+
+    #[test]
+    fn test_write_to_chunked() {
+        use std::io::Write;
+        let req = Request::detached(
+            Post, Url::parse("http://example.dom").unwrap()
+        ).unwrap();
+        let mut body = req.write_to(Vec::new()).unwrap();
+        body.write_all(b"foo").unwrap();
+        let bytes = body.end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(s.ends_with("\r\n\r\n3\r\nfoo\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_request_line() {
+        fn line(url: &str) -> String {
+            let req = Request::detached(Get, Url::parse(url).unwrap()).unwrap();
+            let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+            let s = String::from_utf8(bytes).unwrap();
+            s[..s.find("\r\n").unwrap()].to_string()
+        }
+        assert_eq!(line("http://example.dom"), "GET / HTTP/1.1");
+        assert_eq!(line("http://example.dom/a/b/"), "GET /a/b/ HTTP/1.1");
+        assert_eq!(line("http://example.dom/a?b=c&d"), "GET /a?b=c&d HTTP/1.1");
+    }
+
+    #[test]
+    fn test_host_default_port() {
+        use header::Host;
+        fn host(url: &str) -> Host {
+            let req = Request::detached(Get, Url::parse(url).unwrap()).unwrap();
+            req.headers().get::<Host>().unwrap().clone()
+        }
+        assert_eq!(host("http://example.dom").port, None);
+        assert_eq!(host("https://example.dom").port, None);
+        assert_eq!(host("http://example.dom:8080").port, Some(8080));
+        assert_eq!(host("https://example.dom:80").port, Some(80));
+    }
+
+    #[test]
+    fn test_host_override() {
+        use header::Host;
+        let mut req = Request::detached(
+            Get, Url::parse("http://127.0.0.1/").unwrap()
+        ).unwrap();
+        req.headers_mut().set(Host { hostname: "example.dom".to_string(), port: None });
+        let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.contains("Host: example.dom\r\n"));
+        assert!(!s.contains("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_target_encoded() {
+        let mut req = Request::detached(
+            Get, Url::parse("http://example.dom/").unwrap()
+        ).unwrap();
+        let mut url = Url::parse("http://example.dom/").unwrap();
+        url.path_mut().unwrap()[0] = "a b/c\u{e9}".to_string();
+        url.query = Some("q=x y&r=/?".to_string());
+        req.set_url(url);
+        let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.starts_with("GET /a%20b%2Fc%C3%A9?q=x%20y&r=/? HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        let mut req = Request::detached(
+            Get, Url::parse("http://example.dom:8080/a?b").unwrap()
+        ).unwrap();
+        req.set_absolute_form(true);
+        let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.starts_with("GET http://example.dom:8080/a?b HTTP/1.1\r\n"));
+
+        let mut req = Request::detached(
+            Get, Url::parse("http://example.dom").unwrap()
+        ).unwrap();
+        req.set_absolute_form(true);
+        req.set_url(Url::parse("http://example.dom/c").unwrap());
+        let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.starts_with("GET http://example.dom/c HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_asterisk_form() {
+        use method::Method::Options;
+        let mut req = Request::detached(
+            Options, Url::parse("http://example.dom/").unwrap()
+        ).unwrap();
+        req.set_asterisk_form();
+        let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+        let s = from_utf8(&bytes[..]).unwrap();
+        assert!(s.starts_with("OPTIONS * HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_set_url() {
+        let mut req = Request::detached(
+            Get, Url::parse("http://example.dom/old").unwrap()
+        ).unwrap();
+        req.set_url(Url::parse("http://example.dom/new?q").unwrap());
+        assert_eq!(req.url().serialize(), "http://example.dom/new?q");
+        let bytes = req.write_to(Vec::new()).unwrap().end().unwrap();
+        assert!(from_utf8(&bytes[..]).unwrap().starts_with("GET /new?q HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_detached_cannot_start() {
+        let req = Request::detached(
+            Get, Url::parse("http://example.dom").unwrap()
+        ).unwrap();
+        assert!(req.start().and_then(|req| req.send()).is_err());
+    }
 }
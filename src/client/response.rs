@@ -1,17 +1,30 @@
 //! Client Responses
-use std::io::{self, Read};
+use std::cmp::min;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write, BufRead, Cursor, Seek, SeekFrom};
 use std::marker::PhantomData;
-use std::net::Shutdown;
+use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+
+use time::{precise_time_ns, Duration, Timespec};
 
 use buffer::BufReader;
+use clock::{Clock, SystemClock};
 use header;
-use header::{ContentLength, TransferEncoding};
-use header::Encoding::Chunked;
+use header::{ContentLength, ContentType, TransferEncoding};
 use net::{NetworkStream, HttpStream};
 use http::{self, HttpReader, RawStatus};
-use http::HttpReader::{SizedReader, ChunkedReader, EofReader};
+use http::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader, DecodingReader};
+use method::Method;
+use mime::Mime;
 use status;
 use version;
+use {Error, Url};
 
 /// A response for a client request to a remote server.
 #[derive(Debug)]
@@ -23,19 +36,58 @@ pub struct Response<S = HttpStream> {
     /// The HTTP version of this response from the server.
     pub version: version::HttpVersion,
     status_raw: RawStatus,
-    body: HttpReader<BufReader<Box<NetworkStream + Send>>>,
+    body: BufReader<HttpReader<BufReader<Box<NetworkStream + Send>>>>,
+    url: Option<Url>,
+    redirects: Vec<Redirect>,
+    clock: DeadlineClock,
 
     _marker: PhantomData<S>,
 }
 
+// The Clock `read_with_deadline` goes by, which `Debug` leaves out.
+struct DeadlineClock(Arc<Clock>);
+
+impl fmt::Debug for DeadlineClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+/// A redirect followed on the way to a `Response`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    /// The URL that answered with the redirect.
+    pub url: Url,
+    /// The status of the redirect, such as `301 Moved Permanently`.
+    pub status: status::StatusCode,
+}
+
 impl Response {
 
     /// Creates a new response from a server.
     pub fn new(stream: Box<NetworkStream + Send>) -> ::Result<Response> {
-        trace!("Response::new");
+        Response::with_method(stream, &Method::Get)
+    }
+
+    /// Creates a new response from a server, to a request with `method`.
+    ///
+    /// Responses to `HEAD` requests, and `1xx`, `204 No Content` and
+    /// `304 Not Modified` responses, never have a body, whatever their
+    /// headers say.
+    pub fn with_method(stream: Box<NetworkStream + Send>, method: &Method) -> ::Result<Response> {
+        Response::with_unfolding(stream, method, true)
+    }
+
+    /// Creates a new response from a server, to a request with `method`,
+    /// unfolding header values folded over several lines only if `unfold`
+    /// is set. Otherwise a response with one fails to parse.
+    pub fn with_unfolding(stream: Box<NetworkStream + Send>, method: &Method, unfold: bool)
+            -> ::Result<Response> {
+        trace!("Response::with_method {:?}", method);
         let mut stream = BufReader::new(stream);
 
-        let head = try!(http::parse_response(&mut stream));
+        let mut head = try!(http::parse_response_unfolding(&mut stream, unfold));
+        try!(http::check_framing(&mut head.headers));
         let raw_status = head.subject;
         let headers = head.headers;
 
@@ -43,23 +95,23 @@ impl Response {
         debug!("version={:?}, status={:?}", head.version, status);
         debug!("headers={:?}", headers);
 
-        if !http::should_keep_alive(head.version, &headers) {
+        // after a 101 the connection belongs to the upgraded protocol
+        if status != status::StatusCode::SwitchingProtocols &&
+                !http::should_keep_alive(head.version, &headers) {
             try!(stream.get_mut().close(Shutdown::Write));
+        } else {
+            let keep_alive = headers.get::<header::KeepAlive>()
+                .map_or(header::KeepAlive::default(), |&keep_alive| keep_alive);
+            stream.get_mut().set_keep_alive(keep_alive);
         }
 
-        let body = if headers.has::<TransferEncoding>() {
+        let body = if *method == Method::Head || !status.may_have_body() {
+            trace!("response has no body");
+            EmptyReader(stream)
+        } else if headers.has::<TransferEncoding>() {
             match headers.get::<TransferEncoding>() {
                 Some(&TransferEncoding(ref codings)) => {
-                    if codings.len() > 1 {
-                        trace!("TODO: #2 handle other codings: {:?}", codings);
-                    };
-
-                    if codings.contains(&Chunked) {
-                        ChunkedReader(stream, None)
-                    } else {
-                        trace!("not chuncked. read till eof");
-                        EofReader(stream)
-                    }
+                    try!(http::transfer_decoder(stream, codings, true))
                 }
                 None => unreachable!()
             }
@@ -77,20 +129,301 @@ impl Response {
             status: status,
             version: head.version,
             headers: headers,
-            body: body,
+            body: buffered(body),
             status_raw: raw_status,
+            url: None,
+            redirects: Vec::new(),
+            clock: DeadlineClock(Arc::new(SystemClock)),
             _marker: PhantomData,
         })
     }
 
+    /// The most bytes a body sent with a `gzip` or `deflate` transfer
+    /// coding may decode to, before reading it fails with an
+    /// `InvalidInput` error.
+    ///
+    /// The default is `http::DEFAULT_MAX_DECODED`. Set it before reading
+    /// the body.
+    pub fn set_max_decoded_size(&mut self, max: u64) {
+        self.body.get_mut().set_max_decoded(max);
+    }
+
+    /// The most bytes a body sent with a `gzip` or `deflate` transfer
+    /// coding may decode to for each byte received, once it has decoded a
+    /// megabyte, before reading fails with an `InvalidInput` error.
+    ///
+    /// The default is `http::DEFAULT_MAX_RATIO`. Set it before reading the
+    /// body.
+    pub fn set_max_decoded_ratio(&mut self, ratio: u64) {
+        self.body.get_mut().set_max_ratio(ratio);
+    }
+
+    // A body that decoded to more than its limits is too large, rather
+    // than unreadable.
+    fn read_error(&self, e: io::Error) -> Error {
+        if self.body.get_ref().is_over_decode_limit() {
+            Error::TooLarge
+        } else {
+            Error::Io(e)
+        }
+    }
+
+    /// Set the `Clock` that `read_with_deadline` decides when the deadline
+    /// has passed by.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = DeadlineClock(Arc::new(clock));
+    }
+
     /// Get the raw status code and reason.
     pub fn status_raw(&self) -> &RawStatus {
         &self.status_raw
     }
 
+    /// The URL this Response came from, after any redirects.
+    ///
+    /// Only a Response from a `Client` knows its URL.
+    #[inline]
+    pub fn url(&self) -> Option<&Url> {
+        self.url.as_ref()
+    }
+
+    /// The redirects a `Client` followed to get this Response, in order.
+    ///
+    /// Empty if the first request was answered directly.
+    #[inline]
+    pub fn redirect_chain(&self) -> &[Redirect] {
+        &self.redirects
+    }
+
+    // Lets the Client record where the Response came from.
+    #[doc(hidden)]
+    pub fn set_history(&mut self, url: Url, redirects: Vec<Redirect>) {
+        self.url = Some(url);
+        self.redirects = redirects;
+    }
+
+    /// The media type of the body, from the `Content-Type` header.
+    #[inline]
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.headers.get::<ContentType>().map(|&ContentType(ref mime)| mime)
+    }
+
+    /// The length of the body, from the `Content-Length` header.
+    #[inline]
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.get::<ContentLength>().map(|&ContentLength(len)| len)
+    }
+
+    /// Whether the status is a `2xx` Success.
+    #[inline]
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    /// Whether the status is a `3xx` Redirection.
+    #[inline]
+    pub fn is_redirect(&self) -> bool {
+        self.status.is_redirection()
+    }
+
+    /// Copy the rest of the body into `w`, returning how many bytes were
+    /// copied.
+    ///
+    /// Unlike `io::copy`, this writes straight out of the Response's own
+    /// buffer, which is 64KB for bodies without a known length, instead of
+    /// copying through a buffer of its own first.
+    pub fn copy_to<W: Write>(&mut self, w: &mut W) -> io::Result<u64> {
+        let mut copied = 0;
+        loop {
+            let len = match self.body.fill_buf() {
+                Ok(buf) if buf.is_empty() => return Ok(copied),
+                Ok(buf) => {
+                    try!(w.write_all(buf));
+                    buf.len()
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            self.body.consume(len);
+            copied += len as u64;
+        }
+    }
+
+    /// Take over the connection after a `101 Switching Protocols` response.
+    ///
+    /// HTTP is done with the connection once the server has switched
+    /// protocols, so the stream is handed back along with any bytes the
+    /// server already sent after the response head, which belong to the new
+    /// protocol. Any other response is given back unchanged in the `Err`.
+    pub fn upgrade(self) -> Result<(Box<NetworkStream + Send>, Vec<u8>), Response> {
+        if self.status != status::StatusCode::SwitchingProtocols {
+            return Err(self);
+        }
+        let mut read = self.body.get_buf().to_vec();
+        let stream = self.body.into_inner().into_inner();
+        read.extend(stream.get_buf().iter().cloned());
+        Ok((stream.into_inner(), read))
+    }
+
+    /// Read the rest of the body into a `Vec`, failing with
+    /// `Error::TooLarge` if it is longer than `limit` bytes.
+    ///
+    /// A `Content-Length` over the limit fails before anything is read. A
+    /// body that decodes to more than its transfer codings may, as set with
+    /// `set_max_decoded_size` and `set_max_decoded_ratio`, fails with
+    /// `Error::TooLarge` too.
+    pub fn read_to_bytes(&mut self, limit: u64) -> ::Result<Vec<u8>> {
+        if let Some(&ContentLength(len)) = self.headers.get::<ContentLength>() {
+            if len > limit {
+                return Err(Error::TooLarge);
+            }
+        }
+        let mut body = Vec::new();
+        // reading a byte past the limit tells an oversized body apart from
+        // one of exactly `limit` bytes
+        if let Err(e) = self.by_ref().take(limit.saturating_add(1)).read_to_end(&mut body) {
+            return Err(self.read_error(e));
+        }
+        if body.len() as u64 > limit {
+            return Err(Error::TooLarge);
+        }
+        Ok(body)
+    }
+
+    /// Read the rest of the body into a `String`, failing with
+    /// `Error::TooLarge` if it is longer than `limit` bytes.
+    pub fn read_to_text(&mut self, limit: u64) -> ::Result<String> {
+        let body = try!(self.read_to_bytes(limit));
+        String::from_utf8(body).map_err(|_| {
+            Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "body is not valid UTF-8"))
+        })
+    }
+
+    /// Read the rest of the body, giving up once `timeout` has passed.
+    ///
+    /// Unlike an error, a timeout keeps what was received before it, as
+    /// `BodyRead::TimedOut`, which suits health checks and best-effort
+    /// scraping. A body longer than `limit` bytes fails with
+    /// `Error::TooLarge`.
+    ///
+    /// A read blocked on the connection is interrupted at the deadline by
+    /// shutting the connection down, so after a timeout the connection is
+    /// closed, and never reused. On a stream without a `shutdown_handle`,
+    /// the deadline is only checked between reads.
+    pub fn read_with_deadline(&mut self, timeout: Duration, limit: u64) -> ::Result<BodyRead> {
+        let socket = self.body.get_mut().get_mut().get_mut().shutdown_handle();
+        let watchdog = Watchdog::start(socket, timeout, self.clock.0.clone());
+        let mut body = Vec::new();
+        let mut buf = [0; 4096];
+        let timed_out;
+        loop {
+            if watchdog.expired() {
+                timed_out = true;
+                break;
+            }
+            match self.read(&mut buf) {
+                // a read interrupted by the watchdog can end either way
+                Ok(0) => {
+                    timed_out = watchdog.fired();
+                    break;
+                },
+                Ok(n) => {
+                    body.extend(buf[..n].iter().cloned());
+                    if body.len() as u64 > limit {
+                        return Err(Error::TooLarge);
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) if watchdog.fired() => {
+                    timed_out = true;
+                    break;
+                },
+                Err(e) => return Err(self.read_error(e))
+            }
+        }
+        if timed_out {
+            debug!("read_with_deadline timed out after {} bytes", body.len());
+            let _ = self.body.get_mut().get_mut().get_mut().close(Shutdown::Both);
+            Ok(BodyRead::TimedOut(body))
+        } else {
+            Ok(BodyRead::Complete(body))
+        }
+    }
+
+    /// Read the rest of the body into a `SpooledBody`, which can be read
+    /// and seeked again as often as needed.
+    ///
+    /// A body of up to `threshold` bytes is kept in memory. A longer one,
+    /// by its `Content-Length` or once more than `threshold` bytes have
+    /// arrived, is written to a temporary file instead, which is removed
+    /// when the `SpooledBody` is dropped.
+    pub fn spool(&mut self, threshold: u64) -> ::Result<SpooledBody> {
+        let mut body = Vec::new();
+        if self.content_length().map_or(true, |len| len <= threshold) {
+            if let Err(e) = self.by_ref().take(threshold.saturating_add(1))
+                                    .read_to_end(&mut body) {
+                return Err(self.read_error(e));
+            }
+            if body.len() as u64 <= threshold {
+                return Ok(SpooledBody(Spool::Memory(Cursor::new(body))));
+            }
+        }
+
+        let (path, file) = try!(spool_file());
+        debug!("spooling response body to {:?}", path);
+        let mut spooled = SpooledBody(Spool::File(file, path));
+        {
+            let file = match spooled.0 {
+                Spool::File(ref mut file, _) => file,
+                Spool::Memory(_) => unreachable!()
+            };
+            try!(file.write_all(&body));
+            if let Err(e) = self.copy_to(file) {
+                return Err(self.read_error(e));
+            }
+            try!(file.seek(SeekFrom::Start(0)));
+        }
+        Ok(spooled)
+    }
+
+    /// Read the rest of the body, and deserialize it from JSON.
+    ///
+    /// Fails with `Error::TooLarge` if the body is longer than `limit`
+    /// bytes, as `read_to_bytes` does, and with `Error::Json` if it isn't
+    /// the JSON for a `T`. Only available with the `json` feature.
+    #[cfg(feature = "json")]
+    pub fn json<T: ::serde::Deserialize>(&mut self, limit: u64) -> ::Result<T> {
+        let body = try!(self.read_to_bytes(limit));
+        Ok(try!(::serde_json::from_slice(&body)))
+    }
+
+    /// An iterator over the rest of the body, in chunks of up to `size`
+    /// bytes.
+    ///
+    /// The chunks are whatever each read returns, so they don't line up
+    /// with the chunks of a chunked body. Iteration ends at the end of the
+    /// body, or after the first error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn chunks(&mut self, size: usize) -> Chunks {
+        assert!(size > 0, "chunk size must be greater than 0");
+        Chunks {
+            res: self,
+            size: size,
+            done: false,
+        }
+    }
+
+    /// Split the Response into its status, headers, and body.
+    pub fn into_parts(self) -> (status::StatusCode, header::Headers, ResponseBody) {
+        (self.status, self.headers, ResponseBody(self.body))
+    }
+
     /// Consumes the Request to return the NetworkStream underneath.
     pub fn into_inner(self) -> Box<NetworkStream + Send> {
-        self.body.into_inner().into_inner()
+        self.body.into_inner().into_inner().into_inner()
     }
 }
 
@@ -101,13 +434,275 @@ impl Read for Response {
     }
 }
 
+impl BufRead for Response {
+    #[inline]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.body.fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.body.consume(amt)
+    }
+}
+
+/// An iterator over the body of a `Response`, created by `Response::chunks`.
+pub struct Chunks<'a> {
+    res: &'a mut Response,
+    size: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.done {
+            return None;
+        }
+        let mut chunk = vec![0; self.size];
+        loop {
+            match self.res.read(&mut chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                },
+                Ok(n) => {
+                    chunk.truncate(n);
+                    return Some(Ok(chunk));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// The body read by `Response::read_with_deadline`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BodyRead {
+    /// The whole body was read before the deadline.
+    Complete(Vec<u8>),
+    /// The deadline passed first, after reading this much of the body.
+    TimedOut(Vec<u8>),
+}
+
+impl BodyRead {
+    /// Whether the deadline passed before the end of the body.
+    pub fn is_timed_out(&self) -> bool {
+        match *self {
+            BodyRead::TimedOut(_) => true,
+            BodyRead::Complete(_) => false,
+        }
+    }
+
+    /// The bytes that were read, whether or not the body was complete.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            BodyRead::Complete(body) | BodyRead::TimedOut(body) => body,
+        }
+    }
+}
+
+/// A whole body read by `Response::spool`, in memory or in a temporary
+/// file.
+#[derive(Debug)]
+pub struct SpooledBody(Spool);
+
+#[derive(Debug)]
+enum Spool {
+    Memory(Cursor<Vec<u8>>),
+    File(File, PathBuf),
+}
+
+impl SpooledBody {
+    /// Whether the body was written to a temporary file.
+    pub fn is_file(&self) -> bool {
+        match self.0 {
+            Spool::File(..) => true,
+            Spool::Memory(_) => false,
+        }
+    }
+}
+
+impl Read for SpooledBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {
+            Spool::Memory(ref mut body) => body.read(buf),
+            Spool::File(ref mut file, _) => file.read(buf),
+        }
+    }
+}
+
+impl Seek for SpooledBody {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.0 {
+            Spool::Memory(ref mut body) => body.seek(pos),
+            Spool::File(ref mut file, _) => file.seek(pos),
+        }
+    }
+}
+
+impl Drop for SpooledBody {
+    fn drop(&mut self) {
+        if let Spool::File(_, ref path) = self.0 {
+            if let Err(e) = fs::remove_file(path) {
+                debug!("error removing spooled body {:?}: {:?}", path, e);
+            }
+        }
+    }
+}
+
+static SPOOLED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Creates a file for a spooled body, which only this user can read. The
+// name is only likely to be unique, so creating the file fails if it is
+// already there, such as one another user made to read the body from.
+fn spool_file() -> io::Result<(PathBuf, File)> {
+    loop {
+        let name = format!("hyper-spool-{}-{}", precise_time_ns(),
+                           SPOOLED.fetch_add(1, Ordering::Relaxed));
+        let path = env::temp_dir().join(name);
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        owner_only(&mut options);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn owner_only(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn owner_only(_: &mut OpenOptions) {}
+
+// Shuts the connection down for reading once the deadline passes, unless
+// it has been dropped first.
+struct Watchdog {
+    deadline: Timespec,
+    clock: Arc<Clock>,
+    // (done, fired), checked together so the watchdog can't fire after the
+    // read has finished
+    state: Arc<Mutex<(bool, bool)>>,
+    thread: Option<thread::Thread>,
+}
+
+impl Watchdog {
+    fn start(socket: Option<TcpStream>, timeout: Duration, clock: Arc<Clock>) -> Watchdog {
+        let deadline = clock.now() + timeout;
+        let state = Arc::new(Mutex::new((false, false)));
+        let thread = socket.map(|socket| {
+            let state = state.clone();
+            let clock = clock.clone();
+            thread::spawn(move || {
+                loop {
+                    let now = clock.now();
+                    {
+                        let mut state = state.lock().unwrap();
+                        if state.0 {
+                            return;
+                        }
+                        if now >= deadline {
+                            let _ = socket.shutdown(Shutdown::Read);
+                            state.1 = true;
+                            return;
+                        }
+                    }
+                    // woken early when the read finishes
+                    thread::park_timeout_ms((deadline - now).num_milliseconds() as u32 + 1);
+                }
+            }).thread().clone()
+        });
+        Watchdog {
+            deadline: deadline,
+            clock: clock,
+            state: state,
+            thread: thread,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.clock.now() >= self.deadline
+    }
+
+    fn fired(&self) -> bool {
+        self.state.lock().unwrap().1
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().0 = true;
+        if let Some(ref thread) = self.thread {
+            thread.unpark();
+        }
+    }
+}
+
+impl From<Response> for (status::StatusCode, header::Headers, ResponseBody) {
+    #[inline]
+    fn from(res: Response) -> (status::StatusCode, header::Headers, ResponseBody) {
+        res.into_parts()
+    }
+}
+
+/// The body of a `Response`, once split from its status and headers.
+#[derive(Debug)]
+pub struct ResponseBody(BufReader<HttpReader<BufReader<Box<NetworkStream + Send>>>>);
+
+impl Read for ResponseBody {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl BufRead for ResponseBody {
+    #[inline]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt)
+    }
+}
+
+const MAX_BODY_BUFFER: usize = 64 * 1024;
+const STREAM_BODY_BUFFER: usize = 8 * 1024;
+
+// A sized body never needs a buffer larger than itself, and a large one
+// lets a big download take fewer reads. A body of unknown length only gets
+// a small buffer, as it is as likely to be small.
+fn buffered<R: Read>(body: HttpReader<R>) -> BufReader<HttpReader<R>> {
+    let cap = match body {
+        SizedReader(_, len) => min(len, MAX_BODY_BUFFER as u64) as usize,
+        EmptyReader(_) => 0,
+        ChunkedReader(..) | EofReader(_) | DecodingReader(..) => STREAM_BODY_BUFFER,
+    };
+    BufReader::with_capacity(body, cap)
+}
+
 #[cfg(test)]
 mod tests {
     use std::borrow::Cow::Borrowed;
-    use std::io::{self, Read};
+    use std::io::{self, Read, BufRead};
     use std::marker::PhantomData;
+    use std::sync::Arc;
 
     use buffer::BufReader;
+    use clock::SystemClock;
     use header::Headers;
     use header::TransferEncoding;
     use header::Encoding;
@@ -117,7 +712,7 @@ mod tests {
     use status;
     use version;
 
-    use super::Response;
+    use super::{DeadlineClock, Response, buffered};
 
     fn read_to_string(mut r: Response) -> io::Result<String> {
         let mut s = String::new();
@@ -132,8 +727,11 @@ mod tests {
             status: status::StatusCode::Ok,
             headers: Headers::new(),
             version: version::HttpVersion::Http11,
-            body: EofReader(BufReader::new(Box::new(MockStream::new()))),
+            body: buffered(EofReader(BufReader::new(Box::new(MockStream::new())))),
             status_raw: RawStatus(200, Borrowed("OK")),
+            url: None,
+            redirects: Vec::new(),
+            clock: DeadlineClock(Arc::new(SystemClock)),
             _marker: PhantomData,
         };
 
@@ -175,6 +773,316 @@ mod tests {
         assert_eq!(read_to_string(res).unwrap(), "qwert".to_string());
     }
 
+    #[test]
+    fn test_read_lines() {
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            6\r\n\
+            first\n\r\n\
+            3\r\n\
+            sec\r\n\
+            4\r\n\
+            ond\n\r\n\
+            0\r\n\
+            \r\n"
+        );
+
+        let res = Response::new(Box::new(stream)).unwrap();
+        let lines = res.lines().map(|line| line.unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines, vec!["first".to_string(), "second".to_string()]);
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            3\r\n\
+            foo\r\n\
+            3\r\n\
+            bar\r\n\
+            0\r\n\
+            \r\n"
+        );
+
+        let mut res = Response::new(Box::new(stream)).unwrap();
+        let mut body = Vec::new();
+        assert_eq!(res.copy_to(&mut body).unwrap(), 6);
+        assert_eq!(body, b"foobar");
+        assert_eq!(res.copy_to(&mut body).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_no_body() {
+        use method::Method;
+
+        fn head(status: &str, method: Method) -> Vec<u8> {
+            let raw = format!("HTTP/1.1 {}\r\nContent-Length: 5\r\n\r\nhello", status);
+            let stream = MockStream::with_input(raw.as_bytes());
+            let mut res = Response::with_method(Box::new(stream), &method).unwrap();
+            let mut body = Vec::new();
+            res.read_to_end(&mut body).unwrap();
+            body
+        }
+
+        assert_eq!(head("200 OK", Method::Head), b"");
+        assert_eq!(head("204 No Content", Method::Get), b"");
+        assert_eq!(head("304 Not Modified", Method::Get), b"");
+        assert_eq!(head("100 Continue", Method::Get), b"");
+        assert_eq!(head("200 OK", Method::Get), b"hello");
+    }
+
+    #[test]
+    fn test_read_to_bytes_limit() {
+        use std::io::Write;
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use Error;
+
+        fn res(raw: &[u8]) -> Response {
+            Response::new(Box::new(MockStream::with_input(raw))).unwrap()
+        }
+
+        let sized = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        assert_eq!(res(sized).read_to_bytes(5).unwrap(), b"hello");
+        match res(sized).read_to_bytes(4) {
+            Err(Error::TooLarge) => (),
+            other => panic!("expected TooLarge, got {:?}", other)
+        }
+
+        let eof = b"HTTP/1.1 200 OK\r\n\r\nhello";
+        assert_eq!(res(eof).read_to_text(5).unwrap(), "hello");
+        match res(eof).read_to_text(4) {
+            Err(Error::TooLarge) => (),
+            other => panic!("expected TooLarge, got {:?}", other)
+        }
+
+        // a body that decodes to far more than it was sent as
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&[0; 4 * 1024 * 1024]).unwrap();
+        let mut bomb = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n".to_vec();
+        bomb.extend(gzipped.finish().unwrap());
+        match res(&bomb).read_to_bytes(8 * 1024 * 1024) {
+            Err(Error::TooLarge) => (),
+            other => panic!("expected TooLarge, got {:?}", other)
+        }
+        let mut bombed = res(&bomb);
+        bombed.set_max_decoded_ratio(u64::max_value());
+        assert_eq!(bombed.read_to_bytes(8 * 1024 * 1024).unwrap().len(), 4 * 1024 * 1024);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        use Error;
+
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n[1, 2, 3]";
+        let mut res = Response::new(Box::new(MockStream::with_input(raw))).unwrap();
+        assert_eq!(res.json::<Vec<u32>>(64).unwrap(), vec![1, 2, 3]);
+
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n[1, 2";
+        let mut res = Response::new(Box::new(MockStream::with_input(raw))).unwrap();
+        match res.json::<Vec<u32>>(64) {
+            Err(Error::Json(_)) => (),
+            other => panic!("expected Json, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_spool() {
+        use std::io::{Seek, SeekFrom};
+
+        fn res(raw: &[u8]) -> Response {
+            Response::new(Box::new(MockStream::with_input(raw))).unwrap()
+        }
+
+        fn read_twice(mut body: super::SpooledBody) -> (String, String) {
+            let mut first = String::new();
+            body.read_to_string(&mut first).unwrap();
+            body.seek(SeekFrom::Start(1)).unwrap();
+            let mut second = String::new();
+            body.read_to_string(&mut second).unwrap();
+            (first, second)
+        }
+
+        let body = res(b"HTTP/1.1 200 OK\r\n\r\nhello").spool(5).unwrap();
+        assert!(!body.is_file());
+        assert_eq!(read_twice(body), ("hello".to_string(), "ello".to_string()));
+
+        let body = res(b"HTTP/1.1 200 OK\r\n\r\nhello").spool(4).unwrap();
+        assert!(body.is_file());
+        assert_eq!(read_twice(body), ("hello".to_string(), "ello".to_string()));
+
+        let body = res(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").spool(4).unwrap();
+        let path = match body.0 {
+            super::Spool::File(_, ref path) => path.clone(),
+            super::Spool::Memory(_) => panic!("expected a file")
+        };
+        assert_eq!(read_twice(body), ("hello".to_string(), "ello".to_string()));
+        assert!(::std::fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn test_read_with_deadline() {
+        use time::Duration;
+        use super::BodyRead;
+
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mut res = Response::new(Box::new(MockStream::with_input(raw))).unwrap();
+        let body = res.read_with_deadline(Duration::seconds(5), 1024).unwrap();
+        assert_eq!(body, BodyRead::Complete(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_read_with_deadline_clock() {
+        use std::sync::Mutex;
+        use time::{Duration, Timespec};
+        use clock::Clock;
+        use super::BodyRead;
+
+        // a second passes every time the clock is read
+        struct Ticking(Mutex<Timespec>);
+
+        impl Clock for Ticking {
+            fn now(&self) -> Timespec {
+                let mut now = self.0.lock().unwrap();
+                *now = *now + Duration::seconds(1);
+                *now
+            }
+        }
+
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    2\r\nhe\r\n2\r\nll\r\n1\r\no\r\n0\r\n\r\n";
+        let mut res = Response::new(Box::new(MockStream::with_input(raw))).unwrap();
+        res.set_clock(Ticking(Mutex::new(Timespec::new(0, 0))));
+        let body = res.read_with_deadline(Duration::seconds(3), 1024).unwrap();
+        assert_eq!(body, BodyRead::TimedOut(b"hell".to_vec()));
+    }
+
+    #[test]
+    fn test_read_with_deadline_partial() {
+        use client::Client;
+        use header::ContentLength;
+        use method::Method::Get;
+        use server::testing::{TestServer, Expected, Reply};
+        use time::Duration;
+        use super::BodyRead;
+
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/"),
+                      Reply::new(status::StatusCode::Ok)
+                          .header(ContentLength(10))
+                          .chunk(0, "hello")
+                          .chunk(1000, "world"));
+
+        let mut res = Client::new().get(&server.url("/")).send().unwrap();
+        let body = res.read_with_deadline(Duration::milliseconds(100), 1024).unwrap();
+        assert!(body.is_timed_out());
+        assert_eq!(body, BodyRead::TimedOut(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_chunks() {
+        fn chunks(raw: &[u8]) -> Vec<Vec<u8>> {
+            let mut res = Response::new(Box::new(MockStream::with_input(raw))).unwrap();
+            let chunks = res.chunks(3).map(|chunk| chunk.unwrap()).collect();
+            chunks
+        }
+
+        let expected = vec![b"hel".to_vec(), b"lo".to_vec()];
+        assert_eq!(chunks(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"), expected);
+        assert_eq!(chunks(b"HTTP/1.1 200 OK\r\n\r\nhello"), expected);
+        assert_eq!(chunks(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                            5\r\nhello\r\n0\r\n\r\n"), expected);
+        assert!(chunks(b"HTTP/1.1 204 No Content\r\n\r\n").is_empty());
+
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            X\r\n"
+        );
+        let mut res = Response::new(Box::new(stream)).unwrap();
+        let mut chunks = res.chunks(3);
+        assert!(chunks.next().unwrap().is_err());
+        assert!(chunks.next().is_none());
+    }
+
+    #[test]
+    fn test_helpers() {
+        use mime::{Mime, TopLevel, SubLevel};
+
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: 2\r\n\
+            \r\n\
+            ok"
+        );
+        let res = Response::new(Box::new(stream)).unwrap();
+        assert_eq!(res.content_type(), Some(&Mime(TopLevel::Text, SubLevel::Plain, vec![])));
+        assert_eq!(res.content_length(), Some(2));
+        assert!(res.is_success());
+        assert!(!res.is_redirect());
+
+        let stream = MockStream::with_input(b"HTTP/1.1 302 Found\r\n\r\n");
+        let res = Response::new(Box::new(stream)).unwrap();
+        assert_eq!(res.content_type(), None);
+        assert_eq!(res.content_length(), None);
+        assert!(!res.is_success());
+        assert!(res.is_redirect());
+    }
+
+    #[test]
+    fn test_into_parts() {
+        let stream = MockStream::with_input(b"HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\nnope");
+        let res = Response::new(Box::new(stream)).unwrap();
+        let (status, headers, mut body): (status::StatusCode, Headers, _) = res.into();
+        assert_eq!(status, status::StatusCode::NotFound);
+        assert_eq!(headers.get(), Some(&::header::ContentLength(4)));
+        let mut s = String::new();
+        body.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "nope");
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            \r\n\
+            \x81\x02hi"
+        );
+
+        let res = Response::new(Box::new(stream)).unwrap();
+        let (_, read) = res.upgrade().unwrap();
+        assert_eq!(read, b"\x81\x02hi");
+
+        let stream = MockStream::with_input(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let res = Response::new(Box::new(stream)).unwrap();
+        let res = res.upgrade().err().unwrap();
+        assert_eq!(read_to_string(res).unwrap(), "ok".to_string());
+    }
+
+    #[test]
+    fn test_sized_body_buffer() {
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello"
+        );
+
+        let mut res = Response::new(Box::new(stream)).unwrap();
+        assert_eq!(res.fill_buf().unwrap(), b"hello");
+        res.consume(2);
+        assert_eq!(read_to_string(res).unwrap(), "llo".to_string());
+    }
+
     /// Tests that when a chunk size is not a valid radix-16 number, an error
     /// is returned.
     #[test]
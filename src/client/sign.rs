@@ -0,0 +1,141 @@
+//! Signing requests just before they are sent.
+//!
+//! APIs such as AWS, with Signature Version 4, or those using HMAC
+//! signatures, ask for a signature over the method, URL, some headers and
+//! a hash of the body of each request. A `Client` given a `Signer` with
+//! `set_signer` calls it once every header of a request is final, right
+//! before the head is written, so it can add the `Authorization`, `Date`
+//! or content hash headers the API wants. Each request of a redirect is
+//! signed again.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use hyper::client::sign::SignRequest;
+//! use hyper::header::Authorization;
+//!
+//! # fn signature(_: &SignRequest, _: Option<Vec<u8>>) -> String { String::new() }
+//! let mut client = Client::new();
+//! client.set_signer(Some(Box::new(|req: &mut SignRequest| {
+//!     let hash = req.body.sha256();
+//!     let signature = signature(req, hash);
+//!     req.headers.set(Authorization(signature));
+//!     Ok(())
+//! })));
+//! ```
+use openssl::crypto::hash::{hash, Type};
+
+use header::Headers;
+use method::Method;
+use Url;
+
+/// Adds the headers that sign a request.
+pub trait Signer: Send + Sync {
+    /// Sign `req`, before its head is written.
+    ///
+    /// An error stops the request from being sent, and is returned from
+    /// `send`.
+    fn sign(&self, req: &mut SignRequest) -> ::Result<()>;
+}
+
+impl<F> Signer for F where F: Fn(&mut SignRequest) -> ::Result<()> + Send + Sync {
+    fn sign(&self, req: &mut SignRequest) -> ::Result<()> {
+        self(req)
+    }
+}
+
+/// A request about to be sent, for a `Signer`.
+#[derive(Debug)]
+pub struct SignRequest<'a> {
+    /// The method of the request.
+    pub method: &'a Method,
+    /// The URL the request is sent to, after any `Strict-Transport-Security`
+    /// upgrade.
+    pub url: &'a Url,
+    /// Every header the request is sent with, including `Host` and
+    /// `Content-Length` or `Transfer-Encoding`.
+    pub headers: &'a mut Headers,
+    /// The body of the request.
+    pub body: Payload<'a>,
+}
+
+/// The body of a request to be signed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Payload<'a> {
+    /// The request has no body.
+    Empty,
+    /// The body, when it is already in memory.
+    Bytes(&'a [u8]),
+    /// The body is read from a reader as it is sent, so it can't be seen
+    /// ahead of time. APIs usually accept an unsigned payload in this case.
+    Streamed,
+}
+
+impl<'a> Payload<'a> {
+    /// The SHA-256 hash of the body, or `None` if it is streamed.
+    pub fn sha256(&self) -> Option<Vec<u8>> {
+        match *self {
+            Payload::Empty => Some(hash(Type::SHA256, b"")),
+            Payload::Bytes(bytes) => Some(hash(Type::SHA256, bytes)),
+            Payload::Streamed => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use client::Client;
+    use header::{Authorization, ContentLength};
+    use method::Method::{Get, Post};
+    use serialize::hex::ToHex;
+    use server::testing::{TestServer, Expected, Reply};
+    use status::StatusCode;
+    use super::{Payload, SignRequest};
+
+    #[test]
+    fn test_payload_sha256() {
+        assert_eq!(Payload::Empty.sha256().unwrap().to_hex(),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(Payload::Bytes(b"abc").sha256().unwrap().to_hex(),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(Payload::Streamed.sha256(), None);
+    }
+
+    #[test]
+    fn test_signer() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Post, "/upload")
+                          .header(Authorization("POST /upload 5 5".to_string()))
+                          .body("hello"),
+                      Reply::new(StatusCode::Ok));
+        server.expect(Expected::new(Get, "/")
+                          .header(Authorization("GET / empty".to_string())),
+                      Reply::new(StatusCode::Ok));
+        server.expect(Expected::new(Post, "/upload")
+                          .header(Authorization("POST /upload streamed".to_string()))
+                          .body("hello"),
+                      Reply::new(StatusCode::Ok));
+
+        let mut client = Client::new();
+        client.set_signer(Some(Box::new(|req: &mut SignRequest| {
+            let body = match req.body {
+                Payload::Empty => "empty".to_string(),
+                Payload::Bytes(bytes) => {
+                    let len = req.headers.get::<ContentLength>().unwrap();
+                    format!("{} {}", len, bytes.len())
+                },
+                Payload::Streamed => "streamed".to_string(),
+            };
+            let signature = format!("{} {} {}", req.method, req.url.serialize_path().unwrap(), body);
+            req.headers.set(Authorization(signature));
+            Ok(())
+        })));
+
+        client.post(&server.url("/upload")).body("hello").send().unwrap();
+        client.get(&server.url("/")).send().unwrap();
+        let mut body = Cursor::new(b"hello".to_vec());
+        client.post(&server.url("/upload")).body(&mut body).send().unwrap();
+        server.assert_done();
+    }
+}
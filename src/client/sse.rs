@@ -0,0 +1,239 @@
+//! Server-Sent Events, read from a `text/event-stream` body.
+//!
+//! An `EventStream` wraps any buffered body, and parses it into `Event`s as
+//! they arrive, following the
+//! [EventSource](https://html.spec.whatwg.org/multipage/server-sent-events.html)
+//! parsing rules. It only reads the body as it is streamed, so it works the
+//! same whether the body is chunked or read until the connection closes.
+//! Lines may end with `\n` or `\r\n`, but not a lone `\r`.
+//!
+//! ```no_run
+//! use hyper::Client;
+//! use hyper::client::sse;
+//!
+//! let mut client = Client::new();
+//! let mut events = sse::open(&mut client, "http://example.domain/feed").unwrap();
+//! loop {
+//!     for event in events.by_ref() {
+//!         let event = event.unwrap();
+//!         println!("{}: {}", event.event, event.data);
+//!     }
+//!     // the server closed the stream, so pick up where it left off
+//!     events = events.reconnect(&mut client, "http://example.domain/feed").unwrap();
+//! }
+//! ```
+use std::io::{self, BufRead};
+
+use header::{Accept, CacheControl, CacheDirective, Headers, qitem};
+use mime::{Mime, TopLevel, SubLevel};
+use super::{Client, IntoUrl, Response};
+
+/// An event sent by the server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// The type of the event, `message` unless the server named one.
+    pub event: String,
+    /// The data lines of the event, joined with newlines.
+    pub data: String,
+    /// The last event id the server sent, as of this event.
+    pub id: Option<String>,
+}
+
+/// An iterator of the events in a `text/event-stream` body.
+///
+/// Iteration ends when the body does, dropping any event that wasn't
+/// finished with a blank line.
+pub struct EventStream<R> {
+    body: R,
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+    started: bool,
+}
+
+/// Request a `text/event-stream` from `url`.
+pub fn open<U: IntoUrl>(client: &mut Client, url: U) -> ::Result<EventStream<Response>> {
+    connect(client, url, None, None)
+}
+
+fn connect<U: IntoUrl>(client: &mut Client, url: U, last_event_id: Option<&str>,
+                       retry: Option<u64>) -> ::Result<EventStream<Response>> {
+    let mut headers = Headers::new();
+    headers.set(Accept(vec![
+        qitem(Mime(TopLevel::Text, SubLevel::Ext("event-stream".to_string()), vec![]))
+    ]));
+    headers.set(CacheControl(vec![CacheDirective::NoCache]));
+    if let Some(id) = last_event_id {
+        headers.set_raw("Last-Event-ID", vec![id.as_bytes().to_vec()]);
+    }
+    let mut events = EventStream::new(try!(client.get(url).headers(headers).send()));
+    events.last_event_id = last_event_id.map(|id| id.to_string());
+    events.retry = events.retry.or(retry);
+    Ok(events)
+}
+
+impl<R: BufRead> EventStream<R> {
+    /// Parse events from a body.
+    pub fn new(body: R) -> EventStream<R> {
+        EventStream {
+            body: body,
+            last_event_id: None,
+            retry: None,
+            started: false,
+        }
+    }
+
+    /// The id of the last event, to resume the stream from.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_ref().map(|id| &id[..])
+    }
+
+    /// How many milliseconds the server asked to wait before reconnecting,
+    /// if it did.
+    pub fn retry(&self) -> Option<u64> {
+        self.retry
+    }
+
+    // A line, without its line ending, or None at the end of the body.
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        if try!(self.body.read_until(b'\n', &mut line)) == 0 {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else {
+            // a line cut off by the end of the body is never dispatched
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !self.started {
+            self.started = true;
+            if line.starts_with(b"\xEF\xBB\xBF") {
+                line = line[3..].to_vec();
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+}
+
+impl EventStream<Response> {
+    /// Request the stream again, after the server closed it.
+    ///
+    /// The request carries `Last-Event-ID`, so the server can send only the
+    /// events that were missed. Waiting the `retry` delay first is up to
+    /// the caller.
+    pub fn reconnect<U: IntoUrl>(&self, client: &mut Client, url: U)
+        -> ::Result<EventStream<Response>> {
+        connect(client, url, self.last_event_id(), self.retry)
+    }
+}
+
+impl<R: BufRead> Iterator for EventStream<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        let mut event = None;
+        let mut data = String::new();
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e))
+            };
+            if line.is_empty() {
+                if data.is_empty() {
+                    event = None;
+                    continue;
+                }
+                data.pop();
+                return Some(Ok(Event {
+                    event: event.unwrap_or_else(|| "message".to_string()),
+                    data: data,
+                    id: self.last_event_id.clone(),
+                }));
+            }
+            if line.starts_with(":") {
+                continue;
+            }
+            let (field, value) = match line.find(':') {
+                Some(pos) => {
+                    let value = &line[pos + 1..];
+                    (&line[..pos], if value.starts_with(" ") { &value[1..] } else { value })
+                },
+                None => (&line[..], "")
+            };
+            match field {
+                "event" => event = Some(value.to_string()),
+                "data" => {
+                    data.push_str(value);
+                    data.push('\n');
+                },
+                "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+                "retry" => {
+                    if let Ok(ms) = value.parse() {
+                        self.retry = Some(ms);
+                    }
+                },
+                _ => trace!("ignoring event stream field {:?}", field)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use client::Response;
+    use mock::MockStream;
+    use super::{Event, EventStream};
+
+    fn event(event: &str, data: &str, id: Option<&str>) -> Event {
+        Event {
+            event: event.to_string(),
+            data: data.to_string(),
+            id: id.map(|id| id.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let body = "\u{FEFF}: a comment\n\
+                    data: first\n\
+                    data:second\n\
+                    \n\
+                    event: update\r\n\
+                    id: 7\r\n\
+                    retry: 2500\r\n\
+                    data\r\n\
+                    \r\n\
+                    id: 8\n\
+                    \n\
+                    data: unfinished\n";
+        let mut events = EventStream::new(Cursor::new(body.as_bytes()));
+        assert_eq!(events.next().unwrap().unwrap(), event("message", "first\nsecond", None));
+        assert_eq!(events.next().unwrap().unwrap(), event("update", "", Some("7")));
+        assert!(events.next().is_none());
+        assert_eq!(events.last_event_id(), Some("8"));
+        assert_eq!(events.retry(), Some(2500));
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let stream = MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Type: text/event-stream\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\n\
+            data:\r\n\
+            8\r\n hello\n\n\r\n\
+            0\r\n\
+            \r\n");
+        let res = Response::new(Box::new(stream)).unwrap();
+        let events = EventStream::new(res).map(|e| e.unwrap()).collect::<Vec<_>>();
+        assert_eq!(events, vec![event("message", "hello", None)]);
+    }
+}
@@ -0,0 +1,324 @@
+//! Canned responses for testing code that uses a `Client`.
+//!
+//! A `StubConnector` never touches the network. Each request made through
+//! it is matched against a list of routes, by method and URL, and answered
+//! with the canned response of the first route that matches.
+//!
+//! ```
+//! # use std::io::Read;
+//! use hyper::Client;
+//! use hyper::method::Method::Get;
+//! use hyper::client::stub::{StubConnector, StubResponse};
+//! use hyper::status::StatusCode;
+//!
+//! let stub = StubConnector::new();
+//! stub.route(Some(Get), "http://example.domain/users/*",
+//!            StubResponse::new(StatusCode::Ok).body("[]"));
+//!
+//! let mut client = Client::with_connector(stub);
+//! let mut res = client.get("http://example.domain/users/1").send().unwrap();
+//! let mut body = String::new();
+//! res.read_to_string(&mut body).unwrap();
+//! assert_eq!(body, "[]");
+//! ```
+use std::fmt;
+use std::io::{self, Read, Write, Cursor};
+use std::net::SocketAddr;
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use header::{Header, HeaderFormat, Headers, ContentLength};
+use http::LINE_ENDING;
+use method::Method;
+use net::{NetworkConnector, NetworkStream, ContextVerifier};
+use status::StatusCode;
+
+/// A response to answer matching requests with.
+pub struct StubResponse {
+    status: StatusCode,
+    headers: Headers,
+    body: StubBody,
+}
+
+enum StubBody {
+    Bytes(Vec<u8>),
+    Stream(Option<Box<Read + Send>>),
+}
+
+impl StubResponse {
+    /// A response with this status, and an empty body.
+    pub fn new(status: StatusCode) -> StubResponse {
+        StubResponse {
+            status: status,
+            headers: Headers::new(),
+            body: StubBody::Bytes(Vec::new()),
+        }
+    }
+
+    /// Include this header in the response.
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> StubResponse {
+        self.headers.set(header);
+        self
+    }
+
+    /// Answer with this body. A `Content-Length` is included for it.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> StubResponse {
+        self.body = StubBody::Bytes(body.into());
+        self
+    }
+
+    /// Answer with a body streamed from a reader, until it reaches EOF.
+    ///
+    /// A reader can only be read once, so a route with a streaming body
+    /// only matches a single request.
+    pub fn stream<R: Read + Send + 'static>(mut self, body: R) -> StubResponse {
+        self.body = StubBody::Stream(Some(Box::new(body)));
+        self
+    }
+
+    fn reader(&mut self) -> Box<Read + Send> {
+        let body: Box<Read + Send> = match self.body {
+            StubBody::Bytes(ref bytes) => Box::new(Cursor::new(bytes.clone())),
+            StubBody::Stream(ref mut stream) => stream.take().unwrap(),
+        };
+        let mut headers = self.headers.clone();
+        if let StubBody::Bytes(ref bytes) = self.body {
+            if !headers.has::<ContentLength>() {
+                headers.set(ContentLength(bytes.len() as u64));
+            }
+        }
+        let head = format!("HTTP/1.1 {}{}{}{}", self.status, LINE_ENDING, headers, LINE_ENDING);
+        Box::new(Cursor::new(head.into_bytes()).chain(body))
+    }
+
+    fn is_spent(&self) -> bool {
+        match self.body {
+            StubBody::Stream(None) => true,
+            _ => false
+        }
+    }
+}
+
+impl From<(StatusCode, Headers, Vec<u8>)> for StubResponse {
+    fn from(parts: (StatusCode, Headers, Vec<u8>)) -> StubResponse {
+        let (status, headers, body) = parts;
+        StubResponse {
+            status: status,
+            headers: headers,
+            body: StubBody::Bytes(body),
+        }
+    }
+}
+
+impl fmt::Debug for StubResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StubResponse {{ status: {:?}, headers: {:?} }}", self.status, self.headers)
+    }
+}
+
+struct Route {
+    method: Option<Method>,
+    pattern: String,
+    response: StubResponse,
+}
+
+impl Route {
+    fn matches(&self, method: &Method, url: &str) -> bool {
+        if let Some(ref m) = self.method {
+            if m != method {
+                return false;
+            }
+        }
+        if self.response.is_spent() {
+            return false;
+        }
+        if self.pattern.ends_with('*') {
+            url.starts_with(&self.pattern[..self.pattern.len() - 1])
+        } else {
+            url == self.pattern
+        }
+    }
+}
+
+/// A `NetworkConnector` that answers requests with canned responses.
+pub struct StubConnector {
+    routes: Arc<Mutex<Vec<Route>>>,
+}
+
+impl StubConnector {
+    /// Create a `StubConnector` without any routes.
+    pub fn new() -> StubConnector {
+        StubConnector {
+            routes: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answer requests matching `method` and `pattern` with `response`.
+    ///
+    /// A `None` method matches any method. The pattern is compared to the
+    /// full URL of a request, such as `http://example.domain/path?query`.
+    /// The port is only included if it isn't the default for the scheme.
+    /// A pattern ending in `*` matches any URL starting with the rest of it.
+    ///
+    /// Routes are tried in the order they were added.
+    pub fn route<P: Into<String>>(&self, method: Option<Method>, pattern: P,
+                                  response: StubResponse) {
+        self.routes.lock().unwrap().push(Route {
+            method: method,
+            pattern: pattern.into(),
+            response: response,
+        });
+    }
+}
+
+impl NetworkConnector for StubConnector {
+    type Stream = StubStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<StubStream> {
+        let origin = match (scheme, port) {
+            ("http", 80) | ("https", 443) => format!("{}://{}", scheme, host),
+            _ => format!("{}://{}:{}", scheme, host, port)
+        };
+        Ok(StubStream {
+            routes: self.routes.clone(),
+            origin: origin,
+            written: Vec::new(),
+            response: None,
+        })
+    }
+
+    fn set_ssl_verifier(&mut self, _verifier: ContextVerifier) {
+        // no TLS to verify
+    }
+}
+
+/// The stream created by a `StubConnector`.
+///
+/// The request written to it is matched against the routes when the
+/// response is first read.
+pub struct StubStream {
+    routes: Arc<Mutex<Vec<Route>>>,
+    origin: String,
+    written: Vec<u8>,
+    response: Option<Box<Read + Send>>,
+}
+
+impl StubStream {
+    fn respond(&mut self) -> io::Result<Box<Read + Send>> {
+        let (method, target) = {
+            let line = self.written.split(|&b| b == b'\r').next().unwrap_or(&[]);
+            let mut parts = str::from_utf8(line).unwrap_or("").split(' ');
+            (parts.next().unwrap_or("").parse::<Method>(), parts.next().unwrap_or(""))
+        };
+        let method = match method {
+            Ok(method) => method,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "no request written to StubStream"))
+        };
+        let url = format!("{}{}", self.origin, target);
+        debug!("StubStream matching {} {}", method, url);
+
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|route| route.matches(&method, &url)) {
+            Some(route) => Ok(route.response.reader()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound,
+                                       format!("no stub matched {} {}", method, url)))
+        }
+    }
+}
+
+impl Read for StubStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            self.response = Some(try!(self.respond()));
+        }
+        self.response.as_mut().unwrap().read(buf)
+    }
+}
+
+impl Write for StubStream {
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        Write::write(&mut self.written, msg)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for StubStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok("127.0.0.1:0".parse().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use client::Client;
+    use header::ContentLength;
+    use method::Method::{Get, Post};
+    use status::StatusCode;
+    use super::{StubConnector, StubResponse};
+
+    fn body(mut r: ::client::Response) -> String {
+        let mut s = String::new();
+        r.read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_stub_routes() {
+        let stub = StubConnector::new();
+        stub.route(Some(Post), "http://example.domain/users",
+                   StubResponse::new(StatusCode::Created).body("created"));
+        stub.route(Some(Get), "http://example.domain:8080/users/*",
+                   StubResponse::new(StatusCode::Ok).body("user"));
+        stub.route(None, "*", StubResponse::new(StatusCode::NotFound));
+
+        let mut client = Client::with_connector(stub);
+
+        let res = client.post("http://example.domain/users").body("x").send().unwrap();
+        assert_eq!(res.status, StatusCode::Created);
+        assert_eq!(body(res), "created");
+
+        let res = client.get("http://example.domain:8080/users/1").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.headers.get(), Some(&ContentLength(4)));
+        assert_eq!(body(res), "user");
+
+        let res = client.get("http://example.domain/users/1").send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_stub_from_parts() {
+        use header::{Headers, Server};
+
+        let mut headers = Headers::new();
+        headers.set(Server("stub".to_string()));
+        let stub = StubConnector::new();
+        stub.route(None, "*", (StatusCode::Accepted, headers, b"parts".to_vec()).into());
+
+        let mut client = Client::with_connector(stub);
+        let res = client.get("http://example.domain/").send().unwrap();
+        assert_eq!(res.status, StatusCode::Accepted);
+        assert_eq!(res.headers.get(), Some(&Server("stub".to_string())));
+        assert_eq!(body(res), "parts");
+    }
+
+    #[test]
+    fn test_stub_stream_once() {
+        let stub = StubConnector::new();
+        stub.route(None, "http://example.domain/",
+                   StubResponse::new(StatusCode::Ok).stream(Cursor::new(b"streamed".to_vec())));
+
+        let mut client = Client::with_connector(stub);
+        let res = client.get("http://example.domain/").send().unwrap();
+        assert!(res.headers.get::<ContentLength>().is_none());
+        assert_eq!(body(res), "streamed");
+
+        assert!(client.get("http://example.domain/").send().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! URI Templates, as defined in [RFC 6570](https://tools.ietf.org/html/rfc6570).
+//!
+//! REST APIs often publish the URLs of their resources as templates, such
+//! as `https://api.example.domain/users{/id}{?page,per_page}`. A
+//! `UriTemplate` expands one with a set of variables, into a URL that can be
+//! handed straight to the `Client`.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use hyper::client::template::UriTemplate;
+//!
+//! let users = UriTemplate::new("http://example.domain/users{/id}{?page,per_page}");
+//! let url = users.expand(vec![("id", "42"), ("page", "2")]);
+//! assert_eq!(url, "http://example.domain/users/42?page=2");
+//!
+//! let mut client = Client::new();
+//! let res = client.get(url).send().unwrap();
+//! ```
+//!
+//! All the expression operators of level 3 are supported, along with the
+//! prefix modifier of level 4. Values are strings, so the explode modifier
+//! is accepted but changes nothing. Variables without a value are left out
+//! of the expansion.
+
+/// A URI Template, ready to be expanded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UriTemplate {
+    template: String,
+}
+
+impl UriTemplate {
+    /// Create a template from its source.
+    pub fn new<S: Into<String>>(template: S) -> UriTemplate {
+        UriTemplate {
+            template: template.into(),
+        }
+    }
+
+    /// Expand the template, with the values of the variables it uses.
+    ///
+    /// An expression that is never closed is copied through unchanged.
+    pub fn expand<I, K, V>(&self, vars: I) -> String
+    where I: IntoIterator<Item=(K, V)>, K: AsRef<str>, V: AsRef<str> {
+        let vars = vars.into_iter()
+            .map(|(k, v)| (k.as_ref().to_string(), v.as_ref().to_string()))
+            .collect::<Vec<_>>();
+        let mut url = String::with_capacity(self.template.len());
+        let mut rest = &self.template[..];
+        while let Some(start) = rest.find('{') {
+            url.push_str(&rest[..start]);
+            match rest[start..].find('}') {
+                Some(end) => {
+                    expand_expr(&mut url, &rest[start + 1..start + end], &vars);
+                    rest = &rest[start + end + 1..];
+                },
+                None => break
+            }
+        }
+        url.push_str(rest);
+        url
+    }
+}
+
+struct Operator {
+    first: &'static str,
+    sep: char,
+    named: bool,
+    if_empty: &'static str,
+    allow_reserved: bool,
+}
+
+// The table of operator behavior, from Appendix A of the RFC.
+fn operator(op: char) -> Option<Operator> {
+    let (first, sep, named, if_empty, allow_reserved) = match op {
+        '+' => ("", ',', false, "", true),
+        '#' => ("#", ',', false, "", true),
+        '.' => (".", '.', false, "", false),
+        '/' => ("/", '/', false, "", false),
+        ';' => (";", ';', true, "", false),
+        '?' => ("?", '&', true, "=", false),
+        '&' => ("&", '&', true, "=", false),
+        _ => return None
+    };
+    Some(Operator {
+        first: first,
+        sep: sep,
+        named: named,
+        if_empty: if_empty,
+        allow_reserved: allow_reserved,
+    })
+}
+
+fn expand_expr(url: &mut String, expr: &str, vars: &[(String, String)]) {
+    let (op, list) = match expr.chars().next().and_then(operator) {
+        Some(op) => (op, &expr[1..]),
+        None => (Operator {
+            first: "",
+            sep: ',',
+            named: false,
+            if_empty: "",
+            allow_reserved: false,
+        }, expr)
+    };
+
+    let mut first = true;
+    for spec in list.split(',') {
+        let (name, prefix) = match spec.find(':') {
+            Some(pos) => (&spec[..pos], spec[pos + 1..].parse::<usize>().ok()),
+            None => (spec.trim_right_matches('*'), None)
+        };
+        let value = match vars.iter().find(|&&(ref k, _)| k == name) {
+            Some(&(_, ref v)) => v,
+            None => continue
+        };
+        let value = match prefix {
+            Some(len) => value.chars().take(len).collect::<String>(),
+            None => value.clone()
+        };
+
+        if first {
+            url.push_str(op.first);
+            first = false;
+        } else {
+            url.push(op.sep);
+        }
+        if op.named {
+            url.push_str(name);
+            if value.is_empty() {
+                url.push_str(op.if_empty);
+                continue;
+            }
+            url.push('=');
+        }
+        encode(url, &value, op.allow_reserved);
+    }
+}
+
+fn encode(url: &mut String, value: &str, allow_reserved: bool) {
+    for &b in value.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                url.push(b as char)
+            },
+            b':' | b'/' | b'?' | b'#' | b'[' | b']' | b'@' | b'!' | b'$' | b'&' |
+            b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b'%'
+                if allow_reserved => url.push(b as char),
+            _ => url.push_str(&format!("%{:02X}", b))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UriTemplate;
+
+    fn expand(template: &str) -> String {
+        let vars = vec![
+            ("var", "value"),
+            ("hello", "Hello World!"),
+            ("path", "/foo/bar"),
+            ("empty", ""),
+            ("x", "1024"),
+            ("y", "768"),
+        ];
+        UriTemplate::new(template).expand(vars)
+    }
+
+    #[test]
+    fn test_expand_rfc_examples() {
+        assert_eq!(expand("{var}"), "value");
+        assert_eq!(expand("{hello}"), "Hello%20World%21");
+        assert_eq!(expand("{+hello}"), "Hello%20World!");
+        assert_eq!(expand("{+path}/here"), "/foo/bar/here");
+        assert_eq!(expand("{#path,x}/here"), "#/foo/bar,1024/here");
+        assert_eq!(expand("X{.var,x}"), "X.value.1024");
+        assert_eq!(expand("{/var,x}/here"), "/value/1024/here");
+        assert_eq!(expand("{;x,y,empty}"), ";x=1024;y=768;empty");
+        assert_eq!(expand("{?x,y,empty}"), "?x=1024&y=768&empty=");
+        assert_eq!(expand("?fixed=yes{&x}"), "?fixed=yes&x=1024");
+        assert_eq!(expand("{var:3}"), "val");
+        assert_eq!(expand("{/var*}"), "/value");
+    }
+
+    #[test]
+    fn test_expand_undefined() {
+        assert_eq!(expand("/users{/undef}{?undef,x}"), "/users?x=1024");
+        assert_eq!(expand("{?undef}"), "");
+        assert_eq!(expand("/open{var"), "/open{var");
+    }
+}
@@ -0,0 +1,184 @@
+//! The client side of the WebSocket opening handshake.
+//!
+//! A WebSocket connection starts as an HTTP/1.1 request asking the server
+//! to switch protocols, as described in
+//! [RFC 6455](https://tools.ietf.org/html/rfc6455#section-4.1). `handshake`
+//! sends that request and checks the server's answer, then hands back the
+//! connection. A WebSocket library can so open its connections with
+//! hyper's connectors and TLS, and only needs to handle frames.
+//!
+//! ```no_run
+//! use hyper::client::websocket;
+//! use hyper::Url;
+//!
+//! let ws = websocket::connect(Url::parse("wss://example.domain/chat").unwrap()).unwrap();
+//! // frames go over ws.stream, starting with any bytes in ws.buffered
+//! ```
+use openssl::crypto::hash::{hash, Type};
+use openssl::crypto::rand::rand_bytes;
+use serialize::base64::{ToBase64, STANDARD};
+use unicase::UniCase;
+
+use header::{Connection, ConnectionOption, Headers, Upgrade, Protocol, ProtocolName};
+use method::Method;
+use net::{Fresh, NetworkStream};
+use status::StatusCode;
+use super::Request;
+use {Error, Url};
+
+// Appended to the key before hashing it, from section 1.3 of the RFC.
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A connection after a successful opening handshake.
+pub struct WebSocket {
+    /// The connection, now speaking the WebSocket protocol.
+    pub stream: Box<NetworkStream + Send>,
+    /// Bytes the server sent right after its response, which are the start
+    /// of the first frames.
+    pub buffered: Vec<u8>,
+    /// The headers of the server's response, such as
+    /// `Sec-WebSocket-Protocol`.
+    pub headers: Headers,
+}
+
+/// Open a WebSocket connection to a `ws` or `wss` URL.
+///
+/// The connection is made with an `HttpConnector`. To use another, or to
+/// send headers such as `Origin` or `Sec-WebSocket-Protocol`, make the
+/// request yourself and pass it to `handshake`.
+pub fn connect(mut url: Url) -> ::Result<WebSocket> {
+    let scheme = match &url.scheme[..] {
+        "ws" => "http",
+        "wss" => "https",
+        other => other
+    }.to_string();
+    url.scheme = scheme;
+    handshake(try!(Request::new(Method::Get, url)))
+}
+
+/// Perform the opening handshake over a fresh `GET` request.
+///
+/// The handshake headers are added to whatever headers the request has.
+/// If the server doesn't switch protocols, this fails with `Error::Status`,
+/// and if its response has the wrong upgrade headers or accept key, with
+/// `Error::Header`.
+pub fn handshake(req: Request<Fresh>) -> ::Result<WebSocket> {
+    handshake_with_key(req, rand_bytes(16).to_base64(STANDARD))
+}
+
+fn handshake_with_key(mut req: Request<Fresh>, key: String) -> ::Result<WebSocket> {
+    {
+        let headers = req.headers_mut();
+        headers.set(Connection(vec![
+            ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_string()))
+        ]));
+        headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+        headers.set_raw("Sec-WebSocket-Key", vec![key.clone().into_bytes()]);
+        headers.set_raw("Sec-WebSocket-Version", vec![b"13".to_vec()]);
+    }
+    let res = try!(try!(req.start()).send());
+    if res.status != StatusCode::SwitchingProtocols {
+        debug!("websocket handshake refused with {}", res.status);
+        return Err(Error::Status);
+    }
+
+    let upgraded = match res.headers.get::<Upgrade>() {
+        Some(&Upgrade(ref protocols)) => {
+            protocols.iter().any(|p| p.name == ProtocolName::WebSocket)
+        },
+        None => false
+    };
+    let connection = match res.headers.get::<Connection>() {
+        Some(&Connection(ref options)) => options.iter().any(|opt| match *opt {
+            ConnectionOption::ConnectionHeader(ref name) => {
+                *name == UniCase("upgrade".to_string())
+            },
+            _ => false
+        }),
+        None => false
+    };
+    let accepted = match res.headers.get_raw("Sec-WebSocket-Accept") {
+        Some(values) if values.len() == 1 => values[0] == accept_key(&key).into_bytes(),
+        _ => false
+    };
+    if !(upgraded && connection && accepted) {
+        debug!("websocket handshake invalid, upgrade={}, connection={}, accept={}",
+               upgraded, connection, accepted);
+        return Err(Error::Header);
+    }
+
+    let headers = res.headers.clone();
+    match res.upgrade() {
+        Ok((stream, buffered)) => Ok(WebSocket {
+            stream: stream,
+            buffered: buffered,
+            headers: headers,
+        }),
+        // the status was checked above
+        Err(_) => unreachable!()
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value a server answers `key` with.
+pub fn accept_key(key: &str) -> String {
+    let mut input = key.as_bytes().to_vec();
+    input.extend(GUID.as_bytes().iter().cloned());
+    hash(Type::SHA1, &input).to_base64(STANDARD)
+}
+
+#[cfg(test)]
+mod tests {
+    use client::Request;
+    use method::Method::Get;
+    use url::Url;
+    use Error;
+    use super::{accept_key, handshake_with_key};
+
+    const KEY: &'static str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    #[test]
+    fn test_accept_key() {
+        // the example from section 1.3 of the RFC
+        assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZK+xOo=");
+    }
+
+    mock_connector!(MockWebSocket {
+        "http://127.0.0.1" =>       "HTTP/1.1 101 Switching Protocols\r\n\
+                                     Upgrade: websocket\r\n\
+                                     Connection: Upgrade\r\n\
+                                     Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZK+xOo=\r\n\
+                                     \r\n\
+                                     first frame"
+        "http://127.0.0.2" =>       "HTTP/1.1 101 Switching Protocols\r\n\
+                                     Upgrade: websocket\r\n\
+                                     Connection: Upgrade\r\n\
+                                     Sec-WebSocket-Accept: bm90IHRoZSByaWdodCBrZXk=\r\n\
+                                     \r\n"
+        "http://127.0.0.3" =>       "HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n"
+    });
+
+    fn request(url: &str) -> Request<::net::Fresh> {
+        Request::with_connector(Get, Url::parse(url).unwrap(), &MockWebSocket).unwrap()
+    }
+
+    #[test]
+    fn test_handshake() {
+        let ws = handshake_with_key(request("http://127.0.0.1/chat"), KEY.to_string()).unwrap();
+        assert_eq!(ws.buffered, b"first frame");
+        assert!(ws.headers.get_raw("Sec-WebSocket-Accept").is_some());
+    }
+
+    #[test]
+    fn test_handshake_rejected() {
+        match handshake_with_key(request("http://127.0.0.2/chat"), KEY.to_string()) {
+            Err(Error::Header) => (),
+            other => panic!("expected Header, got {:?}", other.map(|_| ()))
+        }
+        match handshake_with_key(request("http://127.0.0.3/chat"), KEY.to_string()) {
+            Err(Error::Status) => (),
+            other => panic!("expected Status, got {:?}", other.map(|_| ()))
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Time sources.
+//!
+//! Anything in hyper that needs to know the current time, such as writing
+//! the `Date` header or deciding whether an idle connection has expired,
+//! asks a `Clock`. By default that is the `SystemClock`, but a `TestClock`
+//! can be swapped in so time-dependent behavior can be tested without
+//! sleeping.
+use std::sync::{Arc, Mutex};
+
+use time::{self, Duration, Timespec, Tm};
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> Timespec;
+
+    /// The current time, broken down in UTC.
+    fn now_utc(&self) -> Tm {
+        time::at_utc(self.now())
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    #[inline]
+    fn now(&self) -> Timespec {
+        (**self).now()
+    }
+}
+
+/// A `Clock` that reads the system time.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Timespec {
+        time::get_time()
+    }
+}
+
+/// A `Clock` that only moves when told to.
+#[derive(Debug)]
+pub struct TestClock(Mutex<Timespec>);
+
+impl TestClock {
+    /// Create a `TestClock` stopped at `now`.
+    pub fn new(now: Timespec) -> TestClock {
+        TestClock(Mutex::new(now))
+    }
+
+    /// Set the current time.
+    pub fn set(&self, now: Timespec) {
+        *self.0.lock().unwrap() = now;
+    }
+
+    /// Move the current time forward by `dur`.
+    pub fn advance(&self, dur: Duration) {
+        let mut now = self.0.lock().unwrap();
+        *now = *now + dur;
+    }
+}
+
+impl Clock for TestClock {
+    #[inline]
+    fn now(&self) -> Timespec {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, Timespec};
+    use super::{Clock, TestClock};
+
+    #[test]
+    fn test_test_clock_advance() {
+        let clock = TestClock::new(Timespec::new(784111777, 0));
+        assert_eq!(clock.now(), Timespec::new(784111777, 0));
+        clock.advance(Duration::seconds(30));
+        assert_eq!(clock.now(), Timespec::new(784111807, 0));
+        assert_eq!(clock.now_utc().tm_year, 94);
+    }
+}
@@ -5,6 +5,8 @@ use std::io::Error as IoError;
 
 use httparse;
 use openssl::ssl::error::SslError;
+#[cfg(feature = "json")]
+use serde_json;
 use url;
 
 use self::Error::{
@@ -15,7 +17,12 @@ use self::Error::{
     Status,
     Io,
     Ssl,
-    TooLarge
+    TooLarge,
+    Cancelled,
+    Timeout,
+    TlsPolicy,
+    PinMismatch,
+    CertificateRejected
 };
 
 
@@ -33,14 +40,31 @@ pub enum Error {
     Version,
     /// An invalid `Header`.
     Header,
-    /// A message head is too large to be reasonable.
+    /// A message head is too large to be reasonable, or a body is larger
+    /// than the limit it was read with.
     TooLarge,
     /// An invalid `Status`, such as `1337 ELITE`.
     Status,
     /// An `io::Error` that occurred while trying to read or write to a network stream.
     Io(IoError),
     /// An error from the `openssl` library.
-    Ssl(SslError)
+    Ssl(SslError),
+    /// The request was cancelled with a `CancelHandle`.
+    Cancelled,
+    /// A limit on how long to wait, such as for a pooled connection, ran out.
+    Timeout,
+    /// A TLS connection didn't meet the `TlsPolicy` it was made with, such
+    /// as by offering only an older protocol version. It holds the reason.
+    TlsPolicy(String),
+    /// No certificate in the chain a host presented had a public key that
+    /// was pinned for it.
+    PinMismatch,
+    /// A certificate callback rejected the certificates a host presented.
+    CertificateRejected,
+    /// A value couldn't be serialized to JSON, or a body wasn't the JSON
+    /// it was expected to be.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
 }
 
 impl fmt::Display for Error {
@@ -56,10 +80,17 @@ impl StdError for Error {
             Uri(_) => "Invalid Request URI specified",
             Version => "Invalid HTTP version specified",
             Header => "Invalid Header provided",
-            TooLarge => "Message head is too large",
+            TooLarge => "Message is too large",
             Status => "Invalid Status provided",
             Io(ref e) => e.description(),
             Ssl(ref e) => e.description(),
+            Cancelled => "Request was cancelled",
+            Timeout => "Timed out",
+            TlsPolicy(_) => "TLS connection did not meet the required policy",
+            PinMismatch => "Certificate chain did not match a pinned key",
+            CertificateRejected => "Certificate was rejected",
+            #[cfg(feature = "json")]
+            Error::Json(ref e) => e.description(),
         }
     }
 
@@ -68,6 +99,8 @@ impl StdError for Error {
             Io(ref error) => Some(error),
             Ssl(ref error) => Some(error),
             Uri(ref error) => Some(error),
+            #[cfg(feature = "json")]
+            Error::Json(ref error) => Some(error),
             _ => None,
         }
     }
@@ -94,6 +127,13 @@ impl From<SslError> for Error {
     }
 }
 
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
 impl From<httparse::Error> for Error {
     fn from(err: httparse::Error) -> Error {
         match err {
@@ -1,6 +1,6 @@
-use mime::Mime;
+use mime::{Mime, SubLevel, TopLevel};
 
-use header::QualityItem;
+use header::{Quality, QualityItem};
 
 header! {
     #[doc="`Accept` header, defined in [RFC7231](http://tools.ietf.org/html/rfc7231#section-5.3.2)"]
@@ -68,6 +68,89 @@ header! {
                     SubLevel::Plain, vec![(Attr::Charset, Value::Utf8)]),
                     Quality(500)),
             ])));
+
+        #[test]
+        fn test_best_match() {
+            let json = Mime(TopLevel::Application, SubLevel::Json, vec![]);
+            let html = Mime(TopLevel::Text, SubLevel::Html, vec![]);
+            let plain = Mime(TopLevel::Text, SubLevel::Plain, vec![]);
+            let available = [json.clone(), html.clone(), plain.clone()];
+
+            let accept = Accept(vec![
+                QualityItem::new(Mime(TopLevel::Text, SubLevel::Star, vec![]), Quality(500)),
+                qitem(html.clone()),
+            ]);
+            assert_eq!(accept.best_match(&available), Some(&html));
+
+            // the most specific range wins, even with a lower quality
+            let accept = Accept(vec![
+                qitem(Mime(TopLevel::Star, SubLevel::Star, vec![])),
+                QualityItem::new(json.clone(), Quality(0)),
+            ]);
+            assert_eq!(accept.best_match(&available), Some(&html));
+
+            // ties go to the order of the available types
+            let accept = Accept(vec![qitem(plain.clone()), qitem(html.clone())]);
+            assert_eq!(accept.best_match(&available), Some(&html));
+
+            let accept = Accept(vec![qitem(Mime(TopLevel::Image, SubLevel::Png, vec![]))]);
+            assert_eq!(accept.best_match(&available), None);
+        }
+    }
+}
+
+impl Accept {
+    /// Pick the media type to respond with, out of those the server can
+    /// produce, as described in
+    /// [RFC7231](http://tools.ietf.org/html/rfc7231#section-5.3.2).
+    ///
+    /// Each available type takes the quality of the most specific range that
+    /// matches it, and the one with the highest quality wins, with ties going
+    /// to the earlier type in `available`. Returns `None` if every type is
+    /// unacceptable, which a server would answer with `406 Not Acceptable`.
+    pub fn best_match<'a>(&self, available: &'a [Mime]) -> Option<&'a Mime> {
+        let mut best = None;
+        for mime in available {
+            let quality = match self.quality_of(mime) {
+                Some(Quality(0)) | None => continue,
+                Some(quality) => quality
+            };
+            match best {
+                Some((_, best_quality)) if best_quality >= quality => (),
+                _ => best = Some((mime, quality))
+            }
+        }
+        best.map(|(mime, _)| mime)
+    }
+
+    // The quality of the most specific matching range: a type with its
+    // parameters, then the type alone, then `type/*`, then `*/*`.
+    fn quality_of(&self, mime: &Mime) -> Option<Quality> {
+        let mut found: Option<(u8, Quality)> = None;
+        for range in self.0.iter() {
+            let Mime(ref top, ref sub, ref params) = range.item;
+            let Mime(ref mime_top, ref mime_sub, ref mime_params) = *mime;
+            let specificity = if *top == TopLevel::Star {
+                1
+            } else if top != mime_top {
+                continue
+            } else if *sub == SubLevel::Star {
+                2
+            } else if sub != mime_sub {
+                continue
+            } else if params.is_empty() {
+                3
+            } else if params.iter().all(|p| mime_params.contains(p)) {
+                4
+            } else {
+                continue
+            };
+            match found {
+                Some((best, _)) if best >= specificity => (),
+                _ => found = Some((specificity, range.quality))
+            }
+        }
+        found.map(|(_, quality)| quality)
     }
 }
 
@@ -153,9 +153,38 @@ impl FromStr for Basic {
     }
 }
 
+/// Credential holder for Bearer Authentication, as defined in
+/// [RFC6750](https://tools.ietf.org/html/rfc6750)
+#[derive(Clone, PartialEq, Debug)]
+pub struct Bearer {
+    /// The bearer token
+    pub token: String
+}
+
+impl Scheme for Bearer {
+    fn scheme() -> Option<&'static str> {
+        Some("Bearer")
+    }
+
+    fn fmt_scheme(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.token)
+    }
+}
+
+impl FromStr for Bearer {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Bearer, ()> {
+        let token = s.trim();
+        if token.is_empty() || token.contains(' ') {
+            return Err(());
+        }
+        Ok(Bearer { token: token.to_string() })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Authorization, Basic};
+    use super::{Authorization, Basic, Bearer};
     use super::super::super::{Headers, Header};
 
     #[test]
@@ -205,6 +234,17 @@ mod tests {
         assert_eq!(auth.0.password, Some("".to_string()));
     }
 
+    #[test]
+    fn test_bearer_auth() {
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer { token: "mF_9.B5f-4.1JqM".to_string() }));
+        assert_eq!(headers.to_string(), "Authorization: Bearer mF_9.B5f-4.1JqM\r\n".to_string());
+
+        let auth: Authorization<Bearer> = Header::parse_header(
+            &[b"Bearer mF_9.B5f-4.1JqM".to_vec()]).unwrap();
+        assert_eq!(auth.0.token, "mF_9.B5f-4.1JqM");
+        assert!(Authorization::<Bearer>::parse_header(&[b"Basic QWxhZGRpbjo=".to_vec()]).is_none());
+    }
 }
 
 bench_header!(raw, Authorization<String>, { vec![b"foo bar baz".to_vec()] });
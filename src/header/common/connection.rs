@@ -25,10 +25,13 @@ pub enum ConnectionOption {
 impl FromStr for ConnectionOption {
     type Err = ();
     fn from_str(s: &str) -> Result<ConnectionOption, ()> {
-        match s {
-            "keep-alive" => Ok(KeepAlive),
-            "close" => Ok(Close),
-            s => Ok(ConnectionHeader(UniCase(s.to_string())))
+        // connection options are case-insensitive tokens
+        if UniCase(s) == UniCase("keep-alive") {
+            Ok(KeepAlive)
+        } else if UniCase(s) == UniCase("close") {
+            Ok(Close)
+        } else {
+            Ok(ConnectionHeader(UniCase(s.to_string())))
         }
     }
 }
@@ -68,6 +71,13 @@ header! {
         test_header!(test1, vec![b"close"]);
         test_header!(test2, vec![b"keep-alive"]);
         test_header!(test3, vec![b"upgrade"]);
+
+        #[test]
+        fn test_case_insensitive() {
+            let conn = HeaderField::parse_header(&[b"Close, Keep-Alive".to_vec()]);
+            assert_eq!(conn, Some(HeaderField(vec![ConnectionOption::Close,
+                                                   ConnectionOption::KeepAlive])));
+        }
     }
 }
 
@@ -1,28 +1,95 @@
-header! {
-    #[doc="`Content-Length` header, defined in"]
-    #[doc="[RFC7230](http://tools.ietf.org/html/rfc7230#section-3.3.2)"]
-    #[doc=""]
-    #[doc="When a message does not have a `Transfer-Encoding` header field, a"]
-    #[doc="Content-Length header field can provide the anticipated size, as a"]
-    #[doc="decimal number of octets, for a potential payload body.  For messages"]
-    #[doc="that do include a payload body, the Content-Length field-value"]
-    #[doc="provides the framing information necessary for determining where the"]
-    #[doc="body (and message) ends.  For messages that do not include a payload"]
-    #[doc="body, the Content-Length indicates the size of the selected"]
-    #[doc="representation."]
-    #[doc=""]
-    #[doc="# ABNF"]
-    #[doc="```plain"]
-    #[doc="Content-Length = 1*DIGIT"]
-    #[doc="```"]
-    #[doc=""]
-    #[doc="# Example values"]
-    #[doc="* `3495`"]
-    (ContentLength, "Content-Length") => [u64]
-
-    test_content_length {
+use std::fmt;
+
+use header::{Header, HeaderFormat};
+use header::parsing::from_digits;
+
+/// `Content-Length` header, defined in
+/// [RFC7230](http://tools.ietf.org/html/rfc7230#section-3.3.2)
+///
+/// When a message does not have a `Transfer-Encoding` header field, a
+/// Content-Length header field can provide the anticipated size, as a
+/// decimal number of octets, for a potential payload body.  For messages
+/// that do include a payload body, the Content-Length field-value
+/// provides the framing information necessary for determining where the
+/// body (and message) ends.  For messages that do not include a payload
+/// body, the Content-Length indicates the size of the selected
+/// representation.
+///
+/// The value is parsed strictly: a sign, or a number too large for a
+/// `u64`, is not a `Content-Length`. Repeated values, in one field or
+/// several, are merged if they are all the same, as RFC 7230 allows.
+///
+/// # ABNF
+/// ```plain
+/// Content-Length = 1*DIGIT
+/// ```
+///
+/// # Example values
+/// * `3495`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContentLength(pub u64);
+
+deref!(ContentLength => u64);
+
+impl Header for ContentLength {
+    fn header_name() -> &'static str {
+        "Content-Length"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<ContentLength> {
+        let mut len = None;
+        for value in raw.iter().flat_map(|line| line.split(|&b| b == b',')) {
+            match (len, from_digits(value)) {
+                (_, None) => return None,
+                (Some(len), Some(value)) if len != value => return None,
+                (_, value) => len = value
+            }
+        }
+        len.map(ContentLength)
+    }
+}
+
+impl HeaderFormat for ContentLength {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for ContentLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::Header;
+    use super::ContentLength;
+
+    fn parse(raw: &[&[u8]]) -> Option<ContentLength> {
+        let raw = raw.iter().map(|line| line.to_vec()).collect::<Vec<_>>();
+        ContentLength::parse_header(&raw)
+    }
+
+    #[test]
+    fn test_content_length() {
         // Testcase from RFC
-        test_header!(test1, vec![b"3495"], Some(HeaderField(3495)));
+        assert_eq!(parse(&[b"3495"]), Some(ContentLength(3495)));
+        assert_eq!(parse(&[b" 3495\t"]), Some(ContentLength(3495)));
+        assert_eq!(parse(&[b"18446744073709551615"]), Some(ContentLength(u64::max_value())));
+        assert_eq!(parse(&[b"5, 5", b"5"]), Some(ContentLength(5)));
+    }
+
+    #[test]
+    fn test_content_length_invalid() {
+        assert_eq!(parse(&[b"+5"]), None);
+        assert_eq!(parse(&[b"-5"]), None);
+        assert_eq!(parse(&[b"5\x0b"]), None);
+        assert_eq!(parse(&[b"5 5"]), None);
+        assert_eq!(parse(&[b""]), None);
+        assert_eq!(parse(&[b"18446744073709551616"]), None);
+        assert_eq!(parse(&[b"5", b"6"]), None);
+        assert_eq!(parse(&[b"5,"]), None);
     }
 }
 
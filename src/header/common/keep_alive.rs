@@ -0,0 +1,111 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::str;
+use header::{Header, HeaderFormat};
+
+/// `Keep-Alive` header, defined in
+/// [RFC2068](https://tools.ietf.org/html/rfc2068#section-19.7.1.1) and
+/// [draft-thomson-hybi-http-timeout](https://tools.ietf.org/html/draft-thomson-hybi-http-timeout-03)
+///
+/// The `Keep-Alive` header field lets the sender of a persistent
+/// connection say how long it will keep the connection open while it is
+/// idle, and how many more requests it will take on it.
+///
+/// # ABNF
+/// ```plain
+/// Keep-Alive = 1#keep-alive-info
+/// keep-alive-info = "timeout" "=" delta-seconds
+///                 / "max" "=" 1*DIGIT
+///                 / keep-alive-extension
+/// ```
+///
+/// # Example values
+/// * `timeout=5, max=100`
+/// * `timeout=30`
+///
+/// Unknown parameters are ignored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct KeepAlive {
+    /// Seconds an idle connection is kept open.
+    pub timeout: Option<u32>,
+    /// How many more requests may be sent on the connection.
+    pub max: Option<u32>,
+}
+
+impl Header for KeepAlive {
+    fn header_name() -> &'static str {
+        "Keep-Alive"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<KeepAlive> {
+        let mut keep_alive = KeepAlive::default();
+        for line in raw {
+            let line = match str::from_utf8(line) {
+                Ok(line) => line,
+                Err(_) => return None
+            };
+            for param in line.split(',').map(|param| param.trim()).filter(|p| !p.is_empty()) {
+                let (name, value) = match param.find('=') {
+                    Some(idx) => (param[..idx].trim(), param[idx + 1..].trim().trim_matches('"')),
+                    None => continue
+                };
+                let slot = if name.eq_ignore_ascii_case("timeout") {
+                    &mut keep_alive.timeout
+                } else if name.eq_ignore_ascii_case("max") {
+                    &mut keep_alive.max
+                } else {
+                    continue
+                };
+                match value.parse() {
+                    Ok(n) => *slot = Some(n),
+                    Err(_) => return None
+                }
+            }
+        }
+        Some(keep_alive)
+    }
+}
+
+impl HeaderFormat for KeepAlive {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.timeout, self.max) {
+            (Some(timeout), Some(max)) => write!(f, "timeout={}, max={}", timeout, max),
+            (Some(timeout), None) => write!(f, "timeout={}", timeout),
+            (None, Some(max)) => write!(f, "max={}", max),
+            (None, None) => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::Header;
+    use super::KeepAlive;
+
+    #[test]
+    fn test_parse() {
+        let keep_alive = Header::parse_header(&[b"timeout=5, max=100".to_vec()]);
+        assert_eq!(keep_alive, Some(KeepAlive { timeout: Some(5), max: Some(100) }));
+    }
+
+    #[test]
+    fn test_parse_multiple_lines_and_extensions() {
+        let keep_alive = Header::parse_header(&[b"Timeout=\"30\"".to_vec(),
+                                                b"foo, bar=baz".to_vec()]);
+        assert_eq!(keep_alive, Some(KeepAlive { timeout: Some(30), max: None }));
+    }
+
+    #[test]
+    fn test_parse_bad_number() {
+        let keep_alive: Option<KeepAlive> = Header::parse_header(&[b"timeout=-1".to_vec()]);
+        assert_eq!(keep_alive, None);
+    }
+
+    #[test]
+    fn test_format() {
+        let keep_alive = KeepAlive { timeout: Some(5), max: Some(100) };
+        assert_eq!(format!("{}", ::header::HeaderFormatter(&keep_alive)), "timeout=5, max=100");
+    }
+}
+
+bench_header!(normal, KeepAlive, { vec![b"timeout=5, max=100".to_vec()] });
@@ -16,9 +16,9 @@ pub use self::access_control_request_method::AccessControlRequestMethod;
 pub use self::accept_charset::AcceptCharset;
 pub use self::accept_encoding::AcceptEncoding;
 pub use self::accept_language::AcceptLanguage;
-pub use self::accept_ranges::AcceptRanges;
+pub use self::accept_ranges::{AcceptRanges, RangeUnit};
 pub use self::allow::Allow;
-pub use self::authorization::{Authorization, Scheme, Basic};
+pub use self::authorization::{Authorization, Scheme, Basic, Bearer};
 pub use self::cache_control::{CacheControl, CacheDirective};
 pub use self::connection::{Connection, ConnectionOption};
 pub use self::content_length::ContentLength;
@@ -37,6 +37,7 @@ pub use self::if_modified_since::IfModifiedSince;
 pub use self::if_none_match::IfNoneMatch;
 pub use self::if_unmodified_since::IfUnmodifiedSince;
 pub use self::if_range::IfRange;
+pub use self::keep_alive::KeepAlive;
 pub use self::last_modified::LastModified;
 pub use self::location::Location;
 pub use self::pragma::Pragma;
@@ -345,6 +346,7 @@ mod if_modified_since;
 mod if_none_match;
 mod if_range;
 mod if_unmodified_since;
+mod keep_alive;
 mod last_modified;
 mod location;
 mod pragma;
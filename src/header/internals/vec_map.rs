@@ -0,0 +1,161 @@
+use std::mem;
+
+// Most messages have fewer headers than this, so they never allocate
+// storage for the map itself.
+const INLINE: usize = 12;
+
+/// A map that keeps its first entries inline, spilling the rest into a
+/// `Vec`. Lookups are a linear scan, which beats hashing for the handful
+/// of headers a message usually has. Entries iterate in insertion order.
+#[derive(Clone)]
+pub struct VecMap<K, V> {
+    inline: [Option<(K, V)>; INLINE],
+    inline_len: usize,
+    spilled: Vec<(K, V)>,
+}
+
+impl<K: PartialEq, V> VecMap<K, V> {
+    pub fn new() -> VecMap<K, V> {
+        VecMap {
+            inline: [None, None, None, None, None, None,
+                     None, None, None, None, None, None],
+            inline_len: 0,
+            spilled: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.position(&key) {
+            Some(pos) => Some(mem::replace(self.at_mut(pos), value)),
+            None => {
+                if self.inline_len < INLINE {
+                    self.inline[self.inline_len] = Some((key, value));
+                    self.inline_len += 1;
+                } else {
+                    self.spilled.push((key, value));
+                }
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        match self.position(key) {
+            Some(pos) => Some(self.at_mut(pos)),
+            None => None
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.position(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let pos = match self.position(key) {
+            Some(pos) => pos,
+            None => return None
+        };
+        if pos >= INLINE {
+            return Some(self.spilled.remove(pos - INLINE).1);
+        }
+        let removed = self.inline[pos].take();
+        // keep the inline entries packed, and in order
+        for i in pos..self.inline_len - 1 {
+            self.inline[i] = self.inline[i + 1].take();
+        }
+        if self.spilled.is_empty() {
+            self.inline_len -= 1;
+        } else {
+            self.inline[INLINE - 1] = Some(self.spilled.remove(0));
+        }
+        removed.map(|(_, v)| v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inline_len + self.spilled.len()
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.inline[..self.inline_len].iter_mut() {
+            *slot = None;
+        }
+        self.inline_len = 0;
+        self.spilled.clear();
+    }
+
+    pub fn iter(&self) -> Iter<K, V> {
+        Iter {
+            map: self,
+            pos: 0,
+        }
+    }
+
+    fn position(&self, key: &K) -> Option<usize> {
+        self.iter().position(|(k, _)| k == key).map(|pos| {
+            if pos < self.inline_len { pos } else { pos - self.inline_len + INLINE }
+        })
+    }
+
+    // Positions at or past INLINE index into the spilled entries.
+    fn at_mut(&mut self, pos: usize) -> &mut V {
+        if pos < INLINE {
+            &mut self.inline[pos].as_mut().unwrap().1
+        } else {
+            &mut self.spilled[pos - INLINE].1
+        }
+    }
+}
+
+/// An iterator over the entries of a `VecMap`, in insertion order.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    map: &'a VecMap<K, V>,
+    pos: usize,
+}
+
+impl<'a, K: 'a, V: 'a> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let entry = if self.pos < self.map.inline_len {
+            self.map.inline[self.pos].as_ref()
+        } else {
+            self.map.spilled.get(self.pos - self.map.inline_len)
+        };
+        self.pos += 1;
+        entry.map(|&(ref k, ref v)| (k, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VecMap, INLINE};
+
+    #[test]
+    fn test_spill_and_remove() {
+        let mut map = VecMap::new();
+        for i in 0..INLINE + 3 {
+            assert_eq!(map.insert(i, i * 10), None);
+        }
+        assert_eq!(map.len(), INLINE + 3);
+        assert_eq!(map.insert(INLINE + 1, 0), Some((INLINE + 1) * 10));
+        assert_eq!(map.get(&(INLINE + 2)), Some(&((INLINE + 2) * 10)));
+
+        assert_eq!(map.remove(&3), Some(30));
+        assert_eq!(map.remove(&(INLINE + 2)), Some((INLINE + 2) * 10));
+        assert_eq!(map.remove(&3), None);
+        assert_eq!(map.len(), INLINE + 1);
+
+        let keys = map.iter().map(|(&k, _)| k).collect::<Vec<_>>();
+        let expected = (0..INLINE + 2).filter(|&k| k != 3).collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+        assert_eq!(map.get_mut(&(INLINE + 1)).map(|v| *v), Some(0));
+
+        map.clear();
+        assert_eq!(map.len(), 0);
+        assert!(map.iter().next().is_none());
+    }
+}
@@ -6,8 +6,6 @@
 //! are already provided, such as `Host`, `ContentType`, `UserAgent`, and others.
 use std::any::Any;
 use std::borrow::{Cow, ToOwned};
-use std::collections::HashMap;
-use std::collections::hash_map::{Iter, Entry};
 use std::iter::{FromIterator, IntoIterator};
 use std::ops::{Deref, DerefMut};
 use std::{mem, fmt};
@@ -16,13 +14,17 @@ use {httparse, traitobject};
 use typeable::Typeable;
 use unicase::UniCase;
 
-use self::internals::Item;
+use self::internals::{Item, VecMap, VecMapIter};
 
 pub use self::shared::*;
 pub use self::common::*;
+pub use self::redact::{Redaction, REDACTED};
+
+use self::redact::is_redacted_by_default;
 
 mod common;
 mod internals;
+mod redact;
 mod shared;
 pub mod parsing;
 
@@ -99,7 +101,7 @@ fn header_name<T: Header>() -> &'static str {
 /// A map of header fields on requests and responses.
 #[derive(Clone)]
 pub struct Headers {
-    data: HashMap<HeaderName, Item>
+    data: VecMap<HeaderName, Item>
 }
 
 impl Headers {
@@ -107,7 +109,7 @@ impl Headers {
     /// Creates a new, empty headers map.
     pub fn new() -> Headers {
         Headers {
-            data: HashMap::new()
+            data: VecMap::new()
         }
     }
 
@@ -115,15 +117,19 @@ impl Headers {
     pub fn from_raw<'a>(raw: &[httparse::Header<'a>]) -> ::Result<Headers> {
         let mut headers = Headers::new();
         for header in raw {
-            trace!("raw header: {:?}={:?}", header.name, &header.value[..]);
+            if is_redacted_by_default(header.name) {
+                trace!("raw header: {:?}={}", header.name, REDACTED);
+            } else {
+                trace!("raw header: {:?}={:?}", header.name, &header.value[..]);
+            }
             let name = UniCase(CowStr(Cow::Owned(header.name.to_owned())));
-            let mut item = match headers.data.entry(name) {
-                Entry::Vacant(entry) => entry.insert(Item::new_raw(vec![])),
-                Entry::Occupied(entry) => entry.into_mut()
-            };
             let trim = header.value.iter().rev().take_while(|&&x| x == b' ').count();
-            let value = &header.value[.. header.value.len() - trim];
-            item.mut_raw().push(value.to_vec());
+            let value = header.value[.. header.value.len() - trim].to_vec();
+            if let Some(item) = headers.data.get_mut(&name) {
+                item.mut_raw().push(value);
+                continue;
+            }
+            headers.data.insert(name, Item::new_raw(vec![value]));
         }
         Ok(headers)
     }
@@ -210,6 +216,21 @@ impl Headers {
         }
     }
 
+    /// Show these headers with `Debug`, hiding the values `redaction` says
+    /// to, rather than those the default `Redaction` does.
+    ///
+    /// ```
+    /// # use hyper::header::{Headers, Redaction};
+    /// let mut headers = Headers::new();
+    /// headers.set_raw("X-Api-Key", vec![b"secret".to_vec()]);
+    /// let mut redaction = Redaction::new();
+    /// redaction.redact("X-Api-Key");
+    /// assert!(!format!("{:?}", headers.redacted(&redaction)).contains("secret"));
+    /// ```
+    pub fn redacted<'r>(&'r self, redaction: &'r Redaction) -> Redacted<'r, Headers> {
+        Redacted { value: self, redaction: redaction }
+    }
+
     /// Returns the number of headers in the map.
     pub fn len(&self) -> usize {
         self.data.len()
@@ -243,7 +264,7 @@ impl fmt::Debug for Headers {
 
 /// An `Iterator` over the fields in a `Headers` map.
 pub struct HeadersItems<'a> {
-    inner: Iter<'a, HeaderName, Item>
+    inner: VecMapIter<'a, HeaderName, Item>
 }
 
 impl<'a> Iterator for HeadersItems<'a> {
@@ -281,6 +302,13 @@ impl<'a> HeaderView<'a> {
     pub fn value_string(&self) -> String {
         (*self.1).to_string()
     }
+
+    /// Show this header with `Debug`, hiding its value if `redaction` says
+    /// so, rather than if the default `Redaction` does.
+    #[inline]
+    pub fn redacted<'r>(&'r self, redaction: &'r Redaction) -> Redacted<'r, HeaderView<'a>> {
+        Redacted { value: self, redaction: redaction }
+    }
 }
 
 impl<'a> fmt::Display for HeaderView<'a> {
@@ -289,9 +317,42 @@ impl<'a> fmt::Display for HeaderView<'a> {
     }
 }
 
+/// Shows the value of a header the default `Redaction` hides as
+/// `[redacted]`.
 impl<'a> fmt::Debug for HeaderView<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+        if is_redacted_by_default(self.name()) {
+            write!(f, "{}: {}", self.0, REDACTED)
+        } else {
+            fmt::Display::fmt(self, f)
+        }
+    }
+}
+
+/// `Headers` or a `HeaderView`, shown with `Debug` through a `Redaction`.
+pub struct Redacted<'r, T: 'r> {
+    value: &'r T,
+    redaction: &'r Redaction,
+}
+
+impl<'r, 'a> fmt::Debug for Redacted<'r, HeaderView<'a>> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.redaction.is_redacted(self.value.name()) {
+            write!(f, "{}: {}", self.value.0, REDACTED)
+        } else {
+            fmt::Display::fmt(self.value, f)
+        }
+    }
+}
+
+impl<'r> fmt::Debug for Redacted<'r, Headers> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("Headers { "));
+        for header in self.value.iter() {
+            try!(write!(f, "{:?}, ", header.redacted(self.redaction)));
+        }
+        try!(f.write_str("}"));
+        Ok(())
     }
 }
 
@@ -506,11 +567,7 @@ mod tests {
         headers.set(Host { hostname: "foo.bar".to_string(), port: None });
 
         let s = headers.to_string();
-        // hashmap's iterators have arbitrary order, so we must sort first
-        let mut pieces = s.split("\r\n").collect::<Vec<&str>>();
-        pieces.sort();
-        let s = pieces.into_iter().rev().collect::<Vec<&str>>().connect("\r\n");
-        assert_eq!(s, "Host: foo.bar\r\nContent-Length: 15\r\n");
+        assert_eq!(s, "Content-Length: 15\r\nHost: foo.bar\r\n");
     }
 
     #[test]
@@ -571,6 +628,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_debug_redacts() {
+        use super::Redaction;
+
+        let mut headers = Headers::new();
+        headers.set_raw("Cookie", vec![b"session=secret".to_vec()]);
+        headers.set_raw("X-Token", vec![b"t0ken".to_vec()]);
+        let debug = format!("{:?}", headers);
+        assert!(debug.contains("Cookie: [redacted]"));
+        assert!(debug.contains("X-Token: t0ken"));
+        assert!(headers.to_string().contains("Cookie: session=secret"));
+
+        let mut redaction = Redaction::new();
+        redaction.redact("x-token");
+        redaction.unredact("COOKIE");
+        let debug = format!("{:?}", headers.redacted(&redaction));
+        assert!(debug.contains("Cookie: session=secret"));
+        assert!(debug.contains("X-Token: [redacted]"));
+        // the default is untouched
+        assert!(format!("{:?}", headers).contains("Cookie: [redacted]"));
+        let empty = Redaction::empty();
+        let header = headers.iter().find(|header| header.name() == "Cookie").unwrap();
+        assert_eq!(format!("{:?}", header.redacted(&empty)), "Cookie: session=secret");
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn bench_headers_new(b: &mut Bencher) {
@@ -588,6 +670,30 @@ mod tests {
         b.iter(|| Headers::from_raw(&raw).unwrap())
     }
 
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn bench_headers_new_many(b: &mut Bencher) {
+        b.iter(|| {
+            let mut h = Headers::new();
+            h.set(ContentLength(11));
+            h.set(ContentType(Mime(Text, Plain, vec![])));
+            h.set(Host { hostname: "foo.bar".to_string(), port: None });
+            h.set_raw("x-one", vec![b"1".to_vec()]);
+            h.set_raw("x-two", vec![b"2".to_vec()]);
+            h.set_raw("x-three", vec![b"3".to_vec()]);
+            h
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn bench_headers_from_raw_many(b: &mut Bencher) {
+        let raw = raw!(b"Content-Length: 10", b"Content-Type: text/plain",
+                       b"Host: foo.bar", b"Accept: */*", b"User-Agent: bench",
+                       b"Connection: keep-alive", b"Cache-Control: no-cache");
+        b.iter(|| Headers::from_raw(&raw).unwrap())
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn bench_headers_get(b: &mut Bencher) {
@@ -44,6 +44,31 @@ pub fn from_one_comma_delimited<T: str::FromStr>(raw: &[u8]) -> Option<Vec<T>> {
     }
 }
 
+/// Reads a raw value of the form `1*DIGIT`, such as a `Content-Length`.
+///
+/// Unlike `str::parse`, this rejects a sign, whitespace other than spaces
+/// and tabs around the digits, and values too large for a `u64`, rather
+/// than letting two recipients read the same value differently.
+pub fn from_digits(raw: &[u8]) -> Option<u64> {
+    let start = raw.iter().position(|&b| b != b' ' && b != b'\t').unwrap_or(raw.len());
+    let end = raw.iter().rposition(|&b| b != b' ' && b != b'\t').map_or(start, |i| i + 1);
+    let digits = &raw[start..end];
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for &b in digits {
+        if b < b'0' || b > b'9' {
+            return None;
+        }
+        value = match value.checked_mul(10).and_then(|v| v.checked_add((b - b'0') as u64)) {
+            Some(value) => value,
+            None => return None
+        };
+    }
+    Some(value)
+}
+
 /// Format an array into a comma-delimited string.
 pub fn fmt_comma_delimited<T: Display>(f: &mut fmt::Formatter, parts: &[T]) -> fmt::Result {
     for (i, part) in parts.iter().enumerate() {
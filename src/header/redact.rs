@@ -0,0 +1,72 @@
+//! Keeping the values of sensitive headers out of logs.
+use std::ascii::AsciiExt;
+
+/// What a redacted header value is shown as.
+pub const REDACTED: &'static str = "[redacted]";
+
+const DEFAULTS: [&'static str; 4] = ["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+/// The headers whose values are hidden when headers are shown or recorded.
+///
+/// `Authorization`, `Proxy-Authorization`, `Cookie` and `Set-Cookie` are
+/// redacted by default, which is what `Debug` formatting of `Headers`
+/// always uses. Anything that shows headers with a list of its own, such as
+/// `Headers::redacted` or a `HarRecorder`, takes one of these.
+///
+/// ```
+/// use hyper::header::Redaction;
+///
+/// let mut redaction = Redaction::new();
+/// redaction.redact("X-Api-Key");
+/// assert!(redaction.is_redacted("x-api-key"));
+/// assert!(redaction.is_redacted("Cookie"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redaction {
+    names: Vec<String>,
+}
+
+impl Redaction {
+    /// The default list.
+    pub fn new() -> Redaction {
+        Redaction {
+            names: DEFAULTS.iter().map(|name| name.to_string()).collect()
+        }
+    }
+
+    /// A list that hides nothing.
+    pub fn empty() -> Redaction {
+        Redaction { names: vec![] }
+    }
+
+    /// Hide the value of the header `name`.
+    pub fn redact(&mut self, name: &str) {
+        let name = name.to_ascii_lowercase();
+        if !self.names.contains(&name) {
+            self.names.push(name);
+        }
+    }
+
+    /// Show the value of the header `name` again, such as `Cookie` while
+    /// debugging a session.
+    pub fn unredact(&mut self, name: &str) {
+        let name = name.to_ascii_lowercase();
+        self.names.retain(|redacted| *redacted != name);
+    }
+
+    /// Whether the value of the header `name` is hidden.
+    pub fn is_redacted(&self, name: &str) -> bool {
+        self.names.iter().any(|redacted| redacted.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Default for Redaction {
+    fn default() -> Redaction {
+        Redaction::new()
+    }
+}
+
+// Whether `name` is in the default list, without building one.
+pub fn is_redacted_by_default(name: &str) -> bool {
+    DEFAULTS.iter().any(|redacted| redacted.eq_ignore_ascii_case(name))
+}
@@ -1,22 +1,27 @@
 //! Pieces pertaining to the HTTP message protocol.
 use std::borrow::{Cow, ToOwned};
-use std::cmp::min;
+use std::cell::RefCell;
+use std::cmp::{max, min};
 use std::io::{self, Read, Write, BufRead};
+use std::net::SocketAddr;
 use std::fmt;
 
+use flate2::read::{GzDecoder, ZlibDecoder};
 use httparse;
 
 use buffer::BufReader;
-use header::{Headers, Connection};
-use header::ConnectionOption::{Close, KeepAlive};
+use header::{Headers, Connection, ContentLength, Encoding, TransferEncoding};
+use header::ConnectionOption::{Close, KeepAlive, ConnectionHeader};
+use header::parsing::from_digits;
 use method::Method;
 use status::StatusCode;
 use uri::RequestUri;
-use version::HttpVersion::{self, Http10, Http11};
+use version::HttpVersion::{self, Http10, Http11, Http20};
 use {Error};
 
-use self::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader};
-use self::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter, EmptyWriter};
+use self::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader, DecodingReader};
+use self::HttpWriter::{ThroughWriter, ChunkedWriter, ConfiguredChunkedWriter, SizedWriter,
+                       EmptyWriter};
 
 /// Readers to handle different Transfer-Encodings.
 ///
@@ -46,6 +51,153 @@ pub enum HttpReader<R> {
     ///
     /// See https://tools.ietf.org/html/rfc7230#section-3.3.3
     EmptyReader(R),
+    /// A Reader that undoes a `gzip` or `deflate` transfer coding, applied
+    /// before the codings of the reader it wraps, with how much more it
+    /// may decode.
+    DecodingReader(Box<Decoder<R>>, DecodeLimit),
+}
+
+/// The most bytes a `DecodingReader` decodes by default, after which it
+/// fails with an `InvalidInput` error.
+///
+/// A few kilobytes of `gzip` can decode to gigabytes, so a body that
+/// decodes to more than it is likely meant to is treated as an attack.
+pub const DEFAULT_MAX_DECODED: u64 = 64 * 1024 * 1024;
+
+/// The most decoded bytes a `DecodingReader` allows by default for each
+/// encoded byte it has read, after which it fails with an `InvalidInput`
+/// error.
+///
+/// Text seldom compresses by more than 20 times, while a bomb made of a
+/// repeated byte compresses by about 1000 times.
+pub const DEFAULT_MAX_RATIO: u64 = 100;
+
+// Decoding up to this much is allowed whatever the ratio, as a small body
+// can compress far better than a large one.
+const RATIO_ALLOWANCE: u64 = 1024 * 1024;
+
+/// How much more a `DecodingReader` may decode.
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeLimit {
+    remaining: u64,
+    max_ratio: u64,
+    decoded: u64,
+    exceeded: bool,
+}
+
+impl DecodeLimit {
+    fn new() -> DecodeLimit {
+        DecodeLimit {
+            remaining: DEFAULT_MAX_DECODED,
+            max_ratio: DEFAULT_MAX_RATIO,
+            decoded: 0,
+            exceeded: false,
+        }
+    }
+
+    // Counts `count` more decoded bytes, from `encoded` bytes read in all,
+    // and whether they are within the limits.
+    fn allows(&mut self, count: u64, encoded: u64) -> bool {
+        if count > self.remaining {
+            return false;
+        }
+        self.remaining -= count;
+        self.decoded += count;
+        self.decoded <= RATIO_ALLOWANCE || self.decoded / max(encoded, 1) < self.max_ratio
+    }
+}
+
+/// A decompressing reader, for a body with a `gzip` or `deflate` transfer
+/// coding.
+pub enum Decoder<R> {
+    /// Undoes `gzip`.
+    Gzip(GzDecoder<Counted<HttpReader<R>>>),
+    /// Undoes `deflate`, which is the zlib format.
+    Deflate(ZlibDecoder<Counted<HttpReader<R>>>),
+}
+
+/// A reader that counts the bytes read from it, the encoded bytes read
+/// by a `Decoder`.
+pub struct Counted<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    fn get_ref(&self) -> &Counted<HttpReader<R>> {
+        match *self {
+            Decoder::Gzip(ref r) => r.get_ref(),
+            Decoder::Deflate(ref r) => r.get_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut HttpReader<R> {
+        match *self {
+            Decoder::Gzip(ref mut r) => &mut r.get_mut().inner,
+            Decoder::Deflate(ref mut r) => &mut r.get_mut().inner,
+        }
+    }
+
+    fn into_inner(self) -> HttpReader<R> {
+        match self {
+            Decoder::Gzip(r) => r.into_inner().inner,
+            Decoder::Deflate(r) => r.into_inner().inner,
+        }
+    }
+}
+
+/// The reader for a body sent with the transfer `codings`, in the order
+/// they were applied.
+///
+/// `chunked` must be the last coding, unless `until_eof` allows a body
+/// that ends when the connection is closed, as a response's can. The
+/// codings before it are undone over the chunked body, from the last to
+/// the first. Only `gzip`, `deflate` and `identity` are understood; any
+/// other coding is an `Error::Header`.
+///
+/// Each decoding reader fails once it has decoded `DEFAULT_MAX_DECODED`
+/// bytes, or more than `DEFAULT_MAX_RATIO` bytes for each encoded byte;
+/// `HttpReader::set_max_decoded` and `HttpReader::set_max_ratio` change
+/// the limits.
+pub fn transfer_decoder<R: Read>(stream: R, codings: &[Encoding], until_eof: bool)
+    -> ::Result<HttpReader<R>> {
+    let (mut reader, rest) = match codings.split_last() {
+        Some((&Encoding::Chunked, rest)) => (ChunkedReader(stream, None), rest),
+        _ if until_eof => (EofReader(stream), codings),
+        _ => {
+            debug!("chunked is not the last transfer coding: {:?}", codings);
+            return Err(Error::Header);
+        }
+    };
+    for coding in rest.iter().rev() {
+        reader = match *coding {
+            Encoding::Gzip => {
+                let encoded = Counted { inner: reader, count: 0 };
+                DecodingReader(Box::new(Decoder::Gzip(GzDecoder::new(encoded))),
+                               DecodeLimit::new())
+            },
+            Encoding::Deflate => {
+                let encoded = Counted { inner: reader, count: 0 };
+                DecodingReader(Box::new(Decoder::Deflate(ZlibDecoder::new(encoded))),
+                               DecodeLimit::new())
+            },
+            Encoding::Identity => reader,
+            ref coding => {
+                debug!("unsupported transfer coding: {}", coding);
+                return Err(Error::Header);
+            }
+        };
+    }
+    Ok(reader)
 }
 
 impl<R: Read> HttpReader<R> {
@@ -57,6 +209,58 @@ impl<R: Read> HttpReader<R> {
             ChunkedReader(r, _) => r,
             EofReader(r) => r,
             EmptyReader(r) => r,
+            DecodingReader(d, _) => d.into_inner().into_inner(),
+        }
+    }
+
+    /// Set how many bytes each transfer coding of the body may decode to,
+    /// before reading fails with an `InvalidInput` error.
+    ///
+    /// This has no effect on a body without a `gzip` or `deflate` coding,
+    /// and should be set before the body is read.
+    pub fn set_max_decoded(&mut self, max: u64) {
+        if let DecodingReader(ref mut d, ref mut limit) = *self {
+            limit.remaining = max;
+            d.get_mut().set_max_decoded(max);
+        }
+    }
+
+    /// Set how many bytes each transfer coding of the body may decode to
+    /// for every encoded byte, before reading fails with an `InvalidInput`
+    /// error. The first megabyte decoded is allowed whatever the ratio.
+    ///
+    /// Like `set_max_decoded`, this only affects a body with a `gzip` or
+    /// `deflate` coding, and should be set before the body is read.
+    pub fn set_max_ratio(&mut self, ratio: u64) {
+        if let DecodingReader(ref mut d, ref mut limit) = *self {
+            limit.max_ratio = ratio;
+            d.get_mut().set_max_ratio(ratio);
+        }
+    }
+
+    /// Whether reading failed because a transfer coding of the body
+    /// decoded to more than its limits allow.
+    pub fn is_over_decode_limit(&self) -> bool {
+        match *self {
+            DecodingReader(ref d, ref limit) => {
+                limit.exceeded || d.get_ref().inner.is_over_decode_limit()
+            },
+            _ => false
+        }
+    }
+
+    /// Access the inner Reader mutably.
+    ///
+    /// Warning: You should not read from this directly, as you can corrupt
+    /// the state.
+    #[inline]
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut R {
+        match *self {
+            SizedReader(ref mut r, _) => r,
+            ChunkedReader(ref mut r, _) => r,
+            EofReader(ref mut r) => r,
+            EmptyReader(ref mut r) => r,
+            DecodingReader(ref mut d, _) => d.get_mut().get_mut(),
         }
     }
 }
@@ -69,6 +273,10 @@ impl<R> fmt::Debug for HttpReader<R> {
             ChunkedReader(_, Some(rem)) => write!(fmt, "ChunkedReader(chunk_remaining={:?})", rem),
             EofReader(_) => write!(fmt, "EofReader"),
             EmptyReader(_) => write!(fmt, "EmptyReader"),
+            DecodingReader(ref d, _) => match **d {
+                Decoder::Gzip(_) => write!(fmt, "DecodingReader(gzip)"),
+                Decoder::Deflate(_) => write!(fmt, "DecodingReader(deflate)"),
+            },
         }
     }
 }
@@ -81,12 +289,10 @@ impl<R: Read> Read for HttpReader<R> {
                 if *remaining == 0 {
                     Ok(0)
                 } else {
-                    let num = try!(body.read(buf)) as u64;
-                    if num > *remaining {
-                        *remaining = 0;
-                    } else {
-                        *remaining -= num;
-                    }
+                    // never read past the body, into the next message
+                    let to_read = min(*remaining, buf.len() as u64) as usize;
+                    let num = try!(body.read(&mut buf[..to_read])) as u64;
+                    *remaining -= num;
                     Ok(num as usize)
                 }
             },
@@ -108,7 +314,7 @@ impl<R: Read> Read for HttpReader<R> {
                     return Ok(0)
                 }
 
-                let to_read = min(rem as usize, buf.len());
+                let to_read = min(rem, buf.len() as u64) as usize;
                 let count = try!(body.read(&mut buf[..to_read])) as u64;
 
                 rem -= count;
@@ -123,7 +329,24 @@ impl<R: Read> Read for HttpReader<R> {
             EofReader(ref mut body) => {
                 body.read(buf)
             },
-            EmptyReader(_) => Ok(0)
+            EmptyReader(_) => Ok(0),
+            DecodingReader(ref mut decoder, ref mut limit) => {
+                if limit.exceeded {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "decoded body is too large"));
+                }
+                let count = try!(match **decoder {
+                    Decoder::Gzip(ref mut body) => body.read(buf),
+                    Decoder::Deflate(ref mut body) => body.read(buf),
+                }) as u64;
+                if !limit.allows(count, decoder.get_ref().count) {
+                    debug!("decoded body is over the limit");
+                    limit.exceeded = true;
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "decoded body is too large"));
+                }
+                Ok(count as usize)
+            },
         }
     }
 }
@@ -214,7 +437,13 @@ pub enum HttpWriter<W: Write> {
     /// A no-op Writer, used initially before Transfer-Encoding is determined.
     ThroughWriter(W),
     /// A Writer for when Transfer-Encoding includes `chunked`.
+    ///
+    /// Each write is sent as a chunk of its own, once the inner Writer is
+    /// flushed.
     ChunkedWriter(W),
+    /// A Writer for when Transfer-Encoding includes `chunked`, with the size
+    /// of chunks and when they are flushed decided by a `Chunker`.
+    ConfiguredChunkedWriter(W, Chunker),
     /// A Writer for when Content-Length is set.
     ///
     /// Enforces that the body is not longer than the Content-Length header.
@@ -230,6 +459,7 @@ impl<W: Write> HttpWriter<W> {
         match self {
             ThroughWriter(w) => w,
             ChunkedWriter(w) => w,
+            ConfiguredChunkedWriter(w, _) => w,
             SizedWriter(w, _) => w,
             EmptyWriter(w) => w,
         }
@@ -241,6 +471,7 @@ impl<W: Write> HttpWriter<W> {
         match *self {
             ThroughWriter(ref w) => w,
             ChunkedWriter(ref w) => w,
+            ConfiguredChunkedWriter(ref w, _) => w,
             SizedWriter(ref w, _) => w,
             EmptyWriter(ref w) => w,
         }
@@ -255,6 +486,7 @@ impl<W: Write> HttpWriter<W> {
         match *self {
             ThroughWriter(ref mut w) => w,
             ChunkedWriter(ref mut w) => w,
+            ConfiguredChunkedWriter(ref mut w, _) => w,
             SizedWriter(ref mut w, _) => w,
             EmptyWriter(ref mut w) => w,
         }
@@ -270,6 +502,33 @@ impl<W: Write> HttpWriter<W> {
         try!(self.flush());
         Ok(self.into_inner())
     }
+
+    /// Ends the HttpWriter like `end`, sending `trailers` after the
+    /// last-chunk of a chunked body.
+    ///
+    /// Other bodies have no place for trailers, so they are left out.
+    pub fn end_with_trailers(mut self, trailers: &Headers) -> io::Result<W> {
+        match self {
+            ChunkedWriter(..) | ConfiguredChunkedWriter(..) => (),
+            _ => {
+                debug!("leaving out trailers of a body that isn't chunked");
+                return self.end();
+            }
+        }
+        {
+            let w = match self {
+                ConfiguredChunkedWriter(ref mut w, ref mut chunker) => {
+                    try!(chunker.send(w));
+                    w
+                },
+                ref mut other => other.get_mut()
+            };
+            trace!("last-chunk with trailers [\n{:?}]", trailers);
+            try!(write!(w, "0{}{}{}", LINE_ENDING, trailers, LINE_ENDING));
+            try!(w.flush());
+        }
+        Ok(self.into_inner())
+    }
 }
 
 impl<W: Write> Write for HttpWriter<W> {
@@ -278,11 +537,37 @@ impl<W: Write> Write for HttpWriter<W> {
         match *self {
             ThroughWriter(ref mut w) => w.write(msg),
             ChunkedWriter(ref mut w) => {
-                let chunk_size = msg.len();
-                trace!("chunked write, size = {:?}", chunk_size);
-                try!(write!(w, "{:X}{}", chunk_size, LINE_ENDING));
-                try!(w.write_all(msg));
-                try!(w.write_all(LINE_ENDING.as_bytes()));
+                try!(write_chunk(w, msg));
+                Ok(msg.len())
+            },
+            ConfiguredChunkedWriter(ref mut w, ref mut chunker) => {
+                if msg.is_empty() {
+                    try!(chunker.send(w));
+                    try!(write_chunk(w, &[]));
+                } else if chunker.flush {
+                    for chunk in msg.chunks(chunker.size) {
+                        try!(write_chunk(w, chunk));
+                    }
+                    try!(w.flush());
+                } else {
+                    // Top up a partly filled chunk first, then send whole
+                    // chunks straight from `msg`, buffering only the rest.
+                    let mut rest = msg;
+                    if !chunker.buf.is_empty() {
+                        let fill = min(chunker.size - chunker.buf.len(), rest.len());
+                        chunker.buf.extend(rest[..fill].iter().cloned());
+                        rest = &rest[fill..];
+                        if chunker.buf.len() == chunker.size {
+                            try!(write_chunk(w, &chunker.buf));
+                            chunker.buf.clear();
+                        }
+                    }
+                    while rest.len() >= chunker.size {
+                        try!(write_chunk(w, &rest[..chunker.size]));
+                        rest = &rest[chunker.size..];
+                    }
+                    chunker.buf.extend(rest.iter().cloned());
+                }
                 Ok(msg.len())
             },
             SizedWriter(ref mut w, ref mut remaining) => {
@@ -312,6 +597,10 @@ impl<W: Write> Write for HttpWriter<W> {
         match *self {
             ThroughWriter(ref mut w) => w.flush(),
             ChunkedWriter(ref mut w) => w.flush(),
+            ConfiguredChunkedWriter(ref mut w, ref mut chunker) => {
+                try!(chunker.send(w));
+                w.flush()
+            },
             SizedWriter(ref mut w, _) => w.flush(),
             EmptyWriter(ref mut w) => w.flush(),
         }
@@ -323,34 +612,238 @@ impl<W: Write> fmt::Debug for HttpWriter<W> {
         match *self {
             ThroughWriter(_) => write!(fmt, "ThroughWriter"),
             ChunkedWriter(_) => write!(fmt, "ChunkedWriter"),
+            ConfiguredChunkedWriter(_, ref chunker) => {
+                write!(fmt, "ConfiguredChunkedWriter({:?})", chunker)
+            },
             SizedWriter(_, rem) => write!(fmt, "SizedWriter(remaining={:?})", rem),
             EmptyWriter(_) => write!(fmt, "EmptyWriter"),
         }
     }
 }
 
+fn write_chunk<W: Write>(w: &mut W, chunk: &[u8]) -> io::Result<()> {
+    trace!("chunked write, size = {:?}", chunk.len());
+    try!(write!(w, "{:X}{}", chunk.len(), LINE_ENDING));
+    try!(w.write_all(chunk));
+    w.write_all(LINE_ENDING.as_bytes())
+}
+
+/// How a `ConfiguredChunkedWriter` turns writes into chunks.
+///
+/// Buffering up to a chunk size suits large bodies written in small
+/// pieces, where fewer and larger chunks save bytes and writes. Flushing
+/// every write suits event streams and other bodies whose parts the peer
+/// should see as soon as they are written, even while the connection is
+/// otherwise buffered.
+pub struct Chunker {
+    size: usize,
+    flush: bool,
+    buf: Vec<u8>,
+}
+
+impl Chunker {
+    /// Gather writes into chunks of `size` bytes, sending each once it is
+    /// full, and whatever is left when the writer is flushed or ended.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn buffered(size: usize) -> Chunker {
+        assert!(size > 0, "chunk size must be more than 0");
+        Chunker { size: size, flush: false, buf: Vec::with_capacity(size) }
+    }
+
+    /// Send each write right away, in chunks of at most `size` bytes, and
+    /// flush the inner Writer after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn immediate(size: usize) -> Chunker {
+        assert!(size > 0, "chunk size must be more than 0");
+        Chunker { size: size, flush: true, buf: Vec::new() }
+    }
+
+    // Sends what is buffered as a chunk of its own.
+    fn send<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(write_chunk(w, &self.buf));
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Chunker {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Chunker {{ size: {:?}, flush: {:?}, buffered: {:?} }}",
+               self.size, self.flush, self.buf.len())
+    }
+}
+
+/// Adds the hop from `peer` to the `Forwarded` header of RFC 7239, and to
+/// the legacy `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+/// headers, before a proxy forwards a request upstream.
+///
+/// `scheme` is how the request reached the proxy, and `host` its original
+/// `Host`. If `trusted` is false, the values already in these headers were
+/// set by the client itself, and may be forged, so they are replaced.
+/// Otherwise the peer is a proxy trusted to have set them truthfully, and
+/// this hop is added after them, while the original scheme and host it
+/// recorded are kept.
+pub fn append_forwarded(headers: &mut Headers, peer: &SocketAddr, scheme: &str,
+                        host: Option<&str>, trusted: bool) {
+    if !trusted {
+        for name in &["Forwarded", "X-Forwarded-For", "X-Forwarded-Proto", "X-Forwarded-Host"] {
+            headers.remove_raw(name);
+        }
+    }
+
+    let (node, ip) = match *peer {
+        SocketAddr::V4(ref addr) => (addr.ip().to_string(), addr.ip().to_string()),
+        SocketAddr::V6(ref addr) => (format!("\"[{}]\"", addr.ip()), addr.ip().to_string()),
+    };
+    let mut element = format!("for={};proto={}", node, scheme);
+    if let Some(host) = host {
+        element.push_str(";host=");
+        element.push_str(&forwarded_value(host));
+    }
+    let mut forwarded = headers.get_raw("Forwarded").map(|raw| raw.to_vec()).unwrap_or(vec![]);
+    forwarded.push(element.into_bytes());
+    headers.set_raw("Forwarded", forwarded);
+
+    // the legacy header is often read as a single line, so it is kept as one
+    let mut chain = vec![];
+    for line in headers.get_raw("X-Forwarded-For").unwrap_or(&[]) {
+        chain.extend(line.iter().cloned());
+        chain.extend(b", ".iter().cloned());
+    }
+    chain.extend(ip.bytes());
+    headers.set_raw("X-Forwarded-For", vec![chain]);
+    if headers.get_raw("X-Forwarded-Proto").is_none() {
+        headers.set_raw("X-Forwarded-Proto", vec![scheme.as_bytes().to_vec()]);
+    }
+    if let Some(host) = host {
+        if headers.get_raw("X-Forwarded-Host").is_none() {
+            headers.set_raw("X-Forwarded-Host", vec![host.as_bytes().to_vec()]);
+        }
+    }
+}
+
+// A Forwarded value is a token, or else a quoted string.
+fn forwarded_value(value: &str) -> String {
+    if !value.is_empty() && value.bytes().all(is_tchar) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace("\\", "\\\\").replace("\"", "\\\""))
+    }
+}
+
+// The most of a body `copy_body` holds at once.
+const COPY_BUF_SIZE: usize = 16 * 1024;
+
+thread_local!(static COPY_BUF: RefCell<Vec<u8>> = RefCell::new(vec![0; COPY_BUF_SIZE]));
+
+/// Moves a body from an incoming message to an outgoing one, such as from a
+/// server `Request` to a client `Request`, returning how many bytes it moved.
+///
+/// Each chunk read is written and flushed before the next is read, so a
+/// streaming proxy never holds more than a small buffer of the body, which
+/// is reused by every copy on the same thread. The outgoing message is left
+/// open, to be ended by its owner.
+pub fn copy_body<R: Read, W: Write>(incoming: &mut R, outgoing: &mut W) -> io::Result<u64> {
+    COPY_BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        let mut total = 0;
+        loop {
+            let n = match incoming.read(&mut buf) {
+                Ok(0) => return Ok(total),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            try!(outgoing.write_all(&buf[..n]));
+            try!(outgoing.flush());
+            total += n as u64;
+        }
+    })
+}
+
 const MAX_HEADERS: usize = 100;
 
 /// Parses a request into an Incoming message head.
+///
+/// A request with a header value folded over several lines (obs-fold) is
+/// rejected.
 #[inline]
 pub fn parse_request<R: Read>(buf: &mut BufReader<R>) -> ::Result<Incoming<(Method, RequestUri)>> {
-    parse::<R, httparse::Request, (Method, RequestUri)>(buf)
+    parse_request_unfolding(buf, false)
+}
+
+/// Parses a request into an Incoming message head, unfolding header values
+/// folded over several lines (obs-fold) into one if `unfold` is set, instead
+/// of rejecting the request.
+#[inline]
+pub fn parse_request_unfolding<R: Read>(buf: &mut BufReader<R>, unfold: bool)
+        -> ::Result<Incoming<(Method, RequestUri)>> {
+    parse::<R, httparse::Request, (Method, RequestUri)>(buf, unfold)
 }
 
 /// Parses a response into an Incoming message head.
+///
+/// Header values folded over several lines (obs-fold) are unfolded into
+/// one, as RFC 7230 asks of user agents.
 #[inline]
 pub fn parse_response<R: Read>(buf: &mut BufReader<R>) -> ::Result<Incoming<RawStatus>> {
-    parse::<R, httparse::Response, RawStatus>(buf)
+    parse_response_unfolding(buf, true)
 }
 
-fn parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>) -> ::Result<Incoming<I>> {
+/// Parses a response into an Incoming message head, unfolding folded header
+/// values only if `unfold` is set. Otherwise a response with one is
+/// rejected.
+#[inline]
+pub fn parse_response_unfolding<R: Read>(buf: &mut BufReader<R>, unfold: bool)
+        -> ::Result<Incoming<RawStatus>> {
+    parse::<R, httparse::Response, RawStatus>(buf, unfold)
+}
+
+fn parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>, unfold: bool)
+        -> ::Result<Incoming<I>> {
+    // The buffer is only handed to httparse once the empty line ending the
+    // head has been found, instead of re-parsing it after every read. The
+    // scan picks up a few bytes before where the last one ended, in case
+    // the line ending was split between reads.
+    //
+    // The first line is still parsed as soon as it is in, so a peer that
+    // isn't speaking HTTP at all is refused right away. A malformed header
+    // field is only reported once the head ends, or the stream does.
+    let mut scanned = 0;
+    let mut checked = false;
     loop {
-        match try!(try_parse::<R, T, I>(rdr)) {
-            httparse::Status::Complete((inc, len)) => {
-                rdr.consume(len);
-                return Ok(inc);
-            },
-            _partial => ()
+        let end = {
+            let buf = rdr.get_buf();
+            let found = find_head_end(&buf[scanned..]).map(|end| scanned + end);
+            scanned = buf.len().saturating_sub(3);
+            if found.is_none() && !checked {
+                if let Some(pos) = buf.iter().position(|&b| b == LF) {
+                    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+                    try!(<T as TryParse>::try_parse(&mut headers, &buf[..pos + 1]));
+                    checked = true;
+                }
+            }
+            found
+        };
+        if let Some(end) = end {
+            if unfold {
+                unfold_headers(&mut rdr.get_mut_buf()[..end]);
+            }
+            match try!(try_parse::<R, T, I>(rdr)) {
+                httparse::Status::Complete((inc, len)) => {
+                    rdr.consume(len);
+                    return Ok(inc);
+                },
+                _partial => ()
+            }
         }
         match try!(rdr.read_into_buf()) {
             0 if rdr.get_buf().is_empty() => {
@@ -359,12 +852,50 @@ fn parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>) -> ::Result
                     "Connection closed"
                 )))
             },
-            0 => return Err(Error::TooLarge),
+            0 => {
+                // no more is coming, so report a malformed head if it is one
+                try!(try_parse::<R, T, I>(rdr));
+                return Err(Error::TooLarge);
+            },
             _ => ()
         }
     }
 }
 
+/// Finds the empty line that ends a message head, ending with either CRLF
+/// or LF, returning the index just past it.
+pub fn find_head_end(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(pos) = buf[start..].iter().position(|&b| b == LF) {
+        start += pos + 1;
+        let rest = &buf[start..];
+        if rest.starts_with(&[LF]) {
+            return Some(start + 1);
+        } else if rest.starts_with(&[CR, LF]) {
+            return Some(start + 2);
+        }
+    }
+    None
+}
+
+// Replaces each line break followed by whitespace in the header fields with
+// spaces, joining a folded value back into one line. The bytes are changed
+// in place, so the head keeps its length.
+fn unfold_headers(head: &mut [u8]) {
+    let start = match head.iter().position(|&b| b == LF) {
+        Some(pos) => pos + 1,
+        None => return
+    };
+    for i in start..head.len().saturating_sub(1) {
+        if head[i] == LF && (head[i + 1] == SP || head[i + 1] == b'\t') {
+            head[i] = SP;
+            if head[i - 1] == CR {
+                head[i - 1] = SP;
+            }
+        }
+    }
+}
+
 fn try_parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>) -> TryParseResult<I> {
     let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
     <T as TryParse>::try_parse(&mut headers, rdr.get_buf())
@@ -388,7 +919,7 @@ impl<'a> TryParse for httparse::Request<'a, 'a> {
                 httparse::Status::Complete((Incoming {
                     version: if req.version.unwrap() == 1 { Http11 } else { Http10 },
                     subject: (
-                        try!(req.method.unwrap().parse()),
+                        try!(Method::from_bytes(req.method.unwrap().as_bytes())),
                         try!(req.path.unwrap().parse())
                     ),
                     headers: try!(Headers::from_raw(req.headers))
@@ -439,17 +970,133 @@ pub const LF: u8 = b'\n';
 pub const STAR: u8 = b'*';
 pub const LINE_ENDING: &'static str = "\r\n";
 
+/// Whether `b` may appear in a token, such as a method or header name, as
+/// RFC 7230 defines `tchar`.
+#[doc(hidden)]
+pub fn is_tchar(b: u8) -> bool {
+    match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' |
+        b'`' | b'|' | b'~' => true,
+        _ => false
+    }
+}
+
 /// The raw status code and reason-phrase.
 #[derive(Clone, PartialEq, Debug)]
 pub struct RawStatus(pub u16, pub Cow<'static, str>);
 
 /// Checks if a connection should be kept alive.
+///
+/// An HTTP/1.1 connection is persistent unless either side sends
+/// `Connection: close`. An HTTP/1.0 connection is only persistent if it
+/// asks to be with `Connection: keep-alive`.
 pub fn should_keep_alive(version: HttpVersion, headers: &Headers) -> bool {
-    match (version, headers.get::<Connection>()) {
-        (Http10, Some(conn)) if !conn.contains(&KeepAlive) => false,
-        (Http11, Some(conn)) if conn.contains(&Close)  => false,
-        _ => true
+    let conn = headers.get::<Connection>();
+    if conn.map_or(false, |conn| conn.contains(&Close)) {
+        return false;
     }
+    if version.keeps_alive() {
+        true
+    } else {
+        version == Http10 && conn.map_or(false, |conn| conn.contains(&KeepAlive))
+    }
+}
+
+/// Checks that the headers framing an incoming message agree.
+///
+/// A message with both `Transfer-Encoding` and `Content-Length`, or with
+/// differing `Content-Length` values, could be framed differently by two
+/// recipients, which is how requests are smuggled past proxies. Such a
+/// message is rejected with `Error::Header`.
+///
+/// Repeated `Content-Length` values that are all the same are merged into
+/// one, as RFC 7230 allows.
+pub fn check_framing(headers: &mut Headers) -> ::Result<()> {
+    let len = match headers.get_raw("content-length") {
+        Some(raw) => {
+            let mut len = None;
+            for value in raw.iter().flat_map(|line| line.split(|&b| b == b',')) {
+                let value = try!(from_digits(value).ok_or(Error::Header));
+                match len {
+                    Some(len) if len != value => {
+                        debug!("conflicting Content-Length values: {:?}", raw);
+                        return Err(Error::Header);
+                    },
+                    _ => len = Some(value)
+                }
+            }
+            match len {
+                Some(len) => len,
+                None => return Err(Error::Header)
+            }
+        },
+        None => return Ok(())
+    };
+    if headers.has::<TransferEncoding>() {
+        debug!("message has both Transfer-Encoding and Content-Length");
+        return Err(Error::Header);
+    }
+    headers.set(ContentLength(len));
+    Ok(())
+}
+
+/// Removes the `Connection` header, and every header it names.
+///
+/// These headers only apply to a single connection, so they must not be
+/// forwarded to another one.
+pub fn remove_connection_headers(headers: &mut Headers) {
+    let names = match headers.get::<Connection>() {
+        Some(conn) => conn.iter().filter_map(|opt| match *opt {
+            ConnectionHeader(ref name) => Some(name.to_string()),
+            _ => None
+        }).collect::<Vec<_>>(),
+        None => vec![]
+    };
+    for name in names {
+        headers.remove_raw(&name);
+    }
+    // even one that doesn't parse
+    headers.remove_raw("Connection");
+}
+
+/// The headers RFC 7230 defines as hop-by-hop, besides `Connection`.
+pub const HOP_BY_HOP: &'static [&'static str] = &[
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Removes every hop-by-hop header, both those in `HOP_BY_HOP` and those
+/// named by the `Connection` header.
+///
+/// A proxy calls this on a message before forwarding it, in either
+/// direction, and frames the forwarded body itself.
+pub fn remove_hop_by_hop_headers(headers: &mut Headers) {
+    remove_connection_headers(headers);
+    for name in HOP_BY_HOP {
+        headers.remove_raw(name);
+    }
+}
+
+/// Appends this hop to the `Via` header, as `version` received the
+/// message, and `pseudonym` names the intermediary, such as its host name.
+///
+/// Existing `Via` entries are kept, so the header lists every hop in order.
+pub fn append_via(headers: &mut Headers, version: HttpVersion, pseudonym: &str) {
+    let protocol = match version {
+        Http20 => "2",
+        _ => &version.as_ref()["HTTP/".len()..]
+    };
+    let entry = format!("{} {}", protocol, pseudonym);
+    let mut via = headers.get_raw("Via").map(|raw| raw.to_vec()).unwrap_or(vec![]);
+    via.push(entry.into_bytes());
+    headers.set_raw("Via", via);
 }
 
 #[cfg(test)]
@@ -459,7 +1106,8 @@ mod tests {
     use buffer::BufReader;
     use mock::MockStream;
 
-    use super::{read_chunk_size, parse_request, parse_response};
+    use super::{read_chunk_size, parse_request, parse_response, find_head_end};
+    use super::{parse_request_unfolding, parse_response_unfolding};
 
     #[test]
     fn test_write_chunked() {
@@ -472,6 +1120,56 @@ mod tests {
         assert_eq!(s, "7\r\nfoo bar\r\nD\r\nbaz quux herp\r\n0\r\n\r\n");
     }
 
+    #[test]
+    fn test_write_configured_chunked() {
+        use std::str::from_utf8;
+        use super::Chunker;
+        use super::HttpWriter::ConfiguredChunkedWriter;
+
+        let mut w = ConfiguredChunkedWriter(Vec::new(), Chunker::buffered(4));
+        w.write_all(b"foo").unwrap();
+        w.write_all(b"bar baz").unwrap();
+        assert_eq!(from_utf8(w.get_ref()).unwrap(), "4\r\nfoob\r\n4\r\nar b\r\n");
+        w.flush().unwrap();
+        w.write_all(b"!").unwrap();
+        let buf = w.end().unwrap();
+        assert_eq!(from_utf8(&buf).unwrap(),
+                   "4\r\nfoob\r\n4\r\nar b\r\n2\r\naz\r\n1\r\n!\r\n0\r\n\r\n");
+
+        let mut w = ConfiguredChunkedWriter(Vec::new(), Chunker::buffered(4));
+        w.write_all(b"0123456789").unwrap();
+        assert_eq!(from_utf8(w.get_ref()).unwrap(), "4\r\n0123\r\n4\r\n4567\r\n");
+        w.write_all(b"abcdefg").unwrap();
+        assert_eq!(from_utf8(&w.end().unwrap()).unwrap(),
+                   concat!("4\r\n0123\r\n4\r\n4567\r\n4\r\n89ab\r\n4\r\ncdef\r\n",
+                           "1\r\ng\r\n0\r\n\r\n"));
+
+        let mut w = ConfiguredChunkedWriter(io::BufWriter::new(Vec::new()),
+                                            Chunker::immediate(4));
+        w.write_all(b"data: hello\n\n").unwrap();
+        assert_eq!(from_utf8(w.get_ref().get_ref()).unwrap(),
+                   "4\r\ndata\r\n4\r\n: he\r\n4\r\nllo\n\r\n1\r\n\n\r\n");
+    }
+
+    #[test]
+    fn test_copy_body() {
+        use super::HttpReader::ChunkedReader;
+        use super::HttpWriter::ChunkedWriter;
+        use super::copy_body;
+
+        let body = b"3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n";
+        let mut incoming = ChunkedReader(MockStream::with_input(body), None);
+        let mut outgoing = ChunkedWriter(Vec::new());
+        assert_eq!(copy_body(&mut incoming, &mut outgoing).unwrap(), 6);
+        let out = outgoing.end().unwrap();
+        assert_eq!(&out[..], &body[..]);
+
+        let big = vec![b'x'; 40 * 1024];
+        let mut outgoing = Vec::new();
+        assert_eq!(copy_body(&mut &big[..], &mut outgoing).unwrap(), big.len() as u64);
+        assert_eq!(outgoing, big);
+    }
+
     #[test]
     fn test_write_sized() {
         use std::str::from_utf8;
@@ -484,6 +1182,78 @@ mod tests {
         assert_eq!(s, "foo barb");
     }
 
+    #[test]
+    fn test_read_sized() {
+        use std::io::Read;
+        use super::HttpReader::SizedReader;
+
+        let mut input = &b"foobar"[..];
+        let mut buf = vec![];
+        SizedReader(&mut input, 3).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"foo");
+        // the rest belongs to the next message
+        assert_eq!(input, b"bar");
+    }
+
+    #[test]
+    fn test_transfer_decoder() {
+        use std::io::Read;
+        use flate2::Compression;
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use header::Encoding::{Chunked, Deflate, Gzip, Identity, Compress};
+        use super::{transfer_decoder, HttpWriter};
+
+        // deflate applied first, then gzip, then chunked
+        let mut deflated = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflated.write_all(b"foo bar baz").unwrap();
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&deflated.finish().unwrap()).unwrap();
+        let mut chunked = HttpWriter::ChunkedWriter(Vec::new());
+        chunked.write_all(&gzipped.finish().unwrap()).unwrap();
+        let input = chunked.end().unwrap();
+
+        let mut body = vec![];
+        transfer_decoder(&input[..], &[Deflate, Identity, Gzip, Chunked], false).unwrap()
+            .read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"foo bar baz");
+
+        // a response may be read until the connection closes
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(b"foo").unwrap();
+        let input = gzipped.finish().unwrap();
+        let mut body = vec![];
+        transfer_decoder(&input[..], &[Gzip], true).unwrap().read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"foo");
+
+        // but a request may not
+        assert!(transfer_decoder(&input[..], &[Gzip], false).is_err());
+        assert!(transfer_decoder(&input[..], &[Compress, Chunked], true).is_err());
+        // a body that decodes to more than the limit fails
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&[0; 64 * 1024]).unwrap();
+        let input = gzipped.finish().unwrap();
+        let mut reader = transfer_decoder(&input[..], &[Gzip], true).unwrap();
+        reader.set_max_decoded(1024);
+        assert!(!reader.is_over_decode_limit());
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(reader.is_over_decode_limit());
+
+        // as does one that decodes to far more than it was sent as
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&[0; 4 * 1024 * 1024]).unwrap();
+        let input = gzipped.finish().unwrap();
+        let mut reader = transfer_decoder(&input[..], &[Gzip], true).unwrap();
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(reader.is_over_decode_limit());
+        let mut reader = transfer_decoder(&input[..], &[Gzip], true).unwrap();
+        reader.set_max_ratio(u64::max_value());
+        let mut body = vec![];
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body.len(), 4 * 1024 * 1024);
+    }
+
     #[test]
     fn test_read_chunk_size() {
         fn read(s: &str, result: u64) {
@@ -548,6 +1318,244 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_find_head_end() {
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody"), Some(27));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\nHost: a\n\n"), Some(24));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r"), None);
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\n"), None);
+        assert_eq!(find_head_end(b""), None);
+    }
+
+    #[test]
+    fn test_parse_byte_at_a_time() {
+        use std::io::Read;
+
+        struct ByteAtATime(&'static [u8]);
+
+        impl Read for ByteAtATime {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() || buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = self.0[0];
+                self.0 = &self.0[1..];
+                Ok(1)
+            }
+        }
+
+        let mut raw = ByteAtATime(b"HTTP/1.1 200 OK\r\nServer: slow\r\n\r\nbody");
+        let mut buf = BufReader::new(&mut raw);
+        let res = parse_response(&mut buf).unwrap();
+        assert_eq!(res.subject.0, 200);
+        assert_eq!(res.headers.get_raw("server").unwrap(), &[b"slow".to_vec()][..]);
+        assert_eq!(buf.get_buf(), b"");
+    }
+
+    #[test]
+    fn test_parse_obs_fold() {
+        let mut raw = MockStream::with_input(b"HTTP/1.1 200 OK\r\n\
+            X-Folded: one\r\n two\r\n\tthree\r\n\
+            Server: legacy\r\n\
+            \r\n\
+            body\r\n not a fold");
+        let mut buf = BufReader::new(&mut raw);
+        let res = parse_response(&mut buf).unwrap();
+        assert_eq!(res.headers.get_raw("x-folded").unwrap(), &[b"one   two  \tthree".to_vec()][..]);
+        assert_eq!(res.headers.get_raw("server").unwrap(), &[b"legacy".to_vec()][..]);
+        assert_eq!(buf.get_buf(), b"body\r\n not a fold");
+
+        let mut raw = MockStream::with_input(b"GET / HTTP/1.1\r\n\
+            X-Folded: one\r\n two\r\n\
+            \r\n");
+        let mut buf = BufReader::new(&mut raw);
+        assert!(parse_request(&mut buf).is_err());
+
+        let mut raw = MockStream::with_input(b"GET / HTTP/1.1\r\n\
+            X-Folded: one\r\n two\r\n\
+            \r\n");
+        let mut buf = BufReader::new(&mut raw);
+        let req = parse_request_unfolding(&mut buf, true).unwrap();
+        assert_eq!(req.headers.get_raw("x-folded").unwrap(), &[b"one   two".to_vec()][..]);
+
+        let mut raw = MockStream::with_input(b"HTTP/1.1 200 OK\r\n\
+            X-Folded: one\r\n two\r\n\
+            \r\n");
+        let mut buf = BufReader::new(&mut raw);
+        assert!(parse_response_unfolding(&mut buf, false).is_err());
+    }
+
+    #[test]
+    fn test_parse_malformed_start_line() {
+        use std::io::Read;
+
+        // the rest of the head never comes, so only the first line can fail
+        struct Stalled(&'static [u8]);
+
+        impl Read for Stalled {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                assert!(!self.0.is_empty(), "read past a malformed first line");
+                self.0.read(buf)
+            }
+        }
+
+        let mut raw = Stalled(b"GET / HTTX/1.1\r\nHost: a\r\n");
+        let mut buf = BufReader::new(&mut raw);
+        assert!(parse_request(&mut buf).is_err());
+
+        let mut raw = Stalled(b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03\n");
+        let mut buf = BufReader::new(&mut raw);
+        assert!(parse_request(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_parse_malformed_at_eof() {
+        use error::Error;
+
+        let mut raw = MockStream::with_input(b"NOT HTTP\r\n");
+        let mut buf = BufReader::new(&mut raw);
+        match parse_response(&mut buf) {
+            Err(Error::TooLarge) => panic!("malformed head should not be TooLarge"),
+            Err(_) => (),
+            Ok(res) => panic!("unexpected response: {:?}", res)
+        }
+    }
+
+    #[test]
+    fn test_should_keep_alive() {
+        use header::{Headers, Connection};
+        use header::ConnectionOption::KeepAlive;
+        use version::HttpVersion::{Http09, Http10, Http11};
+        use super::should_keep_alive;
+
+        let none = Headers::new();
+        let mut close = Headers::new();
+        close.set_raw("Connection", vec![b"Close".to_vec()]);
+        let mut keep_alive = Headers::new();
+        keep_alive.set(Connection(vec![KeepAlive]));
+
+        assert!(should_keep_alive(Http11, &none));
+        assert!(!should_keep_alive(Http11, &close));
+        assert!(should_keep_alive(Http11, &keep_alive));
+        assert!(!should_keep_alive(Http10, &none));
+        assert!(!should_keep_alive(Http10, &close));
+        assert!(should_keep_alive(Http10, &keep_alive));
+        assert!(!should_keep_alive(Http09, &none));
+    }
+
+    #[test]
+    fn test_check_framing() {
+        use header::{Headers, ContentLength};
+        use super::check_framing;
+
+        fn check(raw: &[(&'static str, &[u8])]) -> Option<Option<u64>> {
+            let mut headers = Headers::new();
+            for &(name, value) in raw {
+                let mut values = headers.get_raw(name).map(|v| v.to_vec()).unwrap_or(vec![]);
+                values.push(value.to_vec());
+                headers.set_raw(name, values);
+            }
+            check_framing(&mut headers).ok()
+                .map(|_| headers.get::<ContentLength>().map(|len| **len))
+        }
+
+        assert_eq!(check(&[]), Some(None));
+        assert_eq!(check(&[("Content-Length", b"5")]), Some(Some(5)));
+        assert_eq!(check(&[("Content-Length", b"5"), ("Content-Length", b"5")]), Some(Some(5)));
+        assert_eq!(check(&[("Content-Length", b"5, 5")]), Some(Some(5)));
+        assert_eq!(check(&[("Content-Length", b"5"), ("Content-Length", b"6")]), None);
+        assert_eq!(check(&[("Content-Length", b"5, 6")]), None);
+        assert_eq!(check(&[("Content-Length", b"-5")]), None);
+        assert_eq!(check(&[("Content-Length", b"")]), None);
+        assert_eq!(check(&[("Content-Length", b"+5")]), None);
+        assert_eq!(check(&[("Content-Length", b"5\x0b")]), None);
+        assert_eq!(check(&[("Content-Length", b"99999999999999999999")]), None);
+        assert_eq!(check(&[("Content-Length", b"8589934592")]), Some(Some(1 << 33)));
+        assert_eq!(check(&[("Content-Length", b"5"), ("Transfer-Encoding", b"chunked")]), None);
+        assert_eq!(check(&[("Transfer-Encoding", b"chunked")]), Some(None));
+    }
+
+    #[test]
+    fn test_remove_connection_headers() {
+        use header::{Headers, Connection, ContentLength};
+        use super::remove_connection_headers;
+
+        let mut headers = Headers::new();
+        headers.set_raw("Connection", vec![b"close, x-hop".to_vec()]);
+        headers.set_raw("X-Hop", vec![b"1".to_vec()]);
+        headers.set(ContentLength(0));
+        remove_connection_headers(&mut headers);
+        assert!(!headers.has::<Connection>());
+        assert!(headers.get_raw("x-hop").is_none());
+        assert!(headers.has::<ContentLength>());
+
+        let mut headers = Headers::new();
+        headers.set_raw("Connection", vec![b"\xff".to_vec()]);
+        remove_connection_headers(&mut headers);
+        assert!(headers.get_raw("Connection").is_none());
+    }
+
+    #[test]
+    fn test_remove_hop_by_hop_headers() {
+        use header::{Headers, ContentLength, TransferEncoding, Encoding};
+        use super::remove_hop_by_hop_headers;
+
+        let mut headers = Headers::new();
+        headers.set_raw("Connection", vec![b"x-hop".to_vec()]);
+        headers.set_raw("X-Hop", vec![b"1".to_vec()]);
+        headers.set_raw("Keep-Alive", vec![b"timeout=5".to_vec()]);
+        headers.set_raw("Proxy-Authorization", vec![b"Basic Zm9vOmJhcg==".to_vec()]);
+        headers.set(TransferEncoding(vec![Encoding::Chunked]));
+        headers.set(ContentLength(0));
+        remove_hop_by_hop_headers(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.has::<ContentLength>());
+    }
+
+    #[test]
+    fn test_append_forwarded() {
+        use header::Headers;
+        use super::append_forwarded;
+
+        fn raw(headers: &Headers, name: &str) -> Vec<String> {
+            headers.get_raw(name).unwrap().iter()
+                .map(|line| String::from_utf8(line.clone()).unwrap()).collect()
+        }
+
+        let mut headers = Headers::new();
+        headers.set_raw("Forwarded", vec![b"for=10.0.0.1".to_vec()]);
+        headers.set_raw("X-Forwarded-For", vec![b"10.0.0.1".to_vec()]);
+        headers.set_raw("X-Forwarded-Proto", vec![b"https".to_vec()]);
+        append_forwarded(&mut headers, &"192.0.2.43:5000".parse().unwrap(), "http",
+                         Some("example.domain:8080"), false);
+        assert_eq!(raw(&headers, "Forwarded"),
+                   vec!["for=192.0.2.43;proto=http;host=\"example.domain:8080\""]);
+        assert_eq!(raw(&headers, "X-Forwarded-For"), vec!["192.0.2.43"]);
+        assert_eq!(raw(&headers, "X-Forwarded-Proto"), vec!["http"]);
+        assert_eq!(raw(&headers, "X-Forwarded-Host"), vec!["example.domain:8080"]);
+
+        append_forwarded(&mut headers, &"[2001:db8::1]:5000".parse().unwrap(), "https",
+                         None, true);
+        assert_eq!(raw(&headers, "Forwarded"),
+                   vec!["for=192.0.2.43;proto=http;host=\"example.domain:8080\"",
+                        "for=\"[2001:db8::1]\";proto=https"]);
+        assert_eq!(raw(&headers, "X-Forwarded-For"), vec!["192.0.2.43, 2001:db8::1"]);
+        assert_eq!(raw(&headers, "X-Forwarded-Proto"), vec!["http"]);
+    }
+
+    #[test]
+    fn test_append_via() {
+        use header::Headers;
+        use version::HttpVersion::{Http10, Http11};
+        use super::append_via;
+
+        let mut headers = Headers::new();
+        append_via(&mut headers, Http10, "first.proxy");
+        append_via(&mut headers, Http11, "second.proxy");
+        assert_eq!(headers.get_raw("via").unwrap(),
+                   &[b"1.0 first.proxy".to_vec(), b"1.1 second.proxy".to_vec()][..]);
+    }
+
     #[test]
     fn test_parse_tcp_closed() {
         use std::io::ErrorKind;
@@ -574,4 +1582,28 @@ mod tests {
             buf.get_mut().read.set_position(0);
         });
     }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn bench_parse_many_headers(b: &mut Bencher) {
+        let mut raw = MockStream::with_input(b"HTTP/1.1 200 OK\r\n\
+            Server: bench\r\n\
+            Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+            Content-Type: text/html; charset=utf-8\r\n\
+            Content-Length: 0\r\n\
+            Cache-Control: private, max-age=0\r\n\
+            Expires: -1\r\n\
+            Vary: Accept-Encoding\r\n\
+            Set-Cookie: a=b; Path=/; HttpOnly\r\n\
+            Set-Cookie: c=d; Path=/; HttpOnly\r\n\
+            X-Frame-Options: SAMEORIGIN\r\n\
+            X-Content-Type-Options: nosniff\r\n\
+            Connection: keep-alive\r\n\
+            \r\n");
+        let mut buf = BufReader::new(&mut raw);
+        b.iter(|| {
+            parse_response(&mut buf).unwrap();
+            buf.get_mut().read.set_position(0);
+        });
+    }
 }
@@ -133,11 +133,16 @@ extern crate time;
 extern crate url;
 extern crate openssl;
 extern crate cookie;
+extern crate flate2;
 extern crate unicase;
 extern crate httparse;
 extern crate num_cpus;
 extern crate traitobject;
 extern crate typeable;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
 
 #[macro_use]
 extern crate log;
@@ -174,6 +179,7 @@ mod mock;
 #[doc(hidden)]
 pub mod buffer;
 pub mod client;
+pub mod clock;
 pub mod error;
 pub mod method;
 pub mod header;
@@ -1,9 +1,10 @@
 //! The HTTP request method
 use std::fmt;
-use std::str::FromStr;
+use std::str::{self, FromStr};
 use std::convert::AsRef;
 
 use error::Error;
+use http::is_tchar;
 use self::Method::{Options, Get, Post, Put, Delete, Head, Trace, Connect, Patch,
                    Extension};
 
@@ -57,12 +58,37 @@ impl AsRef<str> for Method {
 }
 
 impl Method {
+    /// Parse a method from the bytes of a request line.
+    ///
+    /// The standard methods are matched without checking for UTF-8 first.
+    /// Any other method must be a token, as RFC 7230 requires.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Method, Error> {
+        Ok(match bytes {
+            b"GET" => Get,
+            b"POST" => Post,
+            b"PUT" => Put,
+            b"DELETE" => Delete,
+            b"HEAD" => Head,
+            b"OPTIONS" => Options,
+            b"TRACE" => Trace,
+            b"CONNECT" => Connect,
+            b"PATCH" => Patch,
+            _ => {
+                if bytes.is_empty() || !bytes.iter().all(|&b| is_tchar(b)) {
+                    return Err(Error::Method);
+                }
+                // tchars are all ASCII
+                Extension(unsafe { str::from_utf8_unchecked(bytes) }.to_string())
+            }
+        })
+    }
+
     /// Whether a method is considered "safe", meaning the request is
     /// essentially read-only.
     ///
     /// See [the spec](https://tools.ietf.org/html/rfc7231#section-4.2.1)
     /// for more words.
-    pub fn safe(&self) -> bool {
+    pub fn is_safe(&self) -> bool {
         match *self {
             Get | Head | Options | Trace => true,
             _ => false
@@ -70,12 +96,12 @@ impl Method {
     }
 
     /// Whether a method is considered "idempotent", meaning the request has
-    /// the same result is executed multiple times.
+    /// the same result is executed multiple times, so it can be retried.
     ///
     /// See [the spec](https://tools.ietf.org/html/rfc7231#section-4.2.2) for
     /// more words.
-    pub fn idempotent(&self) -> bool {
-        if self.safe() {
+    pub fn is_idempotent(&self) -> bool {
+        if self.is_safe() {
             true
         } else {
             match *self {
@@ -84,6 +110,27 @@ impl Method {
             }
         }
     }
+
+    /// Whether a request with this method is sent with a body.
+    ///
+    /// `GET` and `HEAD` requests have no body, and `TRACE` and `CONNECT`
+    /// requests must not have one.
+    pub fn allows_body(&self) -> bool {
+        match *self {
+            Get | Head | Trace | Connect => false,
+            _ => true
+        }
+    }
+
+    /// The same as `is_safe`.
+    pub fn safe(&self) -> bool {
+        self.is_safe()
+    }
+
+    /// The same as `is_idempotent`.
+    pub fn idempotent(&self) -> bool {
+        self.is_idempotent()
+    }
 }
 
 impl FromStr for Method {
@@ -146,6 +193,22 @@ mod tests {
         assert_eq!(false, Post.idempotent());
     }
 
+    #[test]
+    fn test_allows_body() {
+        assert!(Post.allows_body());
+        assert!(!Get.allows_body());
+        assert!(!super::Method::Trace.allows_body());
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        assert_eq!(Method::from_bytes(b"GET").unwrap(), Get);
+        assert_eq!(Method::from_bytes(b"MOVE").unwrap(), Extension("MOVE".to_string()));
+        assert!(Method::from_bytes(b"").is_err());
+        assert!(Method::from_bytes(b"G(T").is_err());
+        assert!(Method::from_bytes(b"G\xc3\xa9T").is_err());
+    }
+
     #[test]
     fn test_from_str() {
         assert_eq!(Get, FromStr::from_str("GET").unwrap());
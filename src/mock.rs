@@ -67,6 +67,11 @@ impl NetworkStream for MockStream {
     fn peer_addr(&mut self) -> io::Result<SocketAddr> {
         Ok("127.0.0.1:1337".parse().unwrap())
     }
+
+    // The clone reads the same input from the start, and writes on its own.
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        Ok(Box::new(self.clone()))
+    }
 }
 
 pub struct MockConnector;
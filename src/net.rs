@@ -1,20 +1,33 @@
 //! A collection of traits abstracting over Listeners and Streams.
 use std::any::{Any, TypeId};
+use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, ErrorKind, Read, Write};
 use std::net::{SocketAddr, ToSocketAddrs, TcpStream, TcpListener, Shutdown};
 use std::mem;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
 
+use openssl::crypto::hash::{hash, Type};
 use openssl::ssl::{Ssl, SslStream, SslContext, SSL_VERIFY_NONE};
+use openssl::x509::X509VerifyResult;
+use openssl::ssl::{SSL_OP_NO_SSLV2, SSL_OP_NO_SSLV3, SSL_OP_NO_TLSV1, SSL_OP_NO_TLSV1_1,
+                   SSL_OP_NO_COMPRESSION, SSL_OP_NO_RENEGOTIATION};
 use openssl::ssl::SslMethod::Sslv23;
+use openssl::ssl::error::{SslError, OpensslError};
 use openssl::ssl::error::StreamError as SslIoError;
 use openssl::x509::X509FileType;
+use serialize::base64::{ToBase64, STANDARD};
+use time::{precise_time_ns, Duration, Timespec};
 
 use typeable::Typeable;
 use {traitobject};
 
+use clock::{Clock, SystemClock};
+use header::KeepAlive;
+
 /// The write-status indicating headers have not been written.
 pub enum Fresh {}
 
@@ -62,6 +75,32 @@ pub trait NetworkStream: Read + Write + Any + Send + Typeable {
     fn close(&mut self, _how: Shutdown) -> io::Result<()> {
         Ok(())
     }
+    /// The DER encoded certificate the peer presented, if this is a TLS connection.
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// The protocol negotiated with ALPN, if this is a TLS connection that used it.
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// A clone of the underlying socket, which another thread can use to
+    /// shut the connection down while this stream is blocked on it.
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        None
+    }
+    /// Another handle to the same connection, so it can be read on one
+    /// thread while it is written on another.
+    #[inline]
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        Err(io::Error::new(ErrorKind::Other, "stream cannot be cloned"))
+    }
+    /// Called with the `Keep-Alive` header of each response read from the
+    /// stream, or the default if it had none.
+    #[inline]
+    fn set_keep_alive(&mut self, _keep_alive: KeepAlive) {}
 }
 
 /// A connector creates a NetworkStream.
@@ -147,7 +186,10 @@ pub enum HttpListener {
     /// Http variant.
     Http(TcpListener),
     /// Https variant. The two paths point to the certificate and key PEM files, in that order.
-    Https(TcpListener, Arc<SslContext>)
+    Https(TcpListener, Arc<SslContext>),
+    /// Https variant that refuses connections whose handshake doesn't meet
+    /// the `TlsPolicy`.
+    HttpsWithPolicy(TcpListener, Arc<SslContext>, TlsPolicy),
 }
 
 impl Clone for HttpListener {
@@ -155,6 +197,9 @@ impl Clone for HttpListener {
         match *self {
             HttpListener::Http(ref tcp) => HttpListener::Http(tcp.try_clone().unwrap()),
             HttpListener::Https(ref tcp, ref ssl) => HttpListener::Https(tcp.try_clone().unwrap(), ssl.clone()),
+            HttpListener::HttpsWithPolicy(ref tcp, ref ssl, ref policy) => {
+                HttpListener::HttpsWithPolicy(tcp.try_clone().unwrap(), ssl.clone(), policy.clone())
+            },
         }
     }
 }
@@ -176,6 +221,20 @@ impl HttpListener {
         HttpListener::https_with_context(addr, ssl_context)
     }
 
+    /// Start listening to an address over HTTPS, only accepting connections
+    /// that meet `policy`.
+    pub fn https_with_policy<To: ToSocketAddrs>(addr: To, cert: &Path, key: &Path,
+                                                policy: &TlsPolicy) -> ::Result<HttpListener> {
+        let mut ssl_context = try!(SslContext::new(Sslv23));
+        try!(ssl_context.set_cipher_list("DEFAULT"));
+        try!(policy.apply(&mut ssl_context));
+        try!(ssl_context.set_certificate_file(cert, X509FileType::PEM));
+        try!(ssl_context.set_private_key_file(key, X509FileType::PEM));
+        ssl_context.set_verify(SSL_VERIFY_NONE, None);
+        Ok(HttpListener::HttpsWithPolicy(try!(TcpListener::bind(addr)), Arc::new(ssl_context),
+                                         policy.clone()))
+    }
+
     /// Start listening to an address of HTTPS using the given SslContext
     pub fn https_with_context<To: ToSocketAddrs>(addr: To, ssl_context: SslContext) -> ::Result<HttpListener> {
         Ok(HttpListener::Https(try!(TcpListener::bind(addr)), Arc::new(ssl_context)))
@@ -190,14 +249,12 @@ impl NetworkListener for HttpListener {
         match *self {
             HttpListener::Http(ref mut tcp) => Ok(HttpStream::Http(CloneTcpStream(try!(tcp.accept()).0))),
             HttpListener::Https(ref mut tcp, ref ssl_context) => {
-                let stream = CloneTcpStream(try!(tcp.accept()).0);
-                match SslStream::new_server(&**ssl_context, stream) {
-                    Ok(ssl_stream) => Ok(HttpStream::Https(ssl_stream)),
-                    Err(SslIoError(e)) => {
-                        Err(io::Error::new(io::ErrorKind::ConnectionAborted, e).into())
-                    },
-                    Err(e) => Err(e.into())
-                }
+                accept_tls(tcp, ssl_context).map(HttpStream::Https)
+            },
+            HttpListener::HttpsWithPolicy(ref mut tcp, ref ssl_context, ref policy) => {
+                let stream = try!(accept_tls(tcp, ssl_context));
+                try!(policy.check(stream.ssl()));
+                Ok(HttpStream::Https(stream))
             }
         }
     }
@@ -207,10 +264,23 @@ impl NetworkListener for HttpListener {
         match *self {
             HttpListener::Http(ref mut tcp) => tcp.local_addr(),
             HttpListener::Https(ref mut tcp, _) => tcp.local_addr(),
+            HttpListener::HttpsWithPolicy(ref mut tcp, _, _) => tcp.local_addr(),
         }
     }
 }
 
+fn accept_tls(tcp: &mut TcpListener, ssl_context: &SslContext)
+    -> ::Result<SslStream<CloneTcpStream>> {
+    let stream = CloneTcpStream(try!(tcp.accept()).0);
+    match SslStream::new_server(ssl_context, stream) {
+        Ok(ssl_stream) => Ok(ssl_stream),
+        Err(SslIoError(e)) => {
+            Err(io::Error::new(io::ErrorKind::ConnectionAborted, e).into())
+        },
+        Err(e) => Err(handshake_error(e))
+    }
+}
+
 #[doc(hidden)]
 pub struct CloneTcpStream(TcpStream);
 
@@ -311,6 +381,26 @@ impl NetworkStream for HttpStream {
         }
 
     }
+
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        match *self {
+            HttpStream::Http(_) => None,
+            HttpStream::Https(ref inner) => {
+                inner.ssl().peer_certificate().and_then(|cert| cert.save_der().ok())
+            }
+        }
+    }
+
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        match *self {
+            HttpStream::Http(ref inner) => inner.0.try_clone().ok(),
+            HttpStream::Https(ref inner) => inner.get_ref().0.try_clone().ok(),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        Ok(Box::new(self.clone()))
+    }
 }
 
 /// A connector that will produce HttpStreams.
@@ -324,65 +414,1697 @@ impl NetworkConnector for HttpConnector {
 
     fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<HttpStream> {
         let addr = &(host, port);
-        Ok(try!(match scheme {
-            "http" => {
-                debug!("http scheme");
-                Ok(HttpStream::Http(CloneTcpStream(try!(TcpStream::connect(addr)))))
-            },
-            "https" => {
-                debug!("https scheme");
-                let stream = CloneTcpStream(try!(TcpStream::connect(addr)));
-                let mut context = try!(SslContext::new(Sslv23));
-                if let Some(ref verifier) = self.0 {
-                    verifier(&mut context);
+        try!(check_scheme(scheme));
+        self.connect_over(host, try!(TcpStream::connect(addr)), scheme)
+    }
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.0 = Some(verifier);
+    }
+}
+
+impl HttpConnector {
+    /// Use a socket that is already connected, such as a tunnel through a
+    /// proxy, starting TLS with `host` if the scheme is `https`.
+    pub fn connect_over(&self, host: &str, stream: TcpStream, scheme: &str)
+        -> ::Result<HttpStream> {
+        try!(check_scheme(scheme));
+        let stream = CloneTcpStream(stream);
+        if scheme == "http" {
+            debug!("http scheme");
+            return Ok(HttpStream::Http(stream));
+        }
+        debug!("https scheme");
+        let mut context = try!(SslContext::new(Sslv23));
+        if let Some(ref verifier) = self.0 {
+            verifier(&mut context);
+        }
+        start_tls(&context, host, stream).map(HttpStream::Https)
+    }
+}
+
+fn start_tls(context: &SslContext, host: &str, stream: CloneTcpStream)
+    -> ::Result<SslStream<CloneTcpStream>> {
+    let ssl = try!(Ssl::new(context));
+    // the server needs the name to pick its certificate, if it has several
+    try!(ssl.set_hostname(host));
+    SslStream::new_from(ssl, stream).map_err(handshake_error)
+}
+
+fn check_scheme(scheme: &str) -> io::Result<()> {
+    match scheme {
+        "http" | "https" => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                "Invalid scheme for Http"))
+    }
+}
+
+/// An `HttpConnector` that connects to fixed addresses for some hosts,
+/// instead of looking them up in DNS.
+///
+/// Like `curl --resolve`, each entry is for a host and port, and its
+/// addresses are tried in order until one connects. TLS is still started
+/// with the host name, so certificates are checked against it.
+///
+/// ```no_run
+/// # use hyper::Client;
+/// # use hyper::client::Pool;
+/// use hyper::net::ResolvedConnector;
+///
+/// let mut connector = ResolvedConnector::new();
+/// connector.add("api.example.domain", 443, vec!["10.0.0.7:8443".parse().unwrap()]);
+/// connector.set_dns_fallback(false);
+/// let client = Client::with_connector(Pool::with_connector(Default::default(), connector));
+/// ```
+pub struct ResolvedConnector {
+    connector: HttpConnector,
+    resolver: Box<Resolve>,
+    table: HashMap<(String, u16), Vec<SocketAddr>>,
+    dns_fallback: bool,
+}
+
+impl ResolvedConnector {
+    /// Create a connector with an empty table, which looks up every host
+    /// in DNS.
+    pub fn new() -> ResolvedConnector {
+        ResolvedConnector::with_resolver(SystemResolver)
+    }
+
+    /// Create a connector with an empty table, which looks up hosts that
+    /// aren't in it with `resolver`.
+    pub fn with_resolver<R: Resolve + 'static>(resolver: R) -> ResolvedConnector {
+        ResolvedConnector {
+            connector: HttpConnector(None),
+            resolver: Box::new(resolver),
+            table: HashMap::new(),
+            dns_fallback: true,
+        }
+    }
+
+    /// Connect to `addrs` for `host` and `port`, replacing any addresses
+    /// given for them before.
+    pub fn add<H: Into<String>>(&mut self, host: H, port: u16, addrs: Vec<SocketAddr>) {
+        self.table.insert((host.into(), port), addrs);
+    }
+
+    /// Whether a host that isn't in the table is looked up in DNS, or fails
+    /// to connect. The default is to look it up.
+    ///
+    /// Turn this off in tests that must never reach a real host.
+    pub fn set_dns_fallback(&mut self, fallback: bool) {
+        self.dns_fallback = fallback;
+    }
+}
+
+impl NetworkConnector for ResolvedConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<HttpStream> {
+        try!(check_scheme(scheme));
+        let addrs = match self.table.get(&(host.to_string(), port)) {
+            Some(addrs) => addrs.clone(),
+            None if self.dns_fallback => try!(self.resolver.resolve(host, port)).0,
+            None => {
+                debug!("no address for {}:{}, and dns fallback is off", host, port);
+                return Err(::Error::Io(io::Error::new(io::ErrorKind::Other,
+                                                      "no address for host")));
+            }
+        };
+        let mut last_err = io::Error::new(io::ErrorKind::Other, "no address for host");
+        for addr in addrs {
+            trace!("connecting to {} for {}:{}", addr, host, port);
+            match TcpStream::connect(addr) {
+                Ok(stream) => return self.connector.connect_over(host, stream, scheme),
+                Err(e) => last_err = e
+            }
+        }
+        Err(::Error::Io(last_err))
+    }
+
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.connector.set_ssl_verifier(verifier);
+    }
+}
+
+/// An `HttpConnector` that fails over between the addresses a host
+/// resolves to.
+///
+/// Each address is given a limited time to connect before the next one is
+/// tried. An address that fails is penalized for a while, and tried after
+/// the healthy addresses of its host until the penalty runs out, so later
+/// requests don't wait on it again.
+///
+/// ```no_run
+/// # extern crate hyper;
+/// # extern crate time;
+/// # use hyper::Client;
+/// # use hyper::client::Pool;
+/// use hyper::net::FailoverConnector;
+///
+/// # fn main() {
+/// let mut connector = FailoverConnector::new();
+/// connector.set_timeout(time::Duration::seconds(2));
+/// let client = Client::with_connector(Pool::with_connector(Default::default(), connector));
+/// # }
+/// ```
+pub struct FailoverConnector {
+    connector: HttpConnector,
+    resolver: Box<Resolve>,
+    timeout: Duration,
+    penalty: Duration,
+    clock: Arc<Clock>,
+    penalties: Mutex<HashMap<SocketAddr, Timespec>>,
+}
+
+impl FailoverConnector {
+    /// Create a connector that gives each address 5 seconds to connect,
+    /// and penalizes failed addresses for 30 seconds.
+    pub fn new() -> FailoverConnector {
+        FailoverConnector::with_resolver(SystemResolver)
+    }
+
+    /// Create a connector like `new`, that looks up hosts with `resolver`.
+    pub fn with_resolver<R: Resolve + 'static>(resolver: R) -> FailoverConnector {
+        FailoverConnector {
+            connector: HttpConnector(None),
+            resolver: Box::new(resolver),
+            timeout: Duration::seconds(5),
+            penalty: Duration::seconds(30),
+            clock: Arc::new(SystemClock),
+            penalties: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set how long each address is given to connect.
+    ///
+    /// Each attempt waits on a thread of its own, which keeps running until
+    /// the OS gives up on a connection that timed out. While 256 of them
+    /// are running, further attempts fail right away.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set how long an address that failed is tried after the others.
+    pub fn set_penalty(&mut self, penalty: Duration) {
+        self.penalty = penalty;
+    }
+
+    /// Set the `Clock` used to decide when penalties have run out.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    // The healthy addresses in the order given, then the penalized ones,
+    // those whose penalty runs out first before the others.
+    fn order(&self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let now = self.clock.now();
+        let mut penalties = self.penalties.lock().unwrap();
+        let mut healthy = vec![];
+        let mut penalized = vec![];
+        for addr in addrs {
+            match penalties.get(&addr).cloned() {
+                Some(until) if until > now => penalized.push((until, addr)),
+                Some(_) => {
+                    penalties.remove(&addr);
+                    healthy.push(addr);
+                },
+                None => healthy.push(addr)
+            }
+        }
+        penalized.sort_by(|a, b| a.0.cmp(&b.0));
+        healthy.extend(penalized.into_iter().map(|(_, addr)| addr));
+        healthy
+    }
+
+    fn connect_addrs(&self, addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::Other, "no address for host");
+        for addr in self.order(addrs) {
+            trace!("connecting to {}", addr);
+            match connect_timeout(addr, self.timeout) {
+                Ok(stream) => {
+                    self.penalties.lock().unwrap().remove(&addr);
+                    return Ok(stream);
+                },
+                Err(e) => {
+                    debug!("connecting to {} failed, trying the next address: {:?}", addr, e);
+                    let until = self.clock.now() + self.penalty;
+                    self.penalties.lock().unwrap().insert(addr, until);
+                    last_err = e;
                 }
-                let ssl = try!(Ssl::new(&context));
-                try!(ssl.set_hostname(host));
-                let stream = try!(SslStream::new(&context, stream));
-                Ok(HttpStream::Https(stream))
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl NetworkConnector for FailoverConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<HttpStream> {
+        try!(check_scheme(scheme));
+        let (addrs, _) = try!(self.resolver.resolve(host, port));
+        let stream = try!(self.connect_addrs(addrs));
+        self.connector.connect_over(host, stream, scheme)
+    }
+
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.connector.set_ssl_verifier(verifier);
+    }
+}
+
+/// Looks up the addresses of a host.
+pub trait Resolve: Send + Sync {
+    /// The addresses of `host` and `port`, and for how long they may be
+    /// cached, if the resolver knows their TTL.
+    fn resolve(&self, host: &str, port: u16) -> io::Result<(Vec<SocketAddr>, Option<Duration>)>;
+}
+
+/// Resolves hosts with the system resolver, which doesn't tell their TTL.
+#[derive(Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+        let addrs = try!((host, port).to_socket_addrs()).collect();
+        Ok((addrs, None))
+    }
+}
+
+/// The cache of a `CachingResolver` or `DnsCacheConnector`.
+///
+/// Clones share the same entries, so a clone kept aside can flush the
+/// cache of a connector that has been moved into a `Client`.
+#[derive(Clone)]
+pub struct DnsCache {
+    entries: Arc<Mutex<HashMap<(String, u16), (Timespec, Lookup)>>>,
+}
+
+#[derive(Clone)]
+enum Lookup {
+    Found(Vec<SocketAddr>),
+    Failed(io::ErrorKind, String),
+}
+
+impl DnsCache {
+    /// Forget every cached lookup.
+    pub fn flush(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Forget the cached lookup of `host` and `port`, if there is one.
+    pub fn forget(&self, host: &str, port: u16) {
+        self.entries.lock().unwrap().remove(&(host.to_string(), port));
+    }
+
+    /// How many lookups are cached, including expired ones that haven't
+    /// been dropped yet. They are dropped whenever another lookup is cached.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    // Caches `lookup` until `expires`, dropping the lookups that have
+    // already expired, so hosts that are never looked up again don't stay.
+    fn insert(&self, key: (String, u16), expires: Timespec, lookup: Lookup, now: Timespec) {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = entries.iter()
+            .filter(|&(_, &(expires, _))| expires <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            entries.remove(&key);
+        }
+        entries.insert(key, (expires, lookup));
+    }
+}
+
+impl fmt::Debug for DnsCache {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DnsCache").field("len", &self.len()).finish()
+    }
+}
+
+/// A `Resolve` that caches the lookups of another.
+///
+/// A lookup is kept for the TTL the resolver gives, or 60 seconds when it
+/// doesn't know it, unless a TTL is set. Failed lookups are kept for 5
+/// seconds, so a host that doesn't resolve isn't looked up again on every
+/// request.
+///
+/// This is the cache of a `DnsCacheConnector`. Give it to the
+/// `with_resolver` of another connector, such as a `FailoverConnector`, to
+/// cache its lookups too.
+pub struct CachingResolver {
+    resolver: Box<Resolve>,
+    cache: DnsCache,
+    ttl: Option<Duration>,
+    negative_ttl: Duration,
+    clock: Arc<Clock>,
+}
+
+impl CachingResolver {
+    /// Create a resolver that caches the lookups of `resolver`.
+    pub fn new<R: Resolve + 'static>(resolver: R) -> CachingResolver {
+        CachingResolver {
+            resolver: Box::new(resolver),
+            cache: DnsCache { entries: Arc::new(Mutex::new(HashMap::new())) },
+            ttl: None,
+            negative_ttl: Duration::seconds(5),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Keep every lookup for `ttl`, whatever TTL the resolver gives.
+    /// `None`, the default, uses the resolver's TTL.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+
+    /// Set how long a failed lookup is kept.
+    pub fn set_negative_ttl(&mut self, ttl: Duration) {
+        self.negative_ttl = ttl;
+    }
+
+    /// Set the `Clock` used to decide when lookups have expired.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// A handle to the cache, to flush it.
+    pub fn cache(&self) -> DnsCache {
+        self.cache.clone()
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+        let key = (host.to_string(), port);
+        let now = self.clock.now();
+        let cached = match self.cache.entries.lock().unwrap().get(&key) {
+            Some(&(expires, ref lookup)) if now < expires => Some((expires, lookup.clone())),
+            _ => None
+        };
+        let (expires, lookup) = match cached {
+            Some(cached) => {
+                trace!("cached lookup for {}:{}", host, port);
+                cached
             },
-            _ => {
-                Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                "Invalid scheme for Http"))
+            None => {
+                let (lookup, ttl) = match self.resolver.resolve(host, port) {
+                    Ok((addrs, ttl)) => {
+                        let ttl = self.ttl.or(ttl).unwrap_or(Duration::seconds(60));
+                        (Lookup::Found(addrs), ttl)
+                    },
+                    Err(e) => {
+                        debug!("lookup of {}:{} failed: {:?}", host, port, e);
+                        (Lookup::Failed(e.kind(), e.to_string()), self.negative_ttl)
+                    }
+                };
+                self.cache.insert(key, now + ttl, lookup.clone(), now);
+                (now + ttl, lookup)
+            }
+        };
+        match lookup {
+            Lookup::Found(addrs) => Ok((addrs, Some(expires - now))),
+            Lookup::Failed(kind, msg) => Err(io::Error::new(kind, msg))
+        }
+    }
+}
+
+impl fmt::Debug for CachingResolver {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CachingResolver").field("cache", &self.cache).finish()
+    }
+}
+
+/// An `HttpConnector` that caches the addresses hosts resolve to, with a
+/// `CachingResolver`.
+///
+/// When none of the cached addresses of a host connect, its lookup is
+/// dropped and the next connection looks it up again.
+///
+/// ```no_run
+/// # extern crate hyper;
+/// # extern crate time;
+/// # use hyper::Client;
+/// # use hyper::client::Pool;
+/// use hyper::net::DnsCacheConnector;
+///
+/// # fn main() {
+/// let mut connector = DnsCacheConnector::new();
+/// connector.set_ttl(Some(time::Duration::minutes(5)));
+/// let cache = connector.cache();
+/// let client = Client::with_connector(Pool::with_connector(Default::default(), connector));
+/// // later, after a deploy moved the servers
+/// cache.flush();
+/// # }
+/// ```
+pub struct DnsCacheConnector {
+    connector: HttpConnector,
+    resolver: CachingResolver,
+}
+
+impl DnsCacheConnector {
+    /// Create a connector that resolves hosts with the `SystemResolver`.
+    pub fn new() -> DnsCacheConnector {
+        DnsCacheConnector::with_resolver(SystemResolver)
+    }
+
+    /// Create a connector that resolves hosts with `resolver`.
+    pub fn with_resolver<R: Resolve + 'static>(resolver: R) -> DnsCacheConnector {
+        DnsCacheConnector {
+            connector: HttpConnector(None),
+            resolver: CachingResolver::new(resolver),
+        }
+    }
+
+    /// Keep every lookup for `ttl`, whatever TTL the resolver gives.
+    /// `None`, the default, uses the resolver's TTL.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.resolver.set_ttl(ttl);
+    }
+
+    /// Set how long a failed lookup is kept.
+    pub fn set_negative_ttl(&mut self, ttl: Duration) {
+        self.resolver.set_negative_ttl(ttl);
+    }
+
+    /// Set the `Clock` used to decide when lookups have expired.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.resolver.set_clock(clock);
+    }
+
+    /// A handle to the cache, to flush it.
+    pub fn cache(&self) -> DnsCache {
+        self.resolver.cache()
+    }
+}
+
+impl NetworkConnector for DnsCacheConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<HttpStream> {
+        try!(check_scheme(scheme));
+        let mut last_err = io::Error::new(io::ErrorKind::Other, "no address for host");
+        let (addrs, _) = try!(self.resolver.resolve(host, port));
+        for addr in addrs {
+            trace!("connecting to {} for {}:{}", addr, host, port);
+            match TcpStream::connect(addr) {
+                Ok(stream) => return self.connector.connect_over(host, stream, scheme),
+                Err(e) => last_err = e
             }
-        }))
+        }
+        self.resolver.cache.forget(host, port);
+        Err(::Error::Io(last_err))
     }
+
     fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
-        self.0 = Some(verifier);
+        self.connector.set_ssl_verifier(verifier);
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use mock::MockStream;
-    use super::{NetworkStream, HttpConnector, NetworkConnector};
+/// How a `BalancedConnector` picks the address of each new connection.
+pub trait Strategy: Send + Sync {
+    /// Pick one of the addresses of a host, given how many connections are
+    /// open to each, returning its index in `open`.
+    fn pick(&self, open: &[usize]) -> usize;
+}
 
-    #[test]
-    fn test_downcast_box_stream() {
-        // FIXME: Use Type ascription
-        let stream: Box<NetworkStream + Send> = Box::new(MockStream::new());
+/// Use each address in turn.
+#[derive(Debug, Default)]
+pub struct RoundRobin(Mutex<usize>);
 
-        let mock = stream.downcast::<MockStream>().ok().unwrap();
-        assert_eq!(mock, Box::new(MockStream::new()));
+impl Strategy for RoundRobin {
+    fn pick(&self, open: &[usize]) -> usize {
+        let mut next = self.0.lock().unwrap();
+        let picked = *next % open.len();
+        *next = picked + 1;
+        picked
+    }
+}
 
+/// Use the address with the fewest open connections, the first of them on
+/// a tie.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LeastConnections;
+
+impl Strategy for LeastConnections {
+    fn pick(&self, open: &[usize]) -> usize {
+        let mut picked = 0;
+        for (i, &n) in open.iter().enumerate() {
+            if n < open[picked] {
+                picked = i;
+            }
+        }
+        picked
     }
+}
 
-    #[test]
-    fn test_downcast_unchecked_box_stream() {
-        // FIXME: Use Type ascription
-        let stream: Box<NetworkStream + Send> = Box::new(MockStream::new());
+/// Pick two addresses at random, and use the one with fewer open
+/// connections.
+///
+/// This spreads load nearly as well as `LeastConnections`, without every
+/// client piling onto the same address as soon as it becomes the least
+/// loaded.
+#[derive(Debug)]
+pub struct TwoChoices(Mutex<u64>);
 
-        let mock = unsafe { stream.downcast_unchecked::<MockStream>() };
-        assert_eq!(mock, Box::new(MockStream::new()));
+impl TwoChoices {
+    /// Create the strategy, seeded from the clock.
+    pub fn new() -> TwoChoices {
+        TwoChoices(Mutex::new(precise_time_ns() | 1))
+    }
+
+    // xorshift64*, which is plenty for spreading load
+    fn random(&self, below: usize) -> usize {
+        let mut state = self.0.lock().unwrap();
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        (state.wrapping_mul(2685821657736338717) % below as u64) as usize
+    }
+}
 
+impl Strategy for TwoChoices {
+    fn pick(&self, open: &[usize]) -> usize {
+        let a = self.random(open.len());
+        let b = self.random(open.len());
+        if open[b] < open[a] { b } else { a }
     }
+}
 
-    #[test]
-    fn test_http_connector_set_ssl_verifier() {
-        let mut connector = HttpConnector(None);
+/// An `HttpConnector` that spreads connections to a host across its
+/// addresses, for clusters without a load balancer in front.
+///
+/// The addresses are those given with `add`, or else those the host
+/// resolves to. A `Strategy` picks the address of each connection, from
+/// how many connections this connector has open to each. If it can't be
+/// reached, the other addresses are tried in turn.
+///
+/// ```no_run
+/// # use hyper::Client;
+/// # use hyper::client::Pool;
+/// use hyper::net::{BalancedConnector, LeastConnections};
+///
+/// let mut connector = BalancedConnector::new(LeastConnections);
+/// connector.add("api.cluster.domain", 80, vec![
+///     "10.0.0.7:80".parse().unwrap(),
+///     "10.0.0.8:80".parse().unwrap(),
+/// ]);
+/// let client = Client::with_connector(Pool::with_connector(Default::default(), connector));
+/// ```
+pub struct BalancedConnector {
+    connector: HttpConnector,
+    resolver: Box<Resolve>,
+    strategy: Box<Strategy>,
+    table: HashMap<(String, u16), Vec<SocketAddr>>,
+    open: Arc<Mutex<HashMap<SocketAddr, usize>>>,
+}
 
-        connector.set_ssl_verifier(Box::new(|_| {}));
+impl BalancedConnector {
+    /// Create a connector that picks addresses with `strategy`.
+    pub fn new<S: Strategy + 'static>(strategy: S) -> BalancedConnector {
+        BalancedConnector::with_resolver(strategy, SystemResolver)
+    }
 
-        assert!(connector.0.is_some());
+    /// Create a connector that picks addresses with `strategy`, and looks
+    /// up hosts that weren't added with `resolver`.
+    pub fn with_resolver<S, R>(strategy: S, resolver: R) -> BalancedConnector
+    where S: Strategy + 'static, R: Resolve + 'static {
+        BalancedConnector {
+            connector: HttpConnector(None),
+            resolver: Box::new(resolver),
+            strategy: Box::new(strategy),
+            table: HashMap::new(),
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Spread connections to `host` and `port` across `addrs`, instead of
+    /// the addresses it resolves to.
+    pub fn add<H: Into<String>>(&mut self, host: H, port: u16, addrs: Vec<SocketAddr>) {
+        self.table.insert((host.into(), port), addrs);
+    }
+
+    /// How many connections this connector has open to `addr`.
+    pub fn open_connections(&self, addr: &SocketAddr) -> usize {
+        self.open.lock().unwrap().get(addr).cloned().unwrap_or(0)
+    }
+
+    fn connect_addrs(&self, addrs: &[SocketAddr]) -> io::Result<(SocketAddr, TcpStream)> {
+        if addrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Other, "no address for host"));
+        }
+        let picked = {
+            let open = self.open.lock().unwrap();
+            let counts = addrs.iter().map(|addr| open.get(addr).cloned().unwrap_or(0))
+                .collect::<Vec<_>>();
+            self.strategy.pick(&counts) % addrs.len()
+        };
+        let mut last_err = None;
+        for addr in addrs[picked..].iter().chain(addrs[..picked].iter()) {
+            trace!("connecting to {}", addr);
+            match TcpStream::connect(addr) {
+                Ok(stream) => return Ok((*addr, stream)),
+                Err(e) => {
+                    debug!("connecting to {} failed: {:?}", addr, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap())
+    }
+}
+
+impl NetworkConnector for BalancedConnector {
+    type Stream = BalancedStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<BalancedStream> {
+        try!(check_scheme(scheme));
+        let (addr, stream) = match self.table.get(&(host.to_string(), port)) {
+            Some(addrs) => try!(self.connect_addrs(addrs)),
+            None => {
+                let (addrs, _) = try!(self.resolver.resolve(host, port));
+                try!(self.connect_addrs(&addrs))
+            }
+        };
+        let stream = try!(self.connector.connect_over(host, stream, scheme));
+        *self.open.lock().unwrap().entry(addr).or_insert(0) += 1;
+        Ok(BalancedStream {
+            stream: stream,
+            addr: addr,
+            open: self.open.clone(),
+        })
+    }
+
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.connector.set_ssl_verifier(verifier);
+    }
+}
+
+/// A connection made by a `BalancedConnector`, counted as open to its
+/// address until it is dropped.
+pub struct BalancedStream {
+    stream: HttpStream,
+    addr: SocketAddr,
+    open: Arc<Mutex<HashMap<SocketAddr, usize>>>,
+}
+
+impl Read for BalancedStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for BalancedStream {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        self.stream.write(msg)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl NetworkStream for BalancedStream {
+    #[inline]
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    #[inline]
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.stream.close(how)
+    }
+
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.stream.peer_certificate()
+    }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.stream.alpn_protocol()
+    }
+
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        self.stream.shutdown_handle()
+    }
+
+    /// The clone isn't counted, as it is the same connection.
+    #[inline]
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        self.stream.try_clone()
+    }
+
+    #[inline]
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.stream.set_keep_alive(keep_alive)
+    }
+}
+
+impl Drop for BalancedStream {
+    fn drop(&mut self) {
+        if let Ok(mut open) = self.open.lock() {
+            let none_left = match open.get_mut(&self.addr) {
+                Some(n) => {
+                    *n -= 1;
+                    *n == 0
+                },
+                None => false
+            };
+            if none_left {
+                open.remove(&self.addr);
+            }
+        }
+    }
+}
+
+// The most connect_timeout threads that may be running at once. A thread
+// whose connection timed out keeps blocking until the OS gives up on it,
+// which can take minutes, so against an address that never answers they
+// would otherwise pile up without bound.
+const MAX_CONNECTING: usize = 256;
+
+static CONNECTING: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Connects on another thread, so the wait can be cut short. A connection
+// that is only made after the timeout is closed when its thread ends.
+fn connect_timeout(addr: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    if CONNECTING.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTING {
+        CONNECTING.fetch_sub(1, Ordering::SeqCst);
+        return Err(io::Error::new(io::ErrorKind::Other, "too many connections pending"));
+    }
+    let done = Arc::new((Mutex::new(None), Condvar::new()));
+    let result = done.clone();
+    thread::spawn(move || {
+        let res = TcpStream::connect(&addr);
+        CONNECTING.fetch_sub(1, Ordering::SeqCst);
+        let &(ref lock, ref cvar) = &*result;
+        *lock.lock().unwrap() = Some(res);
+        cvar.notify_one();
+    });
+
+    let &(ref lock, ref cvar) = &*done;
+    let mut res = lock.lock().unwrap();
+    let ms = timeout.num_milliseconds();
+    let start = precise_time_ns();
+    while res.is_none() {
+        let waited = ((precise_time_ns() - start) / 1_000_000) as i64;
+        if waited >= ms {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"));
+        }
+        res = cvar.wait_timeout_ms(res, (ms - waited) as u32).unwrap().0;
+    }
+    res.take().unwrap()
+}
+
+/// A version of the TLS protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    /// TLS 1.0
+    Tls10,
+    /// TLS 1.1
+    Tls11,
+    /// TLS 1.2
+    Tls12,
+}
+
+impl TlsVersion {
+    // Parses the names OpenSSL gives the negotiated protocol. SSL versions
+    // come out as `None`, as they are older than any `TlsVersion`.
+    fn from_openssl(name: &str) -> Option<TlsVersion> {
+        match name {
+            "TLSv1" => Some(TlsVersion::Tls10),
+            "TLSv1.1" => Some(TlsVersion::Tls11),
+            "TLSv1.2" => Some(TlsVersion::Tls12),
+            _ => None
+        }
+    }
+}
+
+/// Requirements a TLS connection must meet, for a `TlsConnector` or an
+/// `HttpListener`.
+///
+/// The default policy refuses SSLv2, SSLv3 and TLS compression, and
+/// otherwise leaves OpenSSL's defaults alone.
+///
+/// ```no_run
+/// use hyper::net::{TlsPolicy, TlsVersion};
+///
+/// let mut policy = TlsPolicy::new();
+/// policy.set_min_version(TlsVersion::Tls12);
+/// policy.set_ciphers("ECDHE+AESGCM:!aNULL");
+/// policy.set_renegotiation(false);
+/// ```
+///
+/// A connection that fails to meet the policy fails with
+/// `Error::TlsPolicy`, so it can be told apart from one that couldn't reach
+/// the host at all, which fails with `Error::Io`.
+#[derive(Clone, Debug)]
+pub struct TlsPolicy {
+    min_version: TlsVersion,
+    ciphers: Option<String>,
+    compression: bool,
+    renegotiation: bool,
+}
+
+impl TlsPolicy {
+    /// Create the default policy.
+    pub fn new() -> TlsPolicy {
+        TlsPolicy {
+            min_version: TlsVersion::Tls10,
+            ciphers: None,
+            compression: false,
+            renegotiation: true,
+        }
+    }
+
+    /// Refuse any protocol version older than `version`.
+    pub fn set_min_version(&mut self, version: TlsVersion) {
+        self.min_version = version;
+    }
+
+    /// Only allow the cipher suites in `ciphers`, an OpenSSL cipher list
+    /// such as `"HIGH:!aNULL:!MD5"`.
+    pub fn set_ciphers<S: Into<String>>(&mut self, ciphers: S) {
+        self.ciphers = Some(ciphers.into());
+    }
+
+    /// Whether TLS compression may be used. It is off by default, as it
+    /// exposes secrets to the CRIME attack.
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
+    /// Whether the peer may renegotiate the connection. It is allowed by
+    /// default.
+    pub fn set_renegotiation(&mut self, renegotiation: bool) {
+        self.renegotiation = renegotiation;
+    }
+
+    /// Configure `context` to only make connections that meet this policy.
+    ///
+    /// Connectors and listeners made with this policy call this themselves.
+    /// It is public for servers that build their own `SslContext`.
+    pub fn apply(&self, context: &mut SslContext) -> ::Result<()> {
+        let mut options = SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3;
+        if self.min_version > TlsVersion::Tls10 {
+            options = options | SSL_OP_NO_TLSV1;
+        }
+        if self.min_version > TlsVersion::Tls11 {
+            options = options | SSL_OP_NO_TLSV1_1;
+        }
+        if !self.compression {
+            options = options | SSL_OP_NO_COMPRESSION;
+        }
+        if !self.renegotiation {
+            options = options | SSL_OP_NO_RENEGOTIATION;
+        }
+        context.set_options(options);
+        if let Some(ref ciphers) = self.ciphers {
+            try!(context.set_cipher_list(ciphers));
+        }
+        Ok(())
+    }
+
+    // The options should already keep out older versions, but what was
+    // negotiated is checked as well, in case the OpenSSL in use ignored one.
+    fn check(&self, ssl: &Ssl) -> ::Result<()> {
+        let name = ssl.version();
+        match TlsVersion::from_openssl(name) {
+            Some(version) if version >= self.min_version => Ok(()),
+            // newer than any TlsVersion
+            None if name.starts_with("TLSv1.") => Ok(()),
+            _ => {
+                debug!("negotiated {}, below the minimum {:?}", name, self.min_version);
+                Err(::Error::TlsPolicy(format!("negotiated {}, below the minimum version", name)))
+            }
+        }
+    }
+}
+
+impl Default for TlsPolicy {
+    fn default() -> TlsPolicy {
+        TlsPolicy::new()
+    }
+}
+
+// The reasons, as OpenSSL's SSL library names its reason codes, that a
+// handshake fails with when the peers couldn't agree on a protocol version
+// or cipher suite.
+const POLICY_REASONS: &'static [&'static str] = &[
+    "unsupported protocol",
+    "no protocols available",
+    "wrong version number",
+    "wrong ssl version",
+    "unknown protocol",
+    "no shared cipher",
+    "no ciphers available",
+    "no cipher match",
+    "inappropriate fallback",
+    "sslv3 alert handshake failure",
+    "tlsv1 alert protocol version",
+    "tlsv1 alert insufficient security",
+];
+
+// A handshake that fails because of the policy is a policy violation,
+// rather than an I/O error or some other SSL error.
+fn handshake_error(err: SslError) -> ::Error {
+    if let SslError::OpenSslErrors(ref errors) = err {
+        for error in errors {
+            let OpensslError::UnknownError { ref library, ref reason, .. } = *error;
+            if library == "SSL routines" && POLICY_REASONS.contains(&&reason[..]) {
+                return ::Error::TlsPolicy(reason.clone());
+            }
+        }
+    }
+    err.into()
+}
+
+/// What a certificate callback is given to decide whether to trust a host.
+#[derive(Debug)]
+pub struct CertificateCheck<'a> {
+    host: &'a str,
+    chain: &'a [Vec<u8>],
+    chain_verified: bool,
+}
+
+impl<'a> CertificateCheck<'a> {
+    /// The host that was connected to.
+    pub fn host(&self) -> &str {
+        self.host
+    }
+
+    /// The DER encoded certificates the host presented, starting with its
+    /// own.
+    pub fn chain(&self) -> &[Vec<u8>] {
+        self.chain
+    }
+
+    /// Whether OpenSSL's own verification of the chain succeeded.
+    ///
+    /// This only says the chain leads to a trusted root, and nothing about
+    /// whether the certificate was issued for `host`. A callback has to
+    /// check the host name itself.
+    pub fn chain_verified(&self) -> bool {
+        self.chain_verified
+    }
+}
+
+/// A callback that decides whether to trust the certificates a host
+/// presented.
+pub type CertificateCallback = Box<Fn(&CertificateCheck) -> bool + Send + Sync>;
+
+/// An `HttpConnector` whose TLS connections must meet a `TlsPolicy`.
+///
+/// ```no_run
+/// # use hyper::Client;
+/// # use hyper::client::Pool;
+/// use hyper::net::{TlsConnector, TlsPolicy, TlsVersion};
+///
+/// let mut policy = TlsPolicy::new();
+/// policy.set_min_version(TlsVersion::Tls12);
+/// let connector = TlsConnector::new(policy);
+/// let client = Client::with_connector(Pool::with_connector(Default::default(), connector));
+/// ```
+pub struct TlsConnector {
+    verifier: Option<ContextVerifier>,
+    policy: TlsPolicy,
+    pins: HashMap<String, Vec<String>>,
+    callback: Option<CertificateCallback>,
+}
+
+impl TlsConnector {
+    /// Create a connector that enforces `policy`.
+    pub fn new(policy: TlsPolicy) -> TlsConnector {
+        TlsConnector {
+            verifier: None,
+            policy: policy,
+            pins: HashMap::new(),
+            callback: None,
+        }
+    }
+
+    /// The policy connections must meet.
+    pub fn policy(&self) -> &TlsPolicy {
+        &self.policy
+    }
+
+    /// Pin the public keys `host` may present, replacing any pins given for
+    /// it before.
+    ///
+    /// Each pin is the base64 encoded SHA-256 hash of a DER encoded
+    /// SubjectPublicKeyInfo, as in
+    /// [RFC7469](https://tools.ietf.org/html/rfc7469). After the handshake,
+    /// some certificate in the chain the host presented must have a pinned
+    /// key, or the connection fails with `Error::PinMismatch`. Hosts without
+    /// pins are only checked as usual.
+    ///
+    /// ```no_run
+    /// # use hyper::net::{TlsConnector, TlsPolicy};
+    /// let mut connector = TlsConnector::new(TlsPolicy::new());
+    /// connector.add_pins("api.example.domain", vec![
+    ///     "E9CZ9INDbd+2eRQozYqqbQ2yXLVKB9+xcprMF+44U1g=".to_string(),
+    ///     // a backup key, for when the first is rotated out
+    ///     "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=".to_string(),
+    /// ]);
+    /// ```
+    pub fn add_pins<H: Into<String>>(&mut self, host: H, pins: Vec<String>) {
+        self.pins.insert(host.into(), pins);
+    }
+
+    /// Decide whether to trust each host with `callback`, instead of
+    /// OpenSSL's verification.
+    ///
+    /// The callback is given the chain the host presented, and whether
+    /// OpenSSL verified it, and returns whether to trust it. This allows
+    /// trust policies OpenSSL doesn't have, such as a grace period for
+    /// expired certificates. A rejected connection fails with
+    /// `Error::CertificateRejected`.
+    ///
+    /// The handshake no longer fails by itself when the chain doesn't
+    /// verify, even if an SSL verifier asks for it, so the callback has the
+    /// final say. Nothing checks that the certificate names the host, so
+    /// the callback must do that as well. Pins are still checked after the
+    /// callback accepts.
+    ///
+    /// ```no_run
+    /// # use hyper::net::{TlsConnector, TlsPolicy};
+    /// # fn names_host(_: &[u8], _: &str) -> bool { true }
+    /// let mut connector = TlsConnector::new(TlsPolicy::new());
+    /// connector.set_certificate_callback(|check| {
+    ///     let leaf = match check.chain().first() {
+    ///         Some(leaf) => leaf,
+    ///         None => return false
+    ///     };
+    ///     names_host(leaf, check.host()) &&
+    ///         (check.chain_verified() || check.host() == "staging.example.domain")
+    /// });
+    /// ```
+    pub fn set_certificate_callback<F>(&mut self, callback: F)
+    where F: Fn(&CertificateCheck) -> bool + Send + Sync + 'static {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Use a socket that is already connected, as
+    /// `HttpConnector::connect_over` does.
+    pub fn connect_over(&self, host: &str, stream: TcpStream, scheme: &str)
+        -> ::Result<HttpStream> {
+        try!(check_scheme(scheme));
+        let stream = CloneTcpStream(stream);
+        if scheme == "http" {
+            return Ok(HttpStream::Http(stream));
+        }
+        let mut context = try!(SslContext::new(Sslv23));
+        if let Some(ref verifier) = self.verifier {
+            verifier(&mut context);
+        }
+        try!(self.policy.apply(&mut context));
+        if self.callback.is_some() {
+            context.set_verify(SSL_VERIFY_NONE, None);
+        }
+        let stream = try!(start_tls(&context, host, stream));
+        try!(self.policy.check(stream.ssl()));
+        if self.callback.is_some() || self.pins.contains_key(host) {
+            let verified = stream.ssl().verify_result() == X509VerifyResult::OK;
+            try!(self.check_certificate(host, &peer_chain(stream.ssl()), verified));
+        }
+        Ok(HttpStream::Https(stream))
+    }
+
+    fn check_certificate(&self, host: &str, chain: &[Vec<u8>], chain_verified: bool)
+        -> ::Result<()> {
+        if let Some(ref callback) = self.callback {
+            let check = CertificateCheck {
+                host: host,
+                chain: chain,
+                chain_verified: chain_verified,
+            };
+            if !callback(&check) {
+                debug!("certificate callback rejected {}", host);
+                return Err(::Error::CertificateRejected);
+            }
+        }
+        if let Some(pins) = self.pins.get(host) {
+            if !matches_pin(chain, pins) {
+                debug!("no certificate from {} matched its pins", host);
+                return Err(::Error::PinMismatch);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The DER encoded certificates the peer presented, starting with its own.
+fn peer_chain(ssl: &Ssl) -> Vec<Vec<u8>> {
+    match ssl.peer_cert_chain() {
+        Some(chain) => chain.iter().filter_map(|cert| cert.save_der().ok()).collect(),
+        None => ssl.peer_certificate().and_then(|cert| cert.save_der().ok()).into_iter().collect()
+    }
+}
+
+fn matches_pin(chain: &[Vec<u8>], pins: &[String]) -> bool {
+    chain.iter().filter_map(|cert| spki(cert)).any(|spki| {
+        let pin = hash(Type::SHA256, spki).to_base64(STANDARD);
+        pins.iter().any(|p| *p == pin)
+    })
+}
+
+macro_rules! try_option(
+    ($e:expr) => {{
+        match $e {
+            Some(v) => v,
+            None => return None
+        }
+    }}
+);
+
+// The SubjectPublicKeyInfo of a DER encoded X.509 certificate, with its
+// tag and length.
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = try_option!(der_element(cert));
+    let (_, tbs, _) = try_option!(der_element(cert));
+    let mut rest = tbs;
+    // the version is optional, and explicitly tagged with [0]
+    if rest.first() == Some(&0xa0) {
+        rest = try_option!(der_element(rest)).2;
+    }
+    // skip the serial number, signature, issuer, validity and subject
+    for _ in 0..5 {
+        rest = try_option!(der_element(rest)).2;
+    }
+    let (_, _, after) = try_option!(der_element(rest));
+    Some(&rest[..rest.len() - after.len()])
+}
+
+// Splits the DER element at the start of `der` into its tag, its contents,
+// and whatever follows it.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if der.len() < 2 {
+        return None;
+    }
+    let (len, start) = if der[1] < 0x80 {
+        (der[1] as usize, 2)
+    } else {
+        let octets = (der[1] & 0x7f) as usize;
+        if octets == 0 || octets > 4 || der.len() < 2 + octets {
+            return None;
+        }
+        let len = der[2..2 + octets].iter().fold(0, |len, &b| (len << 8) | b as usize);
+        (len, 2 + octets)
+    };
+    if der.len() - start < len {
+        return None;
+    }
+    Some((der[0], &der[start..start + len], &der[start + len..]))
+}
+
+impl NetworkConnector for TlsConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> ::Result<HttpStream> {
+        try!(check_scheme(scheme));
+        self.connect_over(host, try!(TcpStream::connect(&(host, port))), scheme)
+    }
+
+    fn set_ssl_verifier(&mut self, verifier: ContextVerifier) {
+        self.verifier = Some(verifier);
+    }
+}
+
+/// A stream that pretends to be an established TLS connection.
+///
+/// Reads and writes pass straight through to the wrapped stream, while the
+/// peer certificate and ALPN protocol are whatever was injected. This lets
+/// code that inspects TLS connections be tested without certificates or
+/// a handshake.
+#[derive(Debug)]
+pub struct MockTlsStream<S> {
+    inner: S,
+    certificate: Option<Vec<u8>>,
+    alpn: Option<Vec<u8>>,
+}
+
+impl<S: NetworkStream> MockTlsStream<S> {
+    /// Wrap a stream, without a certificate or ALPN protocol.
+    pub fn new(inner: S) -> MockTlsStream<S> {
+        MockTlsStream {
+            inner: inner,
+            certificate: None,
+            alpn: None,
+        }
+    }
+
+    /// Report this DER encoded certificate as the peer's.
+    pub fn with_certificate(mut self, der: Vec<u8>) -> MockTlsStream<S> {
+        self.certificate = Some(der);
+        self
+    }
+
+    /// Report this protocol as negotiated with ALPN.
+    pub fn with_alpn(mut self, protocol: &[u8]) -> MockTlsStream<S> {
+        self.alpn = Some(protocol.to_vec());
+        self
+    }
+
+    /// Get a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwrap the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: NetworkStream> Read for MockTlsStream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: NetworkStream> Write for MockTlsStream<S> {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+        self.inner.write(msg)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: NetworkStream> NetworkStream for MockTlsStream<S> {
+    #[inline]
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    #[inline]
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.inner.close(how)
+    }
+
+    #[inline]
+    fn peer_certificate(&self) -> Option<Vec<u8>> {
+        self.certificate.clone()
+    }
+
+    #[inline]
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.alpn.clone()
+    }
+
+    #[inline]
+    fn shutdown_handle(&self) -> Option<TcpStream> {
+        self.inner.shutdown_handle()
+    }
+
+    #[inline]
+    fn try_clone(&self) -> io::Result<Box<NetworkStream + Send>> {
+        self.inner.try_clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mock::MockStream;
+    use super::{NetworkStream, HttpConnector, NetworkConnector, MockTlsStream};
+
+    #[test]
+    fn test_strategies() {
+        use super::{Strategy, RoundRobin, LeastConnections, TwoChoices};
+
+        let rr = RoundRobin::default();
+        assert_eq!((0..4).map(|_| rr.pick(&[0, 0, 0])).collect::<Vec<_>>(), vec![0, 1, 2, 0]);
+        assert_eq!(LeastConnections.pick(&[2, 1, 3, 1]), 1);
+
+        // the idle address wins unless it isn't one of the two choices
+        let two = TwoChoices::new();
+        let idle = (0..200).filter(|_| two.pick(&[3, 0]) == 1).count();
+        assert!(idle > 100, "idle address picked {} of 200 times", idle);
+    }
+
+    #[test]
+    fn test_balanced_connector() {
+        use std::net::TcpListener;
+        use super::{BalancedConnector, LeastConnections};
+
+        let first = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = vec![first.local_addr().unwrap(), second.local_addr().unwrap()];
+        let mut connector = BalancedConnector::new(LeastConnections);
+        connector.add("cluster.domain", 80, addrs.clone());
+
+        let mut a = connector.connect("cluster.domain", 80, "http").unwrap();
+        let mut b = connector.connect("cluster.domain", 80, "http").unwrap();
+        assert_eq!(a.peer_addr().unwrap(), addrs[0]);
+        assert_eq!(b.peer_addr().unwrap(), addrs[1]);
+        drop(a);
+        assert_eq!(connector.open_connections(&addrs[0]), 0);
+        let mut c = connector.connect("cluster.domain", 80, "http").unwrap();
+        assert_eq!(c.peer_addr().unwrap(), addrs[0]);
+        drop(b);
+        assert_eq!(connector.open_connections(&addrs[1]), 0);
+    }
+
+    #[test]
+    fn test_failover() {
+        use std::net::TcpListener;
+        use std::sync::Arc;
+        use time::{Duration, Timespec};
+        use clock::TestClock;
+        use super::FailoverConnector;
+
+        let live = TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_addr = live.local_addr().unwrap();
+        let dead_addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let clock = Arc::new(TestClock::new(Timespec::new(0, 0)));
+        let mut connector = FailoverConnector::new();
+        connector.set_clock(clock.clone());
+        connector.set_penalty(Duration::seconds(30));
+
+        let stream = connector.connect_addrs(vec![dead_addr, live_addr]).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live_addr);
+        assert_eq!(connector.order(vec![dead_addr, live_addr]), vec![live_addr, dead_addr]);
+        clock.advance(Duration::seconds(30));
+        assert_eq!(connector.order(vec![dead_addr, live_addr]), vec![dead_addr, live_addr]);
+
+        assert!(connector.connect_addrs(vec![dead_addr]).is_err());
+    }
+
+    #[test]
+    fn test_dns_cache() {
+        use std::io;
+        use std::net::{SocketAddr, TcpListener};
+        use std::sync::{Arc, Mutex};
+        use time::{Duration, Timespec};
+        use clock::TestClock;
+        use super::{CachingResolver, DnsCacheConnector, FailoverConnector, Resolve};
+
+        // Resolves "live" to `addr` with a TTL of 10 seconds, and counts lookups.
+        struct Counting(SocketAddr, Arc<Mutex<usize>>);
+
+        impl Resolve for Counting {
+            fn resolve(&self, host: &str, _port: u16)
+                -> io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+                *self.1.lock().unwrap() += 1;
+                match host {
+                    "live" => Ok((vec![self.0], Some(Duration::seconds(10)))),
+                    _ => Err(io::Error::new(io::ErrorKind::Other, "no such host"))
+                }
+            }
+        }
+
+        let live = TcpListener::bind("127.0.0.1:0").unwrap();
+        let lookups = Arc::new(Mutex::new(0));
+        let clock = Arc::new(TestClock::new(Timespec::new(0, 0)));
+        let mut connector = DnsCacheConnector::with_resolver(
+            Counting(live.local_addr().unwrap(), lookups.clone()));
+        connector.set_clock(clock.clone());
+
+        connector.connect("live", 80, "http").unwrap();
+        connector.connect("live", 80, "http").unwrap();
+        assert_eq!(*lookups.lock().unwrap(), 1);
+        clock.advance(Duration::seconds(10));
+        connector.connect("live", 80, "http").unwrap();
+        assert_eq!(*lookups.lock().unwrap(), 2);
+
+        // failures are cached too, for a shorter time
+        assert!(connector.connect("dead", 80, "http").is_err());
+        assert!(connector.connect("dead", 80, "http").is_err());
+        assert_eq!(*lookups.lock().unwrap(), 3);
+        clock.advance(Duration::seconds(5));
+        assert!(connector.connect("dead", 80, "http").is_err());
+        assert_eq!(*lookups.lock().unwrap(), 4);
+
+        // a fixed ttl overrides the resolver's
+        connector.set_ttl(Some(Duration::seconds(60)));
+        connector.cache().flush();
+        connector.connect("live", 80, "http").unwrap();
+        clock.advance(Duration::seconds(30));
+        connector.connect("live", 80, "http").unwrap();
+        assert_eq!(*lookups.lock().unwrap(), 5);
+
+        // an address that no longer connects is looked up again
+        drop(live);
+        assert!(connector.connect("live", 80, "http").is_err());
+        assert_eq!(connector.cache().len(), 0);
+        assert_eq!(*lookups.lock().unwrap(), 5);
+
+        // other connectors can cache their lookups the same way, and an
+        // expired lookup is dropped once another is cached
+        let live = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut resolver = CachingResolver::new(
+            Counting(live.local_addr().unwrap(), lookups.clone()));
+        resolver.set_clock(clock.clone());
+        let cache = resolver.cache();
+        let connector = FailoverConnector::with_resolver(resolver);
+        assert!(connector.connect("dead", 80, "http").is_err());
+        assert_eq!(cache.len(), 1);
+        clock.advance(Duration::seconds(5));
+        connector.connect("live", 80, "http").unwrap();
+        connector.connect("live", 80, "http").unwrap();
+        assert_eq!(*lookups.lock().unwrap(), 7);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_downcast_box_stream() {
+        // FIXME: Use Type ascription
+        let stream: Box<NetworkStream + Send> = Box::new(MockStream::new());
+
+        let mock = stream.downcast::<MockStream>().ok().unwrap();
+        assert_eq!(mock, Box::new(MockStream::new()));
+
+    }
+
+    #[test]
+    fn test_downcast_unchecked_box_stream() {
+        // FIXME: Use Type ascription
+        let stream: Box<NetworkStream + Send> = Box::new(MockStream::new());
+
+        let mock = unsafe { stream.downcast_unchecked::<MockStream>() };
+        assert_eq!(mock, Box::new(MockStream::new()));
+
+    }
+
+    #[test]
+    fn test_http_connector_set_ssl_verifier() {
+        let mut connector = HttpConnector(None);
+
+        connector.set_ssl_verifier(Box::new(|_| {}));
+
+        assert!(connector.0.is_some());
+    }
+
+    #[test]
+    fn test_resolved_connector() {
+        use client::{Client, Pool};
+        use method::Method::Get;
+        use server::testing::{TestServer, Expected, Reply};
+        use status::StatusCode;
+        use super::ResolvedConnector;
+
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/"), Reply::new(StatusCode::Ok));
+
+        let mut connector = ResolvedConnector::new();
+        // the first address refuses, so the next is tried
+        connector.add("service.test", 80, vec!["127.0.0.1:1".parse().unwrap(), server.addr()]);
+        connector.set_dns_fallback(false);
+        let mut client = Client::with_connector(Pool::with_connector(Default::default(),
+                                                                     connector));
+
+        let res = client.get("http://service.test/").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(client.get("http://other.test/").send().is_err());
+        server.assert_done();
+    }
+
+    #[test]
+    fn test_tls_policy_errors() {
+        use std::io;
+        use openssl::ssl::error::{SslError, OpensslError};
+        use Error;
+        use super::handshake_error;
+
+        fn openssl_error(reason: &str) -> SslError {
+            SslError::OpenSslErrors(vec![OpensslError::UnknownError {
+                library: "SSL routines".to_string(),
+                function: "ssl23_get_server_hello".to_string(),
+                reason: reason.to_string(),
+            }])
+        }
+
+        match handshake_error(openssl_error("unsupported protocol")) {
+            Error::TlsPolicy(ref reason) => assert_eq!(reason, "unsupported protocol"),
+            other => panic!("expected TlsPolicy, got {:?}", other)
+        }
+        match handshake_error(openssl_error("no shared cipher")) {
+            Error::TlsPolicy(_) => (),
+            other => panic!("expected TlsPolicy, got {:?}", other)
+        }
+        match handshake_error(openssl_error("certificate verify failed")) {
+            Error::Ssl(_) => (),
+            other => panic!("expected Ssl, got {:?}", other)
+        }
+        // a reason merely mentioning the handshake isn't about the policy
+        match handshake_error(openssl_error("ssl handshake failure")) {
+            Error::Ssl(_) => (),
+            other => panic!("expected Ssl, got {:?}", other)
+        }
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        match handshake_error(SslError::StreamError(reset)) {
+            Error::Io(_) => (),
+            other => panic!("expected Io, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_spki_pins() {
+        use openssl::crypto::hash::{hash, Type};
+        use serialize::base64::{ToBase64, STANDARD};
+        use super::{spki, matches_pin};
+
+        // the bare skeleton of a certificate, with a NULL for its key
+        let cert = vec![
+            0x30, 0x1a,
+                0x30, 0x14,
+                    0xa0, 0x03, 0x02, 0x01, 0x02,
+                    0x02, 0x01, 0x01,
+                    0x30, 0x00, 0x30, 0x00, 0x30, 0x00, 0x30, 0x00,
+                    0x30, 0x02, 0x05, 0x00,
+                0x30, 0x00,
+                0x03, 0x00,
+        ];
+        assert_eq!(spki(&cert), Some(&[0x30, 0x02, 0x05, 0x00][..]));
+        assert_eq!(spki(&cert[..10]), None);
+
+        let pin = hash(Type::SHA256, &[0x30, 0x02, 0x05, 0x00]).to_base64(STANDARD);
+        let chain = vec![vec![0x30, 0x00], cert];
+        assert!(matches_pin(&chain, &["bogus".to_string(), pin]));
+        assert!(!matches_pin(&chain, &["bogus".to_string()]));
+        assert!(!matches_pin(&[], &["bogus".to_string()]));
+    }
+
+    // A server context with a new self-signed certificate for `name`, and
+    // the pin of its key.
+    fn tls_context(name: &str) -> (::openssl::ssl::SslContext, String) {
+        use openssl::crypto::hash::{hash, Type};
+        use openssl::ssl::SslContext;
+        use openssl::ssl::SslMethod::Sslv23;
+        use openssl::x509::X509Generator;
+        use serialize::base64::{ToBase64, STANDARD};
+        use super::spki;
+
+        let (cert, key) = X509Generator::new().set_CN(name).set_valid_period(1)
+            .set_bitlength(2048).generate().unwrap();
+        let mut context = SslContext::new(Sslv23).unwrap();
+        context.set_certificate(&cert).unwrap();
+        context.set_private_key(&key).unwrap();
+        let der = cert.save_der().unwrap();
+        let pin = hash(Type::SHA256, spki(&der).unwrap()).to_base64(STANDARD);
+        (context, pin)
+    }
+
+    #[test]
+    fn test_pins_with_sni() {
+        use std::mem;
+        use std::net::TcpListener;
+        use std::thread;
+        use openssl::ssl::{Ssl, SslContext, SslStream};
+        use net::NetworkConnector;
+        use Error;
+        use super::{TlsConnector, TlsPolicy};
+
+        // the server only presents the certificate for localhost to a
+        // client that asks for it by name
+        static mut NAMED: *const SslContext = 0 as *const SslContext;
+        fn select(ssl: &mut Ssl, _: &mut i32) -> i32 {
+            if ssl.get_servername().as_ref().map(|name| &name[..]) == Some("localhost") {
+                ssl.set_ssl_context(unsafe { &*NAMED });
+            }
+            0 // SSL_TLSEXT_ERR_OK
+        }
+
+        let (named, named_pin) = tls_context("localhost");
+        let (mut fallback, fallback_pin) = tls_context("fallback");
+        unsafe { NAMED = mem::transmute(Box::new(named)); }
+        fallback.set_servername_callback(Some(select));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let _ = SslStream::new_server(&fallback, stream);
+            }
+        });
+
+        let mut connector = TlsConnector::new(TlsPolicy::new());
+        connector.add_pins("localhost", vec![named_pin]);
+        assert!(connector.connect("localhost", port, "https").is_ok());
+        connector.add_pins("localhost", vec![fallback_pin]);
+        match connector.connect("localhost", port, "https") {
+            Err(Error::PinMismatch) => (),
+            other => panic!("expected PinMismatch, got {:?}", other.map(|_| ()))
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_certificate_callback() {
+        use Error;
+        use super::{TlsConnector, TlsPolicy};
+
+        let mut connector = TlsConnector::new(TlsPolicy::new());
+        connector.set_certificate_callback(|check| {
+            assert_eq!(check.chain(), &[vec![0x30, 0x00]][..]);
+            check.chain_verified() || check.host() == "grace.test"
+        });
+        let chain = [vec![0x30, 0x00]];
+        assert!(connector.check_certificate("strict.test", &chain, true).is_ok());
+        assert!(connector.check_certificate("grace.test", &chain, false).is_ok());
+        match connector.check_certificate("strict.test", &chain, false) {
+            Err(Error::CertificateRejected) => (),
+            other => panic!("expected CertificateRejected, got {:?}", other)
+        }
+
+        // an accepted chain must still match the pins
+        connector.add_pins("grace.test", vec!["bogus".to_string()]);
+        match connector.check_certificate("grace.test", &chain, true) {
+            Err(Error::PinMismatch) => (),
+            other => panic!("expected PinMismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_tls_version_order() {
+        use super::TlsVersion;
+        assert!(TlsVersion::Tls10 < TlsVersion::Tls12);
+        assert_eq!(TlsVersion::from_openssl("TLSv1.1"), Some(TlsVersion::Tls11));
+        assert_eq!(TlsVersion::from_openssl("SSLv3"), None);
+    }
+
+    #[test]
+    fn test_mock_tls_stream() {
+        let stream: Box<NetworkStream + Send> = Box::new(MockStream::new());
+        assert_eq!(stream.peer_certificate(), None);
+        assert_eq!(stream.alpn_protocol(), None);
+
+        let stream: Box<NetworkStream + Send> = Box::new(
+            MockTlsStream::new(MockStream::new())
+                .with_certificate(vec![0x30, 0x82])
+                .with_alpn(b"h2"));
+        assert_eq!(stream.peer_certificate(), Some(vec![0x30, 0x82]));
+        assert_eq!(stream.alpn_protocol(), Some(b"h2".to_vec()));
     }
 }
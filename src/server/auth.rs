@@ -0,0 +1,186 @@
+//! Authenticating requests before they reach the handler.
+//!
+//! A server given an `Authenticator` asks it about the head of each request.
+//! A request it denies is answered with the status and headers it chose,
+//! such as a `401 Unauthorized` with a `WWW-Authenticate` challenge, and
+//! never reaches the handler.
+//!
+//! ```no_run
+//! # use hyper::server::{Server, Request, Response};
+//! use hyper::server::auth::BasicAuth;
+//!
+//! # fn handler(_: Request, _: Response) {}
+//! let mut server = Server::http(handler);
+//! server.set_authenticator(BasicAuth::new("admin", |user: &str, password: &str| {
+//!     user == "Aladdin" && password == "open sesame"
+//! }));
+//! server.listen("0.0.0.0:8080").unwrap();
+//! ```
+use header::{Headers, Authorization, Basic, Bearer};
+use method::Method;
+use status::StatusCode;
+use uri::RequestUri;
+
+/// Whether a request may reach the handler.
+#[derive(Debug)]
+pub enum Verdict {
+    /// Let the request through.
+    Allow,
+    /// Answer the request with this status and these headers instead.
+    Deny(StatusCode, Headers),
+}
+
+impl Verdict {
+    /// Deny with `401 Unauthorized`, and `challenge` as the
+    /// `WWW-Authenticate` header, such as `Basic realm="admin"`.
+    pub fn challenge<S: Into<String>>(challenge: S) -> Verdict {
+        let mut headers = Headers::new();
+        headers.set_raw("WWW-Authenticate", vec![challenge.into().into_bytes()]);
+        Verdict::Deny(StatusCode::Unauthorized, headers)
+    }
+
+    /// Deny with `403 Forbidden`, for a client that authenticated but isn't
+    /// allowed to make the request.
+    pub fn forbidden() -> Verdict {
+        Verdict::Deny(StatusCode::Forbidden, Headers::new())
+    }
+}
+
+/// Decides which requests may reach the handler, from their heads.
+pub trait Authenticator: Send + Sync {
+    /// Judge a request by its method, target and headers.
+    fn authenticate(&self, head: (&Method, &RequestUri, &Headers)) -> Verdict;
+}
+
+impl<F> Authenticator for F
+where F: Fn((&Method, &RequestUri, &Headers)) -> Verdict + Send + Sync {
+    fn authenticate(&self, head: (&Method, &RequestUri, &Headers)) -> Verdict {
+        self(head)
+    }
+}
+
+/// An `Authenticator` for HTTP Basic authentication.
+///
+/// The verifier is given the user name and password of each request, and
+/// returns whether they are valid. Requests without valid credentials are
+/// challenged for them, in the realm given.
+pub struct BasicAuth<F> {
+    realm: String,
+    verify: F,
+}
+
+impl<F: Fn(&str, &str) -> bool + Send + Sync> BasicAuth<F> {
+    /// Check credentials in `realm` with `verify`.
+    pub fn new<S: Into<String>>(realm: S, verify: F) -> BasicAuth<F> {
+        BasicAuth {
+            realm: realm.into(),
+            verify: verify,
+        }
+    }
+}
+
+impl<F: Fn(&str, &str) -> bool + Send + Sync> Authenticator for BasicAuth<F> {
+    fn authenticate(&self, (_, _, headers): (&Method, &RequestUri, &Headers)) -> Verdict {
+        if let Some(&Authorization(ref basic)) = headers.get::<Authorization<Basic>>() {
+            let password = basic.password.as_ref().map(|p| &p[..]).unwrap_or("");
+            if (self.verify)(&basic.username, password) {
+                return Verdict::Allow;
+            }
+            debug!("basic credentials for {:?} were refused", basic.username);
+        }
+        Verdict::challenge(format!("Basic realm=\"{}\"", self.realm))
+    }
+}
+
+/// An `Authenticator` for bearer tokens, as used by OAuth 2.0.
+///
+/// The verifier is given the token of each request, and returns whether it
+/// is valid. A request without a token is challenged for one, and one with
+/// an invalid token is told so, as described in
+/// [RFC6750](https://tools.ietf.org/html/rfc6750#section-3).
+pub struct BearerAuth<F> {
+    realm: String,
+    verify: F,
+}
+
+impl<F: Fn(&str) -> bool + Send + Sync> BearerAuth<F> {
+    /// Check tokens in `realm` with `verify`.
+    pub fn new<S: Into<String>>(realm: S, verify: F) -> BearerAuth<F> {
+        BearerAuth {
+            realm: realm.into(),
+            verify: verify,
+        }
+    }
+}
+
+impl<F: Fn(&str) -> bool + Send + Sync> Authenticator for BearerAuth<F> {
+    fn authenticate(&self, (_, _, headers): (&Method, &RequestUri, &Headers)) -> Verdict {
+        match headers.get::<Authorization<Bearer>>() {
+            Some(&Authorization(ref bearer)) if (self.verify)(&bearer.token) => Verdict::Allow,
+            Some(_) => {
+                debug!("bearer token was refused");
+                Verdict::challenge(format!("Bearer realm=\"{}\", error=\"invalid_token\"",
+                                           self.realm))
+            },
+            None => Verdict::challenge(format!("Bearer realm=\"{}\"", self.realm))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::{Headers, Authorization, Basic, Bearer};
+    use method::Method::Get;
+    use status::StatusCode;
+    use uri::RequestUri;
+    use super::{Authenticator, BasicAuth, BearerAuth, Verdict};
+
+    fn challenge(verdict: Verdict) -> (StatusCode, String) {
+        match verdict {
+            Verdict::Deny(status, headers) => {
+                let raw = headers.get_raw("WWW-Authenticate").unwrap();
+                (status, String::from_utf8(raw[0].clone()).unwrap())
+            },
+            Verdict::Allow => panic!("expected Deny")
+        }
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let auth = BasicAuth::new("admin", |user: &str, password: &str| {
+            user == "Aladdin" && password == "open sesame"
+        });
+        let uri = RequestUri::AbsolutePath("/".to_string());
+
+        let mut headers = Headers::new();
+        assert_eq!(challenge(auth.authenticate((&Get, &uri, &headers))),
+                   (StatusCode::Unauthorized, "Basic realm=\"admin\"".to_string()));
+
+        headers.set(Authorization(Basic {
+            username: "Aladdin".to_string(),
+            password: Some("open sesame".to_string())
+        }));
+        match auth.authenticate((&Get, &uri, &headers)) {
+            Verdict::Allow => (),
+            other => panic!("expected Allow, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_bearer_auth() {
+        let auth = BearerAuth::new("api", |token: &str| token == "secret");
+        let uri = RequestUri::AbsolutePath("/".to_string());
+
+        let mut headers = Headers::new();
+        assert_eq!(challenge(auth.authenticate((&Get, &uri, &headers))).1,
+                   "Bearer realm=\"api\"");
+        headers.set(Authorization(Bearer { token: "guess".to_string() }));
+        assert_eq!(challenge(auth.authenticate((&Get, &uri, &headers))).1,
+                   "Bearer realm=\"api\", error=\"invalid_token\"");
+        headers.set(Authorization(Bearer { token: "secret".to_string() }));
+        match auth.authenticate((&Get, &uri, &headers)) {
+            Verdict::Allow => (),
+            other => panic!("expected Allow, got {:?}", other)
+        }
+    }
+}
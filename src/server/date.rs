@@ -0,0 +1,107 @@
+//! The `Date` header of server responses.
+//!
+//! Every response gets one, but it only changes once a second, so each
+//! server caches the formatted value for the current second of its clock.
+use std::fmt;
+use std::sync::RwLock;
+
+use time;
+
+use clock::Clock;
+use header::HttpDate;
+
+/// The raw `Date` header value of a server, for the current second.
+pub struct DateCache(RwLock<Cached>);
+
+struct Cached {
+    sec: i64,
+    value: Vec<u8>,
+}
+
+impl DateCache {
+    /// An empty cache, filled on first use.
+    pub fn new() -> DateCache {
+        DateCache(RwLock::new(Cached { sec: i64::min_value(), value: vec![] }))
+    }
+
+    /// The raw `Date` header value for the current second of `clock`.
+    pub fn now(&self, clock: &Clock) -> Vec<u8> {
+        let now = clock.now();
+        {
+            let cached = self.0.read().unwrap();
+            if cached.sec == now.sec {
+                return cached.value.clone();
+            }
+        }
+        let value = format(clock);
+        let mut cached = self.0.write().unwrap();
+        // another thread may have moved on to a later second already
+        if now.sec > cached.sec {
+            cached.sec = now.sec;
+            cached.value = value.clone();
+        }
+        value
+    }
+}
+
+/// Where a `Response` gets its `Date` header from: the cache and clock of
+/// the server that made it.
+#[derive(Clone, Copy)]
+pub struct DateSource<'a> {
+    cache: &'a DateCache,
+    clock: &'a Clock,
+}
+
+impl<'a> DateSource<'a> {
+    /// The `Date` of `clock`, cached in `cache`.
+    pub fn new(cache: &'a DateCache, clock: &'a Clock) -> DateSource<'a> {
+        DateSource { cache: cache, clock: clock }
+    }
+
+    /// The raw `Date` header value for the current second.
+    pub fn now(&self) -> Vec<u8> {
+        self.cache.now(self.clock)
+    }
+}
+
+impl<'a> fmt::Debug for DateSource<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DateSource")
+    }
+}
+
+/// The raw `Date` header value for the current second of `clock`, without
+/// any caching.
+pub fn format(clock: &Clock) -> Vec<u8> {
+    HttpDate(time::at_utc(clock.now())).to_string().into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, Timespec};
+
+    use clock::TestClock;
+    use super::DateCache;
+
+    #[test]
+    fn test_date_cache() {
+        let cache = DateCache::new();
+        let clock = TestClock::new(Timespec::new(784111777, 0));
+        assert_eq!(cache.now(&clock), b"Sun, 06 Nov 1994 08:49:37 GMT".to_vec());
+        clock.advance(Duration::milliseconds(500));
+        assert_eq!(cache.now(&clock), b"Sun, 06 Nov 1994 08:49:37 GMT".to_vec());
+        clock.advance(Duration::milliseconds(500));
+        assert_eq!(cache.now(&clock), b"Sun, 06 Nov 1994 08:49:38 GMT".to_vec());
+    }
+
+    #[test]
+    fn test_caches_are_separate() {
+        let later = DateCache::new();
+        let earlier = DateCache::new();
+        let later_clock = TestClock::new(Timespec::new(784111777, 0));
+        let earlier_clock = TestClock::new(Timespec::new(784111000, 0));
+        assert_eq!(later.now(&later_clock), b"Sun, 06 Nov 1994 08:49:37 GMT".to_vec());
+        assert_eq!(earlier.now(&earlier_clock), b"Sun, 06 Nov 1994 08:36:40 GMT".to_vec());
+        assert_eq!(later.now(&later_clock), b"Sun, 06 Nov 1994 08:49:37 GMT".to_vec());
+    }
+}
@@ -0,0 +1,307 @@
+//! Limiting how much each client can ask of a server.
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::cmp::{max, min};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use time::Timespec;
+
+// Idle buckets are only swept out once there are this many, and then once
+// there are twice as many as were left.
+const PRUNE_AT: usize = 1024;
+
+// The most addresses a Limiter keeps buckets for. Past that, buckets
+// without connections are dropped even if they haven't filled back up, and
+// a new address is refused while every bucket still has connections.
+const MAX_PEERS: usize = 64 * 1024;
+
+/// The limits each client IP address is held to.
+///
+/// IPv6 clients are limited by their /64 prefix rather than their whole
+/// address, as a single host is usually given a /64 to pick addresses
+/// from.
+///
+/// A `RateLimit` caps how many connections each address may hold open, and
+/// how fast it may send requests, with a token bucket. A request over either
+/// limit is answered with `429 Too Many Requests`, with a `Retry-After`
+/// header, and its connection is closed.
+///
+/// ```no_run
+/// # use hyper::server::{Server, Request, Response};
+/// use hyper::server::RateLimit;
+///
+/// # fn handler(_: Request, _: Response) {}
+/// let mut limit = RateLimit::new();
+/// limit.set_max_connections(8);
+/// limit.set_rate(10, 20);
+///
+/// let mut server = Server::http(handler);
+/// server.set_rate_limit(limit);
+/// server.listen("0.0.0.0:8080").unwrap();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    max_connections: Option<usize>,
+    rate: Option<(u32, u32)>,
+}
+
+impl RateLimit {
+    /// Create a `RateLimit` that doesn't limit anything yet.
+    pub fn new() -> RateLimit {
+        RateLimit {
+            max_connections: None,
+            rate: None,
+        }
+    }
+
+    /// Allow each address at most `max` open connections.
+    pub fn set_max_connections(&mut self, max: usize) {
+        self.max_connections = Some(max);
+    }
+
+    /// Allow each address `per_second` requests a second on average, with
+    /// bursts of up to `burst` requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per_second` or `burst` is 0.
+    pub fn set_rate(&mut self, per_second: u32, burst: u32) {
+        assert!(per_second > 0 && burst > 0, "rate and burst must be greater than 0");
+        self.rate = Some((per_second, burst));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Peer {
+    V4(Ipv4Addr),
+    // the /64 prefix
+    V6([u16; 4]),
+}
+
+impl Peer {
+    fn new(addr: &SocketAddr) -> Peer {
+        match *addr {
+            SocketAddr::V4(ref addr) => Peer::V4(*addr.ip()),
+            SocketAddr::V6(ref addr) => {
+                let s = addr.ip().segments();
+                if s[..5] == [0, 0, 0, 0, 0] && s[5] == 0xffff {
+                    // an IPv4 client of a dual-stack socket
+                    Peer::V4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8,
+                                           (s[7] >> 8) as u8, s[7] as u8))
+                } else {
+                    Peer::V6([s[0], s[1], s[2], s[3]])
+                }
+            }
+        }
+    }
+}
+
+struct Bucket {
+    connections: usize,
+    tokens: f64,
+    updated: Timespec,
+}
+
+/// The state a server keeps to enforce a `RateLimit`.
+pub struct Limiter {
+    limit: RateLimit,
+    peers: Mutex<HashMap<Peer, Bucket>>,
+    prune_at: AtomicUsize,
+}
+
+impl Limiter {
+    /// Create a `Limiter` with no connections counted yet.
+    pub fn new(limit: RateLimit) -> Limiter {
+        Limiter {
+            limit: limit,
+            peers: Mutex::new(HashMap::new()),
+            prune_at: AtomicUsize::new(PRUNE_AT),
+        }
+    }
+
+    /// Count a connection from `addr`, until the returned guard is dropped.
+    pub fn connect(&self, addr: &SocketAddr, now: Timespec) -> Connection {
+        let peer = Peer::new(addr);
+        let mut peers = self.peers.lock().unwrap();
+        if peers.len() >= self.prune_at.load(Ordering::Relaxed) {
+            self.prune(&mut peers, now);
+            let next = max(PRUNE_AT, min(peers.len() * 2, MAX_PEERS));
+            self.prune_at.store(next, Ordering::Relaxed);
+        }
+        if peers.len() >= MAX_PEERS && !peers.contains_key(&peer) {
+            let unused = peers.iter().filter(|&(_, bucket)| bucket.connections == 0)
+                .map(|(peer, _)| *peer).collect::<Vec<_>>();
+            for peer in unused {
+                peers.remove(&peer);
+            }
+            if peers.len() >= MAX_PEERS {
+                debug!("too many clients to limit, refusing {:?}", peer);
+                return Connection {
+                    limiter: self,
+                    peer: peer,
+                    counted: false,
+                    over: true,
+                };
+            }
+        }
+        let burst = self.burst();
+        let bucket = peers.entry(peer).or_insert(Bucket {
+            connections: 0,
+            tokens: burst,
+            updated: now,
+        });
+        bucket.connections += 1;
+        Connection {
+            limiter: self,
+            peer: peer,
+            counted: true,
+            over: self.limit.max_connections.map_or(false, |max| bucket.connections > max),
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        self.limit.rate.map_or(0.0, |(_, burst)| burst as f64)
+    }
+
+    // Forget the addresses without connections, whose buckets have filled
+    // back up, as they would start out the same if they came back.
+    fn prune(&self, peers: &mut HashMap<Peer, Bucket>, now: Timespec) {
+        let rate = self.limit.rate.map_or(0.0, |(per_second, _)| per_second as f64);
+        let burst = self.burst();
+        let idle = peers.iter().filter(|&(_, bucket)| {
+            let elapsed = (now - bucket.updated).num_milliseconds() as f64 / 1000.0;
+            bucket.connections == 0 && bucket.tokens + elapsed * rate >= burst
+        }).map(|(peer, _)| *peer).collect::<Vec<_>>();
+        trace!("pruning {} idle rate limit buckets", idle.len());
+        for peer in idle {
+            peers.remove(&peer);
+        }
+    }
+}
+
+/// A connection counted by a `Limiter`.
+pub struct Connection<'a> {
+    limiter: &'a Limiter,
+    peer: Peer,
+    counted: bool,
+    over: bool,
+}
+
+impl<'a> Connection<'a> {
+    /// Take a request from the client's bucket.
+    ///
+    /// Returns the number of seconds the client should wait before trying
+    /// again, if the connection is over the limit or the bucket is empty.
+    pub fn request(&self, now: Timespec) -> Result<(), u64> {
+        if self.over {
+            return Err(1);
+        }
+        let (per_second, burst) = match self.limiter.limit.rate {
+            Some(rate) => rate,
+            None => return Ok(())
+        };
+        let mut peers = self.limiter.peers.lock().unwrap();
+        let bucket = peers.get_mut(&self.peer).unwrap();
+        let elapsed = (now - bucket.updated).num_milliseconds() as f64 / 1000.0;
+        if elapsed > 0.0 {
+            bucket.tokens = (bucket.tokens + elapsed * per_second as f64).min(burst as f64);
+            bucket.updated = now;
+        }
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second as f64).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+impl<'a> Drop for Connection<'a> {
+    fn drop(&mut self) {
+        if !self.counted {
+            return;
+        }
+        let mut peers = self.limiter.peers.lock().unwrap();
+        if let Some(bucket) = peers.get_mut(&self.peer) {
+            bucket.connections -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, Timespec};
+    use super::{RateLimit, Limiter};
+
+    #[test]
+    fn test_max_connections() {
+        let mut limit = RateLimit::new();
+        limit.set_max_connections(1);
+        let limiter = Limiter::new(limit);
+        let now = Timespec::new(0, 0);
+        let addr = "127.0.0.1:1000".parse().unwrap();
+
+        let first = limiter.connect(&addr, now);
+        assert_eq!(first.request(now), Ok(()));
+        {
+            let second = limiter.connect(&"127.0.0.1:1001".parse().unwrap(), now);
+            assert_eq!(second.request(now), Err(1));
+            let other = limiter.connect(&"127.0.0.2:1000".parse().unwrap(), now);
+            assert_eq!(other.request(now), Ok(()));
+        }
+        drop(first);
+        assert_eq!(limiter.connect(&addr, now).request(now), Ok(()));
+    }
+
+    #[test]
+    fn test_ipv6_prefix() {
+        let mut limit = RateLimit::new();
+        limit.set_max_connections(1);
+        let limiter = Limiter::new(limit);
+        let now = Timespec::new(0, 0);
+
+        let _first = limiter.connect(&"[2001:db8:0:1::1]:1000".parse().unwrap(), now);
+        let same = limiter.connect(&"[2001:db8:0:1:ffff::2]:1000".parse().unwrap(), now);
+        assert_eq!(same.request(now), Err(1));
+        let other = limiter.connect(&"[2001:db8:0:2::1]:1000".parse().unwrap(), now);
+        assert_eq!(other.request(now), Ok(()));
+    }
+
+    #[test]
+    fn test_max_peers() {
+        use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+        use super::MAX_PEERS;
+
+        let limiter = Limiter::new(RateLimit::new());
+        let now = Timespec::new(0, 0);
+        let addr = |i: usize| SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::new(10, (i >> 16) as u8, (i >> 8) as u8, i as u8), 1000));
+
+        let open = (0..MAX_PEERS).map(|i| limiter.connect(&addr(i), now)).collect::<Vec<_>>();
+        let refused = limiter.connect(&addr(MAX_PEERS), now);
+        assert_eq!(refused.request(now), Err(1));
+        drop(refused);
+        assert_eq!(limiter.connect(&addr(0), now).request(now), Ok(()));
+
+        drop(open);
+        assert_eq!(limiter.connect(&addr(MAX_PEERS), now).request(now), Ok(()));
+        assert_eq!(limiter.peers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut limit = RateLimit::new();
+        limit.set_rate(1, 2);
+        let limiter = Limiter::new(limit);
+        let now = Timespec::new(0, 0);
+        let conn = limiter.connect(&"127.0.0.1:1000".parse().unwrap(), now);
+
+        assert_eq!(conn.request(now), Ok(()));
+        assert_eq!(conn.request(now), Ok(()));
+        assert_eq!(conn.request(now), Err(1));
+        let later = now + Duration::milliseconds(1500);
+        assert_eq!(conn.request(later), Ok(()));
+        assert_eq!(conn.request(later), Err(1));
+    }
+}
@@ -16,41 +16,56 @@
 //!         _ => StatusCode::MethodNotAllowed
 //!     };
 //! }).listen("0.0.0.0:8080").unwrap();
+use std::cell::Cell;
 use std::fmt;
 use std::io::{ErrorKind, BufWriter, Write};
 use std::marker::PhantomData;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::Path;
+use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
 use num_cpus;
 use openssl::ssl::SslContext;
 
+pub use self::limit::RateLimit;
 pub use self::request::Request;
 pub use self::response::Response;
+pub use self::tunnel::{Tunnel, TunnelStats};
 
 pub use net::{Fresh, Streaming};
 
 use Error;
 use buffer::BufReader;
-use header::{Headers, Expect};
+use clock::{Clock, SystemClock};
+use header::{Headers, Expect, Connection, ContentLength};
+use header::ConnectionOption::{Close, KeepAlive};
 use http;
 use method::Method;
-use net::{NetworkListener, NetworkStream, HttpListener};
+use net::{NetworkListener, NetworkStream, HttpListener, TlsPolicy};
 use status::StatusCode;
 use uri::RequestUri;
-use version::HttpVersion::Http11;
+use version::HttpVersion::{Http10, Http11};
 
+use self::auth::{Authenticator, Verdict};
+use self::date::DateCache;
+use self::limit::Limiter;
 use self::listener::ListenerPool;
 
+pub mod auth;
 pub mod request;
 pub mod response;
+pub mod testing;
 
+mod date;
+mod limit;
 mod listener;
+mod tunnel;
 
 #[derive(Debug)]
 enum SslConfig<'a> {
     CertAndKey(&'a Path, &'a Path),
+    Policy(&'a Path, &'a Path, TlsPolicy),
     Context(SslContext),
 }
 
@@ -58,13 +73,50 @@ enum SslConfig<'a> {
 ///
 /// Once listening, it will create a `Request`/`Response` pair for each
 /// incoming connection, and hand them to the provided handler.
-#[derive(Debug)]
 pub struct Server<'a, H: Handler, L = HttpListener> {
     handler: H,
     ssl: Option<SslConfig<'a>>,
+    settings: Settings,
     _marker: PhantomData<L>
 }
 
+// What a server applies to every connection it handles.
+#[derive(Clone)]
+struct Settings {
+    clock: Arc<Clock>,
+    // The Date header of this server, cached for the current second.
+    date: Arc<DateCache>,
+    strict_headers: bool,
+    unfold_headers: bool,
+    max_body: Option<u64>,
+    max_decoded: u64,
+    max_ratio: u64,
+    limiter: Option<Arc<Limiter>>,
+    authenticator: Option<Arc<Authenticator>>,
+}
+
+impl Settings {
+    fn new() -> Settings {
+        Settings {
+            clock: Arc::new(SystemClock),
+            date: Arc::new(DateCache::new()),
+            strict_headers: false,
+            unfold_headers: false,
+            max_body: None,
+            max_decoded: http::DEFAULT_MAX_DECODED,
+            max_ratio: http::DEFAULT_MAX_RATIO,
+            limiter: None,
+            authenticator: None,
+        }
+    }
+}
+
+impl<'a, H: Handler + fmt::Debug, L> fmt::Debug for Server<'a, H, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Server {{ handler: {:?}, ssl: {:?} }}", self.handler, self.ssl)
+    }
+}
+
 macro_rules! try_option(
     ($e:expr) => {{
         match $e {
@@ -80,9 +132,81 @@ impl<'a, H: Handler, L: NetworkListener> Server<'a, H, L> {
         Server {
             handler: handler,
             ssl: None,
+            settings: Settings::new(),
             _marker: PhantomData
         }
     }
+
+    /// Set the `Clock` used for time-dependent behavior, such as the `Date` header.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.settings.clock = Arc::new(clock);
+        self.settings.date = Arc::new(DateCache::new());
+    }
+
+    /// Whether a response with an illegal header fails to start, instead of
+    /// having the header fixed up.
+    ///
+    /// A header value holding a CR or LF, such as one copied from a request,
+    /// would otherwise let a client split the response in two. By default
+    /// such bytes are replaced with spaces, and a header with an illegal
+    /// name is left out. In strict mode, `Response::start` and
+    /// `Response::send` return an error instead, so the handler finds out.
+    pub fn set_strict_headers(&mut self, strict: bool) {
+        self.settings.strict_headers = strict;
+    }
+
+    /// Whether header values a request folds over several lines
+    /// (obs-fold) are unfolded into one, instead of the request being
+    /// refused and the connection closed.
+    ///
+    /// RFC 7230 allows either. The default is to refuse them.
+    pub fn set_unfold_headers(&mut self, unfold: bool) {
+        self.settings.unfold_headers = unfold;
+    }
+
+    /// The largest request body to accept, in bytes.
+    ///
+    /// A request whose `Content-Length` is larger is answered with
+    /// `413 Payload Too Large` before the handler sees it, and the
+    /// connection is closed. A chunked body that grows past the limit fails
+    /// to read in the handler, and the connection is closed once the handler
+    /// returns. If the handler didn't start its response, the response is
+    /// sent as `413 Payload Too Large`. There is no limit by default.
+    pub fn set_max_body_size(&mut self, max: Option<u64>) {
+        self.settings.max_body = max;
+    }
+
+    /// The most bytes a request body sent with a `gzip` or `deflate`
+    /// transfer coding may decode to, before reading it fails in the
+    /// handler.
+    ///
+    /// The default is `http::DEFAULT_MAX_DECODED`.
+    pub fn set_max_decoded_size(&mut self, max: u64) {
+        self.settings.max_decoded = max;
+    }
+
+    /// The most bytes a request body sent with a `gzip` or `deflate`
+    /// transfer coding may decode to for each byte received, once it has
+    /// decoded a megabyte, before reading it fails in the handler.
+    ///
+    /// The default is `http::DEFAULT_MAX_RATIO`.
+    pub fn set_max_decoded_ratio(&mut self, ratio: u64) {
+        self.settings.max_ratio = ratio;
+    }
+
+    /// Hold each client IP address to `limit`, answering requests over it
+    /// with `429 Too Many Requests`.
+    pub fn set_rate_limit(&mut self, limit: RateLimit) {
+        self.settings.limiter = Some(Arc::new(Limiter::new(limit)));
+    }
+
+    /// Ask `authenticator` about each request before the handler sees it.
+    ///
+    /// A request it denies is answered with the status and headers of its
+    /// verdict, and its connection is closed.
+    pub fn set_authenticator<A: Authenticator + 'static>(&mut self, authenticator: A) {
+        self.settings.authenticator = Some(Arc::new(authenticator));
+    }
 }
 
 impl<'a, H: Handler + 'static> Server<'a, H, HttpListener> {
@@ -95,6 +219,18 @@ impl<'a, H: Handler + 'static> Server<'a, H, HttpListener> {
         Server {
             handler: handler,
             ssl: Some(SslConfig::CertAndKey(cert, key)),
+            settings: Settings::new(),
+            _marker: PhantomData
+        }
+    }
+    /// Creates a new server that will handle `HttpStream`s using a TLS
+    /// connection, refusing clients that don't meet `policy`.
+    pub fn https_with_policy(handler: H, cert: &'a Path, key: &'a Path, policy: TlsPolicy)
+        -> Server<'a, H, HttpListener> {
+        Server {
+            handler: handler,
+            ssl: Some(SslConfig::Policy(cert, key, policy)),
+            settings: Settings::new(),
             _marker: PhantomData
         }
     }
@@ -103,6 +239,7 @@ impl<'a, H: Handler + 'static> Server<'a, H, HttpListener> {
         Server {
             handler: handler,
             ssl: Some(SslConfig::Context(ssl_context)),
+            settings: Settings::new(),
             _marker: PhantomData
         }
     }
@@ -113,10 +250,13 @@ impl<'a, H: Handler + 'static> Server<'a, H, HttpListener> {
     pub fn listen_threads<T: ToSocketAddrs>(self, addr: T, threads: usize) -> ::Result<Listening> {
         let listener = try!(match self.ssl {
             Some(SslConfig::CertAndKey(cert, key)) => HttpListener::https(addr, cert, key),
+            Some(SslConfig::Policy(cert, key, policy)) => {
+                HttpListener::https_with_policy(addr, cert, key, &policy)
+            },
             Some(SslConfig::Context(ssl_context)) => HttpListener::https_with_context(addr, ssl_context),
             None => HttpListener::http(addr)
         });
-        with_listener(self.handler, listener, threads)
+        with_listener(self.handler, self.settings, listener, threads)
     }
 
     /// Binds to a socket and starts handling connections.
@@ -131,18 +271,18 @@ L: NetworkListener<Stream=S> + Send + 'static,
 S: NetworkStream + Clone + Send> Server<'a, H, L> {
     /// Creates a new server that will handle `HttpStream`s.
     pub fn with_listener(self, listener: L, threads: usize) -> ::Result<Listening> {
-        with_listener(self.handler, listener, threads)
+        with_listener(self.handler, self.settings, listener, threads)
     }
 }
 
-fn with_listener<H, L>(handler: H, mut listener: L, threads: usize) -> ::Result<Listening>
+fn with_listener<H, L>(handler: H, settings: Settings, mut listener: L, threads: usize) -> ::Result<Listening>
 where H: Handler + 'static,
 L: NetworkListener + Send + 'static {
     let socket = try!(listener.local_addr());
 
     debug!("threads = {:?}", threads);
     let pool = ListenerPool::new(listener.clone());
-    let work = move |mut stream| handle_connection(&mut stream, &handler);
+    let work = move |mut stream| handle_connection(&mut stream, &handler, &settings);
 
     let guard = thread::spawn(move || pool.accept(work, threads));
 
@@ -152,7 +292,7 @@ L: NetworkListener + Send + 'static {
     })
 }
 
-fn handle_connection<'h, S, H>(mut stream: &mut S, handler: &'h H)
+fn handle_connection<'h, S, H>(mut stream: &mut S, handler: &'h H, settings: &Settings)
 where S: NetworkStream + Clone, H: Handler {
     debug!("Incoming stream");
     let addr = match stream.peer_addr() {
@@ -168,9 +308,17 @@ where S: NetworkStream + Clone, H: Handler {
     let mut rdr = BufReader::new(stream_clone);
     let mut wrt = BufWriter::new(stream);
 
+    let limited = settings.limiter.as_ref().map(|limiter| {
+        limiter.connect(&addr, settings.clock.now())
+    });
+
+    let mut tunnel = None;
     let mut keep_alive = true;
     while keep_alive {
-        let req = match Request::new(&mut rdr, addr) {
+        let exceeded = Cell::new(false);
+        let closing = Cell::new(false);
+        let ended = Cell::new(false);
+        let mut req = match Request::with_unfolding(&mut rdr, addr, settings.unfold_headers) {
             Ok(req) => req,
             Err(Error::Io(ref e)) if e.kind() == ErrorKind::ConnectionAborted => {
                 trace!("tcp closed, cancelling keep-alive loop");
@@ -187,6 +335,83 @@ where S: NetworkStream + Clone, H: Handler {
             }
         };
 
+        if let Some(ref limited) = limited {
+            if let Err(retry_after) = limited.request(settings.clock.now()) {
+                debug!("{} is over the rate limit, closing", addr);
+                let mut headers = Headers::new();
+                headers.set_raw("Retry-After", vec![retry_after.to_string().into_bytes()]);
+                refuse(&mut wrt, &req, StatusCode::TooManyRequests, settings, headers);
+                break;
+            }
+        }
+
+        if let Some(max) = settings.max_body {
+            if req.headers.get::<ContentLength>().map_or(false, |len| **len > max) {
+                debug!("request body is larger than {} bytes, closing", max);
+                refuse(&mut wrt, &req, StatusCode::PayloadTooLarge, settings, Headers::new());
+                break;
+            }
+            req.watch_body_limit(max, &exceeded);
+        }
+
+        req.watch_body_end(&ended);
+        req.set_max_decoded_size(settings.max_decoded);
+        req.set_max_decoded_ratio(settings.max_ratio);
+        req.watch_decode_limit(&exceeded);
+
+        if let Some(ref authenticator) = settings.authenticator {
+            let verdict = authenticator.authenticate((&req.method, &req.uri, &req.headers));
+            if let Verdict::Deny(status, headers) = verdict {
+                debug!("authenticator denied request with {}", status);
+                refuse(&mut wrt, &req, status, settings, headers);
+                break;
+            }
+        }
+
+        if req.method == Method::Connect {
+            let target = match req.uri {
+                RequestUri::Authority(ref authority) => authority.clone(),
+                ref uri => {
+                    debug!("CONNECT target {:?} is not host:port", uri);
+                    refuse(&mut wrt, &req, StatusCode::BadRequest, settings, Headers::new());
+                    break;
+                }
+            };
+            let upstream = match handler.check_connect((&req.uri, &req.headers)) {
+                Tunnel::Handle => None,
+                Tunnel::Refuse(status) => {
+                    debug!("handler refused to tunnel with {}", status);
+                    refuse(&mut wrt, &req, status, settings, Headers::new());
+                    break;
+                },
+                Tunnel::Open => match tunnel::connect(&req.uri) {
+                    Ok(upstream) => Some(upstream),
+                    Err(e) => {
+                        debug!("error connecting to {:?}: {:?}", req.uri, e);
+                        refuse(&mut wrt, &req, StatusCode::BadGateway, settings, Headers::new());
+                        break;
+                    }
+                },
+                Tunnel::Through(upstream) => Some(upstream)
+            };
+            if let Some(upstream) = upstream {
+                // both directions of the tunnel need the upstream, so it is
+                // cloned before the client is told the tunnel is open
+                let from_upstream = match upstream.try_clone() {
+                    Ok(from_upstream) => from_upstream,
+                    Err(e) => {
+                        debug!("error cloning the tunnel to {}: {:?}", target, e);
+                        refuse(&mut wrt, &req, StatusCode::BadGateway, settings, Headers::new());
+                        break;
+                    }
+                };
+                // the request borrows the reader, so the tunnel starts
+                // once it is out of the way
+                tunnel = Some((target, req.version, upstream, from_upstream));
+                break;
+            }
+        }
+
         if req.version == Http11 && req.headers.get() == Some(&Expect::Continue) {
             let status = handler.check_continue((&req.method, &req.uri, &req.headers));
             match write!(&mut wrt, "{} {}\r\n\r\n", Http11, status) {
@@ -206,9 +431,62 @@ where S: NetworkStream + Clone, H: Handler {
         keep_alive = http::should_keep_alive(req.version, &req.headers);
         let mut res = Response::new(&mut wrt);
         res.version = req.version;
+        res.headers_mut().set_raw("Date", vec![settings.date.now(&*settings.clock)]);
+        res.set_date_source(&settings.date, &*settings.clock);
+        res.set_strict_headers(settings.strict_headers);
+        res.watch_close(&closing);
+        res.watch_body_limit(&exceeded);
+        if !keep_alive {
+            res.headers_mut().set(Connection(vec![Close]));
+        } else if req.version == Http10 {
+            res.headers_mut().set(Connection(vec![KeepAlive]));
+        }
         handler.handle(req, res);
+        if exceeded.get() {
+            // the rest of the body is still unread
+            debug!("request body was over the limit, closing");
+            break;
+        }
+        if closing.get() {
+            debug!("response closes the connection");
+            break;
+        }
+        if !ended.get() {
+            debug!("request body was not read to its end, closing");
+            break;
+        }
         debug!("keep_alive = {:?}", keep_alive);
     }
+
+    if let Some((target, version, upstream, from_upstream)) = tunnel {
+        // a 2xx to CONNECT has no body, nor any framing headers
+        let res = write!(&mut wrt, "{} {}\r\nDate: ", version, StatusCode::Ok)
+            .and_then(|_| wrt.write_all(&settings.date.now(&*settings.clock)))
+            .and_then(|_| wrt.write_all(b"\r\n\r\n"))
+            .and_then(|_| wrt.flush());
+        if let Err(e) = res {
+            debug!("error answering CONNECT: {:?}", e);
+            return;
+        }
+        let client = (*wrt.get_ref()).clone();
+        let stats = tunnel::splice(client, rdr.get_buf(), upstream, from_upstream, target,
+                                   &*settings.clock);
+        handler.tunnel_closed(&stats);
+    }
+}
+
+// Answer a request the handler won't see, and that ends the connection.
+fn refuse<W: Write>(wrt: &mut W, req: &Request, status: StatusCode, settings: &Settings,
+                    headers: Headers) {
+    let mut res = Response::new(wrt);
+    res.version = req.version;
+    *res.status_mut() = status;
+    res.headers_mut().extend(headers.iter());
+    res.headers_mut().set_raw("Date", vec![settings.date.now(&*settings.clock)]);
+    res.headers_mut().set(Connection(vec![Close]));
+    if let Err(e) = res.send(b"") {
+        debug!("error writing {}: {:?}", status, e);
+    }
 }
 
 /// A listening server, which can later be closed.
@@ -254,6 +532,18 @@ pub trait Handler: Sync + Send {
     fn check_continue(&self, _: (&Method, &RequestUri, &Headers)) -> StatusCode {
         StatusCode::Continue
     }
+
+    /// Called when a Request uses the `CONNECT` method, to decide whether
+    /// to open a tunnel to its target.
+    ///
+    /// By default, the request is given to `handle` like any other.
+    fn check_connect(&self, _: (&RequestUri, &Headers)) -> Tunnel {
+        Tunnel::Handle
+    }
+
+    /// Called when a tunnel opened for a `CONNECT` request has closed, with
+    /// how much went through it.
+    fn tunnel_closed(&self, _: &TunnelStats) {}
 }
 
 impl<F> Handler for F where F: Fn(Request, Response<Fresh>), F: Sync + Send {
@@ -264,13 +554,18 @@ impl<F> Handler for F where F: Fn(Request, Response<Fresh>), F: Sync + Send {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use time::Timespec;
+
+    use clock::TestClock;
     use header::Headers;
     use method::Method;
     use mock::MockStream;
     use status::StatusCode;
     use uri::RequestUri;
 
-    use super::{Request, Response, Fresh, Handler, handle_connection};
+    use super::{Request, Response, Fresh, Handler, Settings, handle_connection};
 
     #[test]
     fn test_check_continue_default() {
@@ -287,7 +582,7 @@ mod tests {
             res.start().unwrap().end().unwrap();
         }
 
-        handle_connection(&mut mock, &handle);
+        handle_connection(&mut mock, &handle, &Settings::new());
         let cont = b"HTTP/1.1 100 Continue\r\n\r\n";
         assert_eq!(&mock.write[..cont.len()], cont);
         let res = b"HTTP/1.1 200 OK\r\n";
@@ -316,7 +611,442 @@ mod tests {
             1234567890\
         ");
 
-        handle_connection(&mut mock, &Reject);
+        handle_connection(&mut mock, &Reject, &Settings::new());
         assert_eq!(mock.write, &b"HTTP/1.1 417 Expectation Failed\r\n\r\n"[..]);
     }
+
+    #[test]
+    fn test_tunnel_without_clone() {
+        use std::io::{self, Read, Write};
+        use std::net::SocketAddr;
+        use net::NetworkStream;
+        use super::Tunnel;
+
+        // An upstream that keeps the default try_clone, which fails.
+        struct Unclonable(MockStream);
+        impl Read for Unclonable {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Write for Unclonable {
+            fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
+                self.0.write(msg)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.0.flush()
+            }
+        }
+        impl NetworkStream for Unclonable {
+            fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+                self.0.peer_addr()
+            }
+        }
+
+        struct Through;
+        impl Handler for Through {
+            fn handle<'a, 'k>(&'a self, _: Request<'a, 'k>, res: Response<'a, Fresh>) {
+                res.start().unwrap().end().unwrap();
+            }
+
+            fn check_connect(&self, _: (&RequestUri, &Headers)) -> Tunnel {
+                Tunnel::Through(Box::new(Unclonable(MockStream::new())))
+            }
+        }
+
+        let mut mock = MockStream::with_input(b"\
+            CONNECT example.domain:443 HTTP/1.1\r\n\
+            Host: example.domain:443\r\n\
+            \r\n\
+        ");
+
+        handle_connection(&mut mock, &Through, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+        assert!(!s.contains("200 OK"));
+    }
+
+    #[test]
+    fn test_http10_closes() {
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.0\r\n\
+            \r\n\
+            GET / HTTP/1.0\r\n\
+            \r\n\
+        ");
+
+        fn handle(_: Request, res: Response<Fresh>) {
+            res.send(b"").unwrap();
+        }
+
+        handle_connection(&mut mock, &handle, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.0 200 OK").count(), 1);
+        assert!(s.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_http10_keep_alive() {
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.0\r\n\
+            Connection: Keep-Alive\r\n\
+            \r\n\
+            GET / HTTP/1.0\r\n\
+            \r\n\
+        ");
+
+        fn handle(_: Request, res: Response<Fresh>) {
+            res.send(b"").unwrap();
+        }
+
+        handle_connection(&mut mock, &handle, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.0 200 OK").count(), 2);
+        assert!(s.contains("Connection: keep-alive\r\n"));
+        assert!(s.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_http10_unsized_body() {
+        use std::io::Write;
+
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.0\r\n\
+            Connection: Keep-Alive\r\n\
+            \r\n\
+            GET / HTTP/1.0\r\n\
+            \r\n\
+        ");
+
+        fn handle(_: Request, res: Response<Fresh>) {
+            let mut res = res.start().unwrap();
+            res.write_all(b"hello").unwrap();
+            res.end().unwrap();
+        }
+
+        handle_connection(&mut mock, &handle, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.0 200 OK").count(), 1);
+        assert!(!s.contains("Transfer-Encoding"));
+        assert!(s.contains("Connection: close\r\n"));
+        assert!(s.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_handler_closes() {
+        use header::{Connection, ConnectionOption};
+
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+
+        fn handle(_: Request, mut res: Response<Fresh>) {
+            res.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+            res.send(b"bye").unwrap();
+        }
+
+        handle_connection(&mut mock, &handle, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(s.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_get_with_body() {
+        use std::io::Read;
+
+        // the body of a GET is framed like any other
+        let mut mock = MockStream::with_input(b"\
+            GET /first HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Content-Length: 32\r\n\
+            \r\n\
+            GET /smuggled HTTP/1.1\r\n\
+            Foo: bar\
+            GET /second HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+
+        fn handle(mut req: Request, res: Response<Fresh>) {
+            let mut body = String::new();
+            req.read_to_string(&mut body).unwrap();
+            let uri = format!("{:?}", req.uri);
+            assert!(!uri.contains("smuggled"));
+            res.send(uri.as_bytes()).unwrap();
+        }
+
+        handle_connection(&mut mock, &handle, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(s.contains("/first"));
+        assert!(s.ends_with("/second\")"));
+
+        // a body left unread ends the connection instead
+        let mut mock = MockStream::with_input(b"\
+            GET /first HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Content-Length: 32\r\n\
+            \r\n\
+            GET /smuggled HTTP/1.1\r\n\
+            Foo: bar\
+            GET /second HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+
+        fn ignore(_: Request, res: Response<Fresh>) {
+            res.send(b"").unwrap();
+        }
+
+        handle_connection(&mut mock, &ignore, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.1 200 OK").count(), 1);
+    }
+
+    #[test]
+    fn test_date_from_clock() {
+        use header::Date;
+
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ");
+
+        fn handle(_: Request, res: Response<Fresh>) {
+            res.send(b"").unwrap();
+        }
+
+        let settings = Settings {
+            clock: Arc::new(TestClock::new(Timespec::new(784111777, 0))),
+            ..Settings::new()
+        };
+        handle_connection(&mut mock, &handle, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.contains("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ");
+
+        fn no_date(_: Request, mut res: Response<Fresh>) {
+            res.set_send_date(false);
+            res.send(b"").unwrap();
+        }
+
+        handle_connection(&mut mock, &no_date, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(!s.contains("Date:"));
+
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ");
+
+        // a Date the handler removes is added back from the server's clock
+        fn removes_date(_: Request, mut res: Response<Fresh>) {
+            res.headers_mut().remove::<Date>();
+            res.send(b"").unwrap();
+        }
+
+        handle_connection(&mut mock, &removes_date, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.contains("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+    }
+
+    #[test]
+    fn test_strict_headers() {
+        let input = b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ";
+
+        fn handle(_: Request, mut res: Response<Fresh>) {
+            res.headers_mut().set_raw("X-Name", vec![b"a\r\nSet-Cookie: evil".to_vec()]);
+            let _ = res.send(b"");
+        }
+
+        let mut mock = MockStream::with_input(input);
+        handle_connection(&mut mock, &handle, &Settings::new());
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.contains("X-Name: a  Set-Cookie: evil\r\n"));
+        assert!(!s.contains("\r\nSet-Cookie"));
+
+        let mut mock = MockStream::with_input(input);
+        let settings = Settings {
+            strict_headers: true,
+            ..Settings::new()
+        };
+        handle_connection(&mut mock, &handle, &settings);
+        assert!(mock.write.is_empty());
+    }
+
+    #[test]
+    fn test_unfold_headers() {
+        let input = b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            X-Folded: one\r\n two\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ";
+
+        fn handle(req: Request, res: Response<Fresh>) {
+            let folded = req.headers.get_raw("X-Folded").unwrap()[0].clone();
+            res.send(&folded).unwrap();
+        }
+
+        let mut mock = MockStream::with_input(input);
+        handle_connection(&mut mock, &handle, &Settings::new());
+        assert!(mock.write.is_empty());
+
+        let mut mock = MockStream::with_input(input);
+        let settings = Settings {
+            unfold_headers: true,
+            ..Settings::new()
+        };
+        handle_connection(&mut mock, &handle, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.ends_with("\r\n\r\none   two"));
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        use std::io::Read;
+
+        fn handle(mut req: Request, res: Response<Fresh>) {
+            let mut body = String::new();
+            match req.read_to_string(&mut body) {
+                Ok(_) => res.send(body.as_bytes()).unwrap(),
+                Err(_) => res.send(b"too long").unwrap()
+            }
+        }
+        let settings = Settings {
+            max_body: Some(5),
+            ..Settings::new()
+        };
+
+        let mut mock = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Content-Length: 11\r\n\
+            \r\n\
+            hello world\
+        ");
+        handle_connection(&mut mock, &handle, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(s.contains("Connection: close\r\n"));
+
+        // the rest of a chunked body is never read as another request
+        let mut mock = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            3\r\nabc\r\n\
+            20\r\nGET /smuggled HTTP/1.1\r\n\r\n123456\r\n\
+            0\r\n\
+            \r\n\
+        ");
+        handle_connection(&mut mock, &handle, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(s.ends_with("too long"));
+
+        // a handler that gives up on the body answers with a 413
+        fn give_up(mut req: Request, _: Response<Fresh>) {
+            let mut body = String::new();
+            assert!(req.read_to_string(&mut body).is_err());
+        }
+        let mut mock = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            b\r\nhello world\r\n\
+            0\r\n\
+            \r\n\
+        ");
+        handle_connection(&mut mock, &give_up, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert!(s.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(s.contains("Connection: close\r\n"));
+        assert!(s.contains("Content-Length: 0\r\n"));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        use super::{RateLimit, Limiter};
+
+        fn handle(_: Request, res: Response<Fresh>) {
+            res.send(b"").unwrap();
+        }
+        let mut limit = RateLimit::new();
+        limit.set_rate(1, 2);
+        let settings = Settings {
+            clock: Arc::new(TestClock::new(Timespec::new(0, 0))),
+            limiter: Some(Arc::new(Limiter::new(limit))),
+            ..Settings::new()
+        };
+
+        let mut mock = MockStream::with_input(b"\
+            GET /a HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+            GET /b HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+            GET /c HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+        handle_connection(&mut mock, &handle, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(s.contains("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(s.contains("Retry-After: 1\r\n"));
+    }
+
+    #[test]
+    fn test_authenticator() {
+        use super::auth::BearerAuth;
+
+        fn handle(_: Request, res: Response<Fresh>) {
+            res.send(b"welcome").unwrap();
+        }
+        let settings = Settings {
+            authenticator: Some(Arc::new(BearerAuth::new("api", |token: &str| token == "secret"))),
+            ..Settings::new()
+        };
+
+        let mut mock = MockStream::with_input(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Authorization: Bearer secret\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+        handle_connection(&mut mock, &handle, &settings);
+        let s = String::from_utf8(mock.write).unwrap();
+        assert_eq!(s.matches("welcome").count(), 1);
+        assert!(s.contains("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(s.contains("WWW-Authenticate: Bearer realm=\"api\"\r\n"));
+    }
 }
@@ -2,17 +2,19 @@
 //!
 //! These are requests that a `hyper::Server` receives, and include its method,
 //! target URI, headers, and message body.
+use std::cell::Cell;
 use std::io::{self, Read};
 use std::net::SocketAddr;
 
 use buffer::BufReader;
 use net::NetworkStream;
 use version::{HttpVersion};
-use method::Method::{self, Get, Head};
+use method::Method::{self, Options};
 use header::{Headers, ContentLength, TransferEncoding};
 use http::{self, Incoming, HttpReader};
-use http::HttpReader::{SizedReader, ChunkedReader, EmptyReader};
+use http::HttpReader::{SizedReader, EmptyReader};
 use uri::RequestUri;
+use url::ParseError as UrlError;
 
 /// A request bundles several parts of an incoming `NetworkStream`, given to a `Handler`.
 pub struct Request<'a, 'b: 'a> {
@@ -26,30 +28,48 @@ pub struct Request<'a, 'b: 'a> {
     pub uri: RequestUri,
     /// The version of HTTP for this request.
     pub version: HttpVersion,
-    body: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>
+    body: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>,
+    limit: Option<u64>,
+    body_read: u64,
+    exceeded: Option<&'a Cell<bool>>,
+    ended: Option<&'a Cell<bool>>,
 }
 
 
 impl<'a, 'b: 'a> Request<'a, 'b> {
     /// Create a new Request, reading the StartLine and Headers so they are
     /// immediately useful.
-    pub fn new(mut stream: &'a mut BufReader<&'b mut NetworkStream>, addr: SocketAddr)
+    pub fn new(stream: &'a mut BufReader<&'b mut NetworkStream>, addr: SocketAddr)
         -> ::Result<Request<'a, 'b>> {
+        Request::with_unfolding(stream, addr, false)
+    }
+
+    /// Create a new Request, unfolding header values folded over several
+    /// lines (obs-fold) into one if `unfold` is set, instead of failing.
+    pub fn with_unfolding(mut stream: &'a mut BufReader<&'b mut NetworkStream>, addr: SocketAddr,
+                          unfold: bool) -> ::Result<Request<'a, 'b>> {
 
-        let Incoming { version, subject: (method, uri), headers } = try!(http::parse_request(stream));
+        let Incoming { version, subject: (method, uri), mut headers } =
+            try!(http::parse_request_unfolding(stream, unfold));
         debug!("Request Line: {:?} {:?} {:?}", method, uri, version);
         debug!("{:?}", headers);
-
-        let body = if method == Get || method == Head {
-            EmptyReader(stream)
-        } else if headers.has::<ContentLength>() {
-            match headers.get::<ContentLength>() {
-                Some(&ContentLength(len)) => SizedReader(stream, len),
-                None => unreachable!()
+        try!(http::check_framing(&mut headers));
+        if uri == RequestUri::Star && method != Options {
+            debug!("asterisk-form target for {:?}", method);
+            return Err(::Error::Uri(UrlError::InvalidCharacter));
+        }
+
+        // a GET or HEAD may have a body too, which has to be read past to
+        // find the next request
+        let body = if headers.has::<TransferEncoding>() {
+            match headers.get::<TransferEncoding>() {
+                Some(&TransferEncoding(ref codings)) => {
+                    try!(http::transfer_decoder(stream, codings, false))
+                },
+                None => return Err(::Error::Header)
             }
-        } else if headers.has::<TransferEncoding>() {
-            todo!("check for Transfer-Encoding: chunked");
-            ChunkedReader(stream, None)
+        } else if let Some(&ContentLength(len)) = headers.get() {
+            SizedReader(stream, len)
         } else {
             EmptyReader(stream)
         };
@@ -60,10 +80,64 @@ impl<'a, 'b: 'a> Request<'a, 'b> {
             uri: uri,
             headers: headers,
             version: version,
-            body: body
+            body: body,
+            limit: None,
+            body_read: 0,
+            exceeded: None,
+            ended: None,
         })
     }
 
+    /// Fail reading the body once more than `limit` bytes of it have been
+    /// read, with an `InvalidInput` error.
+    ///
+    /// A `Server` with a maximum body size sets this on every request, but a
+    /// handler can lower it for a request it expects to be small.
+    pub fn set_body_limit(&mut self, limit: u64) {
+        self.limit = Some(limit);
+    }
+
+    /// The most bytes a body sent with a `gzip` or `deflate` transfer
+    /// coding may decode to, before reading it fails with an
+    /// `InvalidInput` error.
+    pub fn set_max_decoded_size(&mut self, max: u64) {
+        self.body.set_max_decoded(max);
+    }
+
+    /// The most bytes a body sent with a `gzip` or `deflate` transfer
+    /// coding may decode to for each byte received, once it has decoded a
+    /// megabyte, before reading it fails with an `InvalidInput` error.
+    pub fn set_max_decoded_ratio(&mut self, ratio: u64) {
+        self.body.set_max_ratio(ratio);
+    }
+
+    // Lets the server find out that the limit was hit, after the handler
+    // has consumed the request, so it can close the connection.
+    #[doc(hidden)]
+    pub fn watch_body_limit(&mut self, limit: u64, exceeded: &'a Cell<bool>) {
+        self.limit = Some(limit);
+        self.exceeded = Some(exceeded);
+    }
+
+    // Lets the server find out that the body decoded to more than its
+    // limits allow, so it can answer 413 and close the connection.
+    #[doc(hidden)]
+    pub fn watch_decode_limit(&mut self, exceeded: &'a Cell<bool>) {
+        self.exceeded = Some(exceeded);
+    }
+
+    // Lets the server find out whether the handler read the whole body, so
+    // that what is left of it isn't parsed as the next request.
+    #[doc(hidden)]
+    pub fn watch_body_end(&mut self, ended: &'a Cell<bool>) {
+        let empty = match self.body {
+            EmptyReader(..) | SizedReader(_, 0) => true,
+            _ => false
+        };
+        ended.set(empty);
+        self.ended = Some(ended);
+    }
+
     /// Deconstruct a Request into its constituent parts.
     pub fn deconstruct(self) -> (SocketAddr, Method, Headers,
                                  RequestUri, HttpVersion,
@@ -75,7 +149,47 @@ impl<'a, 'b: 'a> Request<'a, 'b> {
 
 impl<'a, 'b> Read for Request<'a, 'b> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.body.read(buf)
+        let n = match self.read_limited(buf) {
+            Ok(n) => n,
+            Err(e) => {
+                if self.body.is_over_decode_limit() {
+                    debug!("request body is over the decoding limits");
+                    if let Some(exceeded) = self.exceeded {
+                        exceeded.set(true);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        if n == 0 && !buf.is_empty() {
+            if let Some(ended) = self.ended {
+                ended.set(true);
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<'a, 'b> Request<'a, 'b> {
+    fn read_limited(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return self.body.read(buf)
+        };
+        // read at most one byte past the limit, to tell if it was passed
+        let left = limit.saturating_sub(self.body_read).saturating_add(1);
+        let max = ::std::cmp::min(buf.len() as u64, left) as usize;
+        let n = try!(self.body.read(&mut buf[..max]));
+        self.body_read += n as u64;
+        if self.body_read > limit {
+            debug!("request body is over the limit of {} bytes", limit);
+            self.body_read = limit;
+            if let Some(exceeded) = self.exceeded {
+                exceeded.set(true);
+            }
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "request body is too large"));
+        }
+        Ok(n)
     }
 }
 
@@ -151,6 +265,51 @@ mod tests {
         assert_eq!(read_to_string(req).unwrap(), "".to_string());
     }
 
+    #[test]
+    fn test_options_asterisk() {
+        use method::Method::Options;
+        use uri::RequestUri;
+
+        let mut mock = MockStream::with_input(b"\
+            OPTIONS * HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+            GET * HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+
+        // FIXME: Use Type ascription
+        let mock: &mut NetworkStream = &mut mock;
+        let mut stream = BufReader::new(mock);
+
+        {
+            let req = Request::new(&mut stream, sock("127.0.0.1:80")).unwrap();
+            assert_eq!(req.method, Options);
+            assert_eq!(req.uri, RequestUri::Star);
+        }
+        assert!(Request::new(&mut stream, sock("127.0.0.1:80")).is_err());
+    }
+
+    #[test]
+    fn test_reject_ambiguous_length() {
+        let mut mock = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Content-Length: 4\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            0\r\n\
+            \r\n\
+        ");
+
+        // FIXME: Use Type ascription
+        let mock: &mut NetworkStream = &mut mock;
+        let mut stream = BufReader::new(mock);
+
+        assert!(Request::new(&mut stream, sock("127.0.0.1:80")).is_err());
+    }
+
     #[test]
     fn test_parse_chunked_request() {
         let mut mock = MockStream::with_input(b"\
@@ -264,4 +423,44 @@ mod tests {
         assert_eq!(read_to_string(req).unwrap(), "1".to_string());
     }
 
+    #[test]
+    fn test_body_limit() {
+        let input = b"\
+            POST / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\n\
+            hello\r\n\
+            6\r\n\
+            \x20world\r\n\
+            0\r\n\
+            \r\n";
+
+        let mut mock = MockStream::with_input(input);
+        let mock: &mut NetworkStream = &mut mock;
+        let mut stream = BufReader::new(mock);
+        let mut req = Request::new(&mut stream, sock("127.0.0.1:80")).unwrap();
+        req.set_body_limit(11);
+        assert_eq!(read_to_string(req).unwrap(), "hello world");
+
+        let mut mock = MockStream::with_input(input);
+        let mock: &mut NetworkStream = &mut mock;
+        let mut stream = BufReader::new(mock);
+        let mut req = Request::new(&mut stream, sock("127.0.0.1:80")).unwrap();
+        req.set_body_limit(8);
+        let mut buf = [0; 64];
+        assert_eq!(req.read(&mut buf).unwrap(), 5);
+        assert_eq!(req.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // lowering the limit below what was read already fails the next read
+        let mut mock = MockStream::with_input(input);
+        let mock: &mut NetworkStream = &mut mock;
+        let mut stream = BufReader::new(mock);
+        let mut req = Request::new(&mut stream, sock("127.0.0.1:80")).unwrap();
+        req.set_body_limit(11);
+        assert_eq!(req.read(&mut buf).unwrap(), 5);
+        req.set_body_limit(2);
+        assert_eq!(req.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
 }
@@ -3,18 +3,23 @@
 //! These are responses sent by a `hyper::Server` to clients, after
 //! receiving a request.
 use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::marker::PhantomData;
 use std::mem;
 use std::io::{self, Write};
 use std::ptr;
 
-use time::now_utc;
+use clock::{Clock, SystemClock};
+use unicase::UniCase;
 
 use header;
-use http::{CR, LF, LINE_ENDING, HttpWriter};
-use http::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter};
+use mime::Mime;
+use http::{self, CR, LF, LINE_ENDING, Chunker, HttpWriter};
+use http::HttpWriter::{ThroughWriter, ChunkedWriter, ConfiguredChunkedWriter, SizedWriter};
 use status;
 use net::{Fresh, Streaming};
+use server::date::{self, DateCache, DateSource};
 use version;
 
 
@@ -27,8 +32,23 @@ pub struct Response<'a, W: Any = Fresh> {
     body: HttpWriter<&'a mut (Write + 'a)>,
     // The status code for the request.
     status: status::StatusCode,
+    // A reason phrase to send instead of the canonical one.
+    reason: Option<Cow<'static, str>>,
     // The outgoing headers on this response.
     headers: header::Headers,
+    // Whether an illegal header is an error, rather than fixed up.
+    strict_headers: bool,
+    // Whether a Date header is added if there isn't one.
+    send_date: bool,
+    // Where that Date comes from, if a server made the response.
+    date: Option<DateSource<'a>>,
+    // Set if the connection is closed after the response, because its
+    // body ends that way or its headers say so.
+    closing: Option<&'a Cell<bool>>,
+    // Set if the request body was over the limit of the server.
+    exceeded: Option<&'a Cell<bool>>,
+    // How a chunked body is split into chunks, if not one per write.
+    chunker: Option<Chunker>,
 
     _writing: PhantomData<W>
 }
@@ -48,9 +68,16 @@ impl<'a, W: Any> Response<'a, W> {
                      headers: header::Headers) -> Response<'a, Fresh> {
         Response {
             status: status,
+            reason: None,
             version: version,
             body: body,
             headers: headers,
+            strict_headers: false,
+            send_date: true,
+            date: None,
+            closing: None,
+            exceeded: None,
+            chunker: None,
             _writing: PhantomData,
         }
     }
@@ -71,20 +98,51 @@ impl<'a, W: Any> Response<'a, W> {
     }
 
     fn write_head(&mut self) -> io::Result<Body> {
+        try!(self.check_headers());
         debug!("writing head: {:?} {:?}", self.version, self.status);
-        try!(write!(&mut self.body, "{} {}{}{}", self.version, self.status, CR as char, LF as char));
+        match self.reason {
+            Some(ref reason) => try!(write!(&mut self.body, "{} {} {}{}{}", self.version,
+                                            self.status.to_u16(), reason, CR as char, LF as char)),
+            None => try!(write!(&mut self.body, "{} {}{}{}", self.version, self.status,
+                                CR as char, LF as char))
+        }
 
-        if !self.headers.has::<header::Date>() {
-            self.headers.set(header::Date(header::HttpDate(now_utc())));
+        if self.send_date && !self.headers.has::<header::Date>() {
+            let value = match self.date {
+                Some(source) => source.now(),
+                None => date::format(&SystemClock)
+            };
+            self.headers.set_raw("Date", vec![value]);
         }
 
 
-        let mut body_type = Body::Chunked;
+        let mut body_type = if self.version.allows_chunked() {
+            Body::Chunked
+        } else {
+            Body::Close
+        };
 
         if let Some(cl) = self.headers.get::<header::ContentLength>() {
             body_type = Body::Sized(**cl);
         };
 
+        if body_type != Body::Chunked && self.headers.get_raw("Trailer").is_some() {
+            debug!("no trailers without a chunked body");
+            self.headers.remove_raw("Trailer");
+        }
+
+        if body_type == Body::Close {
+            // without chunked, only closing the connection can end the body
+            self.headers.set(header::Connection(vec![header::ConnectionOption::Close]));
+        }
+
+        // a handler may also ask for the connection to close
+        if !http::should_keep_alive(self.version, &self.headers) {
+            if let Some(closing) = self.closing {
+                closing.set(true);
+            }
+        }
+
         // can't do in match above, thanks borrowck
         if body_type == Body::Chunked {
             let encodings = match self.headers.get_mut::<header::TransferEncoding>() {
@@ -109,6 +167,52 @@ impl<'a, W: Any> Response<'a, W> {
 
         Ok(body_type)
     }
+
+    // A CR or LF in a header, such as one copied from a request, would end
+    // the header early, and let whatever follows be read as more headers or
+    // even another response.
+    fn check_headers(&mut self) -> io::Result<()> {
+        let reason_ok = self.reason.as_ref().map_or(true, |reason| !reason.bytes().any(is_illegal));
+        if !reason_ok {
+            if self.strict_headers {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("illegal reason phrase: {:?}", self.reason)));
+            }
+            debug!("replacing illegal bytes in reason phrase");
+            let reason = self.reason.take().unwrap().bytes()
+                .map(|b| if is_illegal(b) { ' ' } else { b as char })
+                .collect::<String>();
+            self.reason = Some(Cow::Owned(reason));
+        }
+
+        let bad = self.headers.iter().filter_map(|header| {
+            let name_ok = !header.name().is_empty() && header.name().bytes().all(http::is_tchar);
+            let value = header.value_string();
+            if name_ok && !value.bytes().any(is_illegal) {
+                None
+            } else {
+                Some((header.name().to_string(), value, name_ok))
+            }
+        }).collect::<Vec<_>>();
+
+        for (name, value, name_ok) in bad {
+            if self.strict_headers {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("illegal header: {:?}", name)));
+            }
+            if name_ok {
+                debug!("replacing illegal bytes in header {:?}", name);
+                let value = value.bytes()
+                    .map(|b| if is_illegal(b) { b' ' } else { b })
+                    .collect();
+                self.headers.set_raw(name, vec![value]);
+            } else {
+                debug!("leaving out header with illegal name {:?}", name);
+                self.headers.remove_raw(&name);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<'a> Response<'a, Fresh> {
@@ -117,8 +221,15 @@ impl<'a> Response<'a, Fresh> {
     pub fn new(stream: &'a mut (Write + 'a)) -> Response<'a, Fresh> {
         Response {
             status: status::StatusCode::Ok,
+            reason: None,
             version: version::HttpVersion::Http11,
             headers: header::Headers::new(),
+            strict_headers: false,
+            send_date: true,
+            date: None,
+            closing: None,
+            exceeded: None,
+            chunker: None,
             body: ThroughWriter(stream),
             _writing: PhantomData,
         }
@@ -144,10 +255,13 @@ impl<'a> Response<'a, Fresh> {
     /// Consume this Response<Fresh>, writing the Headers and Status and creating a Response<Streaming>
     pub fn start(mut self) -> io::Result<Response<'a, Streaming>> {
         let body_type = try!(self.write_head());
+        let chunker = self.chunker.take();
         let (version, body, status, headers) = self.deconstruct();
-        let stream = match body_type {
-            Body::Chunked => ChunkedWriter(body.into_inner()),
-            Body::Sized(len) => SizedWriter(body.into_inner(), len)
+        let stream = match (body_type, chunker) {
+            (Body::Chunked, Some(chunker)) => ConfiguredChunkedWriter(body.into_inner(), chunker),
+            (Body::Chunked, None) => ChunkedWriter(body.into_inner()),
+            (Body::Sized(len), _) => SizedWriter(body.into_inner(), len),
+            (Body::Close, _) => ThroughWriter(body.into_inner())
         };
 
         // "copy" to change the phantom type
@@ -155,7 +269,14 @@ impl<'a> Response<'a, Fresh> {
             version: version,
             body: stream,
             status: status,
+            reason: None,
             headers: headers,
+            strict_headers: false,
+            send_date: true,
+            date: None,
+            closing: None,
+            exceeded: None,
+            chunker: None,
             _writing: PhantomData,
         })
     }
@@ -166,6 +287,146 @@ impl<'a> Response<'a, Fresh> {
     /// Get a mutable reference to the Headers.
     #[inline]
     pub fn headers_mut(&mut self) -> &mut header::Headers { &mut self.headers }
+
+    /// Whether the response is sent with a `Date` header.
+    ///
+    /// One is added for the current time unless the handler set its own.
+    /// Passing `false` removes it, for a server without a reliable clock.
+    pub fn set_send_date(&mut self, send: bool) {
+        self.send_date = send;
+        if !send {
+            self.headers.remove::<header::Date>();
+        }
+    }
+
+    /// Send `reason` in the status line, instead of the canonical reason
+    /// phrase for the status.
+    ///
+    /// A CR, LF or other illegal byte in it is handled like one in a header.
+    pub fn set_reason<R: Into<Cow<'static, str>>>(&mut self, reason: R) {
+        self.reason = Some(reason.into());
+    }
+
+    /// Split a chunked body into chunks with `chunker`, instead of sending
+    /// each write as a chunk of its own.
+    ///
+    /// ```
+    /// # use hyper::server::{Request, Response};
+    /// use hyper::http::Chunker;
+    /// use std::io::Write;
+    ///
+    /// fn events(_: Request, mut res: Response) {
+    ///     res.set_chunker(Chunker::immediate(8 * 1024));
+    ///     let mut res = res.start().unwrap();
+    ///     // reaches the client right away
+    ///     res.write_all(b"data: hello\n\n").unwrap();
+    /// }
+    /// ```
+    pub fn set_chunker(&mut self, chunker: Chunker) {
+        self.chunker = Some(chunker);
+    }
+
+    /// Declare the trailers that will be sent after the body, in the
+    /// `Trailer` header, to send them with `end_with_trailers`.
+    ///
+    /// Trailers follow a chunked body, so a response with a
+    /// `Content-Length`, or to an HTTP/1.0 request, can't have them, and
+    /// the `Trailer` header is left out.
+    ///
+    /// ```
+    /// # use hyper::server::{Request, Response};
+    /// use hyper::header::Headers;
+    /// use std::io::Write;
+    ///
+    /// fn handler(_: Request, mut res: Response) {
+    ///     res.set_trailer_names(&["Content-MD5"]);
+    ///     let mut res = res.start().unwrap();
+    ///     res.write_all(b"hello").unwrap();
+    ///     let mut trailers = Headers::new();
+    ///     trailers.set_raw("Content-MD5", vec![b"XUFAKrxLKna5cZ2REBfFkg==".to_vec()]);
+    ///     res.end_with_trailers(trailers).unwrap();
+    /// }
+    /// ```
+    pub fn set_trailer_names(&mut self, names: &[&str]) {
+        self.headers.set_raw("Trailer", vec![names.join(", ").into_bytes()]);
+    }
+
+    /// Whether starting this response fails if a header holds a CR, LF or
+    /// other illegal byte, rather than having the byte replaced.
+    #[inline]
+    pub fn set_strict_headers(&mut self, strict: bool) { self.strict_headers = strict; }
+
+    // Has a Date the handler leaves out filled in from the server's clock.
+    #[doc(hidden)]
+    pub fn set_date_source(&mut self, cache: &'a DateCache, clock: &'a Clock) {
+        self.date = Some(DateSource::new(cache, clock));
+    }
+
+    // Lets the server find out that the body is ended by closing the
+    // connection, so it doesn't wait for another request.
+    #[doc(hidden)]
+    pub fn watch_close(&mut self, closing: &'a Cell<bool>) { self.closing = Some(closing); }
+
+    // Lets a response the handler didn't start become a 413, if reading
+    // the request body went over the limit.
+    #[doc(hidden)]
+    pub fn watch_body_limit(&mut self, exceeded: &'a Cell<bool>) {
+        self.exceeded = Some(exceeded);
+    }
+
+    /// Choose the representation to send, from the types the handler can
+    /// produce and the `Accept` header of the request.
+    ///
+    /// The chosen type is set as the `Content-Type` and returned. Without an
+    /// `Accept` header, the first available type is chosen. If none of them
+    /// are acceptable, the status is set to `406 Not Acceptable` and `None`
+    /// is returned. Either way, `Accept` is added to the `Vary` header, since
+    /// the response depends on it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use hyper::server::{Request, Response};
+    /// # use hyper::header::Accept;
+    /// # use hyper::mime::{Mime, TopLevel, SubLevel};
+    /// fn handler(req: Request, mut res: Response) {
+    ///     let available = [Mime(TopLevel::Application, SubLevel::Json, vec![]),
+    ///                      Mime(TopLevel::Text, SubLevel::Html, vec![])];
+    ///     let body: &[u8] = match res.negotiate(req.headers.get::<Accept>(), &available) {
+    ///         Some(&Mime(_, SubLevel::Json, _)) => b"{}",
+    ///         Some(_) => b"<p></p>",
+    ///         None => b"",
+    ///     };
+    ///     res.send(body).unwrap();
+    /// }
+    /// ```
+    pub fn negotiate<'m>(&mut self, accept: Option<&header::Accept>, available: &'m [Mime])
+        -> Option<&'m Mime> {
+        let accept_name = UniCase("Accept".to_string());
+        let has_vary = match self.headers.get_mut::<header::Vary>() {
+            Some(&mut header::Vary::Any) => true,
+            Some(&mut header::Vary::Items(ref mut items)) => {
+                if !items.contains(&accept_name) {
+                    items.push(UniCase("Accept".to_string()));
+                }
+                true
+            },
+            None => false
+        };
+        if !has_vary {
+            self.headers.set(header::Vary::Items(vec![accept_name]));
+        }
+
+        let chosen = match accept {
+            Some(accept) => accept.best_match(available),
+            None => available.first()
+        };
+        match chosen {
+            Some(mime) => self.headers.set(header::ContentType(mime.clone())),
+            None => self.status = status::StatusCode::NotAcceptable
+        }
+        chosen
+    }
 }
 
 
@@ -178,6 +439,35 @@ impl<'a> Response<'a, Streaming> {
         try!(body.end());
         Ok(())
     }
+
+    /// Ends the response like `end`, sending `trailers` after the body.
+    ///
+    /// Only the trailers declared with `set_trailer_names` are sent, and
+    /// only after a chunked body. One with a CR, LF or other illegal byte
+    /// is left out.
+    pub fn end_with_trailers(self, trailers: header::Headers) -> io::Result<()> {
+        let declared = self.headers.get_raw("Trailer").map_or(vec![], |raw| {
+            raw.iter()
+                .flat_map(|line| String::from_utf8_lossy(line)
+                    .split(',')
+                    .map(|name| UniCase(name.trim().to_string()))
+                    .collect::<Vec<_>>())
+                .collect()
+        });
+        let mut sent = header::Headers::new();
+        sent.extend(trailers.iter().filter(|trailer| {
+            let name = trailer.name();
+            let ok = declared.contains(&UniCase(name.to_string())) &&
+                name.bytes().all(http::is_tchar) && !trailer.value_string().bytes().any(is_illegal);
+            if !ok {
+                debug!("leaving out trailer {:?}", name);
+            }
+            ok
+        }));
+        let (_, body, _, _) = self.deconstruct();
+        try!(body.end_with_trailers(&sent));
+        Ok(())
+    }
 }
 
 impl<'a> Write for Response<'a, Streaming> {
@@ -193,18 +483,32 @@ impl<'a> Write for Response<'a, Streaming> {
     }
 }
 
+// Control characters other than a tab can't appear in a header value.
+fn is_illegal(b: u8) -> bool {
+    (b < b' ' && b != b'\t') || b == 0x7f
+}
+
 #[derive(PartialEq)]
 enum Body {
     Chunked,
     Sized(u64),
+    Close,
 }
 
 impl<'a, T: Any> Drop for Response<'a, T> {
     fn drop(&mut self) {
         if TypeId::of::<T>() == TypeId::of::<Fresh>() {
+            if self.exceeded.map_or(false, |exceeded| exceeded.get()) {
+                debug!("request body was over the limit, sending 413");
+                self.status = status::StatusCode::PayloadTooLarge;
+                self.headers.remove_raw("Transfer-Encoding");
+                self.headers.set(header::ContentLength(0));
+                self.headers.set(header::Connection(vec![header::ConnectionOption::Close]));
+            }
             let mut body = match self.write_head() {
                 Ok(Body::Chunked) => ChunkedWriter(self.body.get_mut()),
                 Ok(Body::Sized(len)) => SizedWriter(self.body.get_mut(), len),
+                Ok(Body::Close) => ThroughWriter(self.body.get_mut()),
                 Err(e) => {
                     debug!("error dropping request: {:?}", e);
                     return;
@@ -326,4 +630,114 @@ mod tests {
             "" // empty zero body
         }
     }
+
+    #[test]
+    fn test_trailers() {
+        use std::io::Write;
+        use header::Headers;
+        let mut stream = MockStream::new();
+        {
+            let mut res = Response::new(&mut stream);
+            res.set_send_date(false);
+            res.set_trailer_names(&["X-Checksum"]);
+            let mut res = res.start().unwrap();
+            res.write_all(b"foo").unwrap();
+            let mut trailers = Headers::new();
+            trailers.set_raw("x-checksum", vec![b"abc".to_vec()]);
+            trailers.set_raw("X-Undeclared", vec![b"nope".to_vec()]);
+            res.end_with_trailers(trailers).unwrap();
+        }
+
+        lines! { stream =
+            "HTTP/1.1 200 OK",
+            _,
+            _,
+            "",
+            "3",
+            "foo",
+            "0",
+            "x-checksum: abc",
+            ""
+        }
+
+        // a sized body has no room for them
+        let mut stream = MockStream::new();
+        {
+            let mut res = Response::new(&mut stream);
+            res.set_send_date(false);
+            res.set_trailer_names(&["X-Checksum"]);
+            res.headers_mut().set(::header::ContentLength(3));
+            let mut res = res.start().unwrap();
+            res.write_all(b"foo").unwrap();
+            let mut trailers = Headers::new();
+            trailers.set_raw("X-Checksum", vec![b"abc".to_vec()]);
+            res.end_with_trailers(trailers).unwrap();
+        }
+
+        lines! { stream =
+            "HTTP/1.1 200 OK",
+            "Content-Length: 3",
+            "",
+            "foo"
+        }
+    }
+
+    #[test]
+    fn test_custom_reason() {
+        use status::StatusCode;
+        let mut stream = MockStream::new();
+        {
+            let mut res = Response::new(&mut stream);
+            *res.status_mut() = StatusCode::Unregistered(299);
+            res.set_reason("Fine\r\nX-Injected: 1");
+            res.start().unwrap();
+        }
+
+        lines! { stream =
+            "HTTP/1.1 299 Fine  X-Injected: 1",
+            _date,
+            _transfer_encoding,
+            "",
+            "0",
+            ""
+        }
+
+        let mut stream = MockStream::new();
+        let mut res = Response::new(&mut stream);
+        res.set_strict_headers(true);
+        res.set_reason("Fine\r\n");
+        assert!(res.start().is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        use header::{Accept, ContentType, Vary, qitem};
+        use mime::{Mime, TopLevel, SubLevel};
+        use status::StatusCode;
+        let json = Mime(TopLevel::Application, SubLevel::Json, vec![]);
+        let html = Mime(TopLevel::Text, SubLevel::Html, vec![]);
+        let available = [json.clone(), html.clone()];
+
+        let mut stream = MockStream::new();
+        let mut res = Response::new(&mut stream);
+        res.headers_mut().set(Vary::Items(vec!["Cookie".parse().unwrap()]));
+        assert_eq!(res.negotiate(Some(&Accept(vec![qitem(html.clone())])), &available),
+                   Some(&html));
+        assert_eq!(res.headers().get(), Some(&ContentType(html.clone())));
+        assert_eq!(res.headers().get(), Some(&Vary::Items(vec!["Cookie".parse().unwrap(),
+                                                                 "Accept".parse().unwrap()])));
+
+        assert_eq!(res.negotiate(None, &available), Some(&json));
+        assert_eq!(res.headers().get(), Some(&ContentType(json.clone())));
+        assert_eq!(res.status(), StatusCode::Ok);
+
+        let png = Mime(TopLevel::Image, SubLevel::Png, vec![]);
+        assert_eq!(res.negotiate(Some(&Accept(vec![qitem(png)])), &available), None);
+        assert_eq!(res.status(), StatusCode::NotAcceptable);
+        assert_eq!(res.headers().get::<Vary>().map(|vary| match *vary {
+            Vary::Items(ref items) => items.len(),
+            Vary::Any => 0
+        }), Some(2));
+        res.deconstruct();
+    }
 }
@@ -0,0 +1,423 @@
+//! An in-process server for testing HTTP clients.
+//!
+//! A `TestServer` listens on an ephemeral port of the loopback interface.
+//! Tests queue up the requests they expect to be made, each paired with a
+//! scripted reply. Requests are checked in order as they arrive, and any
+//! mismatch is reported by a panic in the test thread, either when
+//! `assert_done` is called or when the `TestServer` is dropped.
+//!
+//! ```no_run
+//! # use hyper::Client;
+//! use hyper::method::Method::Get;
+//! use hyper::server::testing::{TestServer, Expected, Reply};
+//! use hyper::status::StatusCode;
+//!
+//! let server = TestServer::new();
+//! server.expect(Expected::new(Get, "/hello"),
+//!               Reply::new(StatusCode::Ok).body("world"));
+//!
+//! let res = Client::new().get(&server.url("/hello")).send().unwrap();
+//! assert_eq!(res.status, StatusCode::Ok);
+//! server.assert_done();
+//! ```
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, Shutdown};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use header::{Header, HeaderFormat, Headers, ContentLength};
+use method::Method;
+use net::Fresh;
+use status::StatusCode;
+use uri::RequestUri;
+
+use super::{Handler, Listening, Request, Response, Server};
+
+/// A request the `TestServer` expects to receive.
+#[derive(Clone, Debug)]
+pub struct Expected {
+    method: Method,
+    path: String,
+    headers: Headers,
+    body: Option<Vec<u8>>,
+}
+
+impl Expected {
+    /// Expect a request with this method and path, including any query.
+    pub fn new<P: Into<String>>(method: Method, path: P) -> Expected {
+        Expected {
+            method: method,
+            path: path.into(),
+            headers: Headers::new(),
+            body: None,
+        }
+    }
+
+    /// Expect the request to include this header.
+    ///
+    /// Headers not mentioned in an expectation are not checked.
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> Expected {
+        self.headers.set(header);
+        self
+    }
+
+    /// Expect the request to have exactly this body.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Expected {
+        self.body = Some(body.into());
+        self
+    }
+}
+
+/// A scripted response the `TestServer` sends back.
+///
+/// The body is sent as a series of chunks, each of which can be delayed,
+/// and the connection can be cut part way through the body, to test how
+/// clients cope with slow and broken servers. Unless the reply includes a
+/// `Content-Length` header, one is added for the full body.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    status: StatusCode,
+    headers: Headers,
+    chunks: Vec<(u32, Vec<u8>)>,
+    delay_ms: u32,
+    disconnect_after: Option<usize>,
+}
+
+impl Reply {
+    /// Reply with this status, and an empty body.
+    pub fn new(status: StatusCode) -> Reply {
+        Reply {
+            status: status,
+            headers: Headers::new(),
+            chunks: Vec::new(),
+            delay_ms: 0,
+            disconnect_after: None,
+        }
+    }
+
+    /// Include this header in the reply.
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> Reply {
+        self.headers.set(header);
+        self
+    }
+
+    /// Send this body with the reply, replacing any chunks added so far.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Reply {
+        self.chunks = vec![(0, body.into())];
+        self
+    }
+
+    /// Add a chunk to the body, sent after waiting `delay_ms` milliseconds.
+    pub fn chunk<B: Into<Vec<u8>>>(mut self, delay_ms: u32, chunk: B) -> Reply {
+        self.chunks.push((delay_ms, chunk.into()));
+        self
+    }
+
+    /// Wait this many milliseconds before sending the head of the reply.
+    pub fn delay_ms(mut self, ms: u32) -> Reply {
+        self.delay_ms = ms;
+        self
+    }
+
+    /// Close the connection once this many bytes of the body have been sent.
+    pub fn disconnect_after(mut self, bytes: usize) -> Reply {
+        self.disconnect_after = Some(bytes);
+        self
+    }
+
+    fn len(&self) -> usize {
+        self.chunks.iter().fold(0, |len, &(_, ref chunk)| len + chunk.len())
+    }
+
+    fn write<W: Write>(&self, res: &mut W) -> io::Result<()> {
+        let mut remaining = self.disconnect_after;
+        for &(delay_ms, ref chunk) in self.chunks.iter() {
+            if delay_ms > 0 {
+                thread::sleep_ms(delay_ms);
+            }
+            let chunk = match remaining {
+                Some(n) if n < chunk.len() => &chunk[..n],
+                _ => &chunk[..]
+            };
+            try!(res.write_all(chunk));
+            try!(res.flush());
+            if let Some(ref mut n) = remaining {
+                *n -= chunk.len();
+                if *n == 0 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Script {
+    queue: VecDeque<(Expected, Reply)>,
+    failures: Vec<String>,
+}
+
+struct ScriptHandler(Arc<Mutex<Script>>);
+
+impl Handler for ScriptHandler {
+    fn handle<'a, 'k>(&'a self, mut req: Request<'a, 'k>, mut res: Response<'a, Fresh>) {
+        let mut body = Vec::new();
+        if let Err(e) = req.read_to_end(&mut body) {
+            debug!("error reading test request body: {:?}", e);
+        }
+
+        let next = {
+            let mut script = self.0.lock().unwrap();
+            match script.queue.pop_front() {
+                Some((expected, reply)) => {
+                    if let Some(diff) = diff(&expected, &req, &body) {
+                        script.failures.push(diff);
+                    }
+                    Some(reply)
+                },
+                None => {
+                    script.failures.push(format!("unexpected request: {} {}",
+                                                 req.method, Path(&req.uri)));
+                    None
+                }
+            }
+        };
+
+        let reply = match next {
+            Some(reply) => reply,
+            None => {
+                *res.status_mut() = StatusCode::InternalServerError;
+                return;
+            }
+        };
+
+        if reply.delay_ms > 0 {
+            thread::sleep_ms(reply.delay_ms);
+        }
+        *res.status_mut() = reply.status;
+        res.headers_mut().extend(reply.headers.iter());
+        if !res.headers().has::<ContentLength>() {
+            res.headers_mut().set(ContentLength(reply.len() as u64));
+        }
+
+        let mut res = match res.start() {
+            Ok(res) => res,
+            Err(e) => {
+                debug!("error writing test reply: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = reply.write(&mut res) {
+            debug!("error writing test reply: {:?}", e);
+            return;
+        }
+
+        if reply.disconnect_after.is_some() {
+            debug!("disconnecting test reply");
+            let (_, _, _, _, _, body) = req.deconstruct();
+            if let Err(e) = body.into_inner().get_mut().close(Shutdown::Both) {
+                debug!("error disconnecting test reply: {:?}", e);
+            }
+        } else if let Err(e) = res.end() {
+            debug!("error writing test reply: {:?}", e);
+        }
+    }
+}
+
+struct Path<'a>(&'a RequestUri);
+
+impl<'a> fmt::Display for Path<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            RequestUri::AbsolutePath(ref path) => f.write_str(path),
+            RequestUri::AbsoluteUri(ref url) => f.write_str(&url.serialize()),
+            RequestUri::Authority(ref authority) => f.write_str(authority),
+            RequestUri::Star => f.write_str("*"),
+        }
+    }
+}
+
+fn diff(expected: &Expected, req: &Request, body: &[u8]) -> Option<String> {
+    let mut lines = Vec::new();
+
+    let path = Path(&req.uri).to_string();
+    if expected.method != req.method || expected.path != path {
+        lines.push(format!("- {} {}", expected.method, expected.path));
+        lines.push(format!("+ {} {}", req.method, path));
+    }
+
+    for header in expected.headers.iter() {
+        let value = header.value_string();
+        let actual = req.headers.get_raw(header.name()).map(|raw| {
+            raw.iter()
+               .map(|line| String::from_utf8_lossy(line).into_owned())
+               .collect::<Vec<_>>()
+               .connect(", ")
+        });
+        match actual {
+            Some(ref actual) if *actual == value => (),
+            Some(actual) => {
+                lines.push(format!("- {}: {}", header.name(), value));
+                lines.push(format!("+ {}: {}", header.name(), actual));
+            },
+            None => lines.push(format!("- {}: {}", header.name(), value)),
+        }
+    }
+
+    if let Some(ref expected_body) = expected.body {
+        if &expected_body[..] != body {
+            lines.push(format!("- {}", String::from_utf8_lossy(expected_body)));
+            lines.push(format!("+ {}", String::from_utf8_lossy(body)));
+        }
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(format!("request did not match expectation for {} {}:\n{}",
+                     expected.method, expected.path, lines.connect("\n")))
+    }
+}
+
+/// A server running in the background, answering with scripted replies.
+pub struct TestServer {
+    listening: Listening,
+    script: Arc<Mutex<Script>>,
+}
+
+impl TestServer {
+    /// Start a `TestServer` on an ephemeral port of `127.0.0.1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server cannot bind to a port.
+    pub fn new() -> TestServer {
+        let script = Arc::new(Mutex::new(Script {
+            queue: VecDeque::new(),
+            failures: Vec::new(),
+        }));
+        let listening = Server::http(ScriptHandler(script.clone()))
+            .listen_threads("127.0.0.1:0", 1)
+            .unwrap();
+        TestServer {
+            listening: listening,
+            script: script,
+        }
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.listening.socket
+    }
+
+    /// A full URL pointing at `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.listening.socket, path)
+    }
+
+    /// Queue an expected request, and the reply to send when it arrives.
+    ///
+    /// Expectations are matched in the order they were queued.
+    pub fn expect(&self, expected: Expected, reply: Reply) {
+        self.script.lock().unwrap().queue.push_back((expected, reply));
+    }
+
+    /// Panics if any request did not match, or an expected request was not made.
+    pub fn assert_done(&self) {
+        let script = self.script.lock().unwrap();
+        let mut failures = script.failures.clone();
+        for &(ref expected, _) in script.queue.iter() {
+            failures.push(format!("expected request was not made: {} {}",
+                                  expected.method, expected.path));
+        }
+        if !failures.is_empty() {
+            panic!("TestServer:\n{}", failures.connect("\n"));
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.listening.close();
+        if !thread::panicking() {
+            self.assert_done();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use client::Client;
+    use header::{Server, UserAgent};
+    use method::Method::{Get, Post};
+    use status::StatusCode;
+    use super::{TestServer, Expected, Reply};
+
+    #[test]
+    fn test_scripted_reply() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Post, "/echo").body("ping"),
+                      Reply::new(StatusCode::Created)
+                          .header(Server("test".to_string()))
+                          .body("pong"));
+
+        let mut client = Client::new();
+        let mut res = client.post(&server.url("/echo")).body("ping").send().unwrap();
+        assert_eq!(res.status, StatusCode::Created);
+        assert_eq!(res.headers.get(), Some(&Server("test".to_string())));
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "pong");
+        server.assert_done();
+    }
+
+    #[test]
+    fn test_chunks_and_disconnect() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/slow"),
+                      Reply::new(StatusCode::Ok)
+                          .delay_ms(10)
+                          .chunk(10, "hello ")
+                          .chunk(10, "world"));
+        server.expect(Expected::new(Get, "/cut"),
+                      Reply::new(StatusCode::Ok)
+                          .body("hello world")
+                          .disconnect_after(5));
+
+        let mut client = Client::new();
+        let mut res = client.get(&server.url("/slow")).send().unwrap();
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello world");
+
+        let mut res = client.get(&server.url("/cut")).send().unwrap();
+        let mut body = Vec::new();
+        let _ = res.read_to_end(&mut body);
+        assert_eq!(body, b"hello");
+        server.assert_done();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mismatch_panics() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/a").header(UserAgent("test".to_string())),
+                      Reply::new(StatusCode::Ok));
+
+        let mut client = Client::new();
+        client.get(&server.url("/b")).send().unwrap();
+        server.assert_done();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unmet_expectation_panics() {
+        let server = TestServer::new();
+        server.expect(Expected::new(Get, "/"), Reply::new(StatusCode::Ok));
+        server.assert_done();
+    }
+}
@@ -0,0 +1,243 @@
+//! Tunnels opened by `CONNECT` requests, for forward proxies.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::thread;
+
+use time::Duration;
+
+use clock::Clock;
+use net::{HttpConnector, NetworkConnector, NetworkStream};
+use status::StatusCode;
+use uri::RequestUri;
+use url::ParseError as UrlError;
+
+/// What a server does with a `CONNECT` request.
+///
+/// A `Handler` decides in `check_connect`, before the handler would see the
+/// request. A `CONNECT` whose target isn't `host:port` is answered with
+/// `400 Bad Request` before it is asked. When a tunnel is opened, the server answers `200 OK` and copies
+/// bytes both ways between the client and the target, until both are done
+/// sending, and then closes the connection.
+pub enum Tunnel {
+    /// Give the request to `handle`, like any other.
+    Handle,
+    /// Answer with this status instead, and close the connection.
+    Refuse(StatusCode),
+    /// Connect to the `host:port` the request names, and tunnel to it.
+    ///
+    /// If the target can't be reached, the client is answered with
+    /// `502 Bad Gateway`.
+    Open,
+    /// Tunnel to a connection the handler already opened, such as one
+    /// through another proxy.
+    ///
+    /// The stream must support `try_clone`. If it doesn't, the client is
+    /// answered with `502 Bad Gateway`.
+    Through(Box<NetworkStream + Send>),
+}
+
+impl fmt::Debug for Tunnel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Tunnel::Handle => f.write_str("Handle"),
+            Tunnel::Refuse(status) => write!(f, "Refuse({})", status),
+            Tunnel::Open => f.write_str("Open"),
+            Tunnel::Through(_) => f.write_str("Through(..)"),
+        }
+    }
+}
+
+/// What went through a tunnel, reported to `Handler::tunnel_closed`.
+#[derive(Clone, Debug)]
+pub struct TunnelStats {
+    /// The `host:port` the client asked for.
+    pub target: String,
+    /// The bytes the client sent to the target.
+    pub sent: u64,
+    /// The bytes the target sent to the client.
+    pub received: u64,
+    /// How long the tunnel was open.
+    pub duration: Duration,
+}
+
+/// Connect to the `host:port` target of a `CONNECT` request.
+pub fn connect(uri: &RequestUri) -> ::Result<Box<NetworkStream + Send>> {
+    let (host, port) = match *uri {
+        RequestUri::Authority(ref authority) => match split_authority(authority) {
+            Some(target) => target,
+            None => return Err(::Error::Uri(UrlError::InvalidPort))
+        },
+        _ => return Err(::Error::Uri(UrlError::InvalidCharacter))
+    };
+    let stream = try!(HttpConnector(None).connect(host, port, "http"));
+    Ok(Box::new(stream))
+}
+
+fn split_authority(authority: &str) -> Option<(&str, u16)> {
+    let colon = match authority.rfind(':') {
+        Some(colon) => colon,
+        None => return None
+    };
+    let port = match authority[colon + 1..].parse() {
+        Ok(port) => port,
+        Err(_) => return None
+    };
+    let host = authority[..colon].trim_left_matches('[').trim_right_matches(']');
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, port))
+    }
+}
+
+/// Copy bytes both ways between `client` and `upstream` until both are
+/// done, starting with what was already read from the client.
+/// `from_upstream` is a clone of `upstream`, to read it on another thread.
+pub fn splice<S>(mut client: S, buffered: &[u8], mut upstream: Box<NetworkStream + Send>,
+                 mut from_upstream: Box<NetworkStream + Send>, target: String, clock: &Clock)
+                 -> TunnelStats
+where S: NetworkStream + Clone {
+    let start = clock.now();
+    let mut to_client = client.clone();
+    let downstream = thread::spawn(move || {
+        let received = pump(&mut from_upstream, &mut to_client);
+        let _ = to_client.close(Shutdown::Write);
+        received
+    });
+
+    let mut sent = 0;
+    if upstream.write_all(buffered).is_ok() {
+        sent = buffered.len() as u64 + pump(&mut client, &mut upstream);
+    }
+    let _ = upstream.close(Shutdown::Write);
+    let received = downstream.join().unwrap_or(0);
+
+    let stats = TunnelStats {
+        target: target,
+        sent: sent,
+        received: received,
+        duration: clock.now() - start,
+    };
+    debug!("tunnel closed: {:?}", stats);
+    stats
+}
+
+// Copies until the reader is done or either side fails, returning how many
+// bytes made it across.
+fn pump<R: Read, W: Write>(from: &mut R, to: &mut W) -> u64 {
+    let mut buf = [0; 8192];
+    let mut total = 0;
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                debug!("tunnel read error: {:?}", e);
+                break;
+            }
+        };
+        if let Err(e) = to.write_all(&buf[..n]).and_then(|_| to.flush()) {
+            debug!("tunnel write error: {:?}", e);
+            break;
+        }
+        total += n as u64;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use server::{Server, Request, Response, Fresh, Handler};
+    use header::Headers;
+    use status::StatusCode;
+    use uri::RequestUri;
+    use super::{Tunnel, TunnelStats, split_authority};
+
+    #[test]
+    fn test_split_authority() {
+        assert_eq!(split_authority("example.domain:443"), Some(("example.domain", 443)));
+        assert_eq!(split_authority("[::1]:8080"), Some(("::1", 8080)));
+        assert_eq!(split_authority("example.domain"), None);
+        assert_eq!(split_authority(":443"), None);
+    }
+
+    struct Proxy(Arc<Mutex<Option<TunnelStats>>>);
+
+    impl Handler for Proxy {
+        fn handle<'a, 'k>(&'a self, _: Request<'a, 'k>, mut res: Response<'a, Fresh>) {
+            *res.status_mut() = StatusCode::NotFound;
+        }
+
+        fn check_connect(&self, (uri, _): (&RequestUri, &Headers)) -> Tunnel {
+            match *uri {
+                RequestUri::Authority(ref target) if target.starts_with("127.0.0.1:") => {
+                    Tunnel::Open
+                },
+                _ => Tunnel::Refuse(StatusCode::Forbidden)
+            }
+        }
+
+        fn tunnel_closed(&self, stats: &TunnelStats) {
+            *self.0.lock().unwrap() = Some(stats.clone());
+        }
+    }
+
+    #[test]
+    fn test_connect_tunnel() {
+        let echo = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = echo.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut conn, _) = echo.accept().unwrap();
+            let mut buf = vec![];
+            conn.read_to_end(&mut buf).unwrap();
+            conn.write_all(&buf).unwrap();
+        });
+
+        let stats = Arc::new(Mutex::new(None));
+        let mut listening = Server::http(Proxy(stats.clone()))
+            .listen_threads("127.0.0.1:0", 1).unwrap();
+
+        let mut client = TcpStream::connect(listening.socket).unwrap();
+        write!(client, "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\nping", target, target).unwrap();
+        client.shutdown(::std::net::Shutdown::Write).unwrap();
+        let mut s = String::new();
+        client.read_to_string(&mut s).unwrap();
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(s.ends_with("\r\n\r\nping"));
+        assert!(!s.contains("Content-Length"));
+
+        // reported once the server is done with the tunnel
+        let mut reported = None;
+        for _ in 0..100 {
+            reported = stats.lock().unwrap().take();
+            if reported.is_some() {
+                break;
+            }
+            thread::sleep_ms(10);
+        }
+        let stats = reported.unwrap();
+        assert_eq!(stats.target, target.to_string());
+        assert_eq!((stats.sent, stats.received), (4, 4));
+
+        let mut client = TcpStream::connect(listening.socket).unwrap();
+        client.write_all(b"CONNECT example.domain:443 HTTP/1.1\r\n\r\n").unwrap();
+        let mut s = String::new();
+        client.read_to_string(&mut s).unwrap();
+        assert!(s.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+        let mut client = TcpStream::connect(listening.socket).unwrap();
+        client.write_all(b"CONNECT /x HTTP/1.1\r\n\r\n").unwrap();
+        let mut s = String::new();
+        client.read_to_string(&mut s).unwrap();
+        assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        listening.close().unwrap();
+    }
+}
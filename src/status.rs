@@ -39,6 +39,9 @@ pub enum StatusCode {
     /// 102 Processing
     /// [[RFC2518](https://tools.ietf.org/html/rfc2518)]
     Processing,
+    /// 103 Early Hints
+    /// [[RFC8297](https://tools.ietf.org/html/rfc8297)]
+    EarlyHints,
 
     /// 200 OK
     /// [[RFC7231, Section 6.3.1](https://tools.ietf.org/html/rfc7231#section-6.3.1)]
@@ -155,6 +158,9 @@ pub enum StatusCode {
     /// [curiously, not registered by IANA, but [RFC2324](https://tools.ietf.org/html/rfc2324)]
     ImATeapot,
 
+    /// 421 Misdirected Request
+    /// [[RFC7540, Section 9.1.2](https://tools.ietf.org/html/rfc7540#section-9.1.2)]
+    MisdirectedRequest,
     /// 422 Unprocessable Entity
     /// [[RFC4918](https://tools.ietf.org/html/rfc4918)]
     UnprocessableEntity,
@@ -164,7 +170,9 @@ pub enum StatusCode {
     /// 424 Failed Dependency
     /// [[RFC4918](https://tools.ietf.org/html/rfc4918)]
     FailedDependency,
-
+    /// 425 Too Early
+    /// [[RFC8470](https://tools.ietf.org/html/rfc8470)]
+    TooEarly,
     /// 426 Upgrade Required
     /// [[RFC7231, Section 6.5.15](https://tools.ietf.org/html/rfc7231#section-6.5.15)]
     UpgradeRequired,
@@ -180,6 +188,10 @@ pub enum StatusCode {
     /// [[RFC6585](https://tools.ietf.org/html/rfc6585)]
     RequestHeaderFieldsTooLarge,
 
+    /// 451 Unavailable For Legal Reasons
+    /// [[RFC7725](https://tools.ietf.org/html/rfc7725)]
+    UnavailableForLegalReasons,
+
     /// 500 Internal Server Error
     /// [[RFC7231, Section 6.6.1](https://tools.ietf.org/html/rfc7231#section-6.6.1)]
     InternalServerError,
@@ -222,12 +234,16 @@ pub enum StatusCode {
 
 impl StatusCode {
 
-    #[doc(hidden)]
+    /// Get the `StatusCode` for the number `n`.
+    ///
+    /// A code that isn't registered is kept as `Unregistered(n)`, so every
+    /// `u16` round-trips through `to_u16` unchanged.
     pub fn from_u16(n: u16) -> StatusCode {
         match n {
             100 => StatusCode::Continue,
             101 => StatusCode::SwitchingProtocols,
             102 => StatusCode::Processing,
+            103 => StatusCode::EarlyHints,
             200 => StatusCode::Ok,
             201 => StatusCode::Created,
             202 => StatusCode::Accepted,
@@ -265,13 +281,16 @@ impl StatusCode {
             416 => StatusCode::RangeNotSatisfiable,
             417 => StatusCode::ExpectationFailed,
             418 => StatusCode::ImATeapot,
+            421 => StatusCode::MisdirectedRequest,
             422 => StatusCode::UnprocessableEntity,
             423 => StatusCode::Locked,
             424 => StatusCode::FailedDependency,
+            425 => StatusCode::TooEarly,
             426 => StatusCode::UpgradeRequired,
             428 => StatusCode::PreconditionRequired,
             429 => StatusCode::TooManyRequests,
             431 => StatusCode::RequestHeaderFieldsTooLarge,
+            451 => StatusCode::UnavailableForLegalReasons,
             500 => StatusCode::InternalServerError,
             501 => StatusCode::NotImplemented,
             502 => StatusCode::BadGateway,
@@ -287,12 +306,13 @@ impl StatusCode {
         }
     }
 
-    #[doc(hidden)]
+    /// Get the number of this status code.
     pub fn to_u16(&self) -> u16 {
         match *self {
             StatusCode::Continue => 100,
             StatusCode::SwitchingProtocols => 101,
             StatusCode::Processing => 102,
+            StatusCode::EarlyHints => 103,
             StatusCode::Ok => 200,
             StatusCode::Created => 201,
             StatusCode::Accepted => 202,
@@ -330,13 +350,16 @@ impl StatusCode {
             StatusCode::RangeNotSatisfiable => 416,
             StatusCode::ExpectationFailed => 417,
             StatusCode::ImATeapot => 418,
+            StatusCode::MisdirectedRequest => 421,
             StatusCode::UnprocessableEntity => 422,
             StatusCode::Locked => 423,
             StatusCode::FailedDependency => 424,
+            StatusCode::TooEarly => 425,
             StatusCode::UpgradeRequired => 426,
             StatusCode::PreconditionRequired => 428,
             StatusCode::TooManyRequests => 429,
             StatusCode::RequestHeaderFieldsTooLarge => 431,
+            StatusCode::UnavailableForLegalReasons => 451,
             StatusCode::InternalServerError => 500,
             StatusCode::NotImplemented => 501,
             StatusCode::BadGateway => 502,
@@ -367,6 +390,7 @@ impl StatusCode {
             StatusCode::Continue => Some("Continue"),
             StatusCode::SwitchingProtocols => Some("Switching Protocols"),
             StatusCode::Processing => Some("Processing"),
+            StatusCode::EarlyHints => Some("Early Hints"),
 
             StatusCode::Ok => Some("OK"),
             StatusCode::Created => Some("Created"),
@@ -410,10 +434,11 @@ impl StatusCode {
             StatusCode::ExpectationFailed => Some("Expectation Failed"),
             StatusCode::ImATeapot => Some("I'm a teapot"),
 
+            StatusCode::MisdirectedRequest => Some("Misdirected Request"),
             StatusCode::UnprocessableEntity => Some("Unprocessable Entity"),
             StatusCode::Locked => Some("Locked"),
             StatusCode::FailedDependency => Some("Failed Dependency"),
-
+            StatusCode::TooEarly => Some("Too Early"),
             StatusCode::UpgradeRequired => Some("Upgrade Required"),
 
             StatusCode::PreconditionRequired => Some("Precondition Required"),
@@ -421,6 +446,8 @@ impl StatusCode {
 
             StatusCode::RequestHeaderFieldsTooLarge => Some("Request Header Fields Too Large"),
 
+            StatusCode::UnavailableForLegalReasons => Some("Unavailable For Legal Reasons"),
+
             StatusCode::InternalServerError => Some("Internal Server Error"),
             StatusCode::NotImplemented => Some("Not Implemented"),
             StatusCode::BadGateway => Some("Bad Gateway"),
@@ -478,6 +505,17 @@ impl StatusCode {
     pub fn is_strange_status(&self) -> bool {
         self.class() == StatusClass::NoClass
     }
+
+    /// Check if a response with this status is allowed to have a body.
+    ///
+    /// Informational, `204 No Content` and `304 Not Modified` responses
+    /// never have one.
+    pub fn may_have_body(&self) -> bool {
+        match *self {
+            StatusCode::NoContent | StatusCode::NotModified => false,
+            _ => !self.is_informational()
+        }
+    }
 }
 
 impl Copy for StatusCode {}
@@ -638,3 +676,31 @@ impl StatusClass {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::StatusCode;
+    use super::StatusCode::*;
+
+    #[test]
+    fn test_from_u16_round_trips() {
+        for n in 0..1000 {
+            assert_eq!(StatusCode::from_u16(n).to_u16(), n);
+        }
+        assert_eq!(StatusCode::from_u16(451), UnavailableForLegalReasons);
+        assert_eq!(StatusCode::from_u16(499), Unregistered(499));
+    }
+
+    #[test]
+    fn test_registered_codes_have_reasons() {
+        for n in 100..600 {
+            match StatusCode::from_u16(n) {
+                Unregistered(..) => (),
+                status => assert!(status.canonical_reason().is_some(), "{} has no reason", n)
+            }
+        }
+        assert_eq!(EarlyHints.to_string(), "103 Early Hints");
+        assert!(TooEarly.is_client_error());
+        assert!(!EarlyHints.may_have_body());
+    }
+}
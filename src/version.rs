@@ -19,14 +19,54 @@ pub enum HttpVersion {
     Http20
 }
 
-impl fmt::Display for HttpVersion {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(match *self {
+impl HttpVersion {
+    /// Whether a message of this version can be framed with the `chunked`
+    /// transfer coding.
+    ///
+    /// HTTP/1.0 predates it, and HTTP/2 frames messages itself.
+    pub fn allows_chunked(&self) -> bool {
+        *self == Http11
+    }
+
+    /// Whether a connection of this version stays open between messages,
+    /// unless a `Connection` header says otherwise.
+    pub fn keeps_alive(&self) -> bool {
+        match *self {
+            Http11 | Http20 => true,
+            Http09 | Http10 => false
+        }
+    }
+}
+
+impl AsRef<str> for HttpVersion {
+    fn as_ref(&self) -> &str {
+        match *self {
             Http09 => "HTTP/0.9",
             Http10 => "HTTP/1.0",
             Http11 => "HTTP/1.1",
             Http20 => "HTTP/2.0",
-        })
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpVersion::{Http09, Http10, Http11, Http20};
+
+    #[test]
+    fn test_version_framing() {
+        assert!(Http11.allows_chunked());
+        assert!(!Http10.allows_chunked());
+        assert!(!Http20.allows_chunked());
+        assert!(Http20.keeps_alive());
+        assert!(!Http09.keeps_alive());
+        assert_eq!(Http20.to_string(), "HTTP/2.0");
     }
 }
 